@@ -1,14 +1,22 @@
+use std::io::{self, Write};
+
 use crate::db;
+use crate::output::{self, OutputMode, OutputOptions};
+use crate::sql;
+use crate::sql::ast::{ResultColumn, Statement};
 use anyhow::{Context, Result};
 
-/// Displays database information including page size and number of tables.
+/// Displays database information including page size, number of tables
+/// and the freelist.
 ///
-/// Reads the SQLite database header and prints the database page size
-/// and the total number of tables in the database.
+/// Reads the SQLite database header and prints the database page size, the
+/// total number of tables in the database, and how many pages are free.
+/// The freelist is checked first: a page both free and used by a B-tree is
+/// reported as an error.
 ///
 /// # Arguments
 ///
-/// * `path` - Path to the SQLite database file
+/// * `pager` - The database connection
 ///
 /// # Returns
 ///
@@ -18,27 +26,34 @@ use anyhow::{Context, Result};
 /// # Examples
 ///
 /// ```no_run
-/// dbinfo("sample.db")?;
+/// dbinfo(&mut db::Pager::open("sample.db"))?;
 /// // Output:
 /// // database page size: 4096
 /// // number of tables: 3
+/// // freelist page count: 0
+/// // freelist trunk count: 0
 /// ```
-pub fn dbinfo(path: &str) -> Result<()> {
-    let (page_size, number_of_tables) =
-        db::read_db_info(path).context("Failed to read database info")?;
-    println!("database page size: {}", page_size);
-    println!("number of tables: {}", number_of_tables);
+pub fn dbinfo(pager: &mut db::Pager) -> Result<()> {
+    let info = db::read_db_info(pager).context("Failed to read database info")?;
+    println!("database page size: {}", info.page_size);
+    println!("number of tables: {}", info.number_of_tables);
+    println!("freelist page count: {}", info.freelist.len());
+    println!("freelist trunk count: {}", info.freelist.trunks.len());
     Ok(())
 }
 
-/// Displays all user-defined table names in the database.
+/// Displays the names of user-defined tables and views, in columns like
+/// sqlite3's `.tables`.
 ///
-/// Reads the sqlite_schema table and prints all user-defined table names
-/// (excluding internal SQLite tables like sqlite_sequence) separated by spaces.
+/// Internal tables such as sqlite_sequence are left out. Names are sorted
+/// and listed down each column in turn, as many columns as fit in 80
+/// characters.
 ///
 /// # Arguments
 ///
-/// * `path` - Path to the SQLite database file
+/// * `pager` - The database connection
+/// * `args` - The text after `.tables`: an optional LIKE pattern the names
+///   must match
 ///
 /// # Returns
 ///
@@ -48,110 +63,588 @@ pub fn dbinfo(path: &str) -> Result<()> {
 /// # Examples
 ///
 /// ```no_run
-/// table("sample.db")?;
+/// table(&mut db::Pager::open("sample.db"), "")?;
+/// // Output:
+/// // comments  posts     users
+///
+/// table(&mut db::Pager::open("sample.db"), "p%")?;
+/// // Output:
+/// // posts
+/// ```
+pub fn table(pager: &mut db::Pager, args: &str) -> Result<()> {
+    let args = split_args(args);
+    if args.len() > 1 {
+        anyhow::bail!("Usage: .tables ?LIKE-PATTERN?");
+    }
+    let pattern = args.first().map_or("%", String::as_str);
+    let entries = db::read_schema_entries(pager).context("Failed to read schema")?;
+    let mut names: Vec<String> = entries
+        .into_iter()
+        .filter(|entry| matches!(entry.entry_type.as_str(), "table" | "view"))
+        .filter(|entry| !like("sqlite_%", &entry.name))
+        .filter(|entry| like(pattern, &entry.name))
+        .map(|entry| entry.name)
+        .collect();
+    names.sort();
+    print_columns(&names);
+    Ok(())
+}
+
+/// Displays the names of indexes, in columns like `.tables`.
+///
+/// # Arguments
+///
+/// * `pager` - The database connection
+/// * `args` - The text after `.indexes`: an optional LIKE pattern the name
+///   of an index's table must match
+///
+/// # Examples
+///
+/// ```no_run
+/// indexes(&mut db::Pager::open("sample.db"), "apples")?;
+/// // Output:
+/// // idx_apples_color           sqlite_autoindex_apples_1
+/// ```
+pub fn indexes(pager: &mut db::Pager, args: &str) -> Result<()> {
+    let args = split_args(args);
+    if args.len() > 1 {
+        anyhow::bail!("Usage: .indexes ?LIKE-PATTERN?");
+    }
+    let pattern = args.first().map_or("%", String::as_str);
+    let entries = db::read_schema_entries(pager).context("Failed to read schema")?;
+    let mut names: Vec<String> = entries
+        .into_iter()
+        .filter(|entry| entry.is_index() && like(pattern, &entry.tbl_name))
+        .map(|entry| entry.name)
+        .collect();
+    names.sort();
+    print_columns(&names);
+    Ok(())
+}
+
+/// Displays the CREATE statements stored in the schema, as sqlite3's
+/// `.schema` does.
+///
+/// Each view is followed by a comment naming its columns.
+///
+/// # Arguments
+///
+/// * `pager` - The database connection
+/// * `args` - The text after `.schema`: optionally `--nosys` to leave out
+///   internal tables, then optionally a pattern the table names must
+///   match. A pattern with `*`, `?` or `[` is a GLOB against the
+///   lowercased name, and any other a LIKE.
+///
+/// # Examples
+///
+/// ```no_run
+/// schema(&mut db::Pager::open("sample.db"), "apples")?;
 /// // Output:
-/// // users posts comments
+/// // CREATE TABLE apples(id integer primary key, name text, color text);
+/// // CREATE INDEX idx_apples_color on apples(color);
 /// ```
-pub fn table(path: &str) -> Result<()> {
-    let table_names = db::read_table_names(path).context("Failed to read table names")?;
-    println!("{}", table_names.join(" "));
+pub fn schema(pager: &mut db::Pager, args: &str) -> Result<()> {
+    let mut no_system = false;
+    let mut pattern = None;
+    for arg in split_args(args) {
+        match arg.as_str() {
+            "--nosys" => no_system = true,
+            // Indenting is accepted, but statements are printed as stored
+            "--indent" => {}
+            _ if pattern.is_none() => pattern = Some(arg),
+            _ => anyhow::bail!("Usage: .schema ?--indent? ?--nosys? ?LIKE-PATTERN?"),
+        }
+    }
+    let entries = db::read_schema_entries(pager).context("Failed to read schema")?;
+
+    if let Some(pattern) = &pattern
+        && ["sqlite_master", "sqlite_schema"]
+            .iter()
+            .any(|name| like(pattern, name))
+    {
+        println!(
+            "CREATE TABLE {} (\n  type text,\n  name text,\n  tbl_name text,\n  rootpage integer,\n  sql text\n);",
+            pattern
+        );
+    }
+    for entry in &entries {
+        if entry.sql.is_empty() || (no_system && like("sqlite_%", &entry.name)) {
+            continue;
+        }
+        let matched = match &pattern {
+            None => true,
+            Some(pattern) if pattern.contains(['*', '?', '[']) => {
+                glob(pattern, &entry.tbl_name.to_lowercase())
+            }
+            Some(pattern) => like(pattern, &entry.tbl_name),
+        };
+        if !matched {
+            continue;
+        }
+        let mut sql = entry.sql.clone();
+        if entry.entry_type == "view"
+            && let Some(columns) = view_columns(&entries, &entry.sql)
+        {
+            let columns: Vec<String> = columns
+                .iter()
+                .map(|name| db::identifier_sql(name))
+                .collect();
+            sql.push_str(&format!(
+                "\n/* {}({}) */",
+                db::identifier_sql(&entry.name),
+                columns.join(",")
+            ));
+        }
+        println!("{}", schema_line(&sql));
+    }
     Ok(())
 }
 
-/// Execute a SQL query.
+/// Displays the whole schema along with the statistics ANALYZE gathered,
+/// as sqlite3's `.fullschema` does, so that a database can be rebuilt to
+/// plan queries the same way.
+///
+/// # Arguments
+///
+/// * `pager` - The database connection
+///
+/// # Examples
 ///
-/// Supports SELECT COUNT(*) and SELECT column FROM table queries.
+/// ```no_run
+/// fullschema(&mut db::Pager::open("sample.db"))?;
+/// // Output:
+/// // CREATE TABLE apples(id integer primary key, name text, color text);
+/// // CREATE INDEX idx_apples_color on apples(color);
+/// // ANALYZE sqlite_schema;
+/// // INSERT INTO sqlite_stat1 VALUES('apples','idx_apples_color','4 2');
+/// // ANALYZE sqlite_schema;
+/// ```
+pub fn fullschema(pager: &mut db::Pager) -> Result<()> {
+    let entries = db::read_schema_entries(pager).context("Failed to read schema")?;
+    for entry in &entries {
+        if !entry.sql.is_empty() && !like("sqlite_%", &entry.name) {
+            println!("{}", schema_line(&entry.sql));
+        }
+    }
+
+    let stat_tables: Vec<&str> = ["sqlite_stat1", "sqlite_stat4"]
+        .into_iter()
+        .filter(|name| entries.iter().any(|entry| entry.name == *name))
+        .collect();
+    if !entries
+        .iter()
+        .any(|entry| glob("sqlite_stat[134]", &entry.name))
+    {
+        println!("/* No STAT tables available */");
+        return Ok(());
+    }
+    println!("ANALYZE sqlite_schema;");
+    let mut out = io::stdout().lock();
+    for name in stat_tables {
+        let mut options = OutputOptions::default();
+        options.set_mode(OutputMode::Insert);
+        options.insert_table = name.to_string();
+        for statement in &sql::parse(&format!("SELECT * FROM {}", name))? {
+            let result = db::execute(pager, statement).context("Failed to read statistics")?;
+            output::write_result(&mut out, &result, &options)?;
+        }
+    }
+    writeln!(out, "ANALYZE sqlite_schema;")?;
+    Ok(())
+}
+
+/// Execute SQL statements and print the rows they return.
 ///
 /// # Arguments
 ///
-/// * `path` - Path to the SQLite database file
-/// * `query` - The SQL query to execute
+/// * `pager` - The database connection, which keeps any open transaction
+///   between calls
+/// * `query` - The SQL to execute: one or more statements separated by semicolons
+/// * `options` - How to print the result rows
 ///
 /// # Returns
 ///
-/// Returns `Ok(())` on success, or an error if the query fails.
+/// Returns `Ok(())` on success, or an error if a statement fails. Statements
+/// after a failing one are not run.
 ///
 /// # Examples
 ///
 /// ```no_run
-/// sql("sample.db", "SELECT COUNT(*) FROM apples")?;
+/// let mut pager = db::Pager::open("sample.db");
+/// sql(&mut pager, "SELECT COUNT(*) FROM apples", &OutputOptions::default())?;
 /// // Output:
 /// // 4
 ///
-/// sql("sample.db", "SELECT name FROM apples")?;
+/// sql(&mut pager, "BEGIN; DELETE FROM apples; ROLLBACK", &OutputOptions::default())?;
+/// sql(&mut pager, "SELECT name, NULL FROM apples", &OutputOptions::default())?;
 /// // Output:
-/// // Granny Smith
-/// // Fuji
+/// // Granny Smith|
+/// // Fuji|
 /// // ...
 /// ```
-pub fn sql(path: &str, query: &str) -> Result<()> {
-    let parts: Vec<&str> = query.split_whitespace().collect();
-
-    if parts.is_empty() {
-        anyhow::bail!("Empty query");
+pub fn sql(pager: &mut db::Pager, query: &str, options: &OutputOptions) -> Result<()> {
+    let statements = sql::parse(query).context("Failed to parse query")?;
+    let mut out = io::stdout().lock();
+    for statement in &statements {
+        let result = db::execute(pager, statement).context("Failed to execute query")?;
+        output::write_result(&mut out, &result, options)?;
     }
+    Ok(())
+}
 
-    let upper_query = query.to_uppercase();
+/// Sets the text printed in place of NULL values.
+///
+/// # Arguments
+///
+/// * `options` - Output settings to update
+/// * `args` - The text after `.nullvalue`, optionally quoted
+///
+/// # Examples
+///
+/// ```no_run
+/// nullvalue(&mut options, "NULL")?;
+/// sql("sample.db", "SELECT NULL", &options)?;
+/// // Output:
+/// // NULL
+/// ```
+pub fn nullvalue(options: &mut OutputOptions, args: &str) -> Result<()> {
+    let args = args.trim();
+    if args.is_empty() {
+        anyhow::bail!("Usage: .nullvalue STRING");
+    }
+    options.null_value = unquote(args).to_string();
+    Ok(())
+}
 
-    // Check if this is a COUNT query
-    if upper_query.contains("COUNT(*)") {
-        let table_name = parts.last().unwrap();
-        let count = db::count_table_rows(path, table_name).context("Failed to count table rows")?;
-        println!("{}", count);
+/// Sets or shows the output mode, and for insert mode the table to insert into.
+///
+/// # Arguments
+///
+/// * `options` - Output settings to update
+/// * `args` - The text after `.mode`: a mode name, and for insert mode an
+///   optional table name. With no mode, the current mode is printed.
+///
+/// # Examples
+///
+/// ```no_run
+/// mode(&mut options, "csv")?;
+/// sql(&mut pager, "SELECT 1, 'a,b'", &options)?;
+/// // Output:
+/// // 1,"a,b"
+/// ```
+pub fn mode(options: &mut OutputOptions, args: &str) -> Result<()> {
+    let args = split_args(args);
+    let Some(name) = args.first() else {
+        match options.mode {
+            OutputMode::Insert => println!("current output mode: insert {}", options.insert_table),
+            mode => println!("current output mode: {}", mode.name()),
+        }
         return Ok(());
+    };
+    let Some(mode) = OutputMode::from_name(name) else {
+        anyhow::bail!("mode should be one of: {}", OutputMode::names().join(" "));
+    };
+    options.set_mode(mode);
+    if mode == OutputMode::Insert {
+        options.insert_table = args.get(1).map_or("table", String::as_str).to_string();
     }
+    Ok(())
+}
 
-    // Parse SELECT columns FROM table [WHERE condition]
-    // Expected format: SELECT <column1>, <column2>, ... FROM <table> [WHERE <column> = <value>]
-    if parts.len() >= 4 && parts[0].eq_ignore_ascii_case("SELECT") {
-        // Find FROM position in the original query (case-insensitive)
-        let upper_query_for_from = query.to_uppercase();
-        let from_pos_in_query = upper_query_for_from.find(" FROM ");
-        if let Some(from_idx) = from_pos_in_query {
-            // Extract columns part (between SELECT and FROM)
-            let select_len = "SELECT ".len();
-            let columns_part = &query[select_len..from_idx];
-
-            // Parse column names (comma-separated, trim whitespace)
-            let column_names: Vec<&str> = columns_part.split(',').map(|s| s.trim()).collect();
-
-            // Extract table name and optional WHERE clause
-            let after_from = &query[from_idx + " FROM ".len()..];
-
-            // Check if there's a WHERE clause
-            let upper_after_from = after_from.to_uppercase();
-            let where_pos = upper_after_from.find(" WHERE ");
-
-            let (table_name, where_clause) = if let Some(where_idx) = where_pos {
-                let table_part = &after_from[..where_idx];
-                let table_name = table_part
-                    .split_whitespace()
-                    .next()
-                    .ok_or_else(|| anyhow::anyhow!("Missing table name after FROM"))?;
-                let where_part = &after_from[where_idx + " WHERE ".len()..];
-                (table_name, Some(where_part))
-            } else {
-                let table_name = after_from
-                    .split_whitespace()
-                    .next()
-                    .ok_or_else(|| anyhow::anyhow!("Missing table name after FROM"))?;
-                (table_name, None)
-            };
+/// Turns the row of column names before results on or off.
+///
+/// # Arguments
+///
+/// * `options` - Output settings to update
+/// * `args` - The text after `.headers`: `on` or `off`, or another boolean
+///   such as `yes` or `1`. Anything else is reported and taken as off.
+///
+/// # Examples
+///
+/// ```no_run
+/// headers(&mut options, "on")?;
+/// sql(&mut pager, "SELECT 1 AS a", &options)?;
+/// // Output:
+/// // a
+/// // 1
+/// ```
+pub fn headers(options: &mut OutputOptions, args: &str) -> Result<()> {
+    let args = split_args(args);
+    let Some(value) = args.first() else {
+        anyhow::bail!("Usage: .headers on|off");
+    };
+    options.headers = parse_boolean(value);
+    options.headers_set = true;
+    Ok(())
+}
 
-            let rows = if let Some(where_clause) = where_clause {
-                db::select_columns_with_filter(path, table_name, &column_names, where_clause)
-                    .context("Failed to select columns with filter")?
-            } else {
-                db::select_columns(path, table_name, &column_names)
-                    .context("Failed to select columns")?
-            };
+/// Sets the text printed between values, and optionally after each row,
+/// in list, csv and quote modes.
+///
+/// # Arguments
+///
+/// * `options` - Output settings to update
+/// * `args` - The text after `.separator`: the column separator, then
+///   optionally the row separator. Double-quoted separators may use
+///   backslash escapes such as `"\t"`.
+///
+/// # Examples
+///
+/// ```no_run
+/// separator(&mut options, "\", \" \";\"")?;
+/// sql(&mut pager, "SELECT 1, 2", &options)?;
+/// // Output:
+/// // 1, 2;
+/// ```
+pub fn separator(options: &mut OutputOptions, args: &str) -> Result<()> {
+    let mut args = split_args(args).into_iter();
+    let Some(separator) = args.next() else {
+        anyhow::bail!("Usage: .separator COL ?ROW?");
+    };
+    options.separator = separator;
+    if let Some(row_separator) = args.next() {
+        options.row_separator = row_separator;
+    }
+    Ok(())
+}
+
+/// Parses a dot-command boolean argument as sqlite3 does, reporting
+/// values that aren't one and taking them as false.
+fn parse_boolean(value: &str) -> bool {
+    match value.to_ascii_lowercase().as_str() {
+        "on" | "yes" | "true" => true,
+        "off" | "no" | "false" => false,
+        number => match number.parse::<i64>() {
+            Ok(n) => n != 0,
+            Err(_) => {
+                eprintln!(
+                    "ERROR: Not a boolean value: \"{}\". Assuming \"no\".",
+                    value
+                );
+                false
+            }
+        },
+    }
+}
+
+/// Splits dot-command arguments at whitespace. Like sqlite3, an argument
+/// may be quoted to hold whitespace: single quotes take the text as it is,
+/// and double quotes resolve backslash escapes such as `\t` and `\n`.
+fn split_args(args: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut chars = args.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut arg = String::new();
+        if c == '\'' || c == '"' {
+            chars.next();
+            while let Some(ch) = chars.next() {
+                match ch {
+                    ch if ch == c => break,
+                    '\\' if c == '"' => match chars.next() {
+                        Some('t') => arg.push('\t'),
+                        Some('n') => arg.push('\n'),
+                        Some('r') => arg.push('\r'),
+                        Some(escaped @ ('\\' | '"' | '\'')) => arg.push(escaped),
+                        Some(other) => {
+                            arg.push('\\');
+                            arg.push(other);
+                        }
+                        None => arg.push('\\'),
+                    },
+                    ch => arg.push(ch),
+                }
+            }
+        } else {
+            while let Some(ch) = chars.next_if(|ch| !ch.is_whitespace()) {
+                arg.push(ch);
+            }
+        }
+        result.push(arg);
+    }
+    result
+}
+
+/// Strips one pair of matching single or double quotes from a dot-command argument.
+fn unquote(arg: &str) -> &str {
+    for quote in ['\'', '"'] {
+        if arg.len() >= 2 && arg.starts_with(quote) && arg.ends_with(quote) {
+            return &arg[1..arg.len() - 1];
+        }
+    }
+    arg
+}
+
+/// Prints names in columns as sqlite3's `.tables` does: as many columns as
+/// fit in 80 characters, each as wide as the longest name, filled top to
+/// bottom.
+fn print_columns(names: &[String]) {
+    if names.is_empty() {
+        return;
+    }
+    let width = names
+        .iter()
+        .map(|name| name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let columns = (80 / (width + 2)).max(1);
+    let rows = names.len().div_ceil(columns);
+    for row in 0..rows {
+        let line: Vec<String> = names[row..]
+            .iter()
+            .step_by(rows)
+            .map(|name| format!("{:<width$}", name, width = width))
+            .collect();
+        println!("{}", line.join("  "));
+    }
+}
+
+/// Finishes a stored CREATE statement for printing as sqlite3 does: a
+/// comment left open at its end is closed before the `;`, and a table name
+/// in quotes gets `IF NOT EXISTS`, as ALTER TABLE RENAME leaves it.
+fn schema_line(sql: &str) -> String {
+    let mut sql = sql.to_string();
+    if (sql.contains("/*") || sql.contains("--"))
+        && let Some(ending) = ["", "*/", "\n"]
+            .into_iter()
+            .find(|ending| sql::is_complete(&format!("{}{};", sql, ending)))
+    {
+        sql.push_str(ending);
+    }
+    if sql.starts_with("CREATE TABLE '") || sql.starts_with("CREATE TABLE \"") {
+        sql = format!("CREATE TABLE IF NOT EXISTS {}", &sql[13..]);
+    }
+    sql.push(';');
+    sql
+}
+
+/// Works out the column names of a view from its CREATE VIEW statement:
+/// the names listed after the view's name, or else those its SELECT gives
+/// its results. `None` if the statement can't be read.
+fn view_columns(entries: &[db::schema::SchemaEntry], create_sql: &str) -> Option<Vec<String>> {
+    let tokens = sql::tokenize(create_sql).ok()?;
+    let mut depth = 0;
+    let as_index = tokens.iter().position(|spanned| match &spanned.token {
+        sql::Token::Symbol(sql::Symbol::LeftParen) => {
+            depth += 1;
+            false
+        }
+        sql::Token::Symbol(sql::Symbol::RightParen) => {
+            depth -= 1;
+            false
+        }
+        sql::Token::Word(word) => depth == 0 && word.eq_ignore_ascii_case("AS"),
+        _ => false,
+    })?;
 
-            for row in rows {
-                println!("{}", row.join("|"));
+    // An explicit list of names: `CREATE VIEW v(a, b) AS ...`
+    if as_index > 0 && tokens[as_index - 1].token == sql::Token::Symbol(sql::Symbol::RightParen) {
+        let open = tokens[..as_index]
+            .iter()
+            .rposition(|spanned| spanned.token == sql::Token::Symbol(sql::Symbol::LeftParen))?;
+        return tokens[open + 1..as_index - 1]
+            .iter()
+            .filter(|spanned| spanned.token != sql::Token::Symbol(sql::Symbol::Comma))
+            .map(|spanned| match &spanned.token {
+                sql::Token::Word(name) | sql::Token::QuotedIdentifier(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+    }
+
+    let statements = sql::parse(&create_sql[tokens[as_index].end..]).ok()?;
+    let [Statement::Select(select)] = statements.as_slice() else {
+        return None;
+    };
+    let mut columns = Vec::new();
+    for column in &select.columns {
+        match column {
+            ResultColumn::Star => {
+                let from = select.from.as_ref()?;
+                let source = entries
+                    .iter()
+                    .find(|entry| !entry.is_index() && entry.name.eq_ignore_ascii_case(from))?;
+                if source.entry_type == "view" {
+                    columns.extend(view_columns(entries, &source.sql)?);
+                } else {
+                    columns.extend(
+                        db::schema::parse_columns(&source.sql)
+                            .ok()?
+                            .into_iter()
+                            .map(|column| column.name),
+                    );
+                }
+            }
+            ResultColumn::Expr { alias, text, .. } => {
+                columns.push(alias.clone().unwrap_or_else(|| text.clone()));
             }
-            return Ok(());
         }
     }
+    Some(columns)
+}
+
+/// Matches text against a LIKE pattern: `%` matches any run of characters
+/// and `_` any one, ignoring ASCII case.
+fn like(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    like_chars(&pattern, &text)
+}
 
-    anyhow::bail!("Unsupported query: {}", query)
+fn like_chars(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('%', rest)) => (0..=text.len()).any(|skip| like_chars(rest, &text[skip..])),
+        Some(('_', rest)) => !text.is_empty() && like_chars(rest, &text[1..]),
+        Some((&c, rest)) => text
+            .first()
+            .is_some_and(|t| t.eq_ignore_ascii_case(&c) && like_chars(rest, &text[1..])),
+    }
+}
+
+/// Matches text against a GLOB pattern: `*` matches any run of characters,
+/// `?` any one, and `[...]` one from a set that may hold ranges, or any
+/// outside it when it starts with `^`. Case matters.
+fn glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    glob_chars(&pattern, &text)
+}
+
+fn glob_chars(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|skip| glob_chars(rest, &text[skip..])),
+        Some(('?', rest)) => !text.is_empty() && glob_chars(rest, &text[1..]),
+        Some(('[', rest)) => {
+            let Some(&c) = text.first() else {
+                return false;
+            };
+            let (negated, set) = match rest.split_first() {
+                Some(('^', set)) => (true, set),
+                _ => (false, rest),
+            };
+            // A `]` first in the set is one of its characters
+            let Some(close) = set.iter().skip(1).position(|&ch| ch == ']').map(|n| n + 1) else {
+                return false;
+            };
+            let members = &set[..close];
+            let mut found = false;
+            let mut i = 0;
+            while i < members.len() {
+                if i + 2 < members.len() && members[i + 1] == '-' {
+                    found |= (members[i]..=members[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    found |= members[i] == c;
+                    i += 1;
+                }
+            }
+            found != negated && glob_chars(&set[close + 1..], &text[1..])
+        }
+        Some((&c, rest)) => text.first() == Some(&c) && glob_chars(rest, &text[1..]),
+    }
 }
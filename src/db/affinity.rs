@@ -0,0 +1,137 @@
+//! Column type affinity and the conversions it implies.
+
+use super::value::Value;
+
+/// Every i64 lies in [-2^63, 2^63).
+const I64_RANGE: std::ops::Range<f64> = -9_223_372_036_854_775_808.0..9_223_372_036_854_775_808.0;
+
+/// The type preference of a column, derived from its declared type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Affinity {
+    Integer,
+    Text,
+    Blob,
+    Real,
+    Numeric,
+    /// No affinity, as most expressions other than columns have. Unlike a
+    /// BLOB column, an operand with none takes on the other side's affinity
+    /// in a comparison.
+    #[default]
+    None,
+}
+
+impl Affinity {
+    /// Determine the affinity of a declared column type, using SQLite's rules in order:
+    /// "INT" gives INTEGER; "CHAR", "CLOB" or "TEXT" give TEXT; "BLOB" or no type
+    /// gives BLOB; "REAL", "FLOA" or "DOUB" give REAL; anything else is NUMERIC.
+    pub fn from_declared_type(declared_type: &str) -> Self {
+        let declared_type = declared_type.to_uppercase();
+        let contains_any = |words: &[&str]| words.iter().any(|w| declared_type.contains(w));
+        if contains_any(&["INT"]) {
+            Affinity::Integer
+        } else if contains_any(&["CHAR", "CLOB", "TEXT"]) {
+            Affinity::Text
+        } else if contains_any(&["BLOB"]) || declared_type.trim().is_empty() {
+            Affinity::Blob
+        } else if contains_any(&["REAL", "FLOA", "DOUB"]) {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+
+    /// Check if this is one of the numeric affinities.
+    pub fn is_numeric(self) -> bool {
+        matches!(self, Affinity::Integer | Affinity::Real | Affinity::Numeric)
+    }
+
+    /// Convert a value the way storing it in a column of this affinity would.
+    ///
+    /// Only lossless conversions happen: text that doesn't look like a number
+    /// stays text in a numeric column.
+    pub fn apply(self, value: Value) -> Value {
+        match self {
+            Affinity::Text => match value {
+                Value::Integer(_) | Value::Real(_) => Value::Text(value.to_string()),
+                value => value,
+            },
+            Affinity::Real => match value {
+                Value::Integer(i) => Value::Real(i as f64),
+                Value::Text(_) => match value.as_number() {
+                    Some(number) => Value::Real(number.as_f64()),
+                    None => value,
+                },
+                value => value,
+            },
+            Affinity::Integer | Affinity::Numeric => match value {
+                Value::Real(_) => real_to_integer(value),
+                Value::Text(_) => match value.as_number() {
+                    Some(number) => real_to_integer(number),
+                    None => value,
+                },
+                value => value,
+            },
+            Affinity::Blob | Affinity::None => value,
+        }
+    }
+
+    /// Convert a value as `CAST(value AS type)` does for a type with this affinity.
+    ///
+    /// Unlike [`Affinity::apply`], casts always produce the target storage
+    /// class, using as much of a text value as looks like a number.
+    pub fn cast(self, value: Value) -> Value {
+        if value == Value::Null {
+            return Value::Null;
+        }
+        match self {
+            Affinity::Text => match value {
+                Value::Blob(bytes) => Value::Text(String::from_utf8_lossy(&bytes).into_owned()),
+                value => Value::Text(value.to_string()),
+            },
+            Affinity::Blob => Value::Blob(value.to_bytes()),
+            Affinity::None => value,
+            Affinity::Real => Value::Real(value.to_numeric().as_f64()),
+            Affinity::Integer => match value {
+                Value::Integer(_) => value,
+                // Float to integer conversion saturates, as in SQLite
+                Value::Real(r) => Value::Integer(r as i64),
+                value => {
+                    Value::Integer(integer_prefix(&String::from_utf8_lossy(&value.to_bytes())))
+                }
+            },
+            Affinity::Numeric => match value {
+                Value::Integer(_) | Value::Real(_) => value,
+                value => real_to_integer(value.to_numeric()),
+            },
+        }
+    }
+}
+
+/// Convert a REAL with no fractional part to an INTEGER, if it fits.
+fn real_to_integer(value: Value) -> Value {
+    match value {
+        Value::Real(r) if r.fract() == 0.0 && I64_RANGE.contains(&r) => Value::Integer(r as i64),
+        value => value,
+    }
+}
+
+/// Parse the optionally signed integer at the start of `text`, saturating on
+/// overflow. Returns 0 if there are no digits.
+fn integer_prefix(text: &str) -> i64 {
+    let text = text.trim_start();
+    let (negative, digits) = match text.as_bytes().first() {
+        Some(b'-') => (true, &text[1..]),
+        Some(b'+') => (false, &text[1..]),
+        _ => (false, text),
+    };
+    let mut result: i64 = 0;
+    for digit in digits.bytes().take_while(u8::is_ascii_digit) {
+        let digit = (digit - b'0') as i64;
+        result = if negative {
+            result.saturating_mul(10).saturating_sub(digit)
+        } else {
+            result.saturating_mul(10).saturating_add(digit)
+        };
+    }
+    result
+}
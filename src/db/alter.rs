@@ -0,0 +1,1038 @@
+//! ALTER TABLE execution: renaming tables and columns, and adding and
+//! dropping columns.
+
+use std::ops::Range;
+
+use crate::sql::ast::{AlterAction, AlterTable, ConflictAction, Expr, UnaryOp};
+use crate::sql::{Spanned, Symbol, Token, tokenize};
+
+use super::btree::traverse_btree_table;
+use super::btree_write::{delete_table_row, insert_table_row};
+use super::constants::SCHEMA_FORMAT_OFFSET;
+use super::create::check_object_name;
+use super::database::Database;
+use super::error::{DbError, DbResult, read_u32, write_u32};
+use super::page::{Page, encode_record};
+use super::query::{QueryResult, filter_rows};
+use super::schema::{
+    SchemaEntry, add_column_definition, drop_column_definition, is_table_constraint_word,
+    parse_columns, read_schema_rows, unique_constraints,
+};
+use super::schema_write::{bump_schema_cookie, replace_schema_entry};
+use super::table_write::{SEQUENCE_TABLE, TableWriter};
+use super::value::Value;
+
+/// The schema format that records may omit trailing columns added by ALTER
+/// TABLE ADD COLUMN in, reading them as their default.
+const ADD_COLUMN_SCHEMA_FORMAT: u32 = 3;
+
+/// Words SQLite reserves, which a name must be quoted to be used as.
+const KEYWORDS: &[&str] = &[
+    "ABORT",
+    "ACTION",
+    "ADD",
+    "AFTER",
+    "ALL",
+    "ALTER",
+    "ALWAYS",
+    "ANALYZE",
+    "AND",
+    "AS",
+    "ASC",
+    "ATTACH",
+    "AUTOINCREMENT",
+    "BEFORE",
+    "BEGIN",
+    "BETWEEN",
+    "BY",
+    "CASCADE",
+    "CASE",
+    "CAST",
+    "CHECK",
+    "COLLATE",
+    "COLUMN",
+    "COMMIT",
+    "CONFLICT",
+    "CONSTRAINT",
+    "CREATE",
+    "CROSS",
+    "CURRENT",
+    "CURRENT_DATE",
+    "CURRENT_TIME",
+    "CURRENT_TIMESTAMP",
+    "DATABASE",
+    "DEFAULT",
+    "DEFERRABLE",
+    "DEFERRED",
+    "DELETE",
+    "DESC",
+    "DETACH",
+    "DISTINCT",
+    "DO",
+    "DROP",
+    "EACH",
+    "ELSE",
+    "END",
+    "ESCAPE",
+    "EXCEPT",
+    "EXCLUDE",
+    "EXCLUSIVE",
+    "EXISTS",
+    "EXPLAIN",
+    "FAIL",
+    "FILTER",
+    "FIRST",
+    "FOLLOWING",
+    "FOR",
+    "FOREIGN",
+    "FROM",
+    "FULL",
+    "GENERATED",
+    "GLOB",
+    "GROUP",
+    "GROUPS",
+    "HAVING",
+    "IF",
+    "IGNORE",
+    "IMMEDIATE",
+    "IN",
+    "INDEX",
+    "INDEXED",
+    "INITIALLY",
+    "INNER",
+    "INSERT",
+    "INSTEAD",
+    "INTERSECT",
+    "INTO",
+    "IS",
+    "ISNULL",
+    "JOIN",
+    "KEY",
+    "LAST",
+    "LEFT",
+    "LIKE",
+    "LIMIT",
+    "MATCH",
+    "MATERIALIZED",
+    "NATURAL",
+    "NO",
+    "NOT",
+    "NOTHING",
+    "NOTNULL",
+    "NULL",
+    "NULLS",
+    "OF",
+    "OFFSET",
+    "ON",
+    "OR",
+    "ORDER",
+    "OTHERS",
+    "OUTER",
+    "OVER",
+    "PARTITION",
+    "PLAN",
+    "PRAGMA",
+    "PRECEDING",
+    "PRIMARY",
+    "QUERY",
+    "RAISE",
+    "RANGE",
+    "RECURSIVE",
+    "REFERENCES",
+    "REGEXP",
+    "REINDEX",
+    "RELEASE",
+    "RENAME",
+    "REPLACE",
+    "RESTRICT",
+    "RETURNING",
+    "RIGHT",
+    "ROLLBACK",
+    "ROW",
+    "ROWS",
+    "SAVEPOINT",
+    "SELECT",
+    "SET",
+    "TABLE",
+    "TEMP",
+    "TEMPORARY",
+    "THEN",
+    "TIES",
+    "TO",
+    "TRANSACTION",
+    "TRIGGER",
+    "UNBOUNDED",
+    "UNION",
+    "UNIQUE",
+    "UPDATE",
+    "USING",
+    "VACUUM",
+    "VALUES",
+    "VIEW",
+    "VIRTUAL",
+    "WHEN",
+    "WHERE",
+    "WINDOW",
+    "WITH",
+    "WITHOUT",
+];
+
+/// Change a table's definition, rewriting its rows when a column is dropped.
+pub fn execute_alter_table(db: &mut Database, alter: &AlterTable) -> DbResult<QueryResult> {
+    let rows = read_schema_rows(db)?;
+    let Some((rowid, entry)) = rows
+        .iter()
+        .find(|(_, e)| e.entry_type == "table" && e.name.eq_ignore_ascii_case(&alter.table))
+    else {
+        return Err(DbError::TableNotFound(alter.table.clone()));
+    };
+    if entry.name.to_lowercase().starts_with("sqlite_") {
+        return Err(DbError::InvalidStatement(format!(
+            "table {} may not be altered",
+            entry.name
+        )));
+    }
+
+    match &alter.action {
+        AlterAction::RenameTable(new) => rename_table(db, &rows, entry, new)?,
+        AlterAction::RenameColumn { old, new } => rename_column(db, &rows, entry, old, new)?,
+        AlterAction::AddColumn { column, text } => {
+            add_column(db, *rowid, entry, &column.name, text)?
+        }
+        AlterAction::DropColumn(name) => drop_column(db, &rows, *rowid, entry, name)?,
+    }
+    bump_schema_cookie(db)?;
+    Ok(QueryResult::default())
+}
+
+/// Rename a table, along with the references its indexes, foreign keys,
+/// views, triggers and its sqlite_sequence row make to it.
+fn rename_table(
+    db: &mut Database,
+    rows: &[(i64, SchemaEntry)],
+    table: &SchemaEntry,
+    new: &str,
+) -> DbResult<()> {
+    if rows.iter().any(|(_, e)| e.name.eq_ignore_ascii_case(new)) {
+        return Err(DbError::InvalidStatement(format!(
+            "there is already another table or index with this name: {}",
+            new
+        )));
+    }
+    check_object_name(new)?;
+
+    let old = &table.tbl_name;
+    let autoindex_prefix = format!("sqlite_autoindex_{}_", old);
+    // SQLite always quotes the new name in the rewritten SQL
+    let quoted = quote_identifier(new);
+    for (rowid, entry) in rows {
+        if !entry.tbl_name.eq_ignore_ascii_case(old) || entry.rootpage == 0 {
+            continue;
+        }
+        let mut entry = entry.clone();
+        entry.tbl_name = new.to_string();
+        if entry.entry_type == "table" {
+            entry.name = new.to_string();
+            let sql = rename_parent_table(&entry.sql, old, &quoted)?;
+            entry.sql = replace_token(&sql, table_name_token(&sql)?, &quoted);
+        } else if let Some(number) = entry.name.strip_prefix(&autoindex_prefix) {
+            entry.name = format!("sqlite_autoindex_{}_{}", new, number);
+        } else {
+            entry.sql = replace_token(&entry.sql, index_table_token(&entry.sql)?, &quoted);
+        }
+        replace_schema_entry(db, *rowid, &entry)?;
+    }
+    // Other tables' foreign keys name it as their parent, and views and
+    // triggers read and write it
+    for (rowid, entry) in rows {
+        let sql = match entry.entry_type.as_str() {
+            "table" if !entry.tbl_name.eq_ignore_ascii_case(old) => {
+                rename_parent_table(&entry.sql, old, &quoted)?
+            }
+            "view" | "trigger" => {
+                let dependent = DependentSql::read(&entry.sql)?;
+                let mut sql = entry.sql.clone();
+                for reference in dependent.table_references(old).iter().rev() {
+                    sql.replace_range(reference.pos..reference.end, &quoted);
+                }
+                sql
+            }
+            _ => continue,
+        };
+        // A trigger on the table is now on the new name
+        let on_table = entry.entry_type == "trigger" && entry.tbl_name.eq_ignore_ascii_case(old);
+        if sql != entry.sql || on_table {
+            let mut entry = SchemaEntry {
+                sql,
+                ..entry.clone()
+            };
+            if on_table {
+                entry.tbl_name = new.to_string();
+            }
+            replace_schema_entry(db, *rowid, &entry)?;
+        }
+    }
+
+    if let Some((_, sequence)) = rows
+        .iter()
+        .find(|(_, e)| e.entry_type == "table" && e.name == SEQUENCE_TABLE)
+    {
+        let mut records = Vec::new();
+        traverse_btree_table(db, sequence.rootpage, &mut records)?;
+        for record in records {
+            if record
+                .read_string(0)
+                .is_some_and(|name| name.eq_ignore_ascii_case(old))
+            {
+                let values = [Value::Text(new.to_string()), record.read_value(1)];
+                delete_table_row(db, sequence.rootpage, record.rowid)?;
+                let payload = encode_record(&values, db.encoding);
+                insert_table_row(db, sequence.rootpage, record.rowid, &payload)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Rename a column everywhere the table, its indexes, foreign keys, views
+/// and triggers naming it refer to it.
+fn rename_column(
+    db: &mut Database,
+    rows: &[(i64, SchemaEntry)],
+    table: &SchemaEntry,
+    old: &str,
+    new: &str,
+) -> DbResult<()> {
+    let columns = parse_columns(&table.sql)?;
+    let Some(column) = columns.iter().find(|c| c.name.eq_ignore_ascii_case(old)) else {
+        return Err(no_such_column(old));
+    };
+    if columns
+        .iter()
+        .any(|c| c.name.eq_ignore_ascii_case(new) && !c.name.eq_ignore_ascii_case(old))
+    {
+        return Err(DbError::InvalidStatement(format!(
+            "duplicate column name: {}",
+            new
+        )));
+    }
+    for (rowid, entry) in rows {
+        if entry.sql.is_empty() {
+            continue;
+        }
+        let own = entry.tbl_name.eq_ignore_ascii_case(&table.tbl_name);
+        let mut references = match entry.entry_type.as_str() {
+            "table" if own => table_column_references(&entry.sql, &column.name)?,
+            "index" if own => index_column_references(&entry.sql, &column.name)?,
+            "table" => Vec::new(),
+            "view" | "trigger" => view_column_references(rows, entry, table, &column.name)?,
+            _ => continue,
+        };
+        if entry.entry_type == "table" {
+            for foreign_key in foreign_keys(&entry.sql)? {
+                if token_name(&foreign_key.table.token)
+                    .is_some_and(|name| name.eq_ignore_ascii_case(&table.tbl_name))
+                {
+                    references.extend(foreign_key.columns.into_iter().filter(|c| {
+                        token_name(&c.token).is_some_and(|name| name.eq_ignore_ascii_case(old))
+                    }));
+                }
+            }
+            references.sort_by_key(|reference| reference.pos);
+            references.dedup_by_key(|reference| reference.pos);
+        }
+        if references.is_empty() && !own {
+            continue;
+        }
+        let mut sql = entry.sql.clone();
+        // Replace from the end, so that earlier offsets stay put
+        for reference in references.iter().rev() {
+            let quoted = !matches!(reference.token, Token::Word(_));
+            let text = match quoted || needs_quotes(new) {
+                true => quote_identifier(new),
+                false => new.to_string(),
+            };
+            sql.replace_range(reference.pos..reference.end, &text);
+        }
+        let entry = SchemaEntry {
+            sql,
+            ..entry.clone()
+        };
+        replace_schema_entry(db, *rowid, &entry)?;
+    }
+    Ok(())
+}
+
+/// Add a column to the end of a table. Existing rows are left alone: their
+/// records lack the column, which reads as its default.
+fn add_column(
+    db: &mut Database,
+    rowid: i64,
+    table: &SchemaEntry,
+    name: &str,
+    definition: &str,
+) -> DbResult<()> {
+    if parse_columns(&table.sql)?
+        .iter()
+        .any(|c| c.name.eq_ignore_ascii_case(name))
+    {
+        return Err(DbError::InvalidStatement(format!(
+            "duplicate column name: {}",
+            name
+        )));
+    }
+    let sql = add_column_definition(&table.sql, definition);
+    let columns = parse_columns(&sql)?;
+    let Some(column) = columns.iter().find(|c| c.name.eq_ignore_ascii_case(name)) else {
+        return Err(DbError::InvalidStatement(format!(
+            "malformed column definition: {}",
+            definition
+        )));
+    };
+    if column.primary_key.is_some() {
+        return Err(DbError::InvalidStatement(
+            "Cannot add a PRIMARY KEY column".to_string(),
+        ));
+    }
+    if unique_constraints(&sql)?.len() > unique_constraints(&table.sql)?.len() {
+        return Err(DbError::InvalidStatement(
+            "Cannot add a UNIQUE column".to_string(),
+        ));
+    }
+
+    // The default stands in for the column in every existing row, so it
+    // must be a value fixed now
+    if !is_empty_btree(db, table.rootpage)? {
+        let default = column.default.as_ref();
+        if column.not_null && matches!(default, None | Some(Expr::Literal(Value::Null))) {
+            return Err(DbError::InvalidStatement(
+                "Cannot add a NOT NULL column with default value NULL".to_string(),
+            ));
+        }
+        if default.is_some_and(|expr| !is_constant(expr)) {
+            return Err(DbError::InvalidStatement(
+                "Cannot add a column with non-constant default".to_string(),
+            ));
+        }
+    }
+
+    replace_schema_entry(
+        db,
+        rowid,
+        &SchemaEntry {
+            sql,
+            ..table.clone()
+        },
+    )?;
+
+    let mut header = db.read_page(1)?;
+    if read_u32(&header, SCHEMA_FORMAT_OFFSET)? < ADD_COLUMN_SCHEMA_FORMAT {
+        write_u32(&mut header, SCHEMA_FORMAT_OFFSET, ADD_COLUMN_SCHEMA_FORMAT)?;
+        db.write_page(1, header)?;
+    }
+    Ok(())
+}
+
+/// Remove a column from a table, rewriting every row without it.
+fn drop_column(
+    db: &mut Database,
+    rows: &[(i64, SchemaEntry)],
+    rowid: i64,
+    table: &SchemaEntry,
+    name: &str,
+) -> DbResult<()> {
+    let columns = parse_columns(&table.sql)?;
+    let Some(position) = columns
+        .iter()
+        .position(|c| c.name.eq_ignore_ascii_case(name))
+    else {
+        return Err(no_such_column(name));
+    };
+    let column = &columns[position];
+    if column.primary_key.is_some() {
+        return Err(DbError::InvalidStatement(format!(
+            "cannot drop PRIMARY KEY column: \"{}\"",
+            column.name
+        )));
+    }
+    if unique_constraints(&table.sql)?.iter().any(|constraint| {
+        constraint
+            .columns
+            .iter()
+            .any(|c| c.name.eq_ignore_ascii_case(&column.name))
+    }) {
+        return Err(DbError::InvalidStatement(format!(
+            "cannot drop UNIQUE column: \"{}\"",
+            column.name
+        )));
+    }
+    let Some(sql) = drop_column_definition(&table.sql, &column.name).filter(|_| columns.len() > 1)
+    else {
+        return Err(DbError::InvalidStatement(format!(
+            "cannot drop column \"{}\": no other columns exist",
+            column.name
+        )));
+    };
+
+    // Nothing left may refer to the column
+    for (_, entry) in rows {
+        if !entry.tbl_name.eq_ignore_ascii_case(&table.tbl_name) || entry.sql.is_empty() {
+            continue;
+        }
+        let (kind, references) = match entry.entry_type.as_str() {
+            "table" => ("table", table_column_references(&sql, &column.name)?),
+            "index" => ("index", index_column_references(&entry.sql, &column.name)?),
+            _ => continue,
+        };
+        if !references.is_empty() {
+            return Err(DbError::InvalidStatement(format!(
+                "error in {} {} after drop column: no such column: {}",
+                kind, entry.name, column.name
+            )));
+        }
+    }
+
+    let writer = TableWriter::open(db, &table.tbl_name)?;
+    let table_rows: Vec<_> = filter_rows(db, &writer.table, None)?
+        .iter()
+        .map(|record| writer.row_from_record(record))
+        .collect();
+    for row in &table_rows {
+        writer.delete(db, row)?;
+    }
+
+    replace_schema_entry(
+        db,
+        rowid,
+        &SchemaEntry {
+            sql,
+            ..table.clone()
+        },
+    )?;
+
+    let writer = TableWriter::open(db, &table.tbl_name)?;
+    for mut row in table_rows {
+        row.values.remove(position);
+        writer.insert(
+            db,
+            Value::Integer(row.rowid),
+            row.values,
+            ConflictAction::Abort,
+        )?;
+    }
+    Ok(())
+}
+
+fn no_such_column(name: &str) -> DbError {
+    DbError::InvalidStatement(format!("no such column: \"{}\"", name))
+}
+
+/// Check if a B-tree holds no rows: its root is a leaf without cells.
+fn is_empty_btree(db: &mut Database, root: u32) -> DbResult<bool> {
+    let page = Page::new(db.read_page(root)?, root)?;
+    Ok(page.is_leaf() && page.cell_count() == 0)
+}
+
+/// Check if an expression is a constant SQLite can store as a default for
+/// existing rows: a literal, possibly signed or cast.
+fn is_constant(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(_) => true,
+        Expr::Unary {
+            op: UnaryOp::Negate | UnaryOp::Plus,
+            expr,
+        }
+        | Expr::Cast { expr, .. } => is_constant(expr),
+        _ => false,
+    }
+}
+
+/// The identifiers of a CREATE TABLE statement that name one of its
+/// columns: the names in column definitions, and identifiers inside the
+/// parentheses of CHECK, DEFAULT, GENERATED and table constraints, except
+/// after COLLATE or REFERENCES.
+fn table_column_references(sql: &str, column: &str) -> DbResult<Vec<Spanned>> {
+    let mut references = Vec::new();
+    let mut depth = 0;
+    let mut definition_start = false;
+    let mut in_constraint = false;
+    let mut after_references = false;
+    let mut previous: Option<&Token> = None;
+    let tokens = tokenize(sql)?;
+    for spanned in &tokens {
+        let mut starts_definition = false;
+        match &spanned.token {
+            Token::Symbol(Symbol::LeftParen) => {
+                depth += 1;
+                starts_definition = depth == 1;
+            }
+            Token::Symbol(Symbol::RightParen) => depth -= 1,
+            Token::Symbol(Symbol::Comma) if depth == 1 => {
+                starts_definition = true;
+                in_constraint = false;
+                after_references = false;
+            }
+            token @ (Token::Word(name) | Token::QuotedIdentifier(name) | Token::String(name))
+                if depth >= 1 =>
+            {
+                let is_word = matches!(token, Token::Word(_));
+                if definition_start {
+                    in_constraint = is_word && is_table_constraint_word(name);
+                }
+                after_references |= is_word && name.eq_ignore_ascii_case("REFERENCES");
+                let names_column = match definition_start {
+                    true => !in_constraint,
+                    false => {
+                        depth >= 2
+                            && !after_references
+                            && !matches!(token, Token::String(_))
+                            && !is_keyword_token(previous, "COLLATE")
+                    }
+                };
+                if names_column && name.eq_ignore_ascii_case(column) {
+                    references.push(spanned.clone());
+                }
+            }
+            _ => {}
+        }
+        definition_start = starts_definition;
+        previous = Some(&spanned.token);
+    }
+    Ok(references)
+}
+
+/// The identifiers of a CREATE INDEX statement that name a column of its
+/// table, in its column list and WHERE clause.
+fn index_column_references(sql: &str, column: &str) -> DbResult<Vec<Spanned>> {
+    let tokens = tokenize(sql)?;
+    let table = index_table_token(sql)?;
+    let mut references = Vec::new();
+    let mut previous: Option<&Token> = None;
+    for spanned in tokens.iter().filter(|t| t.pos >= table.end) {
+        if let Token::Word(name) | Token::QuotedIdentifier(name) = &spanned.token
+            && name.eq_ignore_ascii_case(column)
+            && !is_keyword_token(previous, "COLLATE")
+        {
+            references.push(spanned.clone());
+        }
+        previous = Some(&spanned.token);
+    }
+    Ok(references)
+}
+
+/// A REFERENCES clause of a CREATE TABLE statement.
+struct ForeignKey {
+    /// The parent table's name
+    table: Spanned,
+    /// The parent's columns, if listed
+    columns: Vec<Spanned>,
+}
+
+/// The REFERENCES clauses of a CREATE TABLE statement, in both column and
+/// table constraints.
+fn foreign_keys(sql: &str) -> DbResult<Vec<ForeignKey>> {
+    let tokens = tokenize(sql)?;
+    let mut foreign_keys = Vec::new();
+    for (idx, spanned) in tokens.iter().enumerate() {
+        if !is_keyword_token(Some(&spanned.token), "REFERENCES") {
+            continue;
+        }
+        let Some(table) = tokens
+            .get(idx + 1)
+            .filter(|t| token_name(&t.token).is_some())
+        else {
+            return Err(malformed(sql));
+        };
+        let mut columns = Vec::new();
+        if let Some(Token::Symbol(Symbol::LeftParen)) = tokens.get(idx + 2).map(|t| &t.token) {
+            for spanned in &tokens[idx + 3..] {
+                match &spanned.token {
+                    Token::Symbol(Symbol::Comma) => {}
+                    token if token_name(token).is_some() => columns.push(spanned.clone()),
+                    _ => break,
+                }
+            }
+        }
+        foreign_keys.push(ForeignKey {
+            table: table.clone(),
+            columns,
+        });
+    }
+    Ok(foreign_keys)
+}
+
+/// Rewrite the parent table of the foreign keys in a CREATE TABLE statement
+/// that name a renamed table.
+fn rename_parent_table(sql: &str, old: &str, quoted: &str) -> DbResult<String> {
+    let mut sql = sql.to_string();
+    // Replace from the end, so that earlier offsets stay put
+    for foreign_key in foreign_keys(&sql)?.iter().rev() {
+        if token_name(&foreign_key.table.token).is_some_and(|name| name.eq_ignore_ascii_case(old)) {
+            sql.replace_range(foreign_key.table.pos..foreign_key.table.end, quoted);
+        }
+    }
+    Ok(sql)
+}
+
+/// Words that end a FROM list.
+const FROM_LIST_ENDS: &[&str] = &[
+    "WHERE",
+    "GROUP",
+    "HAVING",
+    "WINDOW",
+    "ORDER",
+    "LIMIT",
+    "UNION",
+    "EXCEPT",
+    "INTERSECT",
+    "SET",
+    "VALUES",
+    "RETURNING",
+    "BEGIN",
+    "END",
+];
+
+/// The tokens of a view or trigger's SQL, with where they name tables.
+///
+/// Names are told apart by what comes before them rather than by resolving
+/// the statements, which is enough to follow a rename through FROM lists,
+/// qualified columns and the statements of a trigger.
+struct DependentSql {
+    tokens: Vec<Spanned>,
+    /// Whether each token names a table: it follows FROM, JOIN, INTO,
+    /// UPDATE, a comma in a FROM list or the ON of a trigger, or the schema
+    /// qualifying such a name.
+    table_names: Vec<bool>,
+    /// The statement each token belongs to. A view is one statement; a
+    /// trigger's header and each statement of its body are apart.
+    statements: Vec<usize>,
+    /// The first token after the view or trigger's own name, and a view's
+    /// column list.
+    body_start: usize,
+}
+
+impl DependentSql {
+    fn read(sql: &str) -> DbResult<Self> {
+        let tokens = tokenize(sql)?;
+        let is_trigger = tokens
+            .iter()
+            .take(3)
+            .any(|t| is_keyword_token(Some(&t.token), "TRIGGER"));
+        let kind = if is_trigger { "TRIGGER" } else { "VIEW" };
+        let mut position = tokens
+            .iter()
+            .position(|t| is_keyword_token(Some(&t.token), kind))
+            .map_or(0, |idx| idx + 1);
+        if is_keyword_token(tokens.get(position).map(|t| &t.token), "IF") {
+            position += 3;
+        }
+        let name = name_token(sql, &tokens, position)?;
+        let mut body_start = tokens
+            .iter()
+            .position(|t| t.pos == name.start)
+            .map_or(tokens.len(), |idx| idx + 1);
+        if !is_trigger {
+            body_start = tokens
+                .iter()
+                .skip(body_start)
+                .position(|t| is_keyword_token(Some(&t.token), "AS"))
+                .map_or(tokens.len(), |idx| body_start + idx + 1);
+        }
+        // A trigger's header names its table after the first ON
+        let trigger_on = tokens
+            .iter()
+            .position(|t| is_trigger && is_keyword_token(Some(&t.token), "ON"));
+
+        let mut table_names = vec![false; tokens.len()];
+        let mut statements = vec![0; tokens.len()];
+        let mut statement = 0;
+        let mut depth = 0;
+        // The depths of the FROM lists being read, innermost last
+        let mut from_lists: Vec<usize> = Vec::new();
+        for idx in body_start..tokens.len() {
+            let previous = &tokens[idx - 1].token;
+            let token = &tokens[idx].token;
+            table_names[idx] = token_name(token).is_some()
+                && match previous {
+                    Token::Word(word) => {
+                        ["FROM", "JOIN", "INTO", "UPDATE"]
+                            .iter()
+                            .any(|k| k.eq_ignore_ascii_case(word))
+                            || trigger_on == Some(idx - 1)
+                    }
+                    Token::Symbol(Symbol::Comma) => from_lists.last() == Some(&depth),
+                    Token::Symbol(Symbol::Dot) => idx >= 2 && table_names[idx - 2],
+                    _ => false,
+                };
+
+            match token {
+                Token::Symbol(Symbol::LeftParen) => depth += 1,
+                Token::Symbol(Symbol::RightParen) => {
+                    depth = depth.saturating_sub(1);
+                    from_lists.retain(|&d| d <= depth);
+                }
+                Token::Symbol(Symbol::Semicolon) if depth == 0 => {
+                    statement += 1;
+                    from_lists.clear();
+                }
+                Token::Word(word)
+                    if is_one_of(word, &["FROM", "JOIN"]) && from_lists.last() != Some(&depth) =>
+                {
+                    from_lists.push(depth)
+                }
+                Token::Word(word) if is_one_of(word, FROM_LIST_ENDS) => {
+                    from_lists.retain(|&d| d < depth);
+                    if depth == 0 && word.eq_ignore_ascii_case("BEGIN") {
+                        statement += 1;
+                    }
+                }
+                _ => {}
+            }
+            statements[idx] = statement;
+        }
+
+        Ok(Self {
+            tokens,
+            table_names,
+            statements,
+            body_start,
+        })
+    }
+
+    fn names(&self, idx: usize, name: &str) -> bool {
+        self.tokens
+            .get(idx)
+            .and_then(|t| token_name(&t.token))
+            .is_some_and(|token| token.eq_ignore_ascii_case(name))
+    }
+
+    fn is_symbol(&self, idx: usize, symbol: Symbol) -> bool {
+        self.tokens
+            .get(idx)
+            .is_some_and(|t| t.token == Token::Symbol(symbol))
+    }
+
+    /// The tokens naming a table, whether where a table goes or as the
+    /// qualifier of one of its columns.
+    fn table_references(&self, table: &str) -> Vec<Spanned> {
+        (self.body_start..self.tokens.len())
+            .filter(|&idx| self.names(idx, table))
+            .filter(|&idx| {
+                let qualifies = self.is_symbol(idx + 1, Symbol::Dot);
+                match self.table_names[idx] {
+                    // Unless it is the schema of the name that follows
+                    true => !qualifies,
+                    false => qualifies && !self.is_symbol(idx - 1, Symbol::Dot),
+                }
+            })
+            .map(|idx| self.tokens[idx].clone())
+            .collect()
+    }
+
+    /// The names a table goes by in a statement: its own and those it is
+    /// given with `[AS] alias`, if the statement reads or writes it.
+    fn table_aliases(&self, table: &str, statement: usize) -> Option<Vec<String>> {
+        let mut aliases = Vec::new();
+        for idx in self.body_start..self.tokens.len() {
+            if self.statements[idx] != statement
+                || !self.table_names[idx]
+                || !self.names(idx, table)
+                || self.is_symbol(idx + 1, Symbol::Dot)
+            {
+                continue;
+            }
+            aliases.push(table.to_string());
+            let alias = match self.tokens.get(idx + 1).map(|t| &t.token) {
+                Some(Token::Word(word)) if word.eq_ignore_ascii_case("AS") => {
+                    self.tokens.get(idx + 2)
+                }
+                _ => self.tokens.get(idx + 1),
+            };
+            match alias.map(|t| &t.token) {
+                Some(Token::Word(name)) if !is_one_of(name, KEYWORDS) => aliases.push(name.clone()),
+                Some(Token::QuotedIdentifier(name)) => aliases.push(name.clone()),
+                _ => {}
+            }
+        }
+        (!aliases.is_empty()).then_some(aliases)
+    }
+}
+
+/// The identifiers of a view or trigger that name a column of a table:
+/// the column qualified by the table, one of its aliases or, in the table's
+/// own triggers, NEW or OLD, and the bare column in statements that read or
+/// write the table.
+///
+/// A bare name is only taken as the table's if no other table the
+/// statement reads has a column of that name too; otherwise the rename is
+/// refused rather than guessing which one it means.
+fn view_column_references(
+    rows: &[(i64, SchemaEntry)],
+    entry: &SchemaEntry,
+    table: &SchemaEntry,
+    column: &str,
+) -> DbResult<Vec<Spanned>> {
+    let dependent = DependentSql::read(&entry.sql)?;
+    let on_table =
+        entry.entry_type == "trigger" && entry.tbl_name.eq_ignore_ascii_case(&table.tbl_name);
+    let mut references = Vec::new();
+    for idx in dependent.body_start..dependent.tokens.len() {
+        if !dependent.names(idx, column)
+            || dependent.table_names[idx]
+            || matches!(dependent.tokens[idx].token, Token::String(_))
+        {
+            continue;
+        }
+        let statement = dependent.statements[idx];
+        let aliases = dependent.table_aliases(&table.tbl_name, statement);
+        if dependent.is_symbol(idx - 1, Symbol::Dot) {
+            let qualifier = idx - 2;
+            let names_table = aliases
+                .iter()
+                .flatten()
+                .any(|alias| dependent.names(qualifier, alias))
+                || (on_table
+                    && (dependent.names(qualifier, "NEW") || dependent.names(qualifier, "OLD")));
+            if names_table {
+                references.push(dependent.tokens[idx].clone());
+            }
+            continue;
+        }
+        let previous = &dependent.tokens[idx - 1].token;
+        if aliases.is_none()
+            || dependent.is_symbol(idx + 1, Symbol::Dot)
+            || dependent.is_symbol(idx + 1, Symbol::LeftParen)
+            || is_keyword_token(Some(previous), "AS")
+            || is_keyword_token(Some(previous), "COLLATE")
+        {
+            continue;
+        }
+
+        let other = (dependent.body_start..dependent.tokens.len())
+            .filter(|&other| {
+                dependent.statements[other] == statement
+                    && dependent.table_names[other]
+                    && !dependent.is_symbol(other + 1, Symbol::Dot)
+                    && !dependent.names(other, &table.tbl_name)
+            })
+            .filter_map(|other| token_name(&dependent.tokens[other].token))
+            .find(|name| {
+                rows.iter().any(|(_, e)| {
+                    e.entry_type == "table"
+                        && e.name.eq_ignore_ascii_case(name)
+                        && parse_columns(&e.sql).is_ok_and(|columns| {
+                            columns.iter().any(|c| c.name.eq_ignore_ascii_case(column))
+                        })
+                })
+            });
+        if let Some(other) = other {
+            return Err(DbError::InvalidStatement(format!(
+                "cannot rename column {}: {} {} may mean the column of that name in table {}",
+                column, entry.entry_type, entry.name, other
+            )));
+        }
+        references.push(dependent.tokens[idx].clone());
+    }
+    Ok(references)
+}
+
+/// The name an identifier or string token gives, which SQLite accepts
+/// either as in a schema.
+fn token_name(token: &Token) -> Option<&str> {
+    match token {
+        Token::Word(name) | Token::QuotedIdentifier(name) | Token::String(name) => Some(name),
+        _ => None,
+    }
+}
+
+/// The byte range of the table name in a CREATE TABLE statement.
+fn table_name_token(sql: &str) -> DbResult<Range<usize>> {
+    let tokens = tokenize(sql)?;
+    let mut position = tokens
+        .iter()
+        .position(|t| is_keyword_token(Some(&t.token), "TABLE"))
+        .map_or(0, |idx| idx + 1);
+    if is_keyword_token(tokens.get(position).map(|t| &t.token), "IF") {
+        position += 3;
+    }
+    name_token(sql, &tokens, position)
+}
+
+/// The byte range of the table name after ON in a CREATE INDEX statement.
+fn index_table_token(sql: &str) -> DbResult<Range<usize>> {
+    let tokens = tokenize(sql)?;
+    // Skip the index name, which could itself be "on"
+    let start = tokens
+        .iter()
+        .position(|t| is_keyword_token(Some(&t.token), "INDEX"))
+        .map_or(0, |idx| idx + 2);
+    let on = tokens
+        .iter()
+        .skip(start)
+        .position(|t| is_keyword_token(Some(&t.token), "ON"))
+        .map(|idx| start + idx + 1);
+    match on {
+        Some(position) => name_token(sql, &tokens, position),
+        None => Err(malformed(sql)),
+    }
+}
+
+/// The byte range of a possibly schema-qualified name starting at a token.
+fn name_token(sql: &str, tokens: &[Spanned], position: usize) -> DbResult<Range<usize>> {
+    let position = match tokens.get(position + 1) {
+        Some(Spanned {
+            token: Token::Symbol(Symbol::Dot),
+            ..
+        }) => position + 2,
+        _ => position,
+    };
+    match tokens.get(position) {
+        Some(Spanned {
+            token: Token::Word(_) | Token::QuotedIdentifier(_) | Token::String(_),
+            pos,
+            end,
+        }) => Ok(*pos..*end),
+        _ => Err(malformed(sql)),
+    }
+}
+
+fn malformed(sql: &str) -> DbError {
+    DbError::InvalidStatement(format!("malformed schema SQL: {}", sql))
+}
+
+/// Check if a word is one of the given keywords, ignoring case.
+fn is_one_of(word: &str, keywords: &[&str]) -> bool {
+    keywords.iter().any(|k| k.eq_ignore_ascii_case(word))
+}
+
+fn is_keyword_token(token: Option<&Token>, keyword: &str) -> bool {
+    matches!(token, Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+}
+
+fn replace_token(sql: &str, range: Range<usize>, text: &str) -> String {
+    format!("{}{}{}", &sql[..range.start], text, &sql[range.end..])
+}
+
+/// Check if a name must be quoted to be read back as an identifier.
+fn needs_quotes(name: &str) -> bool {
+    let mut chars = name.chars();
+    let plain = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    !plain || KEYWORDS.iter().any(|k| k.eq_ignore_ascii_case(name))
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Write a name so that it reads back as an identifier, quoting it only if
+/// it must be.
+pub fn identifier_sql(name: &str) -> String {
+    if needs_quotes(name) {
+        quote_identifier(name)
+    } else {
+        name.to_string()
+    }
+}
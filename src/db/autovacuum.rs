@@ -0,0 +1,177 @@
+//! Auto-vacuum: giving free pages back to the file system by moving the
+//! pages after them into them and shrinking the file.
+//!
+//! Only root pages can't move, since sqlite_schema records them, so they
+//! are kept together at the start of the file, after page 1. The header
+//! records the last of them.
+
+use super::btree_write::free_btree;
+use super::constants::{
+    FREELIST_COUNT_OFFSET, INCREMENTAL_VACUUM_OFFSET, LARGEST_ROOT_PAGE_OFFSET,
+};
+use super::database::Database;
+use super::error::{DbError, DbResult, read_u32, write_u32};
+use super::freelist::{remove_free_page, take_free_page};
+use super::ptrmap::{
+    PtrmapEntry, PtrmapType, is_ptrmap_page, read_entry, relocate_page, write_entries,
+};
+
+/// When a database gives back the pages it frees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AutoVacuum {
+    /// Never: free pages stay on the freelist until VACUUM.
+    #[default]
+    None,
+    /// At every commit.
+    Full,
+    /// When PRAGMA incremental_vacuum asks for it.
+    Incremental,
+}
+
+impl AutoVacuum {
+    /// Read the mode from the database header.
+    pub fn from_header(header: &[u8]) -> DbResult<Self> {
+        if read_u32(header, LARGEST_ROOT_PAGE_OFFSET)? == 0 {
+            Ok(Self::None)
+        } else if read_u32(header, INCREMENTAL_VACUUM_OFFSET)? != 0 {
+            Ok(Self::Incremental)
+        } else {
+            Ok(Self::Full)
+        }
+    }
+
+    /// Parse a `PRAGMA auto_vacuum` value, a name or number. Like SQLite,
+    /// values it doesn't know mean NONE.
+    pub fn parse(value: &str) -> Self {
+        match value.to_ascii_lowercase().as_str() {
+            "full" | "1" => Self::Full,
+            "incremental" | "2" => Self::Incremental,
+            _ => Self::None,
+        }
+    }
+
+    /// The number `PRAGMA auto_vacuum` reports for the mode.
+    pub fn number(self) -> i64 {
+        self as i64
+    }
+}
+
+/// Switch a database with auto-vacuum between FULL and INCREMENTAL.
+pub fn set_incremental(db: &mut Database, incremental: bool) -> DbResult<()> {
+    let mut header = db.read_page(1)?;
+    write_u32(&mut header, INCREMENTAL_VACUUM_OFFSET, incremental as u32)?;
+    db.write_page(1, header)
+}
+
+/// Allocate a page for the root of a new B-tree. With auto-vacuum the root
+/// takes the page after the last root, and whatever used that page moves.
+pub fn allocate_root(db: &mut Database) -> DbResult<u32> {
+    if db.auto_vacuum()? == AutoVacuum::None {
+        return db.allocate_page();
+    }
+    let mut root = read_u32(&db.read_page(1)?, LARGEST_ROOT_PAGE_OFFSET)? + 1;
+    while root == db.pending_page() || is_ptrmap_page(db, root) {
+        root += 1;
+    }
+
+    if root > db.page_count() {
+        while db.page_count() < root {
+            let page_num = db.extend()?;
+            if page_num < root {
+                db.write_page(page_num, vec![0; db.page_size])?;
+                db.free_page(page_num)?;
+            }
+        }
+    } else {
+        match read_entry(db, root)?.kind {
+            PtrmapType::FreePage => remove_free_page(db, root)?,
+            PtrmapType::RootPage => {
+                return Err(DbError::CorruptPage {
+                    page: root,
+                    reason: "root page follows the largest root page".to_string(),
+                });
+            }
+            _ => {
+                let page_num = db.allocate_page()?;
+                relocate_page(db, root, page_num)?;
+            }
+        }
+    }
+    db.write_page(root, vec![0; db.page_size])?;
+    write_entries(db, &[(root, PtrmapEntry::new(PtrmapType::RootPage, 0))])?;
+    set_largest_root(db, root)?;
+    Ok(root)
+}
+
+/// Free every page of a B-tree. With auto-vacuum, the B-tree with the last
+/// root then moves its root into the freed one, keeping the roots together;
+/// returns the page it moved from, so that sqlite_schema can follow.
+pub fn drop_btree(db: &mut Database, root: u32) -> DbResult<Option<u32>> {
+    free_btree(db, root)?;
+    if db.auto_vacuum()? == AutoVacuum::None {
+        return Ok(None);
+    }
+    let largest = read_u32(&db.read_page(1)?, LARGEST_ROOT_PAGE_OFFSET)?;
+    if root > largest {
+        return Ok(None);
+    }
+    let moved = if root < largest {
+        remove_free_page(db, root)?;
+        relocate_page(db, largest, root)?;
+        db.free_page(largest)?;
+        Some(largest)
+    } else {
+        None
+    };
+    let mut largest = largest - 1;
+    while largest == db.pending_page() || is_ptrmap_page(db, largest) {
+        largest -= 1;
+    }
+    set_largest_root(db, largest)?;
+    Ok(moved)
+}
+
+/// Record the last root page in the header.
+fn set_largest_root(db: &mut Database, root: u32) -> DbResult<()> {
+    let mut header = db.read_page(1)?;
+    write_u32(&mut header, LARGEST_ROOT_PAGE_OFFSET, root)?;
+    db.write_page(1, header)
+}
+
+/// Shrink a database with auto-vacuum by up to `limit` pages, or until no
+/// free pages are left if there is no limit. Each step moves the last page
+/// into a free page, unless it is free itself, and cuts it off.
+pub fn incremental_vacuum(db: &mut Database, limit: Option<u32>) -> DbResult<()> {
+    if db.auto_vacuum()? == AutoVacuum::None {
+        return Ok(());
+    }
+    let mut removed = 0;
+    while limit.is_none_or(|limit| removed < limit)
+        && read_u32(&db.read_page(1)?, FREELIST_COUNT_OFFSET)? > 0
+    {
+        let last = db.page_count();
+        match read_entry(db, last)?.kind {
+            PtrmapType::FreePage => remove_free_page(db, last)?,
+            PtrmapType::RootPage => {
+                return Err(DbError::CorruptPage {
+                    page: last,
+                    reason: "root page follows the largest root page".to_string(),
+                });
+            }
+            _ => {
+                let Some(free) = take_free_page(db)? else {
+                    break;
+                };
+                relocate_page(db, last, free)?;
+            }
+        }
+        // Pointer-map pages with no pages after them go too
+        let mut page_count = last - 1;
+        while page_count == db.pending_page() || is_ptrmap_page(db, page_count) {
+            page_count -= 1;
+        }
+        db.truncate(page_count);
+        removed += 1;
+    }
+    Ok(())
+}
@@ -0,0 +1,389 @@
+//! B-tree traversal and search for table and index B-trees.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use crate::db::collation::Collation;
+use crate::db::database::Database;
+use crate::db::error::{DbError, DbResult, read_u32};
+use crate::db::page::{Page, PageType, Record, index_cell_rowid};
+use crate::db::value::Value;
+
+/// Search an index B-tree for matching values and return rowids in index order.
+///
+/// Keys are compared with the index column's collation; `descending` is set
+/// for `DESC` index columns, whose keys are stored in reverse order.
+pub fn search_index_btree(
+    db: &mut Database,
+    page_num: u32,
+    search_value: &Value,
+    collation: &Collation,
+    descending: bool,
+) -> DbResult<Vec<i64>> {
+    let mut payloads = Vec::new();
+    search_index_payloads(
+        db,
+        page_num,
+        search_value,
+        collation,
+        descending,
+        &mut payloads,
+        &mut HashSet::new(),
+    )?;
+    payloads
+        .iter()
+        .map(|payload| index_cell_rowid(payload))
+        .collect()
+}
+
+/// Search a WITHOUT ROWID table for rows whose first PRIMARY KEY column
+/// matches, returning the rows in key order.
+pub fn search_without_rowid_table(
+    db: &mut Database,
+    page_num: u32,
+    search_value: &Value,
+    collation: &Collation,
+    descending: bool,
+) -> DbResult<Vec<Record>> {
+    let mut payloads = Vec::new();
+    search_index_payloads(
+        db,
+        page_num,
+        search_value,
+        collation,
+        descending,
+        &mut payloads,
+        &mut HashSet::new(),
+    )?;
+    payloads
+        .iter()
+        .map(|payload| Record::parse(payload, 0, db.encoding))
+        .collect()
+}
+
+/// Collect, in key order, the payloads of index B-tree entries whose first
+/// column equals `search_value`.
+fn search_index_payloads(
+    db: &mut Database,
+    page_num: u32,
+    search_value: &Value,
+    collation: &Collation,
+    descending: bool,
+    payloads: &mut Vec<Vec<u8>>,
+    visited: &mut HashSet<u32>,
+) -> DbResult<()> {
+    enter_page(visited, page_num)?;
+    let page_data = db.read_page(page_num)?;
+    let page = Page::new(page_data, page_num)?;
+
+    // Keys are ordered by SQLite's sort order, with text compared by the index collation
+    let encoding = db.encoding;
+    let compare_key = |payload: &[u8]| -> DbResult<Ordering> {
+        let key = Record::parse(payload, 0, encoding)?.read_value(0);
+        let ordering = search_value.compare_collated(&key, collation, encoding);
+        Ok(if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        })
+    };
+
+    if page.is_leaf() {
+        // This is a leaf page, check each cell
+        for offset in page.cell_offsets()? {
+            let (_, payload) = db.read_payload(&page, offset)?;
+            if compare_key(&payload)?.is_eq() {
+                payloads.push(payload);
+            }
+        }
+        return Ok(());
+    }
+
+    // This is an interior page. Interior index cells hold real index entries,
+    // and the same value can appear in several children, so every child whose
+    // key range may contain the value has to be searched, in order.
+    for offset in page.cell_offsets()? {
+        let left_child = page.left_child(offset)?;
+        let (_, payload) = db.read_payload(&page, offset)?;
+        let ordering = compare_key(&payload)?;
+
+        // The left child contains all values <= key
+        if ordering.is_le() {
+            search_index_payloads(
+                db,
+                left_child,
+                search_value,
+                collation,
+                descending,
+                payloads,
+                visited,
+            )?;
+        }
+        // The cell itself is an index entry
+        if ordering.is_eq() {
+            payloads.push(payload);
+        }
+        // Everything to the right is greater than the value
+        if ordering.is_lt() {
+            return Ok(());
+        }
+    }
+
+    if let Some(rightmost) = page.rightmost_pointer() {
+        search_index_payloads(
+            db,
+            rightmost,
+            search_value,
+            collation,
+            descending,
+            payloads,
+            visited,
+        )?;
+    }
+    Ok(())
+}
+
+/// Traverse an index B-tree and collect every entry as a record, in key order.
+/// Used to scan WITHOUT ROWID tables, whose rows are the index entries.
+pub fn traverse_btree_index(
+    db: &mut Database,
+    page_num: u32,
+    records: &mut Vec<Record>,
+) -> DbResult<()> {
+    traverse_index_pages(db, page_num, records, &mut HashSet::new())
+}
+
+fn traverse_index_pages(
+    db: &mut Database,
+    page_num: u32,
+    records: &mut Vec<Record>,
+    visited: &mut HashSet<u32>,
+) -> DbResult<()> {
+    enter_page(visited, page_num)?;
+    let page_data = db.read_page(page_num)?;
+    let page = Page::new(page_data, page_num)?;
+
+    for offset in page.cell_offsets()? {
+        // Interior cells sit between their left child and the next cell
+        if page.is_interior() {
+            let left_child = page.left_child(offset)?;
+            traverse_index_pages(db, left_child, records, visited)?;
+        }
+        let (_, payload) = db.read_payload(&page, offset)?;
+        records.push(Record::parse(&payload, 0, db.encoding)?);
+    }
+
+    if page.is_interior()
+        && let Some(rightmost) = page.rightmost_pointer()
+    {
+        traverse_index_pages(db, rightmost, records, visited)?;
+    }
+    Ok(())
+}
+
+/// Collect the payload of every entry of a B-tree in key order, along with
+/// the rowids of a table B-tree's rows.
+pub fn btree_payloads(
+    db: &mut Database,
+    page_num: u32,
+    payloads: &mut Vec<(Option<i64>, Vec<u8>)>,
+) -> DbResult<()> {
+    collect_payloads(db, page_num, payloads, &mut HashSet::new())
+}
+
+fn collect_payloads(
+    db: &mut Database,
+    page_num: u32,
+    payloads: &mut Vec<(Option<i64>, Vec<u8>)>,
+    visited: &mut HashSet<u32>,
+) -> DbResult<()> {
+    enter_page(visited, page_num)?;
+    let page = Page::new(db.read_page(page_num)?, page_num)?;
+    for offset in page.cell_offsets()? {
+        if page.is_interior() {
+            collect_payloads(db, page.left_child(offset)?, payloads, visited)?;
+        }
+        // Interior table cells hold only a rowid
+        if page.page_type() != PageType::InteriorTable {
+            payloads.push(db.read_payload(&page, offset)?);
+        }
+    }
+    if let Some(rightmost) = page.rightmost_pointer() {
+        collect_payloads(db, rightmost, payloads, visited)?;
+    }
+    Ok(())
+}
+
+/// Collect the numbers of every page of a B-tree: its root, the pages
+/// below and the overflow pages of its cells.
+pub fn btree_pages(db: &mut Database, root: u32, pages: &mut Vec<u32>) -> DbResult<()> {
+    collect_pages(db, root, pages, &mut HashSet::new())
+}
+
+fn collect_pages(
+    db: &mut Database,
+    root: u32,
+    pages: &mut Vec<u32>,
+    visited: &mut HashSet<u32>,
+) -> DbResult<()> {
+    enter_page(visited, root)?;
+    pages.push(root);
+    let page = Page::new(db.read_page(root)?, root)?;
+    let usable_size = db.usable_size();
+    for offset in page.cell_offsets()? {
+        if page.is_interior() {
+            collect_pages(db, page.left_child(offset)?, pages, visited)?;
+        }
+        // Interior table cells hold only a rowid
+        if page.page_type() == PageType::InteriorTable {
+            continue;
+        }
+        let cell = page.cell_payload(offset, usable_size)?;
+        let Some(mut next) = cell.overflow_page else {
+            continue;
+        };
+        let overflow_pages = (cell.payload_size - cell.local.len()).div_ceil(usable_size - 4);
+        for _ in 0..overflow_pages {
+            pages.push(next);
+            next = read_u32(&db.read_page(next)?, 0)?;
+            if next == 0 {
+                break;
+            }
+        }
+    }
+    if let Some(rightmost) = page.rightmost_pointer() {
+        collect_pages(db, rightmost, pages, visited)?;
+    }
+    Ok(())
+}
+
+/// Find a record in a table B-tree by rowid.
+pub fn find_record_by_rowid(
+    db: &mut Database,
+    page_num: u32,
+    target_rowid: i64,
+) -> DbResult<Option<Record>> {
+    let mut visited = HashSet::new();
+    let mut page_num = page_num;
+    loop {
+        enter_page(&mut visited, page_num)?;
+        let page_data = db.read_page(page_num)?;
+        let page = Page::new(page_data, page_num)?;
+
+        if page.is_leaf() {
+            // Search this leaf page for the rowid
+            for offset in page.cell_offsets()? {
+                let (rowid, payload) = db.read_payload(&page, offset)?;
+                let rowid = rowid.unwrap_or_default();
+                if rowid == target_rowid {
+                    return Ok(Some(Record::parse(&payload, rowid, db.encoding)?));
+                }
+            }
+            return Ok(None);
+        }
+
+        // This is an interior page, determine which child to search
+        let mut child_to_search = None;
+
+        // Check each interior cell
+        for offset in page.cell_offsets()? {
+            let (left_child, key) = page.parse_interior_cell(offset)?;
+            if target_rowid <= key {
+                child_to_search = Some(left_child);
+                break;
+            }
+        }
+
+        // If not found in any cell, search the rightmost child
+        if child_to_search.is_none() {
+            child_to_search = page.rightmost_pointer();
+        }
+
+        // Search the appropriate child
+        match child_to_search {
+            Some(child_page) => page_num = child_page,
+            None => return Ok(None),
+        }
+    }
+}
+
+/// Traverse a table B-tree starting from the given page and collect all leaf records.
+pub fn traverse_btree_table(
+    db: &mut Database,
+    page_num: u32,
+    records: &mut Vec<Record>,
+) -> DbResult<()> {
+    traverse_table_pages(db, page_num, records, &mut HashSet::new())
+}
+
+fn traverse_table_pages(
+    db: &mut Database,
+    page_num: u32,
+    records: &mut Vec<Record>,
+    visited: &mut HashSet<u32>,
+) -> DbResult<()> {
+    enter_page(visited, page_num)?;
+    let page_data = db.read_page(page_num)?;
+    let page = Page::new(page_data, page_num)?;
+
+    if page.is_leaf() {
+        // This is a leaf page, collect all records
+        for offset in page.cell_offsets()? {
+            let (rowid, payload) = db.read_payload(&page, offset)?;
+            records.push(Record::parse(
+                &payload,
+                rowid.unwrap_or_default(),
+                db.encoding,
+            )?);
+        }
+    } else {
+        // This is an interior page, traverse child pages
+        let mut child_pages = Vec::new();
+
+        // Process each interior cell to get left child pointers
+        for offset in page.cell_offsets()? {
+            let (left_child, _key) = page.parse_interior_cell(offset)?;
+            if left_child == 0 {
+                eprintln!(
+                    "Warning: found zero page number in interior cell at page {}",
+                    page_num
+                );
+                continue;
+            }
+            child_pages.push(left_child);
+        }
+
+        // Add the rightmost child
+        if let Some(rightmost) = page.rightmost_pointer() {
+            if rightmost == 0 {
+                eprintln!(
+                    "Warning: found zero page number in rightmost pointer at page {}",
+                    page_num
+                );
+            } else {
+                child_pages.push(rightmost);
+            }
+        }
+
+        // Recursively traverse all child pages
+        for child_page in child_pages {
+            traverse_table_pages(db, child_page, records, visited)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Note that a walk of a B-tree has reached `page_num`, failing if it has
+/// been there before: a child pointer back to the page itself or to one of
+/// its ancestors would otherwise send the walk round forever.
+fn enter_page(visited: &mut HashSet<u32>, page_num: u32) -> DbResult<()> {
+    if visited.insert(page_num) {
+        Ok(())
+    } else {
+        Err(DbError::CorruptPage {
+            page: page_num,
+            reason: "page is referenced more than once in the B-tree".to_string(),
+        })
+    }
+}
@@ -0,0 +1,853 @@
+//! Inserting entries into table and index B-trees, splitting pages as they fill up.
+
+use std::cmp::Ordering;
+
+use crate::db::autovacuum::{AutoVacuum, allocate_root};
+use crate::db::collation::Collation;
+use crate::db::database::Database;
+use crate::db::encoding::TextEncoding;
+use crate::db::error::{DbError, DbResult, read_u32, write_u32};
+use crate::db::page::{Page, PageType, Record, build_page, cells_fit, local_payload_size};
+use crate::db::ptrmap::{PtrmapEntry, PtrmapType, set_child_entries, write_entries};
+use crate::db::value::Value;
+use crate::db::varint::{read_varint, write_varint};
+
+/// How one key column of an index B-tree is ordered.
+#[derive(Debug, Clone, Default)]
+pub struct KeyColumn {
+    pub collation: Collation,
+    pub descending: bool,
+}
+
+/// Compare two index keys column by column. Only the columns listed in `key`
+/// take part, so entries that agree on them compare equal.
+pub fn compare_keys(
+    a: &[Value],
+    b: &[Value],
+    key: &[KeyColumn],
+    encoding: TextEncoding,
+) -> Ordering {
+    for (i, column) in key.iter().enumerate() {
+        let a = a.get(i).unwrap_or(&Value::Null);
+        let b = b.get(i).unwrap_or(&Value::Null);
+        let ordering = a.compare_collated(b, &column.collation, encoding);
+        let ordering = if column.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Find the largest rowid in a table B-tree, or 0 if the table is empty.
+pub fn max_rowid(db: &mut Database, root: u32) -> DbResult<i64> {
+    let mut page_num = root;
+    loop {
+        let page = Page::new(db.read_page(page_num)?, page_num)?;
+        if let Some(rightmost) = page.rightmost_pointer() {
+            page_num = rightmost;
+            continue;
+        }
+        let Some(&last) = page.cell_offsets()?.last() else {
+            return Ok(0);
+        };
+        let cell = page.cell_payload(last, db.usable_size())?;
+        return Ok(cell.rowid.unwrap_or_default());
+    }
+}
+
+/// Allocate a page for the root of a new, empty B-tree of the given type.
+pub fn create_btree(db: &mut Database, page_type: PageType) -> DbResult<u32> {
+    let root = allocate_root(db)?;
+    write_cells(db, root, page_type, &[], None)?;
+    Ok(root)
+}
+
+/// Return every page of a B-tree to the freelist: its root, the pages below
+/// and the overflow pages of its cells.
+pub fn free_btree(db: &mut Database, root: u32) -> DbResult<()> {
+    let page = Page::new(db.read_page(root)?, root)?;
+    for offset in page.cell_offsets()? {
+        if page.is_interior() {
+            free_btree(db, page.left_child(offset)?)?;
+        }
+        // Interior table cells hold only a rowid
+        if page.page_type() != PageType::InteriorTable {
+            free_overflow(db, &page, offset)?;
+        }
+    }
+    if let Some(rightmost) = page.rightmost_pointer() {
+        free_btree(db, rightmost)?;
+    }
+    db.free_page(root)
+}
+
+/// Fill an empty index B-tree with entries that are already in key order.
+///
+/// The entries are packed into full leaves from left to right, and the
+/// entries between them into the levels above, without searching the tree
+/// for each one as [`insert_index_entry`] does.
+pub fn load_index(db: &mut Database, root: u32, payloads: &[Vec<u8>]) -> DbResult<()> {
+    let mut cells = Vec::with_capacity(payloads.len());
+    for payload in payloads {
+        let mut cell = Vec::new();
+        write_varint(&mut cell, payload.len() as u64);
+        append_payload(db, &mut cell, payload, false)?;
+        cells.push(cell);
+    }
+    store_cells(
+        db,
+        &mut Vec::new(),
+        root,
+        PageType::LeafIndex,
+        cells,
+        None,
+        true,
+    )
+}
+
+/// Fill an empty table B-tree with rows that are already in rowid order,
+/// packing them into full leaves like [`load_index`].
+pub fn load_table(db: &mut Database, root: u32, rows: &[(i64, Vec<u8>)]) -> DbResult<()> {
+    let mut cells = Vec::with_capacity(rows.len());
+    for (rowid, payload) in rows {
+        let mut cell = Vec::new();
+        write_varint(&mut cell, payload.len() as u64);
+        write_varint(&mut cell, *rowid as u64);
+        append_payload(db, &mut cell, payload, true)?;
+        cells.push(cell);
+    }
+    store_cells(
+        db,
+        &mut Vec::new(),
+        root,
+        PageType::LeafTable,
+        cells,
+        None,
+        true,
+    )
+}
+
+/// Descend a table B-tree to the leaf where `rowid` belongs.
+/// Returns the interior pages passed through, from the root down, and the leaf.
+fn find_table_leaf(db: &mut Database, root: u32, rowid: i64) -> DbResult<(Vec<u32>, Page)> {
+    let mut path = Vec::new();
+    let mut page_num = root;
+    loop {
+        let page = Page::new(db.read_page(page_num)?, page_num)?;
+        if page.is_leaf() {
+            return Ok((path, page));
+        }
+        // Each cell's left child holds the rowids up to and including its key
+        let mut child = page.rightmost_pointer().unwrap_or_default();
+        for offset in page.cell_offsets()? {
+            let (left_child, key) = page.parse_interior_cell(offset)?;
+            if rowid <= key {
+                child = left_child;
+                break;
+            }
+        }
+        path.push(page_num);
+        page_num = child;
+    }
+}
+
+/// Insert a row into a table B-tree.
+///
+/// Returns `false`, changing nothing, if a row with the same rowid exists.
+pub fn insert_table_row(
+    db: &mut Database,
+    root: u32,
+    rowid: i64,
+    payload: &[u8],
+) -> DbResult<bool> {
+    let usable_size = db.usable_size();
+    let (mut path, page) = find_table_leaf(db, root, rowid)?;
+
+    let mut position = page.cell_count();
+    for (i, offset) in page.cell_offsets()?.into_iter().enumerate() {
+        let existing = page
+            .cell_payload(offset, usable_size)?
+            .rowid
+            .unwrap_or_default();
+        if existing == rowid {
+            return Ok(false);
+        }
+        if existing > rowid {
+            position = i;
+            break;
+        }
+    }
+
+    let mut cell = Vec::new();
+    write_varint(&mut cell, payload.len() as u64);
+    write_varint(&mut cell, rowid as u64);
+    append_payload(db, &mut cell, payload, true)?;
+
+    let mut cells = page.cells(usable_size)?;
+    let append = position == cells.len();
+    cells.insert(position, cell);
+    let page_num = page.page_num();
+    store_cells(
+        db,
+        &mut path,
+        page_num,
+        PageType::LeafTable,
+        cells,
+        None,
+        append,
+    )?;
+    Ok(true)
+}
+
+/// Delete a row from a table B-tree, freeing its overflow pages.
+///
+/// Returns `false` if there is no row with that rowid.
+pub fn delete_table_row(db: &mut Database, root: u32, rowid: i64) -> DbResult<bool> {
+    let usable_size = db.usable_size();
+    let (mut path, page) = find_table_leaf(db, root, rowid)?;
+    for (i, offset) in page.cell_offsets()?.into_iter().enumerate() {
+        if page.cell_payload(offset, usable_size)?.rowid == Some(rowid) {
+            free_overflow(db, &page, offset)?;
+            remove_leaf_cell(db, &mut path, page, i)?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Insert a record into an index B-tree, ordered by the leading columns described by `key`.
+///
+/// Returns `false`, changing nothing, if an entry with an equal key exists.
+pub fn insert_index_entry(
+    db: &mut Database,
+    root: u32,
+    payload: &[u8],
+    key: &[KeyColumn],
+) -> DbResult<bool> {
+    let encoding = db.encoding;
+    let usable_size = db.usable_size();
+    let new_key = record_values(payload, encoding)?;
+
+    let mut path = Vec::new();
+    let mut page_num = root;
+    let (page, position) = loop {
+        let page = Page::new(db.read_page(page_num)?, page_num)?;
+        // Find the first entry greater than the new one; interior cells are entries too
+        let mut position = None;
+        for (i, offset) in page.cell_offsets()?.into_iter().enumerate() {
+            let (_, existing) = db.read_payload(&page, offset)?;
+            let existing = record_values(&existing, encoding)?;
+            match compare_keys(&new_key, &existing, key, encoding) {
+                Ordering::Equal => return Ok(false),
+                Ordering::Less => {
+                    position = Some((i, page.left_child(offset)?));
+                    break;
+                }
+                Ordering::Greater => {}
+            }
+        }
+        if page.is_leaf() {
+            let position = position.map_or(page.cell_count(), |(i, _)| i);
+            break (page, position);
+        }
+        path.push(page_num);
+        page_num = match position {
+            Some((_, left_child)) => left_child,
+            None => page.rightmost_pointer().unwrap_or_default(),
+        };
+    };
+
+    let mut cell = Vec::new();
+    write_varint(&mut cell, payload.len() as u64);
+    append_payload(db, &mut cell, payload, false)?;
+
+    let mut cells = page.cells(usable_size)?;
+    let append = position == cells.len();
+    cells.insert(position, cell);
+    store_cells(
+        db,
+        &mut path,
+        page_num,
+        PageType::LeafIndex,
+        cells,
+        None,
+        append,
+    )?;
+    Ok(true)
+}
+
+/// Find the first index B-tree entry whose leading columns equal `values`,
+/// compared as `key` describes, and return its payload.
+pub fn find_index_entry(
+    db: &mut Database,
+    root: u32,
+    values: &[Value],
+    key: &[KeyColumn],
+) -> DbResult<Option<Vec<u8>>> {
+    let encoding = db.encoding;
+    let mut page_num = root;
+    loop {
+        let page = Page::new(db.read_page(page_num)?, page_num)?;
+        let mut child = page.rightmost_pointer();
+        for offset in page.cell_offsets()? {
+            let (_, payload) = db.read_payload(&page, offset)?;
+            match compare_keys(values, &record_values(&payload, encoding)?, key, encoding) {
+                Ordering::Equal => return Ok(Some(payload)),
+                Ordering::Less => {
+                    child = page.is_interior().then_some(page.left_child(offset)?);
+                    break;
+                }
+                Ordering::Greater => {}
+            }
+        }
+        match child {
+            Some(child) => page_num = child,
+            None => return Ok(None),
+        }
+    }
+}
+
+/// Delete the index B-tree entry equal to `values`, compared as `key`
+/// describes, freeing its overflow pages.
+///
+/// Returns `false` if there is no such entry.
+pub fn delete_index_entry(
+    db: &mut Database,
+    root: u32,
+    values: &[Value],
+    key: &[KeyColumn],
+) -> DbResult<bool> {
+    let encoding = db.encoding;
+    let mut path = Vec::new();
+    let mut page_num = root;
+    loop {
+        let page = Page::new(db.read_page(page_num)?, page_num)?;
+        let mut child = page.rightmost_pointer();
+        for (i, offset) in page.cell_offsets()?.into_iter().enumerate() {
+            let (_, payload) = db.read_payload(&page, offset)?;
+            match compare_keys(values, &record_values(&payload, encoding)?, key, encoding) {
+                Ordering::Equal => {
+                    free_overflow(db, &page, offset)?;
+                    if page.is_leaf() {
+                        remove_leaf_cell(db, &mut path, page, i)?;
+                    } else {
+                        replace_interior_entry(db, root, path, &page, i, key)?;
+                    }
+                    return Ok(true);
+                }
+                Ordering::Less => {
+                    child = page.is_interior().then_some(page.left_child(offset)?);
+                    break;
+                }
+                Ordering::Greater => {}
+            }
+        }
+        let Some(next) = child else {
+            return Ok(false);
+        };
+        path.push(page_num);
+        page_num = next;
+    }
+}
+
+/// Replace the entry in cell `index` of an interior index page with the
+/// largest entry of its left subtree, which is then removed from its leaf.
+fn replace_interior_entry(
+    db: &mut Database,
+    root: u32,
+    mut path: Vec<u32>,
+    page: &Page,
+    index: usize,
+    key: &[KeyColumn],
+) -> DbResult<()> {
+    let usable_size = db.usable_size();
+    let mut cells = page.cells(usable_size)?;
+    let left_child = read_u32(&cells[index], 0)?;
+
+    // The predecessor is the last cell of the rightmost leaf under the left child
+    let mut leaf_num = left_child;
+    let leaf = loop {
+        let leaf = Page::new(db.read_page(leaf_num)?, leaf_num)?;
+        match leaf.rightmost_pointer() {
+            Some(rightmost) => leaf_num = rightmost,
+            None => break leaf,
+        }
+    };
+    let (Some(predecessor), Some(&offset)) =
+        (leaf.cells(usable_size)?.pop(), leaf.cell_offsets()?.last())
+    else {
+        return Err(DbError::CorruptPage {
+            page: leaf_num,
+            reason: "empty leaf under an interior index entry".to_string(),
+        });
+    };
+    let (_, predecessor_payload) = db.read_payload(&leaf, offset)?;
+
+    // Moving the predecessor up may split the interior page
+    let mut cell = left_child.to_be_bytes().to_vec();
+    cell.extend_from_slice(&predecessor);
+    cells[index] = cell;
+    store_cells(
+        db,
+        &mut path,
+        page.page_num(),
+        page.page_type(),
+        cells,
+        page.rightmost_pointer(),
+        false,
+    )?;
+
+    // Find the leaf again, as a split may have moved things around: it is the
+    // rightmost leaf under the interior entry that now holds the predecessor
+    let encoding = db.encoding;
+    let predecessor = record_values(&predecessor_payload, encoding)?;
+    let mut path = Vec::new();
+    let mut page_num = root;
+    let mut below_entry = false;
+    loop {
+        let page = Page::new(db.read_page(page_num)?, page_num)?;
+        if page.is_leaf() {
+            let last = page.cell_count().saturating_sub(1);
+            return remove_leaf_cell(db, &mut path, page, last);
+        }
+        let mut child = page.rightmost_pointer().unwrap_or_default();
+        if !below_entry {
+            for offset in page.cell_offsets()? {
+                let (_, payload) = db.read_payload(&page, offset)?;
+                let ordering = compare_keys(
+                    &predecessor,
+                    &record_values(&payload, encoding)?,
+                    key,
+                    encoding,
+                );
+                if ordering.is_le() {
+                    below_entry = ordering.is_eq();
+                    child = page.left_child(offset)?;
+                    break;
+                }
+            }
+        }
+        path.push(page_num);
+        page_num = child;
+    }
+}
+
+/// Remove cell `index` from a leaf page, merging the page into a sibling if
+/// that leaves it underfull.
+fn remove_leaf_cell(
+    db: &mut Database,
+    path: &mut Vec<u32>,
+    mut page: Page,
+    index: usize,
+) -> DbResult<()> {
+    let usable_size = db.usable_size();
+    page.remove_cell(index, usable_size)?;
+    let underfull = is_underfull(&page.cells(usable_size)?, usable_size);
+    db.write_page(page.page_num(), page.data().to_vec())?;
+    if underfull && !path.is_empty() {
+        merge_page(db, path, page.page_num())?;
+    }
+    Ok(())
+}
+
+/// Check if a page's cells fill less than a third of it, the point at
+/// which SQLite rebalances a page with its siblings.
+fn is_underfull(cells: &[Vec<u8>], usable_size: usize) -> bool {
+    cells.iter().map(|cell| cell.len() + 2).sum::<usize>() < usable_size / 3
+}
+
+/// Merge an underfull page with a sibling if their cells fit on one page,
+/// removing the divider between them from the parent (for index B-trees and
+/// interior pages, the divider moves down into the merged page). A page with
+/// no cells left is always merged, splitting the result again if needed.
+/// The parent is merged in turn if that leaves it underfull.
+fn merge_page(db: &mut Database, path: &mut Vec<u32>, page_num: u32) -> DbResult<()> {
+    let usable_size = db.usable_size();
+    let Some(parent) = path.pop() else {
+        return shrink_root(db, page_num);
+    };
+    let parent_page = Page::new(db.read_page(parent)?, parent)?;
+    let mut parent_cells = parent_page.cells(usable_size)?;
+    let parent_rightmost = parent_page.rightmost_pointer().unwrap_or_default();
+    let mut children = parent_cells
+        .iter()
+        .map(|cell| read_u32(cell, 0))
+        .collect::<DbResult<Vec<u32>>>()?;
+    children.push(parent_rightmost);
+    if children.len() < 2 {
+        // An only child can't merge, but the root above it can absorb it
+        return shrink_root(db, parent);
+    }
+
+    // Merge with the left sibling, or the right one for the first child
+    let position = children.iter().position(|&c| c == page_num).unwrap_or(0);
+    let left = position.saturating_sub(1);
+    let (left_num, right_num) = (children[left], children[left + 1]);
+    let left_page = Page::new(db.read_page(left_num)?, left_num)?;
+    let right_page = Page::new(db.read_page(right_num)?, right_num)?;
+    let page_type = right_page.page_type();
+    let empty = left_page.cell_count() == 0 || right_page.cell_count() == 0;
+    let divider = &parent_cells[left];
+
+    let mut cells = left_page.cells(usable_size)?;
+    match page_type {
+        PageType::LeafTable => {}
+        PageType::LeafIndex => cells.push(divider[4..].to_vec()),
+        PageType::InteriorTable | PageType::InteriorIndex => {
+            let left_rightmost = left_page.rightmost_pointer().unwrap_or_default();
+            let mut cell = left_rightmost.to_be_bytes().to_vec();
+            cell.extend_from_slice(&divider[4..]);
+            cells.push(cell);
+        }
+    }
+    cells.extend(right_page.cells(usable_size)?);
+    let fits = cells_fit(0, page_type, &cells, usable_size);
+    if !fits && !empty {
+        return Ok(());
+    }
+
+    // The parent keeps its pointer to the right page, which takes all the cells
+    parent_cells.remove(left);
+    write_cells(
+        db,
+        parent,
+        parent_page.page_type(),
+        &parent_cells,
+        Some(parent_rightmost),
+    )?;
+    db.free_page(left_num)?;
+    if !fits {
+        // Too much for one page: splitting again puts a divider back in the parent
+        path.push(parent);
+        return store_cells(
+            db,
+            path,
+            right_num,
+            page_type,
+            cells,
+            right_page.rightmost_pointer(),
+            false,
+        );
+    }
+    write_cells(
+        db,
+        right_num,
+        page_type,
+        &cells,
+        right_page.rightmost_pointer(),
+    )?;
+    if parent_cells.is_empty() || is_underfull(&parent_cells, usable_size) {
+        merge_page(db, path, parent)?;
+    }
+    Ok(())
+}
+
+/// Pull the only child of a root with no cells up into the root, making the
+/// tree one level shallower, if its cells fit there.
+fn shrink_root(db: &mut Database, root: u32) -> DbResult<()> {
+    let page = Page::new(db.read_page(root)?, root)?;
+    let Some(child_num) = page.rightmost_pointer().filter(|_| page.cell_count() == 0) else {
+        return Ok(());
+    };
+    let child = Page::new(db.read_page(child_num)?, child_num)?;
+    let cells = child.cells(db.usable_size())?;
+    if cells_fit(
+        Database::header_offset(root),
+        child.page_type(),
+        &cells,
+        db.usable_size(),
+    ) {
+        write_cells(
+            db,
+            root,
+            child.page_type(),
+            &cells,
+            child.rightmost_pointer(),
+        )?;
+        db.free_page(child_num)?;
+    }
+    Ok(())
+}
+
+/// Free the overflow pages of the cell at `cell_offset`, if it has any.
+fn free_overflow(db: &mut Database, page: &Page, cell_offset: usize) -> DbResult<()> {
+    let usable_size = db.usable_size();
+    let cell = page.cell_payload(cell_offset, usable_size)?;
+    let Some(mut next) = cell.overflow_page else {
+        return Ok(());
+    };
+    let pages = (cell.payload_size - cell.local.len()).div_ceil(usable_size - 4);
+    for _ in 0..pages {
+        let data = db.read_page(next)?;
+        db.free_page(next)?;
+        next = read_u32(&data, 0)?;
+        if next == 0 {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Decode every column of a record.
+fn record_values(payload: &[u8], encoding: TextEncoding) -> DbResult<Vec<Value>> {
+    let record = Record::parse(payload, 0, encoding)?;
+    Ok((0..record.column_count())
+        .map(|i| record.read_value(i))
+        .collect())
+}
+
+/// Append a cell's payload: the part stored on the page, followed by a
+/// pointer to an overflow chain holding the rest if it doesn't all fit.
+fn append_payload(
+    db: &mut Database,
+    cell: &mut Vec<u8>,
+    payload: &[u8],
+    is_table_leaf: bool,
+) -> DbResult<()> {
+    let usable_size = db.usable_size();
+    let local_size = local_payload_size(payload.len(), usable_size, is_table_leaf);
+    cell.extend_from_slice(&payload[..local_size]);
+    if local_size == payload.len() {
+        return Ok(());
+    }
+
+    // Each overflow page holds a 4-byte next-page pointer followed by content
+    let chunks: Vec<&[u8]> = payload[local_size..].chunks(usable_size - 4).collect();
+    let mut pages = Vec::with_capacity(chunks.len());
+    for _ in &chunks {
+        pages.push(db.allocate_page()?);
+    }
+    for (i, chunk) in chunks.iter().enumerate() {
+        let mut data = vec![0; db.page_size];
+        write_u32(&mut data, 0, pages.get(i + 1).copied().unwrap_or_default())?;
+        data[4..4 + chunk.len()].copy_from_slice(chunk);
+        db.write_page(pages[i], data)?;
+    }
+    // The first page's entry points at the page the cell ends up on
+    if db.auto_vacuum()? != AutoVacuum::None {
+        let entries: Vec<(u32, PtrmapEntry)> = pages
+            .windows(2)
+            .map(|pair| (pair[1], PtrmapEntry::new(PtrmapType::Overflow2, pair[0])))
+            .collect();
+        write_entries(db, &entries)?;
+    }
+    cell.extend_from_slice(&pages[0].to_be_bytes());
+    Ok(())
+}
+
+/// The key separating two pages of a split, as stored in their parent.
+enum Divider {
+    /// Table B-trees: the largest rowid on the page to the left.
+    Rowid(i64),
+    /// Index B-trees: an entry moved up out of the page, without a child pointer.
+    Entry(Vec<u8>),
+}
+
+impl Divider {
+    /// Build the parent cell pointing at the page to the left.
+    fn cell(&self, left_child: u32) -> Vec<u8> {
+        let mut cell = left_child.to_be_bytes().to_vec();
+        match self {
+            Divider::Rowid(rowid) => write_varint(&mut cell, *rowid as u64),
+            Divider::Entry(entry) => cell.extend_from_slice(entry),
+        }
+        cell
+    }
+}
+
+/// Write `cells` to a page, splitting them over several pages if they don't fit.
+///
+/// `path` lists the interior pages from the root down to the page's parent;
+/// a split adds cells to the parent, which may split in turn. A split root
+/// stays in place and becomes the parent of the new pages. `append` means
+/// the new cell went at the end, so pages are packed full, leaving room for
+/// further appends on the right.
+fn store_cells(
+    db: &mut Database,
+    path: &mut Vec<u32>,
+    page_num: u32,
+    page_type: PageType,
+    cells: Vec<Vec<u8>>,
+    rightmost: Option<u32>,
+    append: bool,
+) -> DbResult<()> {
+    let usable_size = db.usable_size();
+    if cells_fit(
+        Database::header_offset(page_num),
+        page_type,
+        &cells,
+        usable_size,
+    ) {
+        return write_cells(db, page_num, page_type, &cells, rightmost);
+    }
+
+    // Divide the cells into pages, each with the rightmost pointer it needs
+    // on interior pages, separated by dividers
+    let mut pages = Vec::new();
+    let mut dividers = Vec::new();
+    let mut start = 0;
+    for split in split_points(&cells, page_type, usable_size, append) {
+        let page_cells = cells[start..split].to_vec();
+        match page_type {
+            PageType::LeafTable => {
+                let last = page_cells.last().expect("split pages are never empty");
+                let (_, size_len) = read_varint(last, 0)?;
+                let (rowid, _) = read_varint(last, size_len)?;
+                dividers.push(Divider::Rowid(rowid as i64));
+                pages.push((page_cells, None));
+                start = split;
+                continue;
+            }
+            PageType::LeafIndex => {
+                dividers.push(Divider::Entry(cells[split].clone()));
+                pages.push((page_cells, None));
+            }
+            PageType::InteriorTable => {
+                let (rowid, _) = read_varint(&cells[split], 4)?;
+                dividers.push(Divider::Rowid(rowid as i64));
+                pages.push((page_cells, Some(read_u32(&cells[split], 0)?)));
+            }
+            PageType::InteriorIndex => {
+                dividers.push(Divider::Entry(cells[split][4..].to_vec()));
+                pages.push((page_cells, Some(read_u32(&cells[split], 0)?)));
+            }
+        }
+        start = split + 1;
+    }
+    pages.push((cells[start..].to_vec(), rightmost));
+
+    let Some(parent) = path.pop() else {
+        // Splitting the root: move every page's cells to a new page
+        let mut page_nums = Vec::with_capacity(pages.len());
+        for (page_cells, page_rightmost) in &pages {
+            let new_page = db.allocate_page()?;
+            write_cells(db, new_page, page_type, page_cells, *page_rightmost)?;
+            page_nums.push(new_page);
+        }
+        let root_cells: Vec<Vec<u8>> = dividers
+            .iter()
+            .zip(&page_nums)
+            .map(|(divider, &left)| divider.cell(left))
+            .collect();
+        let root_type = match page_type {
+            PageType::LeafTable | PageType::InteriorTable => PageType::InteriorTable,
+            PageType::LeafIndex | PageType::InteriorIndex => PageType::InteriorIndex,
+        };
+        return store_cells(
+            db,
+            path,
+            page_num,
+            root_type,
+            root_cells,
+            page_nums.last().copied(),
+            false,
+        );
+    };
+
+    // The last page stays where the parent already points; the others are new
+    let (last_cells, last_rightmost) = pages.pop().expect("a split makes several pages");
+    let mut divider_cells = Vec::with_capacity(pages.len());
+    for ((page_cells, page_rightmost), divider) in pages.iter().zip(&dividers) {
+        let new_page = db.allocate_page()?;
+        write_cells(db, new_page, page_type, page_cells, *page_rightmost)?;
+        divider_cells.push(divider.cell(new_page));
+    }
+    write_cells(db, page_num, page_type, &last_cells, last_rightmost)?;
+
+    let parent_page = Page::new(db.read_page(parent)?, parent)?;
+    let mut parent_cells = parent_page.cells(usable_size)?;
+    let position = parent_cells
+        .iter()
+        .position(|cell| read_u32(cell, 0).ok() == Some(page_num))
+        .unwrap_or(parent_cells.len());
+    let append = position == parent_cells.len();
+    parent_cells.splice(position..position, divider_cells);
+    store_cells(
+        db,
+        path,
+        parent,
+        parent_page.page_type(),
+        parent_cells,
+        parent_page.rightmost_pointer(),
+        append,
+    )
+}
+
+/// Choose where to split cells that overflow a page. For table leaves each
+/// split point is the first cell of a new page; otherwise it is the cell
+/// between two pages that moves up to the parent.
+///
+/// Uses as few pages as possible, spreading the cells evenly over them
+/// unless `append` asks for full pages.
+fn split_points(
+    cells: &[Vec<u8>],
+    page_type: PageType,
+    usable_size: usize,
+    append: bool,
+) -> Vec<usize> {
+    let moves_up = page_type != PageType::LeafTable;
+
+    // Close a page once it holds `target` bytes or the next cell doesn't fit
+    let pack = |target: usize| {
+        let mut points = Vec::new();
+        let mut start = 0;
+        let mut size = 0;
+        let mut i = 0;
+        while i < cells.len() {
+            if i > start
+                && (size >= target || !cells_fit(0, page_type, &cells[start..=i], usable_size))
+            {
+                points.push(i);
+                start = if moves_up { i + 1 } else { i };
+                size = 0;
+                i = start;
+                continue;
+            }
+            size += cells[i].len() + 2;
+            i += 1;
+        }
+        // The last page can't be left empty by moving its only cell up
+        if moves_up && points.last() == Some(&(cells.len() - 1)) {
+            *points.last_mut().expect("checked above") -= 1;
+        }
+        points
+    };
+
+    let full = pack(usize::MAX);
+    if append {
+        return full;
+    }
+    let total: usize = cells.iter().map(|cell| cell.len() + 2).sum();
+    let even = pack(total.div_ceil(full.len() + 1));
+    if even.len() <= full.len() { even } else { full }
+}
+
+/// Lay out a page with the given cells and write it.
+fn write_cells(
+    db: &mut Database,
+    page_num: u32,
+    page_type: PageType,
+    cells: &[Vec<u8>],
+    rightmost: Option<u32>,
+) -> DbResult<()> {
+    // Rebuilding from the current contents keeps the database header on page 1
+    let mut data = db.read_page(page_num)?;
+    build_page(
+        &mut data,
+        Database::header_offset(page_num),
+        page_type,
+        cells,
+        rightmost,
+        db.usable_size(),
+    )?;
+    // Cells that moved here bring their children and overflow pages along
+    if db.auto_vacuum()? != AutoVacuum::None {
+        set_child_entries(db, &Page::new(data.clone(), page_num)?)?;
+    }
+    db.write_page(page_num, data)
+}
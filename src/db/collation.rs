@@ -0,0 +1,116 @@
+//! Collating sequences used to compare text.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use super::encoding::TextEncoding;
+use super::error::{DbError, DbResult};
+
+/// A user-supplied function that orders two strings.
+pub type CollationFn = Arc<dyn Fn(&str, &str) -> Ordering + Send + Sync>;
+
+/// Custom collations registered with [`register_collation`], keyed by lowercase name.
+static CUSTOM_COLLATIONS: RwLock<Option<HashMap<String, CollationFn>>> = RwLock::new(None);
+
+/// A collating sequence: how text values are ordered and compared for equality.
+#[derive(Clone, Default)]
+pub enum Collation {
+    /// Compare the bytes of the text in the database encoding.
+    #[default]
+    Binary,
+    /// Like BINARY, but ASCII upper case letters equal their lower case forms.
+    NoCase,
+    /// Like BINARY, but trailing spaces are ignored.
+    RTrim,
+    /// A collation registered by name with [`register_collation`].
+    Custom { name: String, compare: CollationFn },
+}
+
+impl Collation {
+    /// Find a built-in or registered collation by name, ignoring case.
+    pub fn lookup(name: &str) -> DbResult<Self> {
+        let lower = name.to_lowercase();
+        match lower.as_str() {
+            "binary" => return Ok(Collation::Binary),
+            "nocase" => return Ok(Collation::NoCase),
+            "rtrim" => return Ok(Collation::RTrim),
+            _ => {}
+        }
+        let registry = CUSTOM_COLLATIONS
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        registry
+            .as_ref()
+            .and_then(|collations| collations.get(&lower))
+            .map(|compare| Collation::Custom {
+                name: name.to_string(),
+                compare: Arc::clone(compare),
+            })
+            .ok_or_else(|| DbError::CollationNotFound(name.to_string()))
+    }
+
+    /// Find a collation by optional name, defaulting to BINARY.
+    pub fn lookup_or_binary(name: Option<&str>) -> DbResult<Self> {
+        name.map_or(Ok(Collation::Binary), Collation::lookup)
+    }
+
+    /// The name the collation is known by.
+    pub fn name(&self) -> &str {
+        match self {
+            Collation::Binary => "BINARY",
+            Collation::NoCase => "NOCASE",
+            Collation::RTrim => "RTRIM",
+            Collation::Custom { name, .. } => name,
+        }
+    }
+
+    /// Compare two strings. BINARY uses the byte order of the database encoding;
+    /// NOCASE and RTRIM compare UTF-8, as SQLite's built-ins do.
+    pub fn compare(&self, a: &str, b: &str, encoding: TextEncoding) -> Ordering {
+        match self {
+            Collation::Binary => encoding.compare(a, b),
+            Collation::NoCase => a
+                .bytes()
+                .map(|c| c.to_ascii_lowercase())
+                .cmp(b.bytes().map(|c| c.to_ascii_lowercase())),
+            Collation::RTrim => a.trim_end_matches(' ').cmp(b.trim_end_matches(' ')),
+            Collation::Custom { compare, .. } => compare(a, b),
+        }
+    }
+}
+
+impl fmt::Debug for Collation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Collation({})", self.name())
+    }
+}
+
+impl PartialEq for Collation {
+    fn eq(&self, other: &Self) -> bool {
+        self.name().eq_ignore_ascii_case(other.name())
+    }
+}
+
+/// Register a custom collating sequence, usable in `COLLATE name` clauses and
+/// column definitions. Registering a name again replaces the earlier function.
+/// The built-in BINARY, NOCASE and RTRIM collations can't be replaced.
+pub fn register_collation(
+    name: &str,
+    compare: impl Fn(&str, &str) -> Ordering + Send + Sync + 'static,
+) -> DbResult<()> {
+    if matches!(name.to_lowercase().as_str(), "binary" | "nocase" | "rtrim") {
+        return Err(DbError::UnsupportedFeature(format!(
+            "replacing built-in collation {}",
+            name
+        )));
+    }
+    let mut registry = CUSTOM_COLLATIONS
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    registry
+        .get_or_insert_with(HashMap::new)
+        .insert(name.to_lowercase(), Arc::new(compare));
+    Ok(())
+}
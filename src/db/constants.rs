@@ -1,10 +1,91 @@
 //! Constants used throughout the SQLite database parsing.
 
+/// Largest string or blob, in bytes, as SQLite's default SQLITE_MAX_LENGTH.
+pub const MAX_LENGTH: usize = 1_000_000_000;
+
 /// Offset of page size in database header.
 pub const PAGE_SIZE_OFFSET: usize = 16;
 
+/// Offset of the file format version a library must support to write the database.
+pub const WRITE_VERSION_OFFSET: usize = 18;
+
+/// Offset of the file format version a library must support to read the database.
+pub const READ_VERSION_OFFSET: usize = 19;
+
+/// File format version of a database in WAL mode.
+pub const WAL_FORMAT_VERSION: u8 = 2;
+
+/// Offset of the reserved-space-per-page byte in database header.
+pub const RESERVED_SPACE_OFFSET: usize = 20;
+
+/// Page size value stored in the header for 65536-byte pages, which don't fit in a u16.
+pub const MAX_PAGE_SIZE_MARKER: u16 = 1;
+
+/// Largest page size SQLite supports.
+pub const MAX_PAGE_SIZE: usize = 65536;
+
+/// Offset of the text encoding in database header.
+pub const TEXT_ENCODING_OFFSET: usize = 56;
+
 /// Size of the database header (on page 1).
 pub const PAGE1_HEADER_OFFSET: usize = 100;
 
+/// Offset of the rightmost child pointer in an interior page's header.
+pub const RIGHTMOST_POINTER_OFFSET: usize = 8;
+
 /// Offset of cell count in page header.
 pub const CELL_COUNT_OFFSET: usize = 3;
+
+/// Offset of the file change counter in database header.
+pub const CHANGE_COUNTER_OFFSET: usize = 24;
+
+/// Offset of the database size in pages in database header.
+pub const PAGE_COUNT_OFFSET: usize = 28;
+
+/// Offset of the first freelist trunk page in database header.
+pub const FREELIST_TRUNK_OFFSET: usize = 32;
+
+/// Offset of the total number of freelist pages in database header.
+pub const FREELIST_COUNT_OFFSET: usize = 36;
+
+/// Offset of the change counter value the stored page count is valid for.
+pub const VERSION_VALID_FOR_OFFSET: usize = 92;
+
+/// Offset of the version number of the library that last wrote the file.
+pub const SQLITE_VERSION_OFFSET: usize = 96;
+
+/// Version number written to the header, in SQLite's X*1000000+Y*1000+Z form.
+pub const SQLITE_VERSION_NUMBER: u32 = 3_045_000;
+
+/// The page holding the byte range SQLite uses for file locks starts at this
+/// offset, and is never used for database content.
+pub const PENDING_BYTE: u64 = 0x4000_0000;
+
+/// Offset of the schema cookie, which changes whenever the schema does.
+pub const SCHEMA_COOKIE_OFFSET: usize = 40;
+
+/// Offset of the largest root page number in database header, which is
+/// nonzero only for a database with auto-vacuum.
+pub const LARGEST_ROOT_PAGE_OFFSET: usize = 52;
+
+/// Offset of the flag saying whether an auto-vacuum database vacuums only
+/// when asked to, by PRAGMA incremental_vacuum.
+pub const INCREMENTAL_VACUUM_OFFSET: usize = 64;
+
+/// Offset of the schema format number in database header.
+pub const SCHEMA_FORMAT_OFFSET: usize = 44;
+
+/// Schema format written to new databases, supporting every current feature.
+pub const SCHEMA_FORMAT: u32 = 4;
+
+/// Page size of a database created from scratch.
+pub const DEFAULT_PAGE_SIZE: usize = 4096;
+
+/// Every database file starts with this string.
+pub const HEADER_MAGIC: &[u8; 16] = b"SQLite format 3\0";
+
+/// Offset of the maximum, minimum and leaf payload fractions, which are fixed.
+pub const PAYLOAD_FRACTIONS_OFFSET: usize = 21;
+
+/// The only payload fractions SQLite accepts.
+pub const PAYLOAD_FRACTIONS: [u8; 3] = [64, 32, 32];
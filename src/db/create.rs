@@ -0,0 +1,199 @@
+//! CREATE TABLE and CREATE INDEX execution.
+
+use crate::sql::ast::{CreateIndex, CreateTable};
+
+use super::btree_write::create_btree;
+use super::collation::Collation;
+use super::database::Database;
+use super::error::{DbError, DbResult};
+use super::page::PageType;
+use super::query::{QueryResult, Table, check_columns};
+use super::schema::{
+    SchemaEntry, find_table, parse_columns, read_schema, table_columns, unique_constraints,
+};
+use super::schema_write::{bump_schema_cookie, insert_schema_entry};
+use super::table_write::{SEQUENCE_TABLE, TableWriter};
+
+/// How SQLite defines the table AUTOINCREMENT tables record their rowids in.
+const SEQUENCE_TABLE_SQL: &str = "CREATE TABLE sqlite_sequence(name,seq)";
+
+/// Create a table: give it an empty B-tree and record it in sqlite_schema,
+/// along with the automatic indexes of its UNIQUE and PRIMARY KEY constraints.
+pub fn execute_create_table(db: &mut Database, create: &CreateTable) -> DbResult<QueryResult> {
+    let entries = read_schema(db)?;
+    if let Some(existing) = find_entry(&entries, &create.name) {
+        return match existing.entry_type.as_str() {
+            "index" => Err(DbError::InvalidStatement(format!(
+                "there is already an index named {}",
+                create.name
+            ))),
+            _ if create.if_not_exists => Ok(QueryResult::default()),
+            entry_type => Err(DbError::InvalidStatement(format!(
+                "{} {} already exists",
+                entry_type, create.name
+            ))),
+        };
+    }
+    check_object_name(&create.name)?;
+    let entry = SchemaEntry {
+        entry_type: "table".to_string(),
+        name: create.name.clone(),
+        tbl_name: create.name.clone(),
+        rootpage: 0,
+        sql: create.sql.clone(),
+    };
+    check_table_definition(&entry)?;
+    let page_type = if entry.is_without_rowid() {
+        PageType::LeafIndex
+    } else {
+        PageType::LeafTable
+    };
+    // Like SQLite, the table comes before its automatic indexes, in both
+    // page numbers and sqlite_schema rows
+    let root = create_btree(db, page_type)?;
+    insert_schema_entry(
+        db,
+        &SchemaEntry {
+            rootpage: root,
+            ..entry
+        },
+    )?;
+    for (i, constraint) in unique_constraints(&create.sql)?.iter().enumerate() {
+        if constraint.keys_table {
+            continue;
+        }
+        let root = create_btree(db, PageType::LeafIndex)?;
+        insert_schema_entry(
+            db,
+            &SchemaEntry {
+                entry_type: "index".to_string(),
+                name: format!("sqlite_autoindex_{}_{}", create.name, i + 1),
+                tbl_name: create.name.clone(),
+                rootpage: root,
+                sql: String::new(),
+            },
+        )?;
+    }
+
+    let autoincrement = table_columns(create).iter().any(|c| c.autoincrement);
+    if autoincrement && find_entry(&entries, SEQUENCE_TABLE).is_none() {
+        let root = create_btree(db, PageType::LeafTable)?;
+        insert_schema_entry(
+            db,
+            &SchemaEntry {
+                entry_type: "table".to_string(),
+                name: SEQUENCE_TABLE.to_string(),
+                tbl_name: SEQUENCE_TABLE.to_string(),
+                rootpage: root,
+                sql: SEQUENCE_TABLE_SQL.to_string(),
+            },
+        )?;
+    }
+
+    bump_schema_cookie(db)?;
+    Ok(QueryResult::default())
+}
+
+/// Create an index, filling it from the rows already in its table.
+pub fn execute_create_index(db: &mut Database, create: &CreateIndex) -> DbResult<QueryResult> {
+    let entries = read_schema(db)?;
+    if let Some(existing) = find_entry(&entries, &create.name) {
+        return match existing.entry_type.as_str() {
+            "index" if create.if_not_exists => Ok(QueryResult::default()),
+            "index" => Err(DbError::InvalidStatement(format!(
+                "index {} already exists",
+                create.name
+            ))),
+            entry_type => Err(DbError::InvalidStatement(format!(
+                "there is already a {} named {}",
+                entry_type, create.name
+            ))),
+        };
+    }
+    check_object_name(&create.name)?;
+
+    let table = Table::new(find_table(db, &create.table)?, db.encoding)?;
+    let table_name = table.entry.tbl_name.clone();
+    if table_name.to_lowercase().starts_with("sqlite_") {
+        return Err(DbError::InvalidStatement(format!(
+            "table {} may not be indexed",
+            table_name
+        )));
+    }
+    for column in &create.columns {
+        if !table
+            .columns
+            .iter()
+            .any(|c| c.name.eq_ignore_ascii_case(&column.name))
+        {
+            return Err(DbError::ColumnNotFound {
+                table: table_name,
+                column: column.name.clone(),
+            });
+        }
+        if let Some(collation) = &column.collation {
+            Collation::lookup(collation)?;
+        }
+    }
+    check_columns(&table, create.where_clause.as_ref())?;
+
+    let root = create_btree(db, PageType::LeafIndex)?;
+    insert_schema_entry(
+        db,
+        &SchemaEntry {
+            entry_type: "index".to_string(),
+            name: create.name.clone(),
+            tbl_name: table_name.clone(),
+            rootpage: root,
+            sql: create.sql.clone(),
+        },
+    )?;
+    TableWriter::open(db, &table_name)?.build_index(db, &create.name)?;
+
+    bump_schema_cookie(db)?;
+    Ok(QueryResult::default())
+}
+
+/// Find the table or index with a name, ignoring case.
+pub(super) fn find_entry<'a>(entries: &'a [SchemaEntry], name: &str) -> Option<&'a SchemaEntry> {
+    entries.iter().find(|e| e.name.eq_ignore_ascii_case(name))
+}
+
+/// Names starting with `sqlite_` belong to SQLite's own tables and indexes.
+pub(super) fn check_object_name(name: &str) -> DbResult<()> {
+    if name.to_lowercase().starts_with("sqlite_") {
+        return Err(DbError::InvalidStatement(format!(
+            "object name reserved for internal use: {}",
+            name
+        )));
+    }
+    Ok(())
+}
+
+/// Reject table definitions SQLite would refuse to create a table from.
+fn check_table_definition(table: &SchemaEntry) -> DbResult<()> {
+    let columns = parse_columns(&table.sql)?;
+    for (i, column) in columns.iter().enumerate() {
+        if columns[..i]
+            .iter()
+            .any(|c| c.name.eq_ignore_ascii_case(&column.name))
+        {
+            return Err(DbError::InvalidStatement(format!(
+                "duplicate column name: {}",
+                column.name
+            )));
+        }
+    }
+    if table.is_without_rowid() && columns.iter().all(|c| c.primary_key.is_none()) {
+        return Err(DbError::InvalidStatement(format!(
+            "PRIMARY KEY missing on table {}",
+            table.name
+        )));
+    }
+    if columns.iter().any(|c| c.autoincrement && !c.is_rowid_alias) {
+        return Err(DbError::InvalidStatement(
+            "AUTOINCREMENT is only allowed on an INTEGER PRIMARY KEY".to_string(),
+        ));
+    }
+    Ok(())
+}
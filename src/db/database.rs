@@ -1,56 +1,862 @@
 //! Database file abstraction for SQLite.
 
-use anyhow::{Context, Result};
-use std::fs::File;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
 use std::io::prelude::*;
+use std::time::Duration;
 
-use super::constants::{PAGE_SIZE_OFFSET, PAGE1_HEADER_OFFSET};
+use super::autovacuum::AutoVacuum;
+use super::constants::{
+    CHANGE_COUNTER_OFFSET, DEFAULT_PAGE_SIZE, HEADER_MAGIC, INCREMENTAL_VACUUM_OFFSET,
+    LARGEST_ROOT_PAGE_OFFSET, MAX_PAGE_SIZE, MAX_PAGE_SIZE_MARKER, PAGE_COUNT_OFFSET,
+    PAGE_SIZE_OFFSET, PAGE1_HEADER_OFFSET, PAYLOAD_FRACTIONS, PAYLOAD_FRACTIONS_OFFSET,
+    PENDING_BYTE, READ_VERSION_OFFSET, RESERVED_SPACE_OFFSET, SCHEMA_FORMAT, SCHEMA_FORMAT_OFFSET,
+    SQLITE_VERSION_NUMBER, SQLITE_VERSION_OFFSET, TEXT_ENCODING_OFFSET, VERSION_VALID_FOR_OFFSET,
+    WAL_FORMAT_VERSION, WRITE_VERSION_OFFSET,
+};
+use super::encoding::TextEncoding;
+use super::error::{DbError, DbResult, read_u32, slice, write_u32};
+use super::freelist::{add_free_page, take_free_page};
+use super::interrupt::check_interrupt;
+use super::journal;
+use super::lock::{FileLock, LockLevel, wait_for_lock};
+use super::page::{Page, PageType, build_page};
+use super::ptrmap::is_ptrmap_page;
+use super::wal::{CheckpointMode, Wal};
+
+/// Number of frames in the write-ahead log that triggers a checkpoint after a commit.
+const WAL_AUTOCHECKPOINT: u32 = 1000;
+
+/// File format version of a database using a rollback journal.
+const LEGACY_FORMAT_VERSION: u8 = 1;
+
+/// Number of committed pages kept in memory between reads.
+const CACHE_PAGES: usize = 2000;
 
 /// A SQLite database file handle.
+///
+/// Pages are only read between [`Database::begin_read`] and
+/// [`Database::end_read`], which lock the file against writers in other
+/// processes. Committed pages stay cached across reads until another
+/// connection commits.
 pub struct Database {
+    path: String,
     file: File,
     pub page_size: usize,
+    /// Bytes at the end of every page reserved for extensions (checksums, encryption).
+    pub reserved_bytes: usize,
+    /// Encoding of every TEXT value in the database.
+    pub encoding: TextEncoding,
+    /// Auto-vacuum mode of a database created from an empty file.
+    new_auto_vacuum: AutoVacuum,
+    /// Number of pages in the database, including pages allocated since the last commit.
+    page_count: u32,
+    /// Number of pages in the file as of the last commit.
+    committed_page_count: u32,
+    /// The page count the header claims when that is more than the file
+    /// holds. Only an integrity check may read such a database, and only
+    /// up to the end of the file.
+    claimed_page_count: Option<u32>,
+    /// Pages changed since the last commit, keyed by page number.
+    dirty: BTreeMap<u32, Vec<u8>>,
+    /// Undo logs of the open savepoints, innermost last.
+    savepoints: Vec<UndoLog>,
+    /// The write-ahead log, for a database in WAL mode.
+    wal: Option<Wal>,
+    /// Whether commits go to a write-ahead log rather than straight to the file.
+    wal_mode: bool,
+    writable: bool,
+    lock: FileLock,
+    /// How long to wait for other connections' locks before giving up.
+    busy_timeout: Duration,
+    /// Whether a read has begun and not yet ended.
+    reading: bool,
+    /// The committed version the cached pages belong to.
+    version: Option<Version>,
+    /// Committed pages read so far, keyed by page number.
+    cache: HashMap<u32, Vec<u8>>,
+}
+
+/// What changes whenever any connection commits, telling when cached pages
+/// are out of date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Version {
+    change_counter: u32,
+    /// The salt and frame count of the write-ahead log, in WAL mode.
+    wal: Option<(u64, u32)>,
+}
+
+/// What a savepoint needs to undo the changes made since it began.
+struct UndoLog {
+    page_count: u32,
+    /// The changed version each page had when first written after the
+    /// savepoint began, or `None` if it was unchanged then.
+    pages: BTreeMap<u32, Option<Vec<u8>>>,
 }
 
 impl Database {
-    /// Open a SQLite database file.
-    pub fn open(path: &str) -> Result<Self> {
-        let mut file = File::open(path).context("Failed to open database file")?;
+    /// Open a SQLite database file, for writing too if permissions allow.
+    /// A file that doesn't exist is created empty, and becomes a database
+    /// once something is written to it.
+    ///
+    /// Changes are kept in memory until [`Database::commit`] writes them out.
+    pub fn open(path: &str, busy_timeout: Duration) -> DbResult<Self> {
+        let opened = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path);
+        let (file, writable) = match opened {
+            Ok(file) => (file, true),
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                (File::open(path)?, false)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Self::from_file(path, file, writable, busy_timeout)
+    }
+
+    fn from_file(
+        path: &str,
+        mut file: File,
+        writable: bool,
+        busy_timeout: Duration,
+    ) -> DbResult<Self> {
+        // Read the fixed-size database header. The page size, reserved
+        // space and encoding never change once a database has content.
+        let mut header = [0u8; PAGE1_HEADER_OFFSET];
+        if file.metadata()?.len() == 0 {
+            return Ok(Self::new(
+                path,
+                file,
+                DEFAULT_PAGE_SIZE,
+                0,
+                writable,
+                busy_timeout,
+            ));
+        }
+        file.seek(std::io::SeekFrom::Start(0))?;
+        file.read_exact(&mut header)
+            .map_err(|_| DbError::CorruptPage {
+                page: 1,
+                reason: "file is too small to hold a database header".to_string(),
+            })?;
+
+        // A stored page size of 1 means 65536, which doesn't fit in two bytes
+        let raw_page_size =
+            u16::from_be_bytes([header[PAGE_SIZE_OFFSET], header[PAGE_SIZE_OFFSET + 1]]);
+        let page_size = if raw_page_size == MAX_PAGE_SIZE_MARKER {
+            MAX_PAGE_SIZE
+        } else {
+            raw_page_size as usize
+        };
+        if page_size < 512 || !page_size.is_power_of_two() {
+            return Err(DbError::CorruptPage {
+                page: 1,
+                reason: format!("invalid page size {} in database header", page_size),
+            });
+        }
+
+        let reserved_bytes = header[RESERVED_SPACE_OFFSET] as usize;
+        if page_size - reserved_bytes < 480 {
+            return Err(DbError::CorruptPage {
+                page: 1,
+                reason: format!(
+                    "invalid reserved space {} for page size {}",
+                    reserved_bytes, page_size
+                ),
+            });
+        }
+
+        let mut db = Self::new(
+            path,
+            file,
+            page_size,
+            reserved_bytes,
+            writable,
+            busy_timeout,
+        );
+        db.encoding = TextEncoding::from_header(read_u32(&header, TEXT_ENCODING_OFFSET)?)?;
+        Ok(db)
+    }
+
+    fn new(
+        path: &str,
+        file: File,
+        page_size: usize,
+        reserved_bytes: usize,
+        writable: bool,
+        busy_timeout: Duration,
+    ) -> Self {
+        Self {
+            path: path.to_string(),
+            file,
+            page_size,
+            reserved_bytes,
+            encoding: TextEncoding::default(),
+            new_auto_vacuum: AutoVacuum::default(),
+            page_count: 0,
+            committed_page_count: 0,
+            claimed_page_count: None,
+            dirty: BTreeMap::new(),
+            savepoints: Vec::new(),
+            wal: None,
+            wal_mode: false,
+            writable,
+            lock: FileLock::default(),
+            busy_timeout,
+            reading: false,
+            version: None,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// The path the database was opened from.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// How long to wait for other connections' locks.
+    pub fn busy_timeout(&self) -> Duration {
+        self.busy_timeout
+    }
+
+    /// Change how long to wait for other connections' locks.
+    pub fn set_busy_timeout(&mut self, busy_timeout: Duration) {
+        self.busy_timeout = busy_timeout;
+    }
+
+    /// Check if commits go to a write-ahead log.
+    pub fn is_wal_mode(&self) -> bool {
+        self.wal_mode
+    }
+
+    /// Check if there are changes that haven't been committed.
+    pub fn has_changes(&self) -> bool {
+        !self.dirty.is_empty()
+    }
+
+    /// Number of bytes of each page available to B-tree content.
+    pub fn usable_size(&self) -> usize {
+        self.page_size - self.reserved_bytes
+    }
+
+    /// Start reading a snapshot of the latest commit, which other
+    /// connections can't change until [`Database::end_read`]. A commit
+    /// interrupted by a crash is rolled back first.
+    ///
+    /// A header claiming more pages than the file holds is corruption, as
+    /// it is to SQLite.
+    pub fn begin_read(&mut self) -> DbResult<()> {
+        let was_reading = self.reading;
+        self.begin_read_for_check()?;
+        let Some(claimed) = self.claimed_page_count else {
+            return Ok(());
+        };
+        if !was_reading {
+            self.end_read()?;
+        }
+        Err(DbError::CorruptPage {
+            page: 1,
+            reason: format!(
+                "header page count {} exceeds the {} pages in the file",
+                claimed, self.page_count
+            ),
+        })
+    }
+
+    /// Start a read like [`Database::begin_read`], but of a database whose
+    /// header claims more pages than the file holds too, reading only as
+    /// far as the file goes, so that an integrity check can report it.
+    pub fn begin_read_for_check(&mut self) -> DbResult<()> {
+        if self.reading {
+            return Ok(());
+        }
+        let timeout = self.busy_timeout;
+        wait_for_lock(timeout, || self.lock_shared())?;
+        if let Err(e) = self.refresh() {
+            self.lock.unlock(&self.file, LockLevel::None)?;
+            return Err(e);
+        }
+        self.reading = true;
+        Ok(())
+    }
+
+    /// Take a SHARED lock, rolling back a hot journal if there is one.
+    /// Returns false if another connection's lock is in the way.
+    fn lock_shared(&mut self) -> DbResult<bool> {
+        if !self.lock.lock(&self.file, LockLevel::Shared)? {
+            return Ok(false);
+        }
+        // A journal is only in use while its writer holds RESERVED
+        if self.lock.level() == LockLevel::Shared
+            && journal::has_journal(&self.path)?
+            && !self.lock.is_reserved_elsewhere(&self.file)?
+        {
+            if !self.writable {
+                self.lock.unlock(&self.file, LockLevel::None)?;
+                return Err(DbError::ReadOnly);
+            }
+            // Another reader may be rolling it back already
+            if !self.lock.lock(&self.file, LockLevel::Exclusive)? {
+                self.lock.unlock(&self.file, LockLevel::None)?;
+                return Ok(false);
+            }
+            let recovered = journal::recover(&self.path, &mut self.file);
+            self.lock.unlock(&self.file, LockLevel::Shared)?;
+            recovered?;
+        }
+        Ok(true)
+    }
+
+    /// Pick up the latest commit, forgetting cached pages if another
+    /// connection has committed since they were read.
+    fn refresh(&mut self) -> DbResult<()> {
+        // An empty file is a database with nothing in it yet
+        let mut header = [0u8; PAGE1_HEADER_OFFSET];
+        let file_len = self.file.metadata()?.len();
+        let file_pages = file_len.div_ceil(self.page_size as u64);
+        if file_len > 0 {
+            self.file.seek(std::io::SeekFrom::Start(0))?;
+            self.file.read_exact(&mut header)?;
+        }
+
+        // The header's page count is only trusted if it was written by the same
+        // change that last bumped the change counter; otherwise use the file size
+        let change_counter = read_u32(&header, CHANGE_COUNTER_OFFSET)?;
+        let page_count = match read_u32(&header, PAGE_COUNT_OFFSET)? {
+            count
+                if count != 0 && read_u32(&header, VERSION_VALID_FOR_OFFSET)? == change_counter =>
+            {
+                count
+            }
+            _ => (file_len / self.page_size as u64) as u32,
+        };
+
+        // Commits in the write-ahead log are newer than the file. A database
+        // in WAL mode gets a log once it is written to.
+        let wal_mode = header[WRITE_VERSION_OFFSET] == WAL_FORMAT_VERSION;
+        if self.wal.is_none() {
+            self.wal = Wal::open(&self.path, self.page_size, self.writable && wal_mode)?;
+        }
+        if let Some(wal) = &mut self.wal {
+            wait_for_lock(self.busy_timeout, || wal.begin_read())?;
+        }
+        self.wal_mode = wal_mode || self.wal.is_some();
+
+        let version = Version {
+            change_counter,
+            wal: self.wal.as_ref().map(Wal::version),
+        };
+        if self.version != Some(version) {
+            self.cache.clear();
+            self.version = Some(version);
+        }
+        // Without commits in a log, every page must be in the file. Pages
+        // a header claims beyond it are never read or allocated past.
+        self.claimed_page_count = None;
+        let page_count = match self.wal.as_ref().and_then(Wal::page_count) {
+            Some(wal_page_count) => wal_page_count,
+            None if u64::from(page_count) > file_pages => {
+                self.claimed_page_count = Some(page_count);
+                file_pages as u32
+            }
+            None => page_count,
+        };
+        self.page_count = page_count;
+        self.committed_page_count = page_count;
+        Ok(())
+    }
+
+    /// Let other connections commit again. Uncommitted changes must have
+    /// been committed or rolled back. In WAL mode the file stays locked
+    /// SHARED, which keeps other connections from leaving WAL mode.
+    pub fn end_read(&mut self) -> DbResult<()> {
+        if let Some(wal) = &mut self.wal {
+            wal.end_read()?;
+        }
+        let level = if self.wal_mode {
+            LockLevel::Shared
+        } else {
+            LockLevel::None
+        };
+        self.lock.unlock(&self.file, level)?;
+        self.reading = false;
+        Ok(())
+    }
 
-        // Read page size from header
-        let mut header = [0u8; 2];
-        file.seek(std::io::SeekFrom::Start(PAGE_SIZE_OFFSET as u64))?;
-        file.read_exact(&mut header)?;
-        let page_size = u16::from_be_bytes(header) as usize;
+    /// Start a write, which only one connection can do at a time. Other
+    /// connections can still read until the changes are committed.
+    ///
+    /// Unless a read was already under way, the read is ended between
+    /// attempts, so that waiting doesn't keep the writer in the way from
+    /// committing, and the write starts from the latest commit.
+    pub fn begin_write(&mut self) -> DbResult<()> {
+        if !self.writable {
+            return Err(DbError::ReadOnly);
+        }
+        let was_reading = self.reading;
+        let timeout = self.busy_timeout;
+        wait_for_lock(timeout, || {
+            self.begin_read()?;
+            let locked = match &mut self.wal {
+                Some(wal) => match wal.begin_write() {
+                    Err(DbError::Busy) if !was_reading => false,
+                    locked => locked?,
+                },
+                None => self.lock.lock(&self.file, LockLevel::Reserved)?,
+            };
+            if !locked && !was_reading {
+                self.end_read()?;
+            }
+            Ok(locked)
+        })?;
+        // The first write to an empty file starts with the page 1 it lacks
+        if self.page_count == 0 {
+            self.page_count = 1;
+        }
+        Ok(())
+    }
 
-        Ok(Self { file, page_size })
+    /// Lock out readers so that the file can be changed, once those reading
+    /// now are done. Not needed in WAL mode, where commits leave the file alone.
+    pub fn lock_exclusive(&mut self) -> DbResult<()> {
+        if self.wal.is_some() {
+            return Ok(());
+        }
+        let (file, lock) = (&self.file, &mut self.lock);
+        wait_for_lock(self.busy_timeout, || {
+            Ok(lock.lock(file, LockLevel::Exclusive)?)
+        })
     }
 
     /// Read a page from the database (1-indexed).
-    pub fn read_page(&mut self, page_num: u32) -> Result<Vec<u8>> {
+    pub fn read_page(&mut self, page_num: u32) -> DbResult<Vec<u8>> {
+        check_interrupt()?;
+        self.current_page(page_num)
+    }
+
+    /// Read a page as this connection sees it, even when interrupted.
+    fn current_page(&mut self, page_num: u32) -> DbResult<Vec<u8>> {
         if page_num == 0 {
-            anyhow::bail!("Page numbers are 1-indexed, got 0");
-        }
-
-        let page_offset = (page_num as u64 - 1) * self.page_size as u64;
-        let mut page = vec![0u8; self.page_size];
-        self.file
-            .seek(std::io::SeekFrom::Start(page_offset))
-            .context(format!(
-                "Failed to seek to page {} at offset {}",
-                page_num, page_offset
-            ))?;
-        self.file.read_exact(&mut page).context(format!(
-            "Failed to read page {} (offset: {}, size: {})",
-            page_num, page_offset, self.page_size
-        ))?;
+            return Err(DbError::CorruptPage {
+                page: 0,
+                reason: "page numbers are 1-indexed".to_string(),
+            });
+        }
+        if let Some(page) = self.dirty.get(&page_num) {
+            return Ok(page.clone());
+        }
+        self.read_file_page(page_num)
+    }
+
+    /// Read a page as last committed, from the write-ahead log if it has
+    /// the page and from the file otherwise.
+    fn read_file_page(&mut self, page_num: u32) -> DbResult<Vec<u8>> {
+        if let Some(page) = self.cache.get(&page_num) {
+            return Ok(page.clone());
+        }
+        if page_num == 1 && self.committed_page_count == 0 {
+            return self.empty_page1();
+        }
+        let page = match &mut self.wal {
+            Some(wal) => wal.read_page(page_num)?,
+            None => None,
+        };
+        let page = match page {
+            Some(page) => page,
+            None => {
+                let page_offset = (page_num as u64 - 1) * self.page_size as u64;
+                let mut page = vec![0u8; self.page_size];
+                self.file.seek(std::io::SeekFrom::Start(page_offset))?;
+                self.file
+                    .read_exact(&mut page)
+                    .map_err(|e| match e.kind() {
+                        std::io::ErrorKind::UnexpectedEof => DbError::CorruptPage {
+                            page: page_num,
+                            reason: format!(
+                                "page at offset {} is past the end of the file",
+                                page_offset
+                            ),
+                        },
+                        _ => DbError::Io(e),
+                    })?;
+                page
+            }
+        };
+        self.cache_page(page_num, page.clone());
         Ok(page)
     }
 
+    /// Page 1 of a new database: the database header, followed by the
+    /// empty sqlite_schema table.
+    fn empty_page1(&self) -> DbResult<Vec<u8>> {
+        let mut data = vec![0; self.page_size];
+        data[..HEADER_MAGIC.len()].copy_from_slice(HEADER_MAGIC);
+        let raw_page_size = match self.page_size {
+            MAX_PAGE_SIZE => MAX_PAGE_SIZE_MARKER,
+            page_size => page_size as u16,
+        };
+        data[PAGE_SIZE_OFFSET..PAGE_SIZE_OFFSET + 2].copy_from_slice(&raw_page_size.to_be_bytes());
+        data[WRITE_VERSION_OFFSET] = LEGACY_FORMAT_VERSION;
+        data[READ_VERSION_OFFSET] = LEGACY_FORMAT_VERSION;
+        data[RESERVED_SPACE_OFFSET] = self.reserved_bytes as u8;
+        data[PAYLOAD_FRACTIONS_OFFSET..PAYLOAD_FRACTIONS_OFFSET + PAYLOAD_FRACTIONS.len()]
+            .copy_from_slice(&PAYLOAD_FRACTIONS);
+        write_u32(&mut data, SCHEMA_FORMAT_OFFSET, SCHEMA_FORMAT)?;
+        write_u32(&mut data, TEXT_ENCODING_OFFSET, self.encoding.to_header())?;
+        // With auto-vacuum, page 1 is the last root page so far
+        if self.new_auto_vacuum != AutoVacuum::None {
+            write_u32(&mut data, LARGEST_ROOT_PAGE_OFFSET, 1)?;
+        }
+        let incremental = self.new_auto_vacuum == AutoVacuum::Incremental;
+        write_u32(&mut data, INCREMENTAL_VACUUM_OFFSET, incremental as u32)?;
+        build_page(
+            &mut data,
+            PAGE1_HEADER_OFFSET,
+            PageType::LeafTable,
+            &[],
+            None,
+            self.usable_size(),
+        )?;
+        Ok(data)
+    }
+
+    /// Keep a committed page in memory, starting over once the cache is full.
+    fn cache_page(&mut self, page_num: u32, data: Vec<u8>) {
+        if self.cache.len() >= CACHE_PAGES {
+            self.cache.clear();
+        }
+        self.cache.insert(page_num, data);
+    }
+
+    /// Replace the contents of a page. The change is written out by [`Database::commit`].
+    pub fn write_page(&mut self, page_num: u32, data: Vec<u8>) -> DbResult<()> {
+        if !self.writable {
+            return Err(DbError::ReadOnly);
+        }
+        if page_num == 0 || page_num > self.page_count || data.len() != self.page_size {
+            return Err(DbError::CorruptPage {
+                page: page_num,
+                reason: format!(
+                    "cannot write {} bytes to page {} of {}",
+                    data.len(),
+                    page_num,
+                    self.page_count
+                ),
+            });
+        }
+        if let Some(log) = self.savepoints.last_mut()
+            && !log.pages.contains_key(&page_num)
+        {
+            log.pages
+                .insert(page_num, self.dirty.get(&page_num).cloned());
+        }
+        self.dirty.insert(page_num, data);
+        Ok(())
+    }
+
+    /// Start a savepoint, which can later undo every change made after it.
+    pub fn begin_savepoint(&mut self) {
+        self.savepoints.push(UndoLog {
+            page_count: self.page_count,
+            pages: BTreeMap::new(),
+        });
+    }
+
+    /// Close the savepoints after the first `depth`, keeping their changes.
+    pub fn release_savepoints(&mut self, depth: usize) {
+        while self.savepoints.len() > depth {
+            let log = self.savepoints.pop().expect("savepoint to release");
+            // The enclosing savepoint must be able to undo these changes too
+            if let Some(parent) = self.savepoints.last_mut() {
+                for (page_num, image) in log.pages {
+                    parent.pages.entry(page_num).or_insert(image);
+                }
+            }
+        }
+    }
+
+    /// Undo the changes made since each savepoint after the first `depth`
+    /// began, and close them.
+    pub fn rollback_savepoints(&mut self, depth: usize) {
+        while self.savepoints.len() > depth {
+            let log = self.savepoints.pop().expect("savepoint to roll back");
+            for (page_num, image) in log.pages {
+                match image {
+                    Some(data) => self.dirty.insert(page_num, data),
+                    None => self.dirty.remove(&page_num),
+                };
+            }
+            self.page_count = log.page_count;
+        }
+    }
+
+    /// Discard every change made since the last commit.
+    pub fn rollback(&mut self) {
+        self.dirty.clear();
+        self.savepoints.clear();
+        self.page_count = self.committed_page_count;
+    }
+
+    /// Number of pages the file had at the last commit.
+    pub fn committed_page_count(&self) -> u32 {
+        self.committed_page_count
+    }
+
+    /// Number of pages in the database, including pages allocated since the last commit.
+    pub fn page_count(&self) -> u32 {
+        self.page_count
+    }
+
+    /// The page count the header claims, if it is more than the file holds.
+    pub fn claimed_page_count(&self) -> Option<u32> {
+        self.claimed_page_count
+    }
+
+    /// The committed contents of every page the next commit will overwrite,
+    /// which a rollback journal must preserve first. Pages added since the
+    /// last commit have no committed contents and are left out.
+    pub fn original_pages(&mut self) -> DbResult<Vec<(u32, Vec<u8>)>> {
+        if self.dirty.is_empty() {
+            return Ok(Vec::new());
+        }
+        // Committing always updates the header on page 1
+        let mut pages: Vec<u32> = self.dirty.keys().copied().collect();
+        if !self.dirty.contains_key(&1) {
+            pages.insert(0, 1);
+        }
+        pages.retain(|&page_num| page_num <= self.committed_page_count);
+        // So must the pages cut off the end of a database that shrinks
+        pages.extend(self.page_count + 1..=self.committed_page_count);
+        pages
+            .into_iter()
+            .map(|page_num| Ok((page_num, self.read_file_page(page_num)?)))
+            .collect()
+    }
+
+    /// Allocate a zeroed page, reusing a page from the freelist if there is one
+    /// and growing the file otherwise. Returns the new page's number.
+    pub fn allocate_page(&mut self) -> DbResult<u32> {
+        let page_num = match take_free_page(self)? {
+            Some(page_num) => page_num,
+            None => self.extend()?,
+        };
+        self.write_page(page_num, vec![0; self.page_size])?;
+        Ok(page_num)
+    }
+
+    /// Add a page to the end of the database, leaving its contents to the
+    /// caller. The page holding the lock bytes is skipped, and with
+    /// auto-vacuum so are pointer-map pages, which are added empty.
+    pub fn extend(&mut self) -> DbResult<u32> {
+        let auto_vacuum = self.auto_vacuum()? != AutoVacuum::None;
+        loop {
+            self.page_count += 1;
+            if self.page_count == self.pending_page() {
+                continue;
+            }
+            if auto_vacuum && is_ptrmap_page(self, self.page_count) {
+                self.write_page(self.page_count, vec![0; self.page_size])?;
+                continue;
+            }
+            return Ok(self.page_count);
+        }
+    }
+
+    /// The page holding the byte range used for file locks, which is never
+    /// used for database content.
+    pub fn pending_page(&self) -> u32 {
+        (PENDING_BYTE / self.page_size as u64) as u32 + 1
+    }
+
+    /// The auto-vacuum mode, as the database header records it.
+    pub fn auto_vacuum(&mut self) -> DbResult<AutoVacuum> {
+        // Page 1 is read often enough to be worth not copying
+        if let Some(header) = self.dirty.get(&1).or_else(|| self.cache.get(&1)) {
+            return AutoVacuum::from_header(header);
+        }
+        AutoVacuum::from_header(&self.read_page(1)?)
+    }
+
+    /// Change the auto-vacuum mode a database created from an empty file gets.
+    pub fn set_new_auto_vacuum(&mut self, mode: AutoVacuum) {
+        self.new_auto_vacuum = mode;
+    }
+
+    /// Shrink the database to its first `page_count` pages, discarding
+    /// the rest. The file is cut short by the next commit.
+    pub fn truncate(&mut self, page_count: u32) {
+        let removed = self.dirty.split_off(&(page_count + 1));
+        if let Some(log) = self.savepoints.last_mut() {
+            for (page_num, data) in removed {
+                log.pages.entry(page_num).or_insert(Some(data));
+            }
+        }
+        self.page_count = page_count;
+    }
+
+    /// Return a page that is no longer used to the freelist.
+    pub fn free_page(&mut self, page_num: u32) -> DbResult<()> {
+        add_free_page(self, page_num)
+    }
+
+    /// Write every changed page to the file, updating the header's change
+    /// counter and page count.
+    pub fn commit(&mut self) -> DbResult<()> {
+        if self.dirty.is_empty() {
+            return Ok(());
+        }
+
+        // An interrupt can't stop a commit halfway
+        let mut header = self.current_page(1)?;
+        let change_counter = read_u32(&header, CHANGE_COUNTER_OFFSET)?.wrapping_add(1);
+        write_u32(&mut header, CHANGE_COUNTER_OFFSET, change_counter)?;
+        write_u32(&mut header, PAGE_COUNT_OFFSET, self.page_count)?;
+        write_u32(&mut header, VERSION_VALID_FOR_OFFSET, change_counter)?;
+        write_u32(&mut header, SQLITE_VERSION_OFFSET, SQLITE_VERSION_NUMBER)?;
+        self.dirty.insert(1, header);
+
+        if let Some(wal) = &mut self.wal {
+            wal.append(&self.dirty, self.page_count)?;
+            if wal.frame_count() >= WAL_AUTOCHECKPOINT {
+                wal.checkpoint(&mut self.file, CheckpointMode::Passive, Duration::ZERO)?;
+            }
+        } else {
+            for (&page_num, data) in &self.dirty {
+                let page_offset = (page_num as u64 - 1) * self.page_size as u64;
+                self.file.seek(std::io::SeekFrom::Start(page_offset))?;
+                self.file.write_all(data)?;
+            }
+            if self.page_count < self.committed_page_count {
+                self.file
+                    .set_len(self.page_count as u64 * self.page_size as u64)?;
+            }
+            self.file.sync_all()?;
+        }
+
+        // This connection's own commit leaves its cached pages up to date
+        self.version = match (&self.wal, self.version) {
+            (Some(wal), Some(version)) => Some(Version {
+                wal: Some(wal.version()),
+                ..version
+            }),
+            (Some(_), None) => None,
+            (None, _) => Some(Version {
+                change_counter,
+                wal: None,
+            }),
+        };
+        for (page_num, data) in std::mem::take(&mut self.dirty) {
+            self.cache_page(page_num, data);
+        }
+        self.savepoints.clear();
+        self.committed_page_count = self.page_count;
+        Ok(())
+    }
+
+    /// Copy the pages in the write-ahead log into the file. Returns whether
+    /// other connections kept the checkpoint from finishing, the number of
+    /// frames in the log and the number now in the file.
+    pub fn checkpoint(&mut self, mode: CheckpointMode) -> DbResult<(bool, u32, u32)> {
+        if !self.writable {
+            return Err(DbError::ReadOnly);
+        }
+        match &mut self.wal {
+            Some(wal) => wal.checkpoint(&mut self.file, mode, self.busy_timeout),
+            None => Ok((false, 0, 0)),
+        }
+    }
+
+    /// Switch between WAL mode and using a rollback journal, by changing the
+    /// file format versions in the header. Leaving WAL mode checkpoints and
+    /// deletes the log first, which waits for every other connection to
+    /// close the database; the header change itself is an ordinary write,
+    /// committed to the file.
+    pub fn set_wal_mode(&mut self, enabled: bool) -> DbResult<()> {
+        if let Some(mut wal) = self.wal.take() {
+            let (file, lock) = (&self.file, &mut self.lock);
+            let locked = wait_for_lock(self.busy_timeout, || {
+                Ok(lock.lock(file, LockLevel::Exclusive)?)
+            });
+            if let Err(e) = locked {
+                self.wal = Some(wal);
+                return Err(e);
+            }
+            wal.end_read()?;
+            wal.close(&mut self.file)?;
+        }
+        self.wal_mode = false;
+        let version = if enabled {
+            WAL_FORMAT_VERSION
+        } else {
+            LEGACY_FORMAT_VERSION
+        };
+        let mut header = self.read_page(1)?;
+        header[WRITE_VERSION_OFFSET] = version;
+        header[READ_VERSION_OFFSET] = version;
+        self.write_page(1, header)
+    }
+
+    /// Read the complete payload of a cell, following its overflow chain if needed.
+    ///
+    /// Returns the rowid (for table leaf cells) and the payload bytes.
+    pub fn read_payload(
+        &mut self,
+        page: &Page,
+        cell_offset: usize,
+    ) -> DbResult<(Option<i64>, Vec<u8>)> {
+        let usable_size = self.usable_size();
+        let cell = page.cell_payload(cell_offset, usable_size)?;
+
+        // The size comes from a varint on disk, so it is checked against what
+        // the whole file could hold before any space is set aside for it
+        let file_capacity = self.page_count as usize * usable_size;
+        if cell.payload_size > file_capacity {
+            return Err(DbError::CorruptPage {
+                page: page.page_num(),
+                reason: format!(
+                    "cell payload of {} bytes is larger than the {}-byte file",
+                    cell.payload_size, file_capacity
+                ),
+            });
+        }
+
+        let mut payload = Vec::with_capacity(cell.payload_size);
+        payload.extend_from_slice(cell.local);
+
+        // Each overflow page holds a 4-byte next-page pointer followed by content
+        let mut next_page = cell.overflow_page;
+        while let Some(page_num) = next_page {
+            if payload.len() >= cell.payload_size {
+                break;
+            }
+            let data = self.read_page(page_num)?;
+            let next = read_u32(&data, 0)?;
+            let remaining = cell.payload_size - payload.len();
+            let chunk = remaining.min(usable_size - 4);
+            payload.extend_from_slice(slice(&data, 4, chunk)?);
+            next_page = if next == 0 { None } else { Some(next) };
+        }
+
+        if payload.len() < cell.payload_size {
+            return Err(DbError::CorruptPage {
+                page: cell.overflow_page.unwrap_or_default(),
+                reason: format!(
+                    "overflow chain ended after {} of {} payload bytes",
+                    payload.len(),
+                    cell.payload_size
+                ),
+            });
+        }
+
+        Ok((cell.rowid, payload))
+    }
+
     /// Get the header offset for a given page number.
     /// Page 1 has the database header at offset 0, so the page header starts at 100.
     /// Other pages have the page header at offset 0.
-    #[allow(dead_code)]
     pub fn header_offset(page_num: u32) -> usize {
         if page_num == 1 {
             PAGE1_HEADER_OFFSET
@@ -0,0 +1,24 @@
+//! DELETE execution.
+
+use crate::sql::ast::Delete;
+
+use super::database::Database;
+use super::error::DbResult;
+use super::query::{QueryResult, check_columns, filter_rows};
+use super::table_write::{Row, TableWriter};
+
+/// Delete the rows matching a DELETE statement's WHERE clause, using the
+/// same index and rowid seeks as SELECT.
+pub fn execute_delete(db: &mut Database, delete: &Delete) -> DbResult<QueryResult> {
+    let writer = TableWriter::open(db, &delete.table)?;
+    check_columns(&writer.table, delete.where_clause.as_ref())?;
+
+    let rows: Vec<Row> = filter_rows(db, &writer.table, delete.where_clause.as_ref())?
+        .iter()
+        .map(|record| writer.row_from_record(record))
+        .collect();
+    for row in &rows {
+        writer.delete(db, row)?;
+    }
+    Ok(QueryResult::default())
+}
@@ -0,0 +1,114 @@
+//! DROP TABLE and DROP INDEX execution.
+
+use std::cmp::Reverse;
+
+use crate::sql::ast::DropObject;
+
+use super::autovacuum::drop_btree;
+use super::btree::traverse_btree_table;
+use super::btree_write::delete_table_row;
+use super::database::Database;
+use super::error::{DbError, DbResult};
+use super::query::QueryResult;
+use super::schema::{SchemaEntry, read_schema_rows};
+use super::schema_write::{bump_schema_cookie, delete_schema_entry, move_schema_root};
+use super::table_write::SEQUENCE_TABLE;
+
+/// Drop a table along with its indexes, returning all their pages to the freelist.
+pub fn execute_drop_table(db: &mut Database, drop: &DropObject) -> DbResult<QueryResult> {
+    let rows = read_schema_rows(db)?;
+    let Some((_, table)) = rows
+        .iter()
+        .find(|(_, e)| e.entry_type == "table" && e.name.eq_ignore_ascii_case(&drop.name))
+    else {
+        return match drop.if_exists {
+            true => Ok(QueryResult::default()),
+            false => Err(DbError::TableNotFound(drop.name.clone())),
+        };
+    };
+    let lower = table.name.to_lowercase();
+    if lower.starts_with("sqlite_") && !lower.starts_with("sqlite_stat") {
+        return Err(DbError::InvalidStatement(format!(
+            "table {} may not be dropped",
+            table.name
+        )));
+    }
+
+    let table_name = table.tbl_name.clone();
+    let mut dropped: Vec<&(i64, SchemaEntry)> = rows
+        .iter()
+        .filter(|(_, e)| e.tbl_name.eq_ignore_ascii_case(&table_name))
+        .collect();
+    // Last root first, so that a root moved into a dropped one is never
+    // one still to be dropped
+    dropped.sort_by_key(|(_, e)| Reverse(e.rootpage));
+    for (rowid, entry) in dropped {
+        delete_schema_entry(db, *rowid)?;
+        // Triggers go with the table too, but have no B-tree
+        if entry.rootpage != 0 {
+            drop_root(db, entry.rootpage)?;
+        }
+    }
+    // An AUTOINCREMENT table no longer needs its largest rowid recorded
+    if let Some((_, sequence)) = rows
+        .iter()
+        .find(|(_, e)| e.entry_type == "table" && e.name == SEQUENCE_TABLE)
+    {
+        delete_sequence_row(db, sequence, &table_name)?;
+    }
+
+    bump_schema_cookie(db)?;
+    Ok(QueryResult::default())
+}
+
+/// Drop an index, returning its pages to the freelist.
+pub fn execute_drop_index(db: &mut Database, drop: &DropObject) -> DbResult<QueryResult> {
+    let rows = read_schema_rows(db)?;
+    let Some((rowid, index)) = rows
+        .iter()
+        .find(|(_, e)| e.is_index() && e.name.eq_ignore_ascii_case(&drop.name))
+    else {
+        return match drop.if_exists {
+            true => Ok(QueryResult::default()),
+            false => Err(DbError::InvalidStatement(format!(
+                "no such index: {}",
+                drop.name
+            ))),
+        };
+    };
+    // Automatic indexes go only with their table
+    if index.sql.is_empty() {
+        return Err(DbError::InvalidStatement(
+            "index associated with UNIQUE or PRIMARY KEY constraint cannot be dropped".to_string(),
+        ));
+    }
+
+    delete_schema_entry(db, *rowid)?;
+    drop_root(db, index.rootpage)?;
+    bump_schema_cookie(db)?;
+    Ok(QueryResult::default())
+}
+
+/// Free a B-tree, and with auto-vacuum record where the root that moved
+/// into its place came from.
+fn drop_root(db: &mut Database, root: u32) -> DbResult<()> {
+    if let Some(moved) = drop_btree(db, root)? {
+        move_schema_root(db, moved, root)?;
+    }
+    Ok(())
+}
+
+/// Remove a table's row from sqlite_sequence, if it has one.
+fn delete_sequence_row(db: &mut Database, sequence: &SchemaEntry, table: &str) -> DbResult<()> {
+    let mut records = Vec::new();
+    traverse_btree_table(db, sequence.rootpage, &mut records)?;
+    for record in records {
+        if record
+            .read_string(0)
+            .is_some_and(|name| name.eq_ignore_ascii_case(table))
+        {
+            delete_table_row(db, sequence.rootpage, record.rowid)?;
+        }
+    }
+    Ok(())
+}
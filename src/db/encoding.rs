@@ -0,0 +1,84 @@
+//! Text encodings a SQLite database can declare in its header.
+
+use std::cmp::Ordering;
+
+use super::error::{DbError, DbResult};
+
+/// Header values for the database text encoding.
+const ENCODING_UTF8: u32 = 1;
+const ENCODING_UTF16LE: u32 = 2;
+const ENCODING_UTF16BE: u32 = 3;
+
+/// The encoding used for every TEXT value stored in a database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextEncoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl TextEncoding {
+    /// Interpret the text encoding field of the database header.
+    ///
+    /// A value of 0 appears in databases that have no schema yet and means UTF-8.
+    pub fn from_header(value: u32) -> DbResult<Self> {
+        match value {
+            0 | ENCODING_UTF8 => Ok(Self::Utf8),
+            ENCODING_UTF16LE => Ok(Self::Utf16Le),
+            ENCODING_UTF16BE => Ok(Self::Utf16Be),
+            _ => Err(DbError::CorruptPage {
+                page: 1,
+                reason: format!("unknown text encoding {} in database header", value),
+            }),
+        }
+    }
+
+    /// The value of the header's text encoding field for this encoding.
+    pub fn to_header(self) -> u32 {
+        match self {
+            Self::Utf8 => ENCODING_UTF8,
+            Self::Utf16Le => ENCODING_UTF16LE,
+            Self::Utf16Be => ENCODING_UTF16BE,
+        }
+    }
+
+    /// Decode stored text bytes into a string, replacing invalid sequences.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            Self::Utf8 => String::from_utf8_lossy(bytes).to_string(),
+            Self::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+            Self::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+        }
+    }
+
+    /// Encode a string the way it would be stored in the database.
+    pub fn encode(&self, text: &str) -> Vec<u8> {
+        match self {
+            Self::Utf8 => text.as_bytes().to_vec(),
+            Self::Utf16Le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            Self::Utf16Be => text.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+        }
+    }
+
+    /// Compare two strings by their stored bytes, as SQLite's BINARY collation does.
+    ///
+    /// UTF-16 orders some characters differently from UTF-8, so index searches
+    /// must compare in the database encoding to agree with the B-tree order.
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            Self::Utf8 => a.cmp(b),
+            _ => self.encode(a).cmp(&self.encode(b)),
+        }
+    }
+}
+
+/// Decode UTF-16 code units, ignoring a trailing odd byte.
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> String {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|pair| from_bytes([pair[0], pair[1]]));
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
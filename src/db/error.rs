@@ -0,0 +1,124 @@
+//! Error types for database decoding and lookups.
+
+use thiserror::Error;
+
+/// Errors raised while reading and interpreting a SQLite database file.
+#[derive(Debug, Error)]
+pub enum DbError {
+    /// A page's contents contradict the file format.
+    #[error("corrupt page {page}: {reason}")]
+    CorruptPage { page: u32, reason: String },
+
+    /// A B-tree page starts with a byte that is not a known page type.
+    #[error("unknown page type {page_type:#04x} on page {page}")]
+    UnknownPageType { page: u32, page_type: u8 },
+
+    /// A read would run past the end of the buffer it addresses.
+    #[error("read of {len} bytes at offset {offset} is out of bounds (buffer size {size})")]
+    OutOfBounds {
+        offset: usize,
+        len: usize,
+        size: usize,
+    },
+
+    /// The file or query uses a feature this implementation does not handle.
+    #[error("unsupported feature: {0}")]
+    UnsupportedFeature(String),
+
+    /// No table with the given name exists in the schema.
+    #[error("no such table: {0}")]
+    TableNotFound(String),
+
+    /// The table has no column with the given name.
+    #[error("no such column: {column} in table {table}")]
+    ColumnNotFound { table: String, column: String },
+
+    /// No built-in or registered collating sequence has the given name.
+    #[error("no such collation sequence: {0}")]
+    CollationNotFound(String),
+
+    /// A statement parsed but doesn't make sense for the schema it runs against.
+    #[error("{0}")]
+    InvalidStatement(String),
+
+    /// A write would violate a constraint, such as `UNIQUE constraint failed: t.id`.
+    #[error("{0} constraint failed: {1}")]
+    Constraint(&'static str, String),
+
+    /// A write was attempted through a handle opened read-only.
+    #[error("attempt to write a readonly database")]
+    ReadOnly,
+
+    /// Another connection holds a lock that is in the way.
+    #[error("database is locked")]
+    Busy,
+
+    /// A string or blob would be longer than `MAX_LENGTH` bytes.
+    #[error("string or blob too big")]
+    TooBig,
+
+    /// A statement was stopped by an interrupt, such as Ctrl-C in the shell.
+    #[error("interrupted")]
+    Interrupted,
+
+    /// Text (a query or schema SQL) could not be parsed.
+    #[error("parse error at position {pos}: {msg}")]
+    Parse { pos: usize, msg: String },
+
+    /// The underlying file could not be read.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Result type used by the database layer.
+pub type DbResult<T> = std::result::Result<T, DbError>;
+
+/// Borrow `len` bytes at `offset`, failing instead of panicking when out of range.
+pub fn slice(data: &[u8], offset: usize, len: usize) -> DbResult<&[u8]> {
+    offset
+        .checked_add(len)
+        .and_then(|end| data.get(offset..end))
+        .ok_or(DbError::OutOfBounds {
+            offset,
+            len,
+            size: data.len(),
+        })
+}
+
+/// Read a big-endian u16 at `offset`.
+pub fn read_u16(data: &[u8], offset: usize) -> DbResult<u16> {
+    let bytes = slice(data, offset, 2)?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Read a big-endian u32 at `offset`.
+pub fn read_u32(data: &[u8], offset: usize) -> DbResult<u32> {
+    let bytes = slice(data, offset, 4)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Write a big-endian u16 at `offset`.
+pub fn write_u16(data: &mut [u8], offset: usize, value: u16) -> DbResult<()> {
+    let size = data.len();
+    data.get_mut(offset..offset + 2)
+        .ok_or(DbError::OutOfBounds {
+            offset,
+            len: 2,
+            size,
+        })?
+        .copy_from_slice(&value.to_be_bytes());
+    Ok(())
+}
+
+/// Write a big-endian u32 at `offset`.
+pub fn write_u32(data: &mut [u8], offset: usize, value: u32) -> DbResult<()> {
+    let size = data.len();
+    data.get_mut(offset..offset + 4)
+        .ok_or(DbError::OutOfBounds {
+            offset,
+            len: 4,
+            size,
+        })?
+        .copy_from_slice(&value.to_be_bytes());
+    Ok(())
+}
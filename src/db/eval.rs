@@ -0,0 +1,300 @@
+//! Expression evaluation.
+
+use crate::sql::ast::{BinaryOp, Expr, UnaryOp};
+
+use super::affinity::Affinity;
+use super::collation::Collation;
+use super::constants::MAX_LENGTH;
+use super::encoding::TextEncoding;
+use super::error::{DbError, DbResult};
+use super::functions;
+use super::value::Value;
+
+/// Supplies the values an expression can refer to.
+pub trait RowScope {
+    /// Look up a column of the current row by name.
+    fn column(&self, name: &str) -> DbResult<Value>;
+
+    /// Affinity of a column, used to convert the other side of a comparison.
+    fn column_affinity(&self, _name: &str) -> Affinity {
+        Affinity::None
+    }
+
+    /// Collating sequence of a column: its declared one, or BINARY. Names
+    /// that aren't columns have none.
+    fn column_collation(&self, _name: &str) -> Option<String> {
+        None
+    }
+
+    /// Text encoding used to compare strings.
+    fn encoding(&self) -> TextEncoding {
+        TextEncoding::default()
+    }
+
+    /// Look up the precomputed result of an aggregate call, if this scope has one.
+    fn aggregate(&self, _expr: &Expr) -> Option<Value> {
+        None
+    }
+}
+
+/// A scope with no columns, for queries without a FROM clause, in a
+/// database with the given text encoding.
+#[derive(Default)]
+pub struct EmptyScope(pub TextEncoding);
+
+impl RowScope for EmptyScope {
+    fn column(&self, name: &str) -> DbResult<Value> {
+        Err(DbError::InvalidStatement(format!(
+            "no such column: {}",
+            name
+        )))
+    }
+
+    fn encoding(&self) -> TextEncoding {
+        self.0
+    }
+}
+
+/// Evaluate an expression against a row.
+pub fn eval(expr: &Expr, scope: &dyn RowScope) -> DbResult<Value> {
+    match expr {
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Column(name) => scope.column(name),
+        Expr::Unary { op, expr } => {
+            let value = eval(expr, scope)?;
+            Ok(eval_unary(*op, value))
+        }
+        Expr::Binary { op, left, right } => eval_binary(*op, left, right, scope),
+        Expr::Cast { expr, type_name } => {
+            let value = eval(expr, scope)?;
+            Ok(Affinity::from_declared_type(type_name).cast(value))
+        }
+        // COLLATE doesn't change the value, only how comparisons treat it
+        Expr::Collate { expr, .. } => eval(expr, scope),
+        Expr::Function { name, args, .. } => {
+            if let Some(value) = scope.aggregate(expr) {
+                return Ok(value);
+            }
+            let args = args
+                .iter()
+                .map(|arg| eval(arg, scope))
+                .collect::<DbResult<Vec<_>>>()?;
+            functions::call(name, &args, scope.encoding())
+        }
+    }
+}
+
+/// Evaluate an expression as a WHERE condition; NULL counts as false.
+pub fn eval_condition(expr: &Expr, scope: &dyn RowScope) -> DbResult<bool> {
+    Ok(eval(expr, scope)?.is_truthy().unwrap_or(false))
+}
+
+fn eval_unary(op: UnaryOp, value: Value) -> Value {
+    if value == Value::Null {
+        return Value::Null;
+    }
+    match op {
+        UnaryOp::Not => Value::from(!value.is_truthy().unwrap_or(false)),
+        UnaryOp::Plus => value,
+        UnaryOp::Negate => match value.to_numeric() {
+            Value::Integer(i) => i
+                .checked_neg()
+                .map_or(Value::Real(-(i as f64)), Value::Integer),
+            Value::Real(r) => Value::Real(-r),
+            other => other,
+        },
+    }
+}
+
+fn eval_binary(op: BinaryOp, left: &Expr, right: &Expr, scope: &dyn RowScope) -> DbResult<Value> {
+    // AND and OR use three-valued logic, where NULL means "unknown"
+    if matches!(op, BinaryOp::And | BinaryOp::Or) {
+        let left = eval(left, scope)?.is_truthy();
+        let right = eval(right, scope)?.is_truthy();
+        let result = match (op, left, right) {
+            (BinaryOp::And, Some(false), _) | (BinaryOp::And, _, Some(false)) => Some(false),
+            (BinaryOp::And, Some(true), Some(true)) => Some(true),
+            (BinaryOp::Or, Some(true), _) | (BinaryOp::Or, _, Some(true)) => Some(true),
+            (BinaryOp::Or, Some(false), Some(false)) => Some(false),
+            _ => None,
+        };
+        return Ok(result.map_or(Value::Null, Value::from));
+    }
+
+    let (left_affinity, right_affinity) = (affinity(left, scope), affinity(right, scope));
+    let collation = if op.is_comparison() {
+        comparison_collation(left, right, scope)?
+    } else {
+        Collation::Binary
+    };
+    let left = eval(left, scope)?;
+    let right = eval(right, scope)?;
+    let (left, right) = if op.is_comparison() {
+        apply_comparison_affinity(left, left_affinity, right, right_affinity)
+    } else {
+        (left, right)
+    };
+
+    // IS and IS NOT treat NULL as an ordinary value that equals only itself
+    if matches!(op, BinaryOp::Is | BinaryOp::IsNot) {
+        let equal = match (&left, &right) {
+            (Value::Null, Value::Null) => true,
+            (Value::Null, _) | (_, Value::Null) => false,
+            _ => left
+                .compare_collated(&right, &collation, scope.encoding())
+                .is_eq(),
+        };
+        return Ok(Value::from(equal == (op == BinaryOp::Is)));
+    }
+
+    if left == Value::Null || right == Value::Null {
+        return Ok(Value::Null);
+    }
+
+    let result = match op {
+        BinaryOp::Eq => Value::from(
+            left.compare_collated(&right, &collation, scope.encoding())
+                .is_eq(),
+        ),
+        BinaryOp::NotEq => Value::from(
+            left.compare_collated(&right, &collation, scope.encoding())
+                .is_ne(),
+        ),
+        BinaryOp::Lt => Value::from(
+            left.compare_collated(&right, &collation, scope.encoding())
+                .is_lt(),
+        ),
+        BinaryOp::LtEq => Value::from(
+            left.compare_collated(&right, &collation, scope.encoding())
+                .is_le(),
+        ),
+        BinaryOp::Gt => Value::from(
+            left.compare_collated(&right, &collation, scope.encoding())
+                .is_gt(),
+        ),
+        BinaryOp::GtEq => Value::from(
+            left.compare_collated(&right, &collation, scope.encoding())
+                .is_ge(),
+        ),
+        BinaryOp::Concat => concat(&left, &right)?,
+        BinaryOp::Add
+        | BinaryOp::Subtract
+        | BinaryOp::Multiply
+        | BinaryOp::Divide
+        | BinaryOp::Remainder => arithmetic(op, left.to_numeric(), right.to_numeric()),
+        BinaryOp::And | BinaryOp::Or | BinaryOp::Is | BinaryOp::IsNot => {
+            unreachable!("handled above")
+        }
+    };
+    Ok(result)
+}
+
+/// The affinity of an expression: a column's own, a CAST's target, or none.
+fn affinity(expr: &Expr, scope: &dyn RowScope) -> Affinity {
+    match expr {
+        Expr::Column(name) => scope.column_affinity(name),
+        Expr::Cast { type_name, .. } => Affinity::from_declared_type(type_name),
+        Expr::Collate { expr, .. } => affinity(expr, scope),
+        _ => Affinity::None,
+    }
+}
+
+/// The collating sequence an expression asks for, and whether it was given
+/// explicitly with COLLATE rather than coming from a column. A column
+/// without a COLLATE clause still asks for BINARY.
+pub fn expr_collation(expr: &Expr, scope: &dyn RowScope) -> Option<(String, bool)> {
+    match expr {
+        Expr::Collate { collation, .. } => Some((collation.clone(), true)),
+        Expr::Column(name) => scope.column_collation(name).map(|c| (c, false)),
+        _ => None,
+    }
+}
+
+/// Choose the collation for comparing two operands: an explicit COLLATE on
+/// the left, then on the right, then the left column's, then the right
+/// column's, and BINARY otherwise.
+pub fn comparison_collation(
+    left: &Expr,
+    right: &Expr,
+    scope: &dyn RowScope,
+) -> DbResult<Collation> {
+    let left = expr_collation(left, scope);
+    let right = expr_collation(right, scope);
+    let name = match (&left, &right) {
+        (Some((name, true)), _) => Some(name),
+        (_, Some((name, true))) => Some(name),
+        (Some((name, false)), _) => Some(name),
+        (_, Some((name, false))) => Some(name),
+        (None, None) => None,
+    };
+    Collation::lookup_or_binary(name.map(String::as_str))
+}
+
+/// Convert comparison operands following SQLite's rules: a numeric operand
+/// makes the other side numeric if it can be, and a TEXT operand makes an
+/// operand with no affinity text. So `age = '30'` matches an INTEGER 30, but
+/// `1 = '1'` is false.
+fn apply_comparison_affinity(
+    left: Value,
+    left_affinity: Affinity,
+    right: Value,
+    right_affinity: Affinity,
+) -> (Value, Value) {
+    if left_affinity.is_numeric() && !right_affinity.is_numeric() {
+        (left, Affinity::Numeric.apply(right))
+    } else if right_affinity.is_numeric() && !left_affinity.is_numeric() {
+        (Affinity::Numeric.apply(left), right)
+    } else if left_affinity == Affinity::Text && right_affinity == Affinity::None {
+        (left, Affinity::Text.apply(right))
+    } else if right_affinity == Affinity::Text && left_affinity == Affinity::None {
+        (Affinity::Text.apply(left), right)
+    } else {
+        (left, right)
+    }
+}
+
+/// Join two values' text, failing if the result would be too long.
+fn concat(left: &Value, right: &Value) -> DbResult<Value> {
+    let (left, right) = (left.to_string(), right.to_string());
+    if left.len() + right.len() > MAX_LENGTH {
+        return Err(DbError::TooBig);
+    }
+    Ok(Value::Text(left + &right))
+}
+
+/// Apply an arithmetic operator to two numeric values.
+/// Integer overflow falls back to floating point; division by zero yields NULL.
+fn arithmetic(op: BinaryOp, left: Value, right: Value) -> Value {
+    if let (Value::Integer(a), Value::Integer(b)) = (&left, &right) {
+        let (a, b) = (*a, *b);
+        let result = match op {
+            BinaryOp::Add => a.checked_add(b),
+            BinaryOp::Subtract => a.checked_sub(b),
+            BinaryOp::Multiply => a.checked_mul(b),
+            BinaryOp::Divide if b == 0 => return Value::Null,
+            BinaryOp::Divide => a.checked_div(b),
+            BinaryOp::Remainder if b == 0 => return Value::Null,
+            BinaryOp::Remainder => Some(a.wrapping_rem(b)),
+            _ => None,
+        };
+        if let Some(result) = result {
+            return Value::Integer(result);
+        }
+    }
+
+    let (a, b) = (left.as_f64(), right.as_f64());
+    let result = match op {
+        BinaryOp::Add => a + b,
+        BinaryOp::Subtract => a - b,
+        BinaryOp::Multiply => a * b,
+        BinaryOp::Divide if b == 0.0 => return Value::Null,
+        BinaryOp::Divide => a / b,
+        // Like SQLite, the remainder of reals is that of their integer parts
+        BinaryOp::Remainder => match (a as i64, b as i64) {
+            (_, 0) => return Value::Null,
+            (a, b) => a.wrapping_rem(b) as f64,
+        },
+        _ => return Value::Null,
+    };
+    Value::Real(result)
+}
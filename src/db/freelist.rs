@@ -0,0 +1,261 @@
+//! The freelist: pages the database file holds but no B-tree uses.
+//!
+//! Free pages are listed on a chain of trunk pages starting from the
+//! header. Each trunk holds the next trunk's page number, a leaf count and
+//! that many leaf page numbers; trunks are free pages too.
+
+use std::collections::BTreeSet;
+
+use super::autovacuum::AutoVacuum;
+use super::btree::btree_pages;
+use super::constants::{FREELIST_COUNT_OFFSET, FREELIST_TRUNK_OFFSET};
+use super::database::Database;
+use super::error::{DbError, DbResult, read_u32, write_u32};
+use super::ptrmap::{PtrmapEntry, PtrmapType, write_entries};
+use super::schema::read_schema;
+
+/// Offsets within a freelist trunk page.
+const TRUNK_NEXT_OFFSET: usize = 0;
+const TRUNK_LEAF_COUNT_OFFSET: usize = 4;
+const TRUNK_LEAVES_OFFSET: usize = 8;
+
+/// The free pages of a database, as its trunk chain lists them.
+#[derive(Debug, Clone, Default)]
+pub struct Freelist {
+    /// Trunk pages, in chain order.
+    pub trunks: Vec<u32>,
+    /// Leaf pages, in the order the trunks list them.
+    pub leaves: Vec<u32>,
+}
+
+impl Freelist {
+    /// Read the freelist, checking that it only lists pages within the
+    /// database, none of them twice, and as many as the header counts.
+    pub fn read(db: &mut Database) -> DbResult<Self> {
+        let header = db.read_page(1)?;
+        let mut trunk = read_u32(&header, FREELIST_TRUNK_OFFSET)?;
+        let count = read_u32(&header, FREELIST_COUNT_OFFSET)? as usize;
+        let max_leaves = db.usable_size() / 4 - 2;
+        let page_count = db.page_count();
+
+        let mut freelist = Self::default();
+        let mut seen = BTreeSet::new();
+        let mut check = |page_num: u32, what: &str| {
+            if page_num < 2 || page_num > page_count || !seen.insert(page_num) {
+                return Err(corrupt(format!(
+                    "invalid freelist {} page {}",
+                    what, page_num
+                )));
+            }
+            Ok(())
+        };
+        while trunk != 0 {
+            check(trunk, "trunk")?;
+            let data = db.read_page(trunk)?;
+            let leaf_count = read_u32(&data, TRUNK_LEAF_COUNT_OFFSET)? as usize;
+            if leaf_count > max_leaves {
+                return Err(corrupt(format!(
+                    "freelist trunk page {} lists {} leaves",
+                    trunk, leaf_count
+                )));
+            }
+            for i in 0..leaf_count {
+                let leaf = read_u32(&data, TRUNK_LEAVES_OFFSET + 4 * i)?;
+                check(leaf, "leaf")?;
+                freelist.leaves.push(leaf);
+            }
+            freelist.trunks.push(trunk);
+            trunk = read_u32(&data, TRUNK_NEXT_OFFSET)?;
+        }
+
+        if freelist.len() != count {
+            return Err(corrupt(format!(
+                "freelist has {} pages but the header counts {}",
+                freelist.len(),
+                count
+            )));
+        }
+        Ok(freelist)
+    }
+
+    /// Number of free pages, trunks included.
+    pub fn len(&self) -> usize {
+        self.trunks.len() + self.leaves.len()
+    }
+
+    /// Every free page, trunks included.
+    pub fn pages(&self) -> BTreeSet<u32> {
+        self.trunks.iter().chain(&self.leaves).copied().collect()
+    }
+}
+
+/// Take a page off the freelist for reuse: the last leaf of the first
+/// trunk, or the trunk itself once it has no leaves left. Returns `None`
+/// if the freelist is empty.
+pub fn take_free_page(db: &mut Database) -> DbResult<Option<u32>> {
+    let mut header = db.read_page(1)?;
+    let trunk = read_u32(&header, FREELIST_TRUNK_OFFSET)?;
+    if trunk == 0 {
+        return Ok(None);
+    }
+
+    let mut trunk_data = db.read_page(trunk)?;
+    let leaf_count = read_u32(&trunk_data, TRUNK_LEAF_COUNT_OFFSET)?;
+    let page_num = if leaf_count > 0 {
+        let leaf = read_u32(
+            &trunk_data,
+            TRUNK_LEAVES_OFFSET + 4 * (leaf_count as usize - 1),
+        )?;
+        write_u32(&mut trunk_data, TRUNK_LEAF_COUNT_OFFSET, leaf_count - 1)?;
+        db.write_page(trunk, trunk_data)?;
+        leaf
+    } else {
+        let next_trunk = read_u32(&trunk_data, TRUNK_NEXT_OFFSET)?;
+        write_u32(&mut header, FREELIST_TRUNK_OFFSET, next_trunk)?;
+        trunk
+    };
+    let count = read_u32(&header, FREELIST_COUNT_OFFSET)?;
+    write_u32(&mut header, FREELIST_COUNT_OFFSET, count.saturating_sub(1))?;
+    db.write_page(1, header)?;
+    Ok(Some(page_num))
+}
+
+/// Take a particular page off the freelist, wherever it is on it. A trunk
+/// with leaves is replaced by its first leaf.
+pub fn remove_free_page(db: &mut Database, page_num: u32) -> DbResult<()> {
+    let mut previous = None;
+    let mut trunk = read_u32(&db.read_page(1)?, FREELIST_TRUNK_OFFSET)?;
+    while trunk != 0 {
+        let mut data = db.read_page(trunk)?;
+        let next = read_u32(&data, TRUNK_NEXT_OFFSET)?;
+        let leaf_count = read_u32(&data, TRUNK_LEAF_COUNT_OFFSET)? as usize;
+        let leaves_end = TRUNK_LEAVES_OFFSET + 4 * leaf_count;
+        if trunk == page_num {
+            let replacement = if leaf_count > 0 {
+                let first = read_u32(&data, TRUNK_LEAVES_OFFSET)?;
+                let mut replacement = vec![0; db.page_size];
+                write_u32(&mut replacement, TRUNK_NEXT_OFFSET, next)?;
+                write_u32(
+                    &mut replacement,
+                    TRUNK_LEAF_COUNT_OFFSET,
+                    leaf_count as u32 - 1,
+                )?;
+                replacement[TRUNK_LEAVES_OFFSET..leaves_end - 4]
+                    .copy_from_slice(&data[TRUNK_LEAVES_OFFSET + 4..leaves_end]);
+                db.write_page(first, replacement)?;
+                first
+            } else {
+                next
+            };
+            match previous {
+                Some(previous) => {
+                    let mut previous_data = db.read_page(previous)?;
+                    write_u32(&mut previous_data, TRUNK_NEXT_OFFSET, replacement)?;
+                    db.write_page(previous, previous_data)?;
+                }
+                None => {
+                    let mut header = db.read_page(1)?;
+                    write_u32(&mut header, FREELIST_TRUNK_OFFSET, replacement)?;
+                    db.write_page(1, header)?;
+                }
+            }
+        } else {
+            let mut leaf = None;
+            for i in 0..leaf_count {
+                if read_u32(&data, TRUNK_LEAVES_OFFSET + 4 * i)? == page_num {
+                    leaf = Some(i);
+                    break;
+                }
+            }
+            let Some(i) = leaf else {
+                previous = Some(trunk);
+                trunk = next;
+                continue;
+            };
+            data.copy_within(
+                TRUNK_LEAVES_OFFSET + 4 * (i + 1)..leaves_end,
+                TRUNK_LEAVES_OFFSET + 4 * i,
+            );
+            write_u32(&mut data, TRUNK_LEAF_COUNT_OFFSET, leaf_count as u32 - 1)?;
+            db.write_page(trunk, data)?;
+        }
+
+        let mut header = db.read_page(1)?;
+        let count = read_u32(&header, FREELIST_COUNT_OFFSET)?;
+        write_u32(&mut header, FREELIST_COUNT_OFFSET, count.saturating_sub(1))?;
+        return db.write_page(1, header);
+    }
+    Err(corrupt(format!("page {} is not on the freelist", page_num)))
+}
+
+/// Add a page that is no longer used to the freelist.
+pub fn add_free_page(db: &mut Database, page_num: u32) -> DbResult<()> {
+    if db.auto_vacuum()? != AutoVacuum::None {
+        write_entries(db, &[(page_num, PtrmapEntry::new(PtrmapType::FreePage, 0))])?;
+    }
+    let mut header = db.read_page(1)?;
+    let trunk = read_u32(&header, FREELIST_TRUNK_OFFSET)?;
+    let count = read_u32(&header, FREELIST_COUNT_OFFSET)?;
+    write_u32(&mut header, FREELIST_COUNT_OFFSET, count + 1)?;
+
+    // Add the page as a leaf of the first trunk while it has room. Older
+    // SQLite versions read at most usable_size / 4 - 8 leaves per trunk.
+    if trunk != 0 {
+        let mut trunk_data = db.read_page(trunk)?;
+        let leaf_count = read_u32(&trunk_data, TRUNK_LEAF_COUNT_OFFSET)?;
+        if (leaf_count as usize) < db.usable_size() / 4 - 8 {
+            write_u32(
+                &mut trunk_data,
+                TRUNK_LEAVES_OFFSET + 4 * leaf_count as usize,
+                page_num,
+            )?;
+            write_u32(&mut trunk_data, TRUNK_LEAF_COUNT_OFFSET, leaf_count + 1)?;
+            db.write_page(trunk, trunk_data)?;
+            return db.write_page(1, header);
+        }
+    }
+
+    // Otherwise the page becomes the new first trunk
+    let mut data = vec![0; db.page_size];
+    write_u32(&mut data, TRUNK_NEXT_OFFSET, trunk)?;
+    db.write_page(page_num, data)?;
+    write_u32(&mut header, FREELIST_TRUNK_OFFSET, page_num)?;
+    db.write_page(1, header)
+}
+
+/// Read the freelist and check that no B-tree uses any of its pages.
+pub fn verify_freelist(db: &mut Database) -> DbResult<Freelist> {
+    let freelist = Freelist::read(db)?;
+    let free = freelist.pages();
+    if free.is_empty() {
+        return Ok(freelist);
+    }
+
+    // sqlite_schema is rooted at page 1 and lists every other B-tree
+    let mut roots = vec![1];
+    roots.extend(
+        read_schema(db)?
+            .iter()
+            .map(|entry| entry.rootpage)
+            .filter(|&root| root != 0),
+    );
+    for root in roots {
+        let mut pages = Vec::new();
+        btree_pages(db, root, &mut pages)?;
+        if let Some(&page) = pages.iter().find(|page| free.contains(page)) {
+            return Err(DbError::CorruptPage {
+                page,
+                reason: format!(
+                    "page is on the freelist but used by the B-tree rooted at page {}",
+                    root
+                ),
+            });
+        }
+    }
+    Ok(freelist)
+}
+
+/// Freelist problems are reported against the header on page 1, which starts the chain.
+fn corrupt(reason: String) -> DbError {
+    DbError::CorruptPage { page: 1, reason }
+}
@@ -0,0 +1,164 @@
+//! Built-in scalar SQL functions.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::constants::MAX_LENGTH;
+use super::encoding::TextEncoding;
+use super::error::{DbError, DbResult};
+use super::value::{Value, to_hex};
+
+/// Call a scalar function by name with already-evaluated arguments, in a
+/// database with the given text encoding.
+pub fn call(name: &str, args: &[Value], encoding: TextEncoding) -> DbResult<Value> {
+    match (name.to_lowercase().as_str(), args) {
+        ("length", [value]) => Ok(length(value)),
+        ("hex", [value]) => hex(value, encoding),
+        ("unhex", [value]) => Ok(unhex(value, &Value::Text(String::new()))),
+        ("unhex", [value, ignore]) => Ok(unhex(value, ignore)),
+        ("zeroblob", [n]) => zeroblob(n),
+        ("coalesce", [_, _, ..]) | ("ifnull", [_, _]) => Ok(coalesce(args)),
+        ("nullif", [a, b]) => Ok(nullif(a, b)),
+        ("current_timestamp", []) => Ok(current_time("%F %T")),
+        ("current_date", []) => Ok(current_time("%F")),
+        ("current_time", []) => Ok(current_time("%T")),
+        ("length" | "hex" | "unhex" | "zeroblob" | "coalesce" | "ifnull" | "nullif", _) => {
+            Err(DbError::UnsupportedFeature(format!(
+                "wrong number of arguments to function {}()",
+                name
+            )))
+        }
+        _ => Err(DbError::UnsupportedFeature(format!(
+            "no such function: {}",
+            name
+        ))),
+    }
+}
+
+/// Characters in text, bytes in a blob, or characters in a number's text form.
+fn length(value: &Value) -> Value {
+    match value {
+        Value::Null => Value::Null,
+        Value::Blob(bytes) => Value::Integer(bytes.len() as i64),
+        value => Value::Integer(value.to_string().chars().count() as i64),
+    }
+}
+
+/// Decode hexadecimal text into a blob, skipping any characters listed in
+/// `ignore` between hex digit pairs. Returns NULL if the text isn't valid hex.
+fn unhex(value: &Value, ignore: &Value) -> Value {
+    if *value == Value::Null || *ignore == Value::Null {
+        return Value::Null;
+    }
+    let text = value.to_string();
+    let ignore = ignore.to_string();
+
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if ignore.contains(c) {
+            continue;
+        }
+        let high = c.to_digit(16);
+        let low = chars.next().and_then(|c| c.to_digit(16));
+        match (high, low) {
+            (Some(high), Some(low)) => bytes.push((high * 16 + low) as u8),
+            _ => return Value::Null,
+        }
+    }
+    Value::Blob(bytes)
+}
+
+/// A value's bytes as uppercase hexadecimal text: text in the database's
+/// encoding, and numbers as UTF-8 text, as SQLite reads them as a blob.
+fn hex(value: &Value, encoding: TextEncoding) -> DbResult<Value> {
+    let bytes = match value {
+        Value::Text(text) => encoding.encode(text),
+        value => value.to_bytes(),
+    };
+    if bytes.len() > MAX_LENGTH / 2 {
+        return Err(DbError::TooBig);
+    }
+    Ok(Value::Text(to_hex(&bytes)))
+}
+
+/// A blob of `n` zero bytes.
+fn zeroblob(n: &Value) -> DbResult<Value> {
+    let len = match n.to_numeric() {
+        Value::Integer(i) => i.max(0) as usize,
+        Value::Real(r) => r.max(0.0) as usize,
+        _ => 0,
+    };
+    if len > MAX_LENGTH {
+        return Err(DbError::TooBig);
+    }
+    Ok(Value::Blob(vec![0; len]))
+}
+
+/// The first argument that isn't NULL, or NULL if they all are.
+fn coalesce(args: &[Value]) -> Value {
+    args.iter()
+        .find(|value| **value != Value::Null)
+        .cloned()
+        .unwrap_or(Value::Null)
+}
+
+/// NULL if the arguments are equal, otherwise the first argument.
+fn nullif(a: &Value, b: &Value) -> Value {
+    // Equality doesn't depend on the text encoding, only ordering does
+    if *b != Value::Null && a.compare(b, TextEncoding::default()).is_eq() {
+        Value::Null
+    } else {
+        a.clone()
+    }
+}
+
+/// The current UTC time as text, in a format made of `%F` (`YYYY-MM-DD`),
+/// `%T` (`HH:MM:SS`) and literal characters.
+fn current_time(format: &str) -> Value {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let time = seconds.rem_euclid(86400);
+    let text = format
+        .replace("%F", &format!("{:04}-{:02}-{:02}", year, month, day))
+        .replace(
+            "%T",
+            &format!("{:02}:{:02}:{:02}", time / 3600, time / 60 % 60, time % 60),
+        );
+    Value::Text(text)
+}
+
+/// Convert days since 1970-01-01 to a proleptic Gregorian (year, month, day),
+/// using Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::civil_from_days;
+
+    #[test]
+    fn converts_days_to_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+        assert_eq!(civil_from_days(20744), (2026, 10, 18));
+    }
+}
@@ -1,18 +1,34 @@
 //! Database header parsing for SQLite format.
 
-use anyhow::Result;
-
-use super::database::Database;
+use super::error::DbResult;
+use super::freelist::{Freelist, verify_freelist};
 use super::page::Page;
+use super::pager::Pager;
+
+/// Database information from the header and schema page.
+#[derive(Debug, Clone)]
+pub struct DbInfo {
+    pub page_size: u32,
+    pub number_of_tables: u16,
+    /// The free pages, checked not to be used by any B-tree.
+    pub freelist: Freelist,
+}
 
-/// Read database information including page size and number of tables.
-pub fn read_db_info(path: &str) -> Result<(u16, u16)> {
-    let mut db = Database::open(path)?;
-    let page_size = db.page_size as u16;
+/// Read database information including page size, number of tables and the freelist.
+pub fn read_db_info(pager: &mut Pager) -> DbResult<DbInfo> {
+    let db = pager.read()?;
+    let page_size = db.page_size as u32;
 
-    let page_data = db.read_page(1)?;
-    let page = Page::new(page_data, 1);
-    let number_of_tables = page.cell_count() as u16;
+    let info = db
+        .read_page(1)
+        .and_then(|data| Page::new(data, 1))
+        .and_then(|page| Ok((page, verify_freelist(db)?)));
+    pager.end_statement()?;
+    let (page, freelist) = info?;
 
-    Ok((page_size, number_of_tables))
+    Ok(DbInfo {
+        page_size,
+        number_of_tables: page.cell_count() as u16,
+        freelist,
+    })
 }
@@ -0,0 +1,101 @@
+//! INSERT execution.
+
+use crate::sql::ast::{ConflictAction, Insert, InsertSource};
+
+use super::database::Database;
+use super::error::{DbError, DbResult};
+use super::eval::{EmptyScope, eval};
+use super::query::{ColumnRef, QueryResult, execute_select};
+use super::table_write::{TableWriter, default_value};
+use super::value::Value;
+
+/// Insert the rows of an INSERT statement.
+pub fn execute_insert(db: &mut Database, insert: &Insert) -> DbResult<QueryResult> {
+    // Rows from a SELECT are read before the table changes
+    let rows = match &insert.source {
+        InsertSource::Values(rows) => rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|expr| eval(expr, &EmptyScope(db.encoding)))
+                    .collect()
+            })
+            .collect::<DbResult<Vec<Vec<Value>>>>()?,
+        InsertSource::Select(select) => execute_select(db, select)?.rows,
+        InsertSource::DefaultValues => vec![Vec::new()],
+    };
+
+    let writer = TableWriter::open(db, &insert.table)?;
+    let table = &writer.table;
+
+    // The column each supplied value goes to
+    let targets = match &insert.columns {
+        Some(names) => names
+            .iter()
+            .map(|name| {
+                table.resolve(name).ok_or_else(|| DbError::ColumnNotFound {
+                    table: table.entry.tbl_name.clone(),
+                    column: name.clone(),
+                })
+            })
+            .collect::<DbResult<Vec<_>>>()?,
+        None => (0..table.columns.len())
+            .map(|idx| match table.columns[idx].is_rowid_alias {
+                true => ColumnRef::Rowid,
+                false => ColumnRef::Column(idx),
+            })
+            .collect(),
+    };
+    if insert.source != InsertSource::DefaultValues {
+        for row in &rows {
+            if row.len() == targets.len() {
+                continue;
+            }
+            return Err(DbError::InvalidStatement(match insert.columns {
+                Some(_) => format!("{} values for {} columns", row.len(), targets.len()),
+                None => format!(
+                    "table {} has {} columns but {} values were supplied",
+                    table.entry.tbl_name,
+                    targets.len(),
+                    row.len()
+                ),
+            }));
+        }
+    }
+
+    for row in rows {
+        insert_row(db, &writer, &targets, row, insert.conflict)?;
+    }
+    Ok(QueryResult::default())
+}
+
+/// Insert one row, given the values for `targets`. Other columns take their defaults.
+fn insert_row(
+    db: &mut Database,
+    writer: &TableWriter,
+    targets: &[ColumnRef],
+    row: Vec<Value>,
+    conflict: ConflictAction,
+) -> DbResult<()> {
+    let columns = &writer.table.columns;
+    let mut given = vec![None; columns.len()];
+    let mut rowid = Value::Null;
+    for (target, value) in targets.iter().zip(row) {
+        match *target {
+            ColumnRef::Rowid => rowid = value,
+            ColumnRef::Column(idx) => given[idx] = Some(value),
+        }
+    }
+
+    let values = columns
+        .iter()
+        .zip(given)
+        .map(|(def, value)| match value {
+            Some(value) => Ok(value),
+            None if def.is_rowid_alias => Ok(Value::Null),
+            None => default_value(def, writer.table.encoding),
+        })
+        .collect::<DbResult<Vec<_>>>()?;
+    writer.insert(db, rowid, values, conflict)?;
+    Ok(())
+}
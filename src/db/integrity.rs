@@ -0,0 +1,570 @@
+//! PRAGMA integrity_check and quick_check: walking the freelist and every
+//! B-tree to check the file's structure, and comparing each index with
+//! the rows of its table.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use super::autovacuum::AutoVacuum;
+use super::btree::traverse_btree_index;
+use super::btree_write::{KeyColumn, compare_keys};
+use super::constants::{FREELIST_COUNT_OFFSET, FREELIST_TRUNK_OFFSET, LARGEST_ROOT_PAGE_OFFSET};
+use super::database::Database;
+use super::error::{DbResult, read_u32};
+use super::page::{Page, PageType, Record, corruption_reason};
+use super::ptrmap::{PtrmapEntry, PtrmapType, is_ptrmap_page, read_entry};
+use super::query::filter_rows;
+use super::schema::{SchemaEntry, check_schema_sql, read_schema};
+use super::table_write::TableWriter;
+use super::value::Value;
+
+/// How many problems are reported when the pragma doesn't say.
+pub const DEFAULT_MAX_ERRORS: usize = 100;
+
+/// Check the database, returning a description of each problem found, up
+/// to `max_errors` of them, or just "ok".
+///
+/// A quick check leaves out comparing indexes with their tables, which
+/// takes a search for every index entry of every row.
+pub fn check_integrity(db: &mut Database, max_errors: usize, quick: bool) -> DbResult<Vec<String>> {
+    let entries = read_schema(db)?;
+    check_schema_sql(&entries)?;
+    let tables: Vec<&SchemaEntry> = entries
+        .iter()
+        .filter(|e| e.entry_type == "table" && e.rootpage != 0)
+        .collect();
+
+    // Tables whose definitions can't be opened for writing, such as ones
+    // with indexes on expressions, still get their B-trees walked
+    let mut orders = HashMap::new();
+    for table in &tables {
+        if let Ok(writer) = TableWriter::open(db, &table.tbl_name) {
+            orders.extend(writer.key_orders());
+        }
+    }
+
+    let mut checker = Checker::new(db, max_errors)?;
+    checker.check_freelist()?;
+    // sqlite_schema is a table B-tree rooted at page 1
+    checker.check_tree(1, true, None)?;
+    for entry in entries.iter().filter(|e| e.rootpage != 0) {
+        let intkey = entry.entry_type == "table" && !entry.is_without_rowid();
+        checker.check_tree(entry.rootpage, intkey, orders.get(&entry.rootpage))?;
+    }
+    checker.check_largest_root(&entries)?;
+    checker.check_unused_pages();
+    for table in tables {
+        checker.check_table(table, &entries, quick)?;
+    }
+
+    match checker.errors.is_empty() {
+        true => Ok(vec!["ok".to_string()]),
+        false => Ok(checker.errors),
+    }
+}
+
+/// A B-tree being walked.
+struct Tree {
+    root: u32,
+    /// Whether this is a table B-tree keyed by rowid, rather than an index B-tree.
+    intkey: bool,
+    /// How keys are ordered: by rowid, or by the index's columns if known.
+    order: Option<Vec<KeyColumn>>,
+}
+
+/// The state of a check: which pages have been seen, and the problems found.
+struct Checker<'a> {
+    db: &'a mut Database,
+    page_count: u32,
+    /// The page holding the lock bytes, which is never used.
+    pending_page: u32,
+    /// Whether the database has pointer-map pages to check pages against.
+    auto_vacuum: bool,
+    /// Whether each page has been referenced, by page number.
+    referenced: Vec<bool>,
+    /// Whether the B-tree being walked has a page that couldn't be read or
+    /// was reached twice.
+    tree_unreadable: bool,
+    /// Root pages of the B-trees that can't be read safely, which aren't
+    /// compared as tables and indexes afterwards.
+    unreadable_trees: HashSet<u32>,
+    errors: Vec<String>,
+    max_errors: usize,
+}
+
+impl<'a> Checker<'a> {
+    fn new(db: &'a mut Database, max_errors: usize) -> DbResult<Self> {
+        // The pages that exist, which never run past the end of the file,
+        // whatever the header claims
+        let page_count = db.page_count();
+        let pending_page = db.pending_page();
+        let auto_vacuum = db.auto_vacuum()? != AutoVacuum::None;
+        let mut checker = Self {
+            db,
+            page_count,
+            pending_page,
+            auto_vacuum,
+            referenced: vec![false; page_count as usize + 1],
+            tree_unreadable: false,
+            unreadable_trees: HashSet::new(),
+            errors: Vec::new(),
+            max_errors,
+        };
+        if let Some(claimed) = checker.db.claimed_page_count() {
+            checker.error(format!(
+                "Page count in header ({}) exceeds the {} pages in the file",
+                claimed, page_count
+            ));
+        }
+        Ok(checker)
+    }
+
+    fn error(&mut self, message: String) {
+        if !self.done() {
+            self.errors.push(message);
+        }
+    }
+
+    fn done(&self) -> bool {
+        self.errors.len() >= self.max_errors
+    }
+
+    /// Record a reference to a page, reporting a page number outside the
+    /// database or a page referenced before. Returns whether the page is
+    /// safe to read.
+    fn reference(&mut self, page_num: u32, context: &str) -> bool {
+        if page_num == 0 || page_num > self.page_count {
+            self.error(format!("{}: invalid page number {}", context, page_num));
+            return false;
+        }
+        if std::mem::replace(&mut self.referenced[page_num as usize], true) {
+            self.error(format!("{}: 2nd reference to page {}", context, page_num));
+            return false;
+        }
+        true
+    }
+
+    /// With auto-vacuum, check that a page's pointer-map entry says what
+    /// uses it. Problems with the page number itself are reported elsewhere.
+    fn check_ptrmap(
+        &mut self,
+        page_num: u32,
+        kind: PtrmapType,
+        parent: u32,
+        context: Option<&str>,
+    ) {
+        if !self.auto_vacuum
+            || page_num < 2
+            || page_num > self.page_count
+            || is_ptrmap_page(self.db, page_num)
+        {
+            return;
+        }
+        let problem = match read_entry(self.db, page_num) {
+            Ok(entry) if entry == PtrmapEntry::new(kind, parent) => return,
+            Ok(entry) => format!(
+                "Bad ptr map entry key={} expected=({},{}) got=({},{})",
+                page_num, kind as u8, parent, entry.kind as u8, entry.parent
+            ),
+            Err(_) => format!("Failed to read ptrmap key={}", page_num),
+        };
+        match context {
+            Some(context) => self.error(format!("{}: {}", context, problem)),
+            None => self.error(problem),
+        }
+    }
+
+    /// With auto-vacuum, check that the header records the last root page.
+    fn check_largest_root(&mut self, entries: &[SchemaEntry]) -> DbResult<()> {
+        if !self.auto_vacuum {
+            return Ok(());
+        }
+        let largest = entries.iter().map(|e| e.rootpage).fold(1, u32::max);
+        let recorded = read_u32(&self.db.read_page(1)?, LARGEST_ROOT_PAGE_OFFSET)?;
+        if largest != recorded {
+            self.error(format!(
+                "max rootpage ({}) disagrees with header ({})",
+                largest, recorded
+            ));
+        }
+        Ok(())
+    }
+
+    /// Walk the freelist trunk chain, checking it against the header's count.
+    fn check_freelist(&mut self) -> DbResult<()> {
+        let header = self.db.read_page(1)?;
+        let mut trunk = read_u32(&header, FREELIST_TRUNK_OFFSET)?;
+        let expected = read_u32(&header, FREELIST_COUNT_OFFSET)?;
+        let max_leaves = self.db.usable_size() / 4 - 2;
+
+        let mut count = 0;
+        while trunk != 0 && self.reference(trunk, "Freelist") {
+            self.check_ptrmap(trunk, PtrmapType::FreePage, 0, Some("Freelist"));
+            count += 1;
+            let data = self.db.read_page(trunk)?;
+            let leaves = read_u32(&data, 4)? as usize;
+            if leaves > max_leaves {
+                self.error(format!(
+                    "Freelist: trunk page {} lists {} leaves",
+                    trunk, leaves
+                ));
+                break;
+            }
+            for i in 0..leaves {
+                let leaf = read_u32(&data, 8 + 4 * i)?;
+                if self.reference(leaf, "Freelist") {
+                    self.check_ptrmap(leaf, PtrmapType::FreePage, 0, Some("Freelist"));
+                }
+                count += 1;
+            }
+            trunk = read_u32(&data, 0)?;
+        }
+        if count != expected {
+            self.error(format!(
+                "Freelist: size is {} but should be {}",
+                count, expected
+            ));
+        }
+        Ok(())
+    }
+
+    /// Walk a B-tree.
+    fn check_tree(
+        &mut self,
+        root: u32,
+        intkey: bool,
+        order: Option<&Vec<KeyColumn>>,
+    ) -> DbResult<()> {
+        let order = match intkey {
+            true => Some(vec![KeyColumn::default()]),
+            false => order.cloned(),
+        };
+        let tree = Tree {
+            root,
+            intkey,
+            order,
+        };
+        self.tree_unreadable = false;
+        if self.reference_in_tree(root, &format!("Tree {}", root)) {
+            self.check_ptrmap(root, PtrmapType::RootPage, 0, None);
+            self.check_page(&tree, root, None, None)?;
+        }
+        if self.tree_unreadable {
+            self.unreadable_trees.insert(root);
+        }
+        Ok(())
+    }
+
+    /// Record a reference to a page of the B-tree being walked, which
+    /// can't be read safely if the page number is bad or seen before.
+    fn reference_in_tree(&mut self, page_num: u32, context: &str) -> bool {
+        let readable = self.reference(page_num, context);
+        self.tree_unreadable |= !readable;
+        readable
+    }
+
+    /// Check a B-tree page and the pages below it. Every key must fall
+    /// after `lower` and before `upper`, or on it in a table B-tree, where
+    /// keys equal to an interior cell's key sit on its left. Returns the
+    /// depth of the page's leaves, or `None` if the page couldn't be read.
+    fn check_page(
+        &mut self,
+        tree: &Tree,
+        page_num: u32,
+        lower: Option<&[Value]>,
+        upper: Option<&[Value]>,
+    ) -> DbResult<Option<usize>> {
+        if self.done() {
+            return Ok(None);
+        }
+        let context = format!("Tree {} page {}", tree.root, page_num);
+        let page = match Page::new(self.db.read_page(page_num)?, page_num) {
+            Ok(page) => page,
+            Err(err) => {
+                self.error(format!("{}: {}", context, corruption_reason(err)));
+                self.tree_unreadable = true;
+                return Ok(None);
+            }
+        };
+        let intkey = matches!(
+            page.page_type(),
+            PageType::InteriorTable | PageType::LeafTable
+        );
+        if intkey != tree.intkey {
+            self.error(format!(
+                "{}: {} page in {} B-tree",
+                context,
+                if intkey { "table" } else { "index" },
+                if tree.intkey { "a table" } else { "an index" },
+            ));
+            self.tree_unreadable = true;
+            return Ok(None);
+        }
+        for problem in page.space_problems(self.db.usable_size()) {
+            self.error(format!("{}: {}", context, problem));
+        }
+        let Ok(offsets) = page.cell_offsets() else {
+            self.tree_unreadable = true;
+            return Ok(None);
+        };
+
+        let mut depths = Vec::new();
+        let mut previous: Option<Vec<Value>> = lower.map(<[Value]>::to_vec);
+        for (i, &offset) in offsets.iter().enumerate() {
+            let cell_context = format!("{} cell {}", context, i);
+            let key = self.cell_key(&page, offset, &cell_context)?;
+
+            if page.is_interior() {
+                let child = page.left_child(offset)?;
+                if self.reference_in_tree(child, &cell_context) {
+                    self.check_ptrmap(child, PtrmapType::Btree, page_num, Some(&cell_context));
+                    let child_upper = key.as_deref().or(upper);
+                    depths.push(self.check_page(tree, child, previous.as_deref(), child_upper)?);
+                }
+            }
+            if let Some(key) = key {
+                if !self.in_order(tree, previous.as_deref(), &key, upper) {
+                    let what = match &key[..] {
+                        [Value::Integer(rowid)] if tree.intkey => format!("Rowid {}", rowid),
+                        _ => "Entry".to_string(),
+                    };
+                    self.error(format!("{}: {} out of order", cell_context, what));
+                }
+                previous = Some(key);
+            }
+        }
+        if let Some(rightmost) = page.rightmost_pointer() {
+            let context = format!("{} right child", context);
+            if self.reference_in_tree(rightmost, &context) {
+                self.check_ptrmap(rightmost, PtrmapType::Btree, page_num, Some(&context));
+                depths.push(self.check_page(tree, rightmost, previous.as_deref(), upper)?);
+            }
+        }
+
+        let depths: Vec<usize> = depths.into_iter().flatten().collect();
+        if depths.windows(2).any(|pair| pair[0] != pair[1]) {
+            self.error(format!("{}: Child page depth differs", context));
+        }
+        Ok(Some(depths.first().map_or(1, |depth| depth + 1)))
+    }
+
+    /// Check that a key comes after `lower` and before `upper`, or on it in a table B-tree.
+    fn in_order(
+        &self,
+        tree: &Tree,
+        lower: Option<&[Value]>,
+        key: &[Value],
+        upper: Option<&[Value]>,
+    ) -> bool {
+        let Some(order) = &tree.order else {
+            return true;
+        };
+        let encoding = self.db.encoding;
+        let after_lower = lower
+            .is_none_or(|lower| compare_keys(key, lower, order, encoding) == Ordering::Greater);
+        let before_upper =
+            upper.is_none_or(|upper| match compare_keys(key, upper, order, encoding) {
+                Ordering::Less => true,
+                Ordering::Equal => tree.intkey,
+                Ordering::Greater => false,
+            });
+        after_lower && before_upper
+    }
+
+    /// Read the key of a cell: its rowid in a table B-tree, or its record in
+    /// an index B-tree. Checks the cell's overflow chain on the way, which
+    /// must have just enough pages for the payload. Returns `None` if the
+    /// key couldn't be read.
+    fn cell_key(
+        &mut self,
+        page: &Page,
+        offset: usize,
+        context: &str,
+    ) -> DbResult<Option<Vec<Value>>> {
+        if page.page_type() == PageType::InteriorTable {
+            return match page.parse_interior_cell(offset) {
+                Ok((_, rowid)) => Ok(Some(vec![Value::Integer(rowid)])),
+                Err(err) => {
+                    self.error(format!("{}: {}", context, corruption_reason(err)));
+                    Ok(None)
+                }
+            };
+        }
+        let usable_size = self.db.usable_size();
+        let cell = match page.cell_payload(offset, usable_size) {
+            Ok(cell) => cell,
+            Err(err) => {
+                self.error(format!("{}: {}", context, corruption_reason(err)));
+                return Ok(None);
+            }
+        };
+        let rowid = cell.rowid;
+        let payload_size = cell.payload_size;
+        let mut payload = cell.local.to_vec();
+
+        if let Some(first) = cell.overflow_page {
+            let expected = (payload_size - payload.len()).div_ceil(usable_size - 4);
+            let mut next = first;
+            let mut length = 0;
+            let mut pointer = (PtrmapType::Overflow1, page.page_num());
+            while next != 0 && length < expected && self.reference(next, context) {
+                self.check_ptrmap(next, pointer.0, pointer.1, Some(context));
+                pointer = (PtrmapType::Overflow2, next);
+                let data = self.db.read_page(next)?;
+                let chunk = (payload_size - payload.len()).min(usable_size - 4);
+                payload.extend_from_slice(&data[4..4 + chunk]);
+                length += 1;
+                next = read_u32(&data, 0)?;
+            }
+            if length < expected || next != 0 {
+                self.error(format!(
+                    "{}: overflow list length is {} but should be {}",
+                    context,
+                    if next != 0 { length + 1 } else { length },
+                    expected
+                ));
+            }
+        }
+
+        if let Some(rowid) = rowid {
+            return Ok(Some(vec![Value::Integer(rowid)]));
+        }
+        if payload.len() < payload_size {
+            return Ok(None);
+        }
+        match Record::parse(&payload, 0, self.db.encoding) {
+            Ok(record) => Ok(Some(
+                (0..record.column_count())
+                    .map(|i| record.read_value(i))
+                    .collect(),
+            )),
+            Err(err) => {
+                self.error(format!("{}: {}", context, corruption_reason(err)));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Report every page neither a B-tree nor the freelist uses, other than
+    /// pointer-map pages, which nothing may point at.
+    fn check_unused_pages(&mut self) {
+        for page_num in 1..=self.page_count {
+            let referenced = self.referenced[page_num as usize];
+            let ptrmap = self.auto_vacuum && is_ptrmap_page(self.db, page_num);
+            if !referenced && !ptrmap && page_num != self.pending_page {
+                self.error(format!("Page {}: never used", page_num));
+            }
+            if referenced && ptrmap {
+                self.error(format!("Page {}: pointer map referenced", page_num));
+            }
+        }
+    }
+
+    /// Check a table's rows: NOT NULL columns must have values and, unless
+    /// the check is quick, each index must hold an entry for every row it
+    /// indexes and no more. Like building an index, this sorts the entries
+    /// the rows call for, rather than searching the index for each one.
+    /// Trees whose pages couldn't all be reached are left out, as reading
+    /// them as a table or index would only report the same problem again.
+    fn check_table(
+        &mut self,
+        table: &SchemaEntry,
+        entries: &[SchemaEntry],
+        quick: bool,
+    ) -> DbResult<()> {
+        if self.done() || self.unreadable_trees.contains(&table.rootpage) {
+            return Ok(());
+        }
+        let Ok(writer) = TableWriter::open(self.db, &table.tbl_name) else {
+            return Ok(());
+        };
+        let records = match filter_rows(self.db, &writer.table, None) {
+            Ok(records) => records,
+            Err(err) => {
+                // The error names the page it found corrupt
+                self.error(format!("Tree {}: {}", table.rootpage, err));
+                return Ok(());
+            }
+        };
+
+        // The entries each index should hold, with the rowid of their row, by root page
+        let mut wanted: HashMap<u32, Vec<(i64, Vec<Value>)>> = HashMap::new();
+        for record in &records {
+            let row = writer.row_from_record(record);
+            for (def, value) in writer.table.columns.iter().zip(&row.values) {
+                if def.not_null && *value == Value::Null {
+                    self.error(format!("NULL value in {}.{}", table.tbl_name, def.name));
+                }
+            }
+            if quick {
+                continue;
+            }
+            match writer.index_entries(&row) {
+                Ok(index_entries) => {
+                    for (index, entry) in index_entries {
+                        wanted
+                            .entry(index.rootpage)
+                            .or_default()
+                            .push((row.rowid, entry));
+                    }
+                }
+                Err(err) => self.error(format!("Tree {}: {}", table.rootpage, err)),
+            }
+        }
+        if quick {
+            return Ok(());
+        }
+
+        let orders: HashMap<u32, Vec<KeyColumn>> = writer.key_orders().into_iter().collect();
+        let encoding = self.db.encoding;
+        let indexes: Vec<&SchemaEntry> = entries
+            .iter()
+            .filter(|e| e.is_index() && e.tbl_name.eq_ignore_ascii_case(&table.tbl_name))
+            .filter(|e| !self.unreadable_trees.contains(&e.rootpage))
+            .collect();
+        for index in indexes {
+            let Some(order) = orders.get(&index.rootpage) else {
+                continue;
+            };
+            let compare = |a: &[Value], b: &[Value]| compare_keys(a, b, order, encoding);
+            let mut records = Vec::new();
+            if let Err(err) = traverse_btree_index(self.db, index.rootpage, &mut records) {
+                self.error(format!("Tree {}: {}", index.rootpage, err));
+                continue;
+            }
+            let mut actual: Vec<Vec<Value>> = records
+                .iter()
+                .map(|record| {
+                    (0..record.column_count())
+                        .map(|i| record.read_value(i))
+                        .collect()
+                })
+                .collect();
+            actual.sort_by(|a, b| compare(a, b));
+            let mut expected = wanted.remove(&index.rootpage).unwrap_or_default();
+            expected.sort_by(|(_, a), (_, b)| compare(a, b));
+
+            let mut missing = Vec::new();
+            let mut position = 0;
+            for (rowid, entry) in &expected {
+                while position < actual.len() && compare(&actual[position], entry).is_lt() {
+                    position += 1;
+                }
+                if position < actual.len() && compare(&actual[position], entry).is_eq() {
+                    position += 1;
+                } else {
+                    missing.push(*rowid);
+                }
+            }
+            // Report missing rows in table order, as a row-by-row check would
+            missing.sort_unstable();
+            for rowid in missing {
+                self.error(format!("row {} missing from index {}", rowid, index.name));
+            }
+            if expected.len() != actual.len() {
+                self.error(format!("wrong # of entries in index {}", index.name));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,30 @@
+//! Interrupting a running statement, from a signal handler or another
+//! thread, like `sqlite3_interrupt`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::error::{DbError, DbResult};
+
+/// Set when running statements should stop.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Make running statements fail with an "interrupted" error the next time
+/// they read a page, until the interrupt is cleared. Only sets a flag, so
+/// signal handlers may call it.
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+/// Let statements run again after an interrupt.
+pub fn clear_interrupt() {
+    INTERRUPTED.store(false, Ordering::Relaxed);
+}
+
+/// Fail if statements have been interrupted.
+pub(super) fn check_interrupt() -> DbResult<()> {
+    if INTERRUPTED.load(Ordering::Relaxed) {
+        Err(DbError::Interrupted)
+    } else {
+        Ok(())
+    }
+}
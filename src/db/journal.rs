@@ -0,0 +1,170 @@
+//! The rollback journal, which makes commits to a database file atomic.
+//!
+//! Before a commit overwrites any page, the page's original contents are
+//! saved to a `-journal` file next to the database, in the format SQLite
+//! uses. A journal left behind by an interrupted commit is "hot": the next
+//! connection to read the database copies the saved pages back, undoing
+//! the partial commit.
+
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Seek, SeekFrom, Write};
+
+use super::error::{DbResult, read_u32};
+
+/// Every journal header starts with these bytes.
+const JOURNAL_MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+
+/// Journal headers are padded to a whole sector, so that writing one never
+/// tears a page record.
+const SECTOR_SIZE: usize = 512;
+
+/// A journal header's record count of all ones means "until the end of the file".
+const RECORDS_TO_END: u32 = u32::MAX;
+
+/// The rollback journal of the database at `path`.
+pub fn journal_path(path: &str) -> String {
+    format!("{}-journal", path)
+}
+
+/// Checksum of a journaled page: every 200th byte, counting back from the
+/// end, added to the journal's nonce.
+fn page_checksum(nonce: u32, data: &[u8]) -> u32 {
+    let mut checksum = nonce;
+    let mut i = data.len() as isize - 200;
+    while i > 0 {
+        checksum = checksum.wrapping_add(data[i as usize] as u32);
+        i -= 200;
+    }
+    checksum
+}
+
+/// Write a journal holding the original contents of the pages a commit is
+/// about to change, and sync it to disk.
+pub fn write_journal(
+    path: &str,
+    page_size: usize,
+    page_count: u32,
+    pages: &[(u32, Vec<u8>)],
+) -> DbResult<()> {
+    let nonce = RandomState::new().build_hasher().finish() as u32;
+
+    let mut journal = Vec::with_capacity(SECTOR_SIZE + pages.len() * (page_size + 8));
+    journal.extend_from_slice(&JOURNAL_MAGIC);
+    journal.extend_from_slice(&(pages.len() as u32).to_be_bytes());
+    journal.extend_from_slice(&nonce.to_be_bytes());
+    journal.extend_from_slice(&page_count.to_be_bytes());
+    journal.extend_from_slice(&(SECTOR_SIZE as u32).to_be_bytes());
+    journal.extend_from_slice(&(page_size as u32).to_be_bytes());
+    journal.resize(SECTOR_SIZE, 0);
+    for (page_num, data) in pages {
+        journal.extend_from_slice(&page_num.to_be_bytes());
+        journal.extend_from_slice(data);
+        journal.extend_from_slice(&page_checksum(nonce, data).to_be_bytes());
+    }
+
+    let mut file = File::create(path)?;
+    file.write_all(&journal)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Check if the database at `path` has a journal that isn't empty. Unless
+/// another connection is writing, such a journal is hot.
+pub fn has_journal(path: &str) -> DbResult<bool> {
+    match fs::metadata(journal_path(path)) {
+        Ok(metadata) => Ok(metadata.len() > 0),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Roll back an interrupted commit by copying the pages saved in a hot
+/// journal back into the database file `db`, then delete the journal. The
+/// caller must hold an exclusive lock on the database.
+///
+/// Journals written by SQLite may hold several segments, each with its own
+/// header at a sector boundary. Replay stops at the first record whose
+/// checksum doesn't match, as it was never completely written.
+pub fn recover(path: &str, db: &mut File) -> DbResult<()> {
+    let journal_path = journal_path(path);
+    let journal = match fs::read(&journal_path) {
+        Ok(journal) => journal,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    // An empty or zeroed journal belongs to a finished transaction
+    if !journal.starts_with(&JOURNAL_MAGIC) {
+        return Ok(());
+    }
+
+    // A journal naming a super-journal that no longer exists is from a
+    // multi-database transaction that committed
+    if let Some(super_journal) = super_journal_name(&journal)
+        && !super_journal.is_empty()
+        && !fs::exists(&super_journal)?
+    {
+        fs::remove_file(&journal_path)?;
+        return Ok(());
+    }
+
+    let mut original_size = None;
+    let mut offset = 0;
+    'segments: while journal.get(offset..offset + 8) == Some(&JOURNAL_MAGIC[..]) {
+        let mut records = read_u32(&journal, offset + 8)?;
+        let nonce = read_u32(&journal, offset + 12)?;
+        let page_count = read_u32(&journal, offset + 16)?;
+        let sector_size = read_u32(&journal, offset + 20)? as usize;
+        let page_size = read_u32(&journal, offset + 24)? as usize;
+        if !sector_size.is_power_of_two()
+            || !(32..=65536).contains(&sector_size)
+            || !page_size.is_power_of_two()
+            || !(512..=65536).contains(&page_size)
+        {
+            break;
+        }
+        let page_count = original_size.get_or_insert((page_count, page_size)).0;
+
+        offset += sector_size;
+        let record_size = page_size + 8;
+        if records == RECORDS_TO_END {
+            records = (journal.len().saturating_sub(offset) / record_size) as u32;
+        }
+        for _ in 0..records {
+            let Some(record) = journal.get(offset..offset + record_size) else {
+                break 'segments;
+            };
+            let page_num = read_u32(record, 0)?;
+            let data = &record[4..4 + page_size];
+            if read_u32(record, 4 + page_size)? != page_checksum(nonce, data) {
+                break 'segments;
+            }
+            // Pages past the original end are cut off below anyway
+            if page_num != 0 && page_num <= page_count {
+                db.seek(SeekFrom::Start((page_num as u64 - 1) * page_size as u64))?;
+                db.write_all(data)?;
+            }
+            offset += record_size;
+        }
+        offset = offset.next_multiple_of(sector_size);
+    }
+
+    if let Some((page_count, page_size)) = original_size {
+        db.set_len(page_count as u64 * page_size as u64)?;
+    }
+    db.sync_all()?;
+    fs::remove_file(&journal_path)?;
+    Ok(())
+}
+
+/// The super-journal name recorded at the end of a journal, if it has one.
+fn super_journal_name(journal: &[u8]) -> Option<String> {
+    let end = journal.len().checked_sub(16)?;
+    if journal[end + 8..] != JOURNAL_MAGIC {
+        return None;
+    }
+    let len = read_u32(journal, end).ok()? as usize;
+    let start = end.checked_sub(len)?;
+    String::from_utf8(journal[start..end].to_vec()).ok()
+}
@@ -0,0 +1,200 @@
+//! Advisory file locks, taken the way SQLite takes them on Unix so that this
+//! library and sqlite3 processes can safely share a database.
+//!
+//! A database file is locked with `fcntl` locks on bytes of the page that
+//! starts at [`PENDING_BYTE`], which never holds database content:
+//!
+//! - SHARED, held while reading, is a read lock on the shared range.
+//! - RESERVED, held by the one connection preparing to write, is a write
+//!   lock on the reserved byte. Readers can still start.
+//! - PENDING is a write lock on the pending byte, which keeps new readers
+//!   out while a writer waits for the existing ones to finish.
+//! - EXCLUSIVE, needed to change the file, is a write lock on the shared range.
+//!
+//! `fcntl` locks belong to the process, and closing any descriptor of a
+//! file drops every lock the process holds on it. A process must therefore
+//! keep each file open through one handle only.
+
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::constants::PENDING_BYTE;
+use super::error::{DbError, DbResult};
+
+/// Byte locked by the connection that is preparing to write.
+const RESERVED_BYTE: u64 = PENDING_BYTE + 1;
+
+/// First byte of the range readers lock.
+const SHARED_FIRST: u64 = PENDING_BYTE + 2;
+
+/// Number of bytes in the range readers lock.
+const SHARED_SIZE: u64 = 510;
+
+/// How strongly a connection has locked a database file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockLevel {
+    #[default]
+    None,
+    Shared,
+    Reserved,
+    Pending,
+    Exclusive,
+}
+
+/// A kind of `fcntl` byte-range lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeLock {
+    Read,
+    Write,
+    Unlock,
+}
+
+/// The lock a connection holds on a database file.
+#[derive(Debug, Default)]
+pub struct FileLock {
+    level: LockLevel,
+}
+
+impl FileLock {
+    pub fn level(&self) -> LockLevel {
+        self.level
+    }
+
+    /// Raise the lock to at least `level` without waiting, going through
+    /// the levels in between. Returns false if another connection's lock is
+    /// in the way; a connection that fails to reach EXCLUSIVE keeps PENDING,
+    /// so that it gets in once the current readers finish.
+    pub fn lock(&mut self, file: &File, level: LockLevel) -> io::Result<bool> {
+        if self.level >= level {
+            return Ok(true);
+        }
+        if self.level == LockLevel::None {
+            // Taking the pending byte for a moment makes sure no writer is
+            // waiting for readers to leave
+            if !lock_range(file, RangeLock::Read, PENDING_BYTE, 1)? {
+                return Ok(false);
+            }
+            let locked = lock_range(file, RangeLock::Read, SHARED_FIRST, SHARED_SIZE)?;
+            lock_range(file, RangeLock::Unlock, PENDING_BYTE, 1)?;
+            if !locked {
+                return Ok(false);
+            }
+            self.level = LockLevel::Shared;
+        }
+        if level >= LockLevel::Reserved && self.level < LockLevel::Reserved {
+            if !lock_range(file, RangeLock::Write, RESERVED_BYTE, 1)? {
+                return Ok(false);
+            }
+            self.level = LockLevel::Reserved;
+        }
+        if level >= LockLevel::Pending && self.level < LockLevel::Pending {
+            if !lock_range(file, RangeLock::Write, PENDING_BYTE, 1)? {
+                return Ok(false);
+            }
+            self.level = LockLevel::Pending;
+        }
+        if level == LockLevel::Exclusive {
+            if !lock_range(file, RangeLock::Write, SHARED_FIRST, SHARED_SIZE)? {
+                return Ok(false);
+            }
+            self.level = LockLevel::Exclusive;
+        }
+        Ok(true)
+    }
+
+    /// Lower the lock to SHARED or drop it altogether.
+    pub fn unlock(&mut self, file: &File, level: LockLevel) -> io::Result<()> {
+        if self.level <= level {
+            return Ok(());
+        }
+        if level == LockLevel::Shared {
+            if self.level == LockLevel::Exclusive {
+                lock_range(file, RangeLock::Read, SHARED_FIRST, SHARED_SIZE)?;
+            }
+            // The pending and reserved bytes are next to each other
+            lock_range(file, RangeLock::Unlock, PENDING_BYTE, 2)?;
+        } else {
+            // A length of zero covers the whole file
+            lock_range(file, RangeLock::Unlock, 0, 0)?;
+        }
+        self.level = level;
+        Ok(())
+    }
+
+    /// Check if another connection is preparing to write or writing, in
+    /// which case a rollback journal is in use rather than left behind.
+    pub fn is_reserved_elsewhere(&self, file: &File) -> io::Result<bool> {
+        if self.level >= LockLevel::Reserved {
+            return Ok(false);
+        }
+        is_range_locked(file, RangeLock::Write, RESERVED_BYTE, 1)
+    }
+}
+
+/// Lock or unlock a byte range of a file without waiting. Returns false if
+/// another process holds a conflicting lock.
+pub fn lock_range(file: &File, kind: RangeLock, start: u64, len: u64) -> io::Result<bool> {
+    let mut lock = flock(kind, start, len);
+    // SAFETY: the descriptor stays open while `file` is borrowed, and `lock`
+    // is a valid lock description that outlives the call
+    let result = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETLK, &mut lock) };
+    if result == 0 {
+        return Ok(true);
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EAGAIN) | Some(libc::EACCES) => Ok(false),
+        _ => Err(err),
+    }
+}
+
+/// Check if another process holds a lock on a byte range that would
+/// conflict with taking a lock of the given kind.
+pub fn is_range_locked(file: &File, kind: RangeLock, start: u64, len: u64) -> io::Result<bool> {
+    let mut lock = flock(kind, start, len);
+    // SAFETY: as in `lock_range`; F_GETLK only writes to `lock`
+    let result = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETLK, &mut lock) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(lock.l_type != libc::F_UNLCK as libc::c_short)
+}
+
+fn flock(kind: RangeLock, start: u64, len: u64) -> libc::flock {
+    // SAFETY: flock is plain data, for which all zero bytes are valid
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = match kind {
+        RangeLock::Read => libc::F_RDLCK,
+        RangeLock::Write => libc::F_WRLCK,
+        RangeLock::Unlock => libc::F_UNLCK,
+    } as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+    lock.l_start = start as libc::off_t;
+    lock.l_len = len as libc::off_t;
+    lock
+}
+
+/// Retry `attempt` until it succeeds, sleeping a little longer after each
+/// failure like SQLite's busy handler, or fail with [`DbError::Busy`] once
+/// `timeout` has passed.
+pub fn wait_for_lock(
+    timeout: Duration,
+    mut attempt: impl FnMut() -> DbResult<bool>,
+) -> DbResult<()> {
+    const DELAYS_MS: [u64; 12] = [1, 2, 5, 10, 15, 20, 25, 25, 25, 50, 50, 100];
+    let start = Instant::now();
+    for delay in DELAYS_MS.iter().chain(std::iter::repeat(&100)) {
+        if attempt()? {
+            return Ok(());
+        }
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            return Err(DbError::Busy);
+        }
+        thread::sleep(Duration::from_millis(*delay).min(timeout - elapsed));
+    }
+    unreachable!("the delays never run out")
+}
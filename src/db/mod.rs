@@ -1,13 +1,49 @@
 //! SQLite database file parsing and manipulation.
 
+mod affinity;
+mod alter;
+mod autovacuum;
+mod btree;
+mod btree_write;
+mod collation;
 mod constants;
+mod create;
 mod database;
+mod delete;
+mod drop;
+mod encoding;
+mod error;
+mod eval;
+mod freelist;
+mod functions;
 mod header;
+mod insert;
+mod integrity;
+mod interrupt;
+mod journal;
+mod lock;
+mod pager;
+mod pragma;
+mod ptrmap;
+mod query;
+mod schema_write;
+mod table_write;
+mod update;
+mod vacuum;
+mod value;
 mod varint;
+mod wal;
 
 pub mod page;
 pub mod schema;
 
 // Re-export public API
+pub use alter::identifier_sql;
+pub use collation::{CollationFn, register_collation};
+pub use error::{DbError, DbResult};
 pub use header::read_db_info;
-pub use schema::{count_table_rows, read_table_names, select_columns, select_columns_with_filter};
+pub use interrupt::{clear_interrupt, interrupt};
+pub use pager::Pager;
+pub use query::{QueryResult, execute};
+pub use schema::read_schema_entries;
+pub use value::Value;
@@ -1,7 +1,8 @@
 //! Page and record parsing for SQLite database format.
 
+#[allow(clippy::module_inception)]
 mod page;
 mod record;
 
-pub use page::Page;
-pub use record::{Record, parse_index_cell};
+pub use page::{Page, PageType, build_page, cells_fit, corruption_reason, local_payload_size};
+pub use record::{Record, encode_record, index_cell_rowid};
@@ -1,6 +1,7 @@
 //! Page parsing utilities for SQLite database format.
 
 use crate::db::constants::{CELL_COUNT_OFFSET, PAGE1_HEADER_OFFSET};
+use crate::db::error::{DbError, DbResult, read_u16, read_u32, slice, write_u16, write_u32};
 use crate::db::varint::read_varint;
 
 /// Page type constants from SQLite documentation
@@ -9,6 +10,10 @@ const INTERIOR_TABLE_BTREE_PAGE: u8 = 0x05;
 const LEAF_INDEX_BTREE_PAGE: u8 = 0x0a;
 const LEAF_TABLE_BTREE_PAGE: u8 = 0x0d;
 
+/// Interior pages have a 12-byte header, leaf pages have an 8-byte header
+const INTERIOR_HEADER_SIZE: usize = 12;
+const LEAF_HEADER_SIZE: usize = 8;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PageType {
     InteriorIndex,
@@ -17,16 +22,39 @@ pub enum PageType {
     LeafTable,
 }
 
+impl PageType {
+    /// The type byte stored at the start of the page header.
+    fn to_byte(self) -> u8 {
+        match self {
+            PageType::InteriorIndex => INTERIOR_INDEX_BTREE_PAGE,
+            PageType::InteriorTable => INTERIOR_TABLE_BTREE_PAGE,
+            PageType::LeafIndex => LEAF_INDEX_BTREE_PAGE,
+            PageType::LeafTable => LEAF_TABLE_BTREE_PAGE,
+        }
+    }
+
+    /// Size of the page header for pages of this type.
+    fn header_size(self) -> usize {
+        match self {
+            PageType::InteriorIndex | PageType::InteriorTable => INTERIOR_HEADER_SIZE,
+            PageType::LeafIndex | PageType::LeafTable => LEAF_HEADER_SIZE,
+        }
+    }
+}
+
 /// A SQLite database page.
 pub struct Page {
     data: Vec<u8>,
+    page_num: u32,
     header_offset: usize,
     page_type: PageType,
 }
 
 impl Page {
     /// Create a new Page from raw data.
-    pub fn new(data: Vec<u8>, page_num: u32) -> Self {
+    ///
+    /// Fails if the page type byte is unknown or the page header doesn't fit.
+    pub fn new(data: Vec<u8>, page_num: u32) -> DbResult<Self> {
         let header_offset = if page_num == 1 {
             PAGE1_HEADER_OFFSET
         } else {
@@ -34,20 +62,38 @@ impl Page {
         };
 
         // Read page type from first byte of page header
-        let page_type_byte = data[header_offset];
+        let page_type_byte = *data.get(header_offset).ok_or(DbError::CorruptPage {
+            page: page_num,
+            reason: "page is too small to hold a page header".to_string(),
+        })?;
         let page_type = match page_type_byte {
             INTERIOR_INDEX_BTREE_PAGE => PageType::InteriorIndex,
             INTERIOR_TABLE_BTREE_PAGE => PageType::InteriorTable,
             LEAF_INDEX_BTREE_PAGE => PageType::LeafIndex,
             LEAF_TABLE_BTREE_PAGE => PageType::LeafTable,
-            _ => panic!("Unknown page type: {}", page_type_byte),
+            _ => {
+                return Err(DbError::UnknownPageType {
+                    page: page_num,
+                    page_type: page_type_byte,
+                });
+            }
         };
 
-        Self {
+        let page = Self {
             data,
+            page_num,
             header_offset,
             page_type,
+        };
+        if page.cell_pointer_array_offset() > page.data.len() {
+            return Err(page.corrupt("page is too small to hold a page header"));
         }
+        Ok(page)
+    }
+
+    /// Get the page's number in the database file.
+    pub fn page_num(&self) -> u32 {
+        self.page_num
     }
 
     /// Get the raw page data.
@@ -62,16 +108,30 @@ impl Page {
     }
 
     /// Get cell offsets from the cell pointer array.
-    pub fn cell_offsets(&self) -> Vec<usize> {
+    ///
+    /// Fails if the array runs past the end of the page or a pointer falls
+    /// outside the cell content area.
+    pub fn cell_offsets(&self) -> DbResult<Vec<usize>> {
         let num_cells = self.cell_count();
-        // Interior pages have a 12-byte header, leaf pages have an 8-byte header
-        let header_size = if self.is_interior() { 12 } else { 8 };
-        let array_offset = self.header_offset + header_size;
+        let array_offset = self.cell_pointer_array_offset();
+        let array_end = array_offset + num_cells * 2;
+        if array_end > self.data.len() {
+            return Err(self.corrupt(format!(
+                "cell pointer array for {} cells runs past the end of the page",
+                num_cells
+            )));
+        }
 
         (0..num_cells)
             .map(|i| {
-                let pos = array_offset + i * 2;
-                u16::from_be_bytes([self.data[pos], self.data[pos + 1]]) as usize
+                let offset = read_u16(&self.data, array_offset + i * 2)? as usize;
+                if offset < array_end || offset >= self.data.len() {
+                    return Err(self.corrupt(format!(
+                        "cell {} has offset {} outside the cell content area",
+                        i, offset
+                    )));
+                }
+                Ok(offset)
             })
             .collect()
     }
@@ -108,70 +168,373 @@ impl Page {
     }
 
     /// Get the page type.
-    #[allow(dead_code)]
     pub fn page_type(&self) -> PageType {
         self.page_type
     }
 
     /// Parse a cell from an interior table page and return the left child pointer.
     /// Interior cells contain: left_child_pointer (4 bytes) + key (varint)
-    pub fn parse_interior_cell(&self, cell_offset: usize) -> (u32, i64) {
-        // Read 4-byte page number of left child
-        let left_child = u32::from_be_bytes([
-            self.data[cell_offset],
-            self.data[cell_offset + 1],
-            self.data[cell_offset + 2],
-            self.data[cell_offset + 3],
-        ]);
+    pub fn parse_interior_cell(&self, cell_offset: usize) -> DbResult<(u32, i64)> {
+        let left_child = self.left_child(cell_offset)?;
 
         // Read the key (rowid) as a varint
-        let (key, _) = read_varint(&self.data, cell_offset + 4);
+        let (key, _) = read_varint(&self.data, cell_offset + 4)?;
+
+        Ok((left_child, key as i64))
+    }
 
-        (left_child, key as i64)
+    /// Read the left child page pointer of a cell on an interior page.
+    pub fn left_child(&self, cell_offset: usize) -> DbResult<u32> {
+        read_u32(&self.data, cell_offset)
     }
 
-    /// Parse a cell from an interior index page.
-    /// Returns (left_child_page, key_value)
-    pub fn parse_interior_index_cell(&self, cell_offset: usize) -> Result<(u32, String), String> {
+    /// Locate the payload of a cell on a leaf page or an interior index page.
+    ///
+    /// Only the part of the payload stored on this page is returned; the rest
+    /// lives in the overflow chain starting at `overflow_page`.
+    pub fn cell_payload(
+        &self,
+        cell_offset: usize,
+        usable_size: usize,
+    ) -> DbResult<CellPayload<'_>> {
+        if self.page_type == PageType::InteriorTable {
+            return Err(self.corrupt("interior table cells have no payload"));
+        }
+
         let mut pos = cell_offset;
 
-        // Read 4-byte page number of left child
-        let left_child = u32::from_be_bytes([
-            self.data[pos],
-            self.data[pos + 1],
-            self.data[pos + 2],
-            self.data[pos + 3],
-        ]);
-        pos += 4;
-
-        // Read payload size
-        let (_payload_size, bytes_read) = read_varint(&self.data, pos);
-        pos += bytes_read;
+        // Interior index cells start with the 4-byte left child pointer
+        if self.page_type == PageType::InteriorIndex {
+            pos += 4;
+        }
 
-        // Parse record header
-        let record_start = pos;
-        let (header_size, bytes_read) = read_varint(&self.data, pos);
+        let (payload_size, bytes_read) = read_varint(&self.data, pos)?;
         pos += bytes_read;
+        let payload_size = payload_size as usize;
+
+        // Only table leaf cells carry a rowid
+        let rowid = if self.page_type == PageType::LeafTable {
+            let (rowid, bytes_read) = read_varint(&self.data, pos)?;
+            pos += bytes_read;
+            Some(rowid as i64)
+        } else {
+            None
+        };
+
+        let local_size = local_payload_size(
+            payload_size,
+            usable_size,
+            self.page_type == PageType::LeafTable,
+        );
+        let local = slice(&self.data, pos, local_size)?;
+
+        let (overflow_page, cell_size) = if local_size < payload_size {
+            let overflow_page = read_u32(&self.data, pos + local_size)?;
+            (Some(overflow_page), pos + local_size + 4 - cell_offset)
+        } else {
+            (None, pos + local_size - cell_offset)
+        };
+
+        Ok(CellPayload {
+            rowid,
+            payload_size,
+            local,
+            overflow_page,
+            cell_size,
+        })
+    }
+
+    /// Copy out the raw bytes of every cell on the page, in order.
+    pub fn cells(&self, usable_size: usize) -> DbResult<Vec<Vec<u8>>> {
+        self.cell_offsets()?
+            .into_iter()
+            .map(|offset| {
+                let size = self.cell_size(offset, usable_size)?;
+                Ok(slice(&self.data, offset, size)?.to_vec())
+            })
+            .collect()
+    }
+
+    /// Remove cell `index` from the page, adding the space it used to the
+    /// free block list.
+    ///
+    /// As in SQLite, the freed space joins the free blocks right before and
+    /// after it, absorbing fragmented bytes of under four bytes between them,
+    /// and space freed at the start of the cell content area goes back to
+    /// the unallocated area instead.
+    pub fn remove_cell(&mut self, index: usize, usable_size: usize) -> DbResult<()> {
+        let offsets = self.cell_offsets()?;
+        let Some(&offset) = offsets.get(index) else {
+            return Err(self.corrupt(format!("no cell {} to remove", index)));
+        };
+        let size = self.cell_size(offset, usable_size)?;
+
+        let array_offset = self.cell_pointer_array_offset();
+        let array_end = array_offset + offsets.len() * 2;
+        self.data.copy_within(
+            array_offset + (index + 1) * 2..array_end,
+            array_offset + index * 2,
+        );
+        self.data[array_end - 2..array_end].fill(0);
+        write_u16(
+            &mut self.data,
+            self.header_offset + CELL_COUNT_OFFSET,
+            offsets.len() as u16 - 1,
+        )?;
+        self.free_space(offset, size, usable_size)
+    }
+
+    /// Add `size` bytes at `start` to the free block list.
+    fn free_space(&mut self, start: usize, size: usize, usable_size: usize) -> DbResult<()> {
+        let header = self.header_offset;
+
+        // Free blocks are chained in order of offset: find the ones around the new block
+        let mut previous = None;
+        let mut next = read_u16(&self.data, header + 1)? as usize;
+        while next != 0 && next < start {
+            if previous.is_some_and(|p| next <= p) || next + 4 > usable_size {
+                return Err(self.corrupt("free block list is out of order"));
+            }
+            previous = Some(next);
+            next = read_u16(&self.data, next)? as usize;
+        }
+
+        let mut fragmented = self.data[header + 7] as usize;
+        let (mut start, mut end) = (start, start + size);
+        let mut following = next;
+        if next != 0 && next < end {
+            return Err(self.corrupt("freed space overlaps a free block"));
+        }
+        if next != 0 && next <= end + 3 {
+            fragmented = fragmented
+                .checked_sub(next - end)
+                .ok_or_else(|| self.corrupt("fragmented byte count is too small"))?;
+            end = next + read_u16(&self.data, next + 2)? as usize;
+            following = read_u16(&self.data, next)? as usize;
+        }
+        if let Some(prev) = previous {
+            let prev_end = prev + read_u16(&self.data, prev + 2)? as usize;
+            if prev_end > start {
+                return Err(self.corrupt("freed space overlaps a free block"));
+            }
+            if prev_end + 3 >= start {
+                fragmented = fragmented
+                    .checked_sub(start - prev_end)
+                    .ok_or_else(|| self.corrupt("fragmented byte count is too small"))?;
+                start = prev;
+            }
+        }
+        self.data[header + 7] = fragmented as u8;
 
-        // Read first serial type (for the indexed column)
-        let (serial_type, _) = read_varint(&self.data, pos);
+        // A content start of 0 stands for 65536
+        let content_start = match read_u16(&self.data, header + 5)? {
+            0 => 65536,
+            offset => offset as usize,
+        };
+        if start <= content_start {
+            if start < content_start {
+                return Err(self.corrupt("freed space overlaps the unallocated area"));
+            }
+            write_u16(&mut self.data, header + 1, following as u16)?;
+            write_u16(&mut self.data, header + 5, end as u16)?;
+            return Ok(());
+        }
+
+        // A block merged into the previous one keeps that block's place in the list
+        match previous {
+            Some(prev) if prev == start => {}
+            Some(prev) => write_u16(&mut self.data, prev, start as u16)?,
+            None => write_u16(&mut self.data, header + 1, start as u16)?,
+        }
+        write_u16(&mut self.data, start, following as u16)?;
+        write_u16(&mut self.data, start + 2, (end - start) as u16)?;
+        Ok(())
+    }
 
-        // Skip to data section
-        pos = record_start + header_size as usize;
+    /// Check that the cells and free blocks lie inside the cell content area
+    /// without overlapping, and that the bytes left between them add up to
+    /// the header's fragmented byte count. Returns a description of each
+    /// problem found.
+    pub fn space_problems(&self, usable_size: usize) -> Vec<String> {
+        let header = self.header_offset;
+        let offsets = match self.cell_offsets() {
+            Ok(offsets) => offsets,
+            Err(err) => return vec![corruption_reason(err)],
+        };
+        let mut problems = Vec::new();
+        let array_end = self.cell_pointer_array_offset() + offsets.len() * 2;
+        // A content start of 0 stands for 65536
+        let content_start = match read_u16(&self.data, header + 5).unwrap_or_default() {
+            0 => 65536,
+            offset => offset as usize,
+        };
+        if content_start < array_end || content_start > usable_size {
+            problems.push(format!(
+                "cell content area starts at offset {}",
+                content_start
+            ));
+            return problems;
+        }
+
+        // Cells and free blocks as (start, end, description)
+        let mut regions = Vec::new();
+        for (i, &offset) in offsets.iter().enumerate() {
+            match self.cell_size(offset, usable_size) {
+                Ok(size) => regions.push((offset, offset + size, format!("cell {}", i))),
+                Err(err) => problems.push(format!("cell {}: {}", i, corruption_reason(err))),
+            }
+        }
+        let mut next = read_u16(&self.data, header + 1).unwrap_or_default() as usize;
+        let mut previous = 0;
+        while next != 0 {
+            if next <= previous || next + 4 > usable_size {
+                problems.push("free block list is out of order".to_string());
+                break;
+            }
+            let size = read_u16(&self.data, next + 2).unwrap_or_default() as usize;
+            regions.push((next, next + size, format!("free block at offset {}", next)));
+            previous = next;
+            next = read_u16(&self.data, next).unwrap_or_default() as usize;
+        }
 
-        // Extract the key value (first column)
-        let _size = crate::db::page::record::get_column_size(serial_type);
-        if serial_type >= 13 && serial_type % 2 == 1 {
-            // Text
-            let text_size = ((serial_type - 13) / 2) as usize;
-            if pos + text_size <= self.data.len() {
-                let key = String::from_utf8_lossy(&self.data[pos..pos + text_size]).to_string();
-                Ok((left_child, key))
+        regions.sort();
+        let mut fragmented = 0;
+        let mut position = content_start;
+        for (start, end, what) in regions {
+            if start < content_start || end > usable_size {
+                problems.push(format!("{} extends outside the cell content area", what));
+                continue;
+            }
+            if start < position {
+                problems.push(format!("{} overlaps the space before it", what));
             } else {
-                Err("Not enough data for key".to_string())
+                fragmented += start - position;
             }
+            position = position.max(end);
+        }
+        fragmented += usable_size.saturating_sub(position);
+        let reported = self.data[header + 7] as usize;
+        if fragmented != reported {
+            problems.push(format!(
+                "fragmentation of {} bytes reported as {}",
+                fragmented, reported
+            ));
+        }
+        problems
+    }
+
+    /// Size of the cell at `offset`, including any child and overflow pointers.
+    fn cell_size(&self, offset: usize, usable_size: usize) -> DbResult<usize> {
+        match self.page_type {
+            PageType::InteriorTable => Ok(4 + read_varint(&self.data, offset + 4)?.1),
+            _ => Ok(self.cell_payload(offset, usable_size)?.cell_size),
+        }
+    }
+
+    /// Offset of the cell pointer array, which directly follows the page header.
+    fn cell_pointer_array_offset(&self) -> usize {
+        let header_size = if self.is_interior() {
+            INTERIOR_HEADER_SIZE
         } else {
-            Err("Key is not text".to_string())
+            LEAF_HEADER_SIZE
+        };
+        self.header_offset + header_size
+    }
+
+    /// Build a corruption error for this page.
+    fn corrupt(&self, reason: impl Into<String>) -> DbError {
+        DbError::CorruptPage {
+            page: self.page_num,
+            reason: reason.into(),
         }
     }
 }
+
+/// The portion of a cell's payload stored on its B-tree page.
+pub struct CellPayload<'a> {
+    pub rowid: Option<i64>,
+    pub payload_size: usize,
+    pub local: &'a [u8],
+    pub overflow_page: Option<u32>,
+    /// Size of the whole cell on the page, including any child and overflow pointers.
+    pub cell_size: usize,
+}
+
+/// Check if `cells` fit on one page of the given type.
+pub fn cells_fit(
+    header_offset: usize,
+    page_type: PageType,
+    cells: &[Vec<u8>],
+    usable_size: usize,
+) -> bool {
+    let needed: usize = cells.iter().map(|cell| cell.len() + 2).sum();
+    header_offset + page_type.header_size() + needed <= usable_size
+}
+
+/// Lay out a B-tree page holding `cells` in order, packed at the end of the
+/// usable area with no free blocks.
+///
+/// Bytes before `header_offset` (the database header on page 1) are kept.
+/// Fails if the cells don't fit.
+pub fn build_page(
+    data: &mut [u8],
+    header_offset: usize,
+    page_type: PageType,
+    cells: &[Vec<u8>],
+    rightmost: Option<u32>,
+    usable_size: usize,
+) -> DbResult<()> {
+    if !cells_fit(header_offset, page_type, cells, usable_size) {
+        return Err(DbError::CorruptPage {
+            page: 0,
+            reason: format!("{} cells don't fit on one page", cells.len()),
+        });
+    }
+    data[header_offset..usable_size].fill(0);
+
+    let array_offset = header_offset + page_type.header_size();
+    let mut content_start = usable_size;
+    for (i, cell) in cells.iter().enumerate() {
+        content_start -= cell.len();
+        data[content_start..content_start + cell.len()].copy_from_slice(cell);
+        write_u16(data, array_offset + i * 2, content_start as u16)?;
+    }
+
+    data[header_offset] = page_type.to_byte();
+    write_u16(data, header_offset + CELL_COUNT_OFFSET, cells.len() as u16)?;
+    // A content area starting at 65536 is stored as 0
+    write_u16(data, header_offset + 5, content_start as u16)?;
+    if let Some(rightmost) = rightmost {
+        write_u32(data, header_offset + 8, rightmost)?;
+    }
+    Ok(())
+}
+
+/// Compute how many payload bytes are stored on the B-tree page itself.
+///
+/// Follows the overflow thresholds from the SQLite file format: payloads up to
+/// the maximum local size stay on the page, larger ones keep between the
+/// minimum and maximum local size on the page and spill the rest.
+pub fn local_payload_size(payload_size: usize, usable_size: usize, is_table_leaf: bool) -> usize {
+    let max_local = if is_table_leaf {
+        usable_size - 35
+    } else {
+        (usable_size - 12) * 64 / 255 - 23
+    };
+    if payload_size <= max_local {
+        return payload_size;
+    }
+
+    let min_local = (usable_size - 12) * 32 / 255 - 23;
+    let k = min_local + (payload_size - min_local) % (usable_size - 4);
+    if k <= max_local { k } else { min_local }
+}
+
+/// The reason given by a corruption error, without the page number, or the
+/// whole message for other errors.
+pub fn corruption_reason(err: DbError) -> String {
+    match err {
+        DbError::CorruptPage { reason, .. } => reason,
+        err => err.to_string(),
+    }
+}
@@ -1,129 +1,156 @@
 //! Record parsing utilities for SQLite database format.
 
-use crate::db::varint::read_varint;
+use crate::db::encoding::TextEncoding;
+use crate::db::error::{DbError, DbResult};
+use crate::db::value::Value;
+use crate::db::varint::{read_varint, varint_len, write_varint};
 
 /// A parsed SQLite record from a table cell.
 pub struct Record {
     serial_types: Vec<u64>,
     column_offsets: Vec<usize>,
     data: Vec<u8>,
-    pub rowid: i64,
-}
-
-/// An index cell contains the indexed value(s) and rowid
-pub struct IndexCell {
-    pub values: Vec<String>,
+    encoding: TextEncoding,
     pub rowid: i64,
 }
 
 impl Record {
-    /// Parse a record from a cell in a page.
-    /// Returns the Record and the number of bytes consumed.
-    pub fn parse(page: &[u8], cell_offset: usize) -> (Self, usize) {
-        let start = cell_offset;
-        let mut pos = cell_offset;
-
-        // Read record size (varint)
-        let (_record_size, bytes_read) = read_varint(page, pos);
-        pos += bytes_read;
-
-        // Read rowid (varint)
-        let (rowid, bytes_read) = read_varint(page, pos);
-        pos += bytes_read;
-
-        // Parse record header
-        let record_start = pos;
-        let (header_size, bytes_read) = read_varint(page, pos);
-        pos += bytes_read;
-
-        let mut serial_types = Vec::new();
-        let header_end = record_start + header_size as usize;
-
-        while pos < header_end {
-            let (serial_type, bytes_read) = read_varint(page, pos);
-            serial_types.push(serial_type);
-            pos += bytes_read;
-        }
-
-        // Calculate column offsets
+    /// Parse a record from a complete cell payload.
+    /// Text columns are decoded using the database's text encoding.
+    pub fn parse(payload: &[u8], rowid: i64, encoding: TextEncoding) -> DbResult<Self> {
+        let (serial_types, data_start) = parse_record_header(payload)?;
+
+        // Calculate column offsets relative to the start of the data section.
+        // A corrupt serial type can claim any size, so the end of the data is
+        // checked rather than trusted to stay within the payload.
         let mut column_offsets = Vec::new();
-        let mut offset = pos;
-        for &serial_type in &serial_types {
-            column_offsets.push(offset);
-            offset += get_column_size(serial_type);
-        }
+        let data_end = serial_types
+            .iter()
+            .try_fold(data_start, |end, &serial_type| {
+                column_offsets.push(end - data_start);
+                end.checked_add(get_column_size(serial_type))
+                    .filter(|&end| end <= payload.len())
+            })
+            .ok_or_else(|| DbError::CorruptPage {
+                // A record doesn't know its page; 0 stands for "unknown"
+                page: 0,
+                reason: format!(
+                    "record columns overrun its payload of {} bytes",
+                    payload.len()
+                ),
+            })?;
 
         // Copy data portion
-        let data = page[pos..offset].to_vec();
-
-        // Adjust offsets to be relative to data start
-        let data_start = pos;
-        let column_offsets: Vec<usize> = column_offsets.iter().map(|&o| o - data_start).collect();
-
-        (
-            Self {
-                serial_types,
-                column_offsets,
-                data,
-                rowid: rowid as i64,
-            },
-            offset - start,
-        )
+        let data = payload[data_start..data_end].to_vec();
+
+        Ok(Self {
+            serial_types,
+            column_offsets,
+            data,
+            encoding,
+            rowid,
+        })
     }
 
     /// Get the number of columns in this record.
-    #[allow(dead_code)]
     pub fn column_count(&self) -> usize {
         self.serial_types.len()
     }
 
-    /// Read a column value as a string.
-    /// Special case: column_index of usize::MAX means read the rowid
-    pub fn read_string(&self, column_index: usize) -> Option<String> {
+    /// Read a column value as a typed value.
+    /// Columns past the end of the record (added by ALTER TABLE) read as NULL.
+    pub fn read_value(&self, column_index: usize) -> Value {
         // Special case for rowid
         if column_index == usize::MAX {
-            return Some(self.rowid.to_string());
+            return Value::Integer(self.rowid);
         }
 
         if column_index >= self.serial_types.len() {
-            return None;
+            return Value::Null;
         }
 
         let serial_type = self.serial_types[column_index];
         let offset = self.column_offsets[column_index];
 
-        // Try text first
-        if let Some(text) = extract_text_from_serial_type(serial_type, &self.data, offset) {
-            return Some(text);
-        }
+        decode_value(serial_type, &self.data, offset, self.encoding)
+    }
 
-        // Try integer
-        if let Some(int_val) = extract_int_from_serial_type(serial_type, &self.data, offset) {
-            return Some(int_val.to_string());
+    /// Read a column value as a string.
+    /// Special case: column_index of usize::MAX means read the rowid
+    pub fn read_string(&self, column_index: usize) -> Option<String> {
+        match self.read_value(column_index) {
+            Value::Null => None,
+            value => Some(value.to_string()),
         }
-
-        // NULL or unknown
-        None
     }
 
     /// Read a column value as an integer.
+    /// Returns None for NULL or a value of another storage class.
     pub fn read_int(&self, column_index: usize) -> Option<i64> {
-        if column_index >= self.serial_types.len() {
-            return None;
+        match self.read_value(column_index) {
+            Value::Integer(int) => Some(int),
+            _ => None,
         }
+    }
+}
 
-        let serial_type = self.serial_types[column_index];
-        let offset = self.column_offsets[column_index];
+/// Encode values as a record: a header of serial types followed by the values.
+/// Text is stored in the database's text encoding.
+pub fn encode_record(values: &[Value], encoding: TextEncoding) -> Vec<u8> {
+    let mut serial_types = Vec::new();
+    let mut body = Vec::new();
+    for value in values {
+        let serial_type = match value {
+            Value::Null => 0,
+            Value::Integer(0) => 8,
+            Value::Integer(1) => 9,
+            Value::Integer(int) => {
+                let (serial_type, size) = integer_serial_type(*int);
+                body.extend_from_slice(&int.to_be_bytes()[8 - size..]);
+                serial_type
+            }
+            Value::Real(real) => {
+                body.extend_from_slice(&real.to_be_bytes());
+                7
+            }
+            Value::Text(text) => {
+                let bytes = encoding.encode(text);
+                body.extend_from_slice(&bytes);
+                bytes.len() as u64 * 2 + 13
+            }
+            Value::Blob(bytes) => {
+                body.extend_from_slice(bytes);
+                bytes.len() as u64 * 2 + 12
+            }
+        };
+        serial_types.push(serial_type);
+    }
 
-        extract_int_from_serial_type(serial_type, &self.data, offset)
+    // The header size counts its own varint
+    let types_len: usize = serial_types.iter().map(|&t| varint_len(t)).sum();
+    let mut header_size = types_len + 1;
+    while varint_len(header_size as u64) + types_len != header_size {
+        header_size = varint_len(header_size as u64) + types_len;
     }
 
-    /// Read multiple columns as strings.
-    pub fn read_strings(&self, column_indices: &[usize]) -> Vec<String> {
-        column_indices
-            .iter()
-            .map(|&idx| self.read_string(idx).unwrap_or_default())
-            .collect()
+    let mut record = Vec::with_capacity(header_size + body.len());
+    write_varint(&mut record, header_size as u64);
+    for serial_type in serial_types {
+        write_varint(&mut record, serial_type);
+    }
+    record.extend_from_slice(&body);
+    record
+}
+
+/// The smallest integer serial type that holds `int`, and its size in bytes.
+fn integer_serial_type(int: i64) -> (u64, usize) {
+    match int {
+        -0x80..0x80 => (1, 1),
+        -0x8000..0x8000 => (2, 2),
+        -0x80_0000..0x80_0000 => (3, 3),
+        -0x8000_0000..0x8000_0000 => (4, 4),
+        -0x8000_0000_0000..0x8000_0000_0000 => (5, 6),
+        _ => (6, 8),
     }
 }
 
@@ -148,88 +175,114 @@ pub fn get_column_size(serial_type: u64) -> usize {
 }
 
 /// Extract text string from data based on serial type.
-fn extract_text_from_serial_type(serial_type: u64, data: &[u8], pos: usize) -> Option<String> {
+fn extract_text_from_serial_type(
+    serial_type: u64,
+    data: &[u8],
+    pos: usize,
+    encoding: TextEncoding,
+) -> Option<String> {
     if serial_type >= 13 && serial_type % 2 == 1 {
         let text_size = ((serial_type - 13) / 2) as usize;
         if pos + text_size > data.len() {
             return None;
         }
         let text_bytes = &data[pos..pos + text_size];
-        Some(String::from_utf8_lossy(text_bytes).to_string())
+        Some(encoding.decode(text_bytes))
     } else {
         None
     }
 }
 
-/// Parse an index leaf cell.
-/// For index B-trees, the cell format is: payload_size(varint) + payload
-/// The payload contains: record_header + indexed_columns + rowid
-pub fn parse_index_cell(page: &[u8], cell_offset: usize) -> IndexCell {
-    let mut pos = cell_offset;
-
-    // Read payload size
-    let (_payload_size, bytes_read) = read_varint(page, pos);
-    pos += bytes_read;
-
-    // Now parse the record header
-    let record_start = pos;
-    let (header_size, bytes_read) = read_varint(page, pos);
-    pos += bytes_read;
+/// Parse a record header into its serial types.
+/// Returns the serial types and the offset where the column data begins.
+fn parse_record_header(payload: &[u8]) -> DbResult<(Vec<u64>, usize)> {
+    let (header_size, mut pos) = read_varint(payload, 0)?;
+    let header_end = header_size as usize;
+    if header_end > payload.len() || header_end < pos {
+        return Err(DbError::Parse {
+            pos: 0,
+            msg: format!(
+                "record header size {} doesn't fit payload of {} bytes",
+                header_size,
+                payload.len()
+            ),
+        });
+    }
 
-    let header_end = record_start + header_size as usize;
     let mut serial_types = Vec::new();
-
-    // Read all serial types from the header
-    while pos < header_end && pos < page.len() {
-        let (serial_type, bytes_read) = read_varint(page, pos);
+    while pos < header_end {
+        let (serial_type, bytes_read) = read_varint(&payload[..header_end], pos)?;
+        if serial_type == 10 || serial_type == 11 {
+            return Err(DbError::Parse {
+                pos,
+                msg: format!("reserved serial type {}", serial_type),
+            });
+        }
         serial_types.push(serial_type);
         pos += bytes_read;
     }
 
+    Ok((serial_types, header_end))
+}
+
+/// Read the rowid from the payload of an index cell.
+/// The payload contains: record_header + indexed_columns + rowid
+pub fn index_cell_rowid(payload: &[u8]) -> DbResult<i64> {
+    let (mut serial_types, header_size) = parse_record_header(payload)?;
+
     // The last serial type is for the rowid, everything else is indexed columns
     let rowid_serial_type = serial_types.pop();
+    let pos = header_size
+        + serial_types
+            .iter()
+            .map(|&serial_type| get_column_size(serial_type))
+            .sum::<usize>();
+
+    rowid_serial_type
+        .and_then(|serial_type| extract_int_from_serial_type(serial_type, payload, pos))
+        .ok_or_else(|| DbError::Parse {
+            pos,
+            msg: "index record doesn't end with an integer rowid".to_string(),
+        })
+}
 
-    // Read the indexed column values
-    let mut values = Vec::new();
-    for &serial_type in &serial_types {
+/// Decode a value of any storage class from data based on serial type.
+fn decode_value(serial_type: u64, data: &[u8], pos: usize, encoding: TextEncoding) -> Value {
+    if serial_type == 0 {
+        return Value::Null;
+    }
+    if let Some(text) = extract_text_from_serial_type(serial_type, data, pos, encoding) {
+        return Value::Text(text);
+    }
+    if let Some(real) = extract_real_from_serial_type(serial_type, data, pos) {
+        return Value::Real(real);
+    }
+    if serial_type >= 12 && serial_type.is_multiple_of(2) {
         let size = get_column_size(serial_type);
-
-        // Make sure we have enough data
-        if pos + size > page.len() {
-            break;
-        }
-
-        if let Some(text) = extract_text_from_serial_type(serial_type, page, pos) {
-            values.push(text);
-        } else if let Some(int) = extract_int_from_serial_type(serial_type, page, pos) {
-            values.push(int.to_string());
-        } else if serial_type == 0 {
-            values.push(String::new()); // NULL
-        } else {
-            values.push(String::new());
-        }
-        pos += size;
+        return match data.get(pos..pos + size) {
+            Some(bytes) => Value::Blob(bytes.to_vec()),
+            None => Value::Null,
+        };
     }
+    match extract_int_from_serial_type(serial_type, data, pos) {
+        Some(int) => Value::Integer(int),
+        None => Value::Null,
+    }
+}
 
-    // Read the rowid
-    let rowid = if let Some(serial_type) = rowid_serial_type {
-        let _size = get_column_size(serial_type);
-        if let Some(int) = extract_int_from_serial_type(serial_type, page, pos) {
-            int
-        } else {
-            0
-        }
-    } else {
-        0
-    };
-
-    IndexCell { values, rowid }
+/// Extract a floating point value from data based on serial type.
+fn extract_real_from_serial_type(serial_type: u64, data: &[u8], pos: usize) -> Option<f64> {
+    if serial_type != 7 || pos + 8 > data.len() {
+        return None;
+    }
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[pos..pos + 8]);
+    Some(f64::from_be_bytes(bytes))
 }
 
 /// Extract integer value from data based on serial type.
 fn extract_int_from_serial_type(serial_type: u64, data: &[u8], pos: usize) -> Option<i64> {
     match serial_type {
-        0 => Some(0), // NULL treated as 0
         1 => {
             if pos >= data.len() {
                 return None;
@@ -0,0 +1,301 @@
+//! Transactions over a database file.
+//!
+//! Commits are made atomic with a rollback journal, or in WAL mode by
+//! appending them to the write-ahead log.
+
+use std::fs;
+use std::time::Duration;
+
+use crate::sql::ast::{ConflictAction, TransactionKind};
+
+use super::autovacuum::{AutoVacuum, incremental_vacuum};
+use super::database::Database;
+use super::error::{DbError, DbResult};
+use super::journal::{journal_path, write_journal};
+
+/// The open transaction of a [`Pager`].
+struct Transaction {
+    /// Started by a SAVEPOINT outside a transaction, so that releasing the
+    /// savepoint commits it.
+    from_savepoint: bool,
+}
+
+/// A database file opened for a series of statements.
+///
+/// Outside a transaction each statement commits on its own. Between `BEGIN`
+/// and `COMMIT` changes build up in memory until committed or rolled back,
+/// and other connections can't commit.
+pub struct Pager {
+    path: String,
+    /// The open database, once a statement has needed it.
+    db: Option<Database>,
+    /// How long to wait for other connections' locks before giving up.
+    busy_timeout: Duration,
+    transaction: Option<Transaction>,
+    /// Names of the open savepoints, outermost first.
+    savepoints: Vec<String>,
+    /// The auto-vacuum mode last asked for, which the next VACUUM switches to.
+    next_auto_vacuum: Option<AutoVacuum>,
+}
+
+impl Pager {
+    /// Prepare to run statements against the database at `path`. The file
+    /// is opened by the first statement that needs it.
+    pub fn open(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            db: None,
+            busy_timeout: Duration::ZERO,
+            transaction: None,
+            savepoints: Vec::new(),
+            next_auto_vacuum: None,
+        }
+    }
+
+    /// How long statements wait for other connections' locks.
+    pub(super) fn busy_timeout(&self) -> Duration {
+        self.busy_timeout
+    }
+
+    /// Change how long statements wait for other connections' locks.
+    pub(super) fn set_busy_timeout(&mut self, busy_timeout: Duration) {
+        self.busy_timeout = busy_timeout;
+        if let Some(db) = &mut self.db {
+            db.set_busy_timeout(busy_timeout);
+        }
+    }
+
+    /// The auto-vacuum mode the next VACUUM switches to, if one was asked for.
+    pub(super) fn next_auto_vacuum(&self) -> Option<AutoVacuum> {
+        self.next_auto_vacuum
+    }
+
+    /// Ask for an auto-vacuum mode that only VACUUM can switch to.
+    pub(super) fn set_next_auto_vacuum(&mut self, mode: AutoVacuum) {
+        self.next_auto_vacuum = Some(mode);
+    }
+
+    /// The database, for a statement that only reads.
+    pub(super) fn read(&mut self) -> DbResult<&mut Database> {
+        self.database(false)
+    }
+
+    /// The database, for an integrity check, which can read one whose header
+    /// claims more pages than the file holds in order to report it.
+    pub(super) fn read_for_check(&mut self) -> DbResult<&mut Database> {
+        let db = self.opened()?;
+        db.begin_read_for_check()?;
+        Ok(db)
+    }
+
+    /// Run a statement that writes, undoing its changes if it fails. Outside
+    /// a transaction, its changes are committed when it succeeds.
+    ///
+    /// `conflict` says what a failure undoes: OR FAIL keeps the changes made
+    /// before it, OR ROLLBACK ends the whole transaction, and anything else
+    /// undoes the statement.
+    pub(super) fn write<T>(
+        &mut self,
+        conflict: ConflictAction,
+        run: impl FnOnce(&mut Database) -> DbResult<T>,
+    ) -> DbResult<T> {
+        let depth = self.savepoints.len();
+        let db = self.database(true)?;
+        db.begin_savepoint();
+        let result = run(db);
+        match (&result, conflict) {
+            (Ok(_), _) | (Err(_), ConflictAction::Fail) => db.release_savepoints(depth),
+            (Err(_), ConflictAction::Rollback) => {
+                db.rollback();
+                self.end_transaction()?;
+            }
+            (Err(_), _) => db.rollback_savepoints(depth),
+        }
+        // A commit that fails, say because readers hold their locks for
+        // too long, takes the statement's changes with it
+        if self.transaction.is_none()
+            && let Err(e) = self.commit_changes()
+        {
+            if let Some(db) = &mut self.db {
+                db.rollback();
+            }
+            return Err(e);
+        }
+        result
+    }
+
+    /// Finish a statement: outside a transaction, other connections may
+    /// commit again, and the next statement sees their changes.
+    pub(super) fn end_statement(&mut self) -> DbResult<()> {
+        match &mut self.db {
+            Some(db) if self.transaction.is_none() => db.end_read(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check if a transaction started by `BEGIN` or `SAVEPOINT` is open.
+    pub(super) fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    /// Handle `BEGIN`. IMMEDIATE and EXCLUSIVE transactions open the
+    /// database for writing right away; DEFERRED ones wait for a write.
+    pub(super) fn begin(&mut self, kind: TransactionKind) -> DbResult<()> {
+        if self.transaction.is_some() {
+            return Err(DbError::InvalidStatement(
+                "cannot start a transaction within a transaction".to_string(),
+            ));
+        }
+        match kind {
+            TransactionKind::Deferred => {}
+            TransactionKind::Immediate => {
+                self.database(true)?;
+            }
+            TransactionKind::Exclusive => self.database(true)?.lock_exclusive()?,
+        }
+        self.transaction = Some(Transaction {
+            from_savepoint: false,
+        });
+        Ok(())
+    }
+
+    /// Handle `COMMIT`.
+    pub(super) fn commit(&mut self) -> DbResult<()> {
+        if self.transaction.is_none() {
+            return Err(DbError::InvalidStatement(
+                "cannot commit - no transaction is active".to_string(),
+            ));
+        }
+        self.commit_changes()?;
+        self.end_transaction()
+    }
+
+    /// Handle `ROLLBACK`.
+    pub(super) fn rollback(&mut self) -> DbResult<()> {
+        if self.transaction.is_none() {
+            return Err(DbError::InvalidStatement(
+                "cannot rollback - no transaction is active".to_string(),
+            ));
+        }
+        self.end_transaction()
+    }
+
+    /// Handle `SAVEPOINT name`, starting a transaction if none is open.
+    pub(super) fn savepoint(&mut self, name: &str) -> DbResult<()> {
+        if self.transaction.is_none() {
+            self.transaction = Some(Transaction {
+                from_savepoint: true,
+            });
+        }
+        if let Some(db) = &mut self.db {
+            db.begin_savepoint();
+        }
+        self.savepoints.push(name.to_string());
+        Ok(())
+    }
+
+    /// Handle `RELEASE name`: close the savepoint and every one opened after
+    /// it, keeping their changes. Releasing the savepoint that started the
+    /// transaction commits it.
+    pub(super) fn release(&mut self, name: &str) -> DbResult<()> {
+        let depth = self.find_savepoint(name)?;
+        if let Some(db) = &mut self.db {
+            db.release_savepoints(depth);
+        }
+        self.savepoints.truncate(depth);
+        if depth == 0 && self.transaction.as_ref().is_some_and(|t| t.from_savepoint) {
+            self.commit_changes()?;
+            self.end_transaction()?;
+        }
+        Ok(())
+    }
+
+    /// Handle `ROLLBACK TO name`: undo the changes made since the savepoint
+    /// began. The savepoint stays open, but those opened after it are closed.
+    pub(super) fn rollback_to(&mut self, name: &str) -> DbResult<()> {
+        let depth = self.find_savepoint(name)?;
+        if let Some(db) = &mut self.db {
+            db.rollback_savepoints(depth);
+            db.begin_savepoint();
+        }
+        self.savepoints.truncate(depth + 1);
+        Ok(())
+    }
+
+    /// Find the most recent savepoint with a name, returning how many are
+    /// open outside it.
+    fn find_savepoint(&self, name: &str) -> DbResult<usize> {
+        self.savepoints
+            .iter()
+            .rposition(|s| s.eq_ignore_ascii_case(name))
+            .ok_or_else(|| DbError::InvalidStatement(format!("no such savepoint: {}", name)))
+    }
+
+    /// The database, opening it if needed.
+    fn opened(&mut self) -> DbResult<&mut Database> {
+        if self.db.is_none() {
+            let mut db = Database::open(&self.path, self.busy_timeout)?;
+            for _ in &self.savepoints {
+                db.begin_savepoint();
+            }
+            self.db = Some(db);
+        }
+        Ok(self.db.as_mut().expect("database was just opened"))
+    }
+
+    /// The open database, opening it if needed, with a read begun and, for
+    /// a statement that writes, a write too.
+    fn database(&mut self, writable: bool) -> DbResult<&mut Database> {
+        let db = self.opened()?;
+        if writable {
+            db.begin_write()?;
+        } else {
+            db.begin_read()?;
+        }
+        Ok(db)
+    }
+
+    /// Write the open transaction's changes to the file, journaling the
+    /// pages they overwrite first.
+    fn commit_changes(&mut self) -> DbResult<()> {
+        let Some(db) = &mut self.db else {
+            return Ok(());
+        };
+        // With full auto-vacuum, no free pages outlive a commit
+        if db.has_changes() && db.auto_vacuum()? == AutoVacuum::Full {
+            incremental_vacuum(db, None)?;
+        }
+        // A commit to the write-ahead log leaves the file alone until a checkpoint
+        if db.is_wal_mode() {
+            return db.commit();
+        }
+        if !db.has_changes() {
+            return Ok(());
+        }
+        let originals = db.original_pages()?;
+        db.lock_exclusive()?;
+        let journal = journal_path(&self.path);
+        write_journal(
+            &journal,
+            db.page_size,
+            db.committed_page_count(),
+            &originals,
+        )?;
+        db.commit()?;
+        fs::remove_file(&journal)?;
+        Ok(())
+    }
+
+    /// Close the transaction, discarding any changes it hasn't committed.
+    fn end_transaction(&mut self) -> DbResult<()> {
+        self.transaction = None;
+        self.savepoints.clear();
+        match &mut self.db {
+            Some(db) => {
+                db.rollback();
+                db.end_read()
+            }
+            None => Ok(()),
+        }
+    }
+}
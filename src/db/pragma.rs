@@ -0,0 +1,170 @@
+//! PRAGMA statements, which read and change settings of the database.
+
+use std::time::Duration;
+
+use crate::sql::ast::{ConflictAction, Pragma};
+
+use super::autovacuum::{AutoVacuum, incremental_vacuum, set_incremental};
+use super::error::{DbError, DbResult};
+use super::integrity::{DEFAULT_MAX_ERRORS, check_integrity};
+use super::pager::Pager;
+use super::query::QueryResult;
+use super::value::Value;
+use super::wal::CheckpointMode;
+
+/// Run a PRAGMA statement. Like SQLite, pragmas that aren't known do nothing.
+pub(super) fn execute_pragma(pager: &mut Pager, pragma: &Pragma) -> DbResult<QueryResult> {
+    let value = pragma.value.as_deref().map(str::to_ascii_lowercase);
+    match pragma.name.to_ascii_lowercase().as_str() {
+        "journal_mode" => journal_mode(pager, value.as_deref()),
+        "wal_checkpoint" => wal_checkpoint(pager, value.as_deref()),
+        "busy_timeout" => busy_timeout(pager, value.as_deref()),
+        "integrity_check" => integrity_check(pager, "integrity_check", value.as_deref()),
+        "quick_check" => integrity_check(pager, "quick_check", value.as_deref()),
+        "auto_vacuum" => auto_vacuum(pager, value.as_deref()),
+        "incremental_vacuum" => incremental_vacuum_pragma(pager, value.as_deref()),
+        _ => Ok(QueryResult::default()),
+    }
+}
+
+/// `PRAGMA journal_mode [= DELETE | WAL]`: report the journal mode after
+/// changing it, if asked to. Other modes aren't supported, and like modes
+/// SQLite doesn't know they leave the journal mode unchanged.
+fn journal_mode(pager: &mut Pager, value: Option<&str>) -> DbResult<QueryResult> {
+    let mut wal_mode = pager.read()?.is_wal_mode();
+    let requested = match value {
+        Some("wal") => Some(true),
+        Some("delete") => Some(false),
+        _ => None,
+    };
+    if let Some(enabled) = requested
+        && enabled != wal_mode
+    {
+        if enabled && pager.in_transaction() {
+            return Err(DbError::InvalidStatement(
+                "cannot change into wal mode from within a transaction".to_string(),
+            ));
+        }
+        // Leaving WAL mode waits for the transaction to end
+        if !pager.in_transaction() {
+            pager.write(ConflictAction::default(), |db| db.set_wal_mode(enabled))?;
+            wal_mode = enabled;
+        }
+    }
+    let mode = if wal_mode { "wal" } else { "delete" };
+    Ok(QueryResult {
+        columns: vec!["journal_mode".to_string()],
+        rows: vec![vec![Value::Text(mode.to_string())]],
+    })
+}
+
+/// `PRAGMA wal_checkpoint[(PASSIVE | FULL | RESTART | TRUNCATE)]`: copy the
+/// write-ahead log into the database file, reporting whether the checkpoint
+/// was blocked, the frames in the log and the frames copied. Both counts are
+/// -1 for a database not in WAL mode.
+fn wal_checkpoint(pager: &mut Pager, value: Option<&str>) -> DbResult<QueryResult> {
+    let mode = match value {
+        Some("full") => CheckpointMode::Full,
+        Some("restart") => CheckpointMode::Restart,
+        Some("truncate") => CheckpointMode::Truncate,
+        _ => CheckpointMode::Passive,
+    };
+    let db = pager.read()?;
+    let (busy, log, checkpointed) = if !db.is_wal_mode() {
+        (false, -1, -1)
+    } else if db.has_changes() {
+        return Err(DbError::InvalidStatement(
+            "database table is locked".to_string(),
+        ));
+    } else {
+        let (busy, log, checkpointed) = db.checkpoint(mode)?;
+        (busy, log as i64, checkpointed as i64)
+    };
+    Ok(QueryResult {
+        columns: vec![
+            "busy".to_string(),
+            "log".to_string(),
+            "checkpointed".to_string(),
+        ],
+        rows: vec![vec![
+            Value::Integer(busy as i64),
+            Value::Integer(log),
+            Value::Integer(checkpointed),
+        ]],
+    })
+}
+
+/// `PRAGMA busy_timeout [= milliseconds]`: report how long statements wait
+/// for other connections' locks after changing it, if asked to. Negative
+/// values turn waiting off.
+fn busy_timeout(pager: &mut Pager, value: Option<&str>) -> DbResult<QueryResult> {
+    if let Some(value) = value {
+        let millis = value.parse::<i64>().unwrap_or_default().max(0);
+        pager.set_busy_timeout(Duration::from_millis(millis as u64));
+    }
+    Ok(QueryResult {
+        columns: vec!["timeout".to_string()],
+        rows: vec![vec![
+            Value::Integer(pager.busy_timeout().as_millis() as i64),
+        ]],
+    })
+}
+
+/// `PRAGMA integrity_check[(N)]` and `PRAGMA quick_check[(N)]`: check the
+/// database, reporting up to N problems (100 by default), or a single "ok".
+fn integrity_check(pager: &mut Pager, name: &str, value: Option<&str>) -> DbResult<QueryResult> {
+    let max_errors = value
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|&n| n > 0)
+        .map_or(DEFAULT_MAX_ERRORS, |n| n as usize);
+    let quick = name == "quick_check";
+    let problems = check_integrity(pager.read_for_check()?, max_errors, quick)?;
+    Ok(QueryResult {
+        columns: vec![name.to_string()],
+        rows: problems
+            .into_iter()
+            .map(|problem| vec![Value::Text(problem)])
+            .collect(),
+    })
+}
+
+/// `PRAGMA auto_vacuum [= NONE | FULL | INCREMENTAL]`: report the
+/// auto-vacuum mode, or change it. Auto-vacuum can only be switched on or
+/// off right away in a database with no pages yet, and otherwise waits for
+/// the next VACUUM, but a database with it switches between FULL and
+/// INCREMENTAL right away.
+fn auto_vacuum(pager: &mut Pager, value: Option<&str>) -> DbResult<QueryResult> {
+    let Some(value) = value else {
+        let mode = pager.read()?.auto_vacuum()?;
+        return Ok(QueryResult {
+            columns: vec!["auto_vacuum".to_string()],
+            rows: vec![vec![Value::Integer(mode.number())]],
+        });
+    };
+    let mode = AutoVacuum::parse(value);
+    pager.set_next_auto_vacuum(mode);
+    let db = pager.read()?;
+    let current = db.auto_vacuum()?;
+    if db.committed_page_count() == 0 && !db.has_changes() {
+        db.set_new_auto_vacuum(mode);
+    } else if current != AutoVacuum::None && mode != AutoVacuum::None && mode != current {
+        pager.write(ConflictAction::default(), |db| {
+            set_incremental(db, mode == AutoVacuum::Incremental)
+        })?;
+    }
+    Ok(QueryResult::default())
+}
+
+/// `PRAGMA incremental_vacuum[(N)]`: in a database with auto-vacuum, give
+/// up to N free pages back to the file system, or all of them if N isn't
+/// positive.
+fn incremental_vacuum_pragma(pager: &mut Pager, value: Option<&str>) -> DbResult<QueryResult> {
+    let limit = value
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|&n| n > 0)
+        .map(|n| u32::try_from(n).unwrap_or(u32::MAX));
+    pager.write(ConflictAction::default(), |db| {
+        incremental_vacuum(db, limit)
+    })?;
+    Ok(QueryResult::default())
+}
@@ -0,0 +1,223 @@
+//! Pointer maps: pages of a database with auto-vacuum recording what
+//! points at every other page, so that any page but a root can be moved.
+//!
+//! Page 2 is the first pointer-map page, with an entry for each of the
+//! pages after it up to the next pointer-map page. Each entry is a type
+//! byte and the number of the page holding the pointer.
+
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+
+use super::constants::RIGHTMOST_POINTER_OFFSET;
+use super::database::Database;
+use super::error::{DbError, DbResult, read_u32, write_u32};
+use super::page::{Page, PageType};
+
+/// Size of a pointer-map entry: the type byte and a page number.
+const ENTRY_SIZE: usize = 5;
+
+/// What uses a page, and so what the page number in its entry means.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PtrmapType {
+    /// The root of a B-tree, which nothing points at.
+    RootPage = 1,
+    /// A page on the freelist.
+    FreePage = 2,
+    /// The first overflow page of a cell on the B-tree page it points at.
+    Overflow1 = 3,
+    /// A later overflow page, following the overflow page it points at.
+    Overflow2 = 4,
+    /// A non-root B-tree page, a child of the page it points at.
+    Btree = 5,
+}
+
+impl PtrmapType {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            1 => Some(Self::RootPage),
+            2 => Some(Self::FreePage),
+            3 => Some(Self::Overflow1),
+            4 => Some(Self::Overflow2),
+            5 => Some(Self::Btree),
+            _ => None,
+        }
+    }
+}
+
+/// A page's pointer-map entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PtrmapEntry {
+    pub kind: PtrmapType,
+    /// The page pointing at this one, or 0 for root and free pages.
+    pub parent: u32,
+}
+
+impl PtrmapEntry {
+    pub fn new(kind: PtrmapType, parent: u32) -> Self {
+        Self { kind, parent }
+    }
+}
+
+/// The pointer-map page holding the entry for a page after page 1.
+pub fn ptrmap_page(db: &Database, page_num: u32) -> u32 {
+    let span = (db.usable_size() / ENTRY_SIZE) as u32 + 1;
+    let map = (page_num - 2) / span * span + 2;
+    // The page holding the lock bytes can't be one; the page after it is instead
+    if map == db.pending_page() {
+        map + 1
+    } else {
+        map
+    }
+}
+
+/// Check if a page of a database with auto-vacuum is a pointer-map page.
+pub fn is_ptrmap_page(db: &Database, page_num: u32) -> bool {
+    page_num >= 2 && ptrmap_page(db, page_num) == page_num
+}
+
+/// Where a page's entry is on its pointer-map page, checking it has one.
+fn entry_location(db: &Database, page_num: u32) -> DbResult<(u32, usize)> {
+    if page_num < 2 || is_ptrmap_page(db, page_num) {
+        return Err(DbError::CorruptPage {
+            page: page_num,
+            reason: "page has no pointer-map entry".to_string(),
+        });
+    }
+    let map = ptrmap_page(db, page_num);
+    Ok((map, ENTRY_SIZE * (page_num - map - 1) as usize))
+}
+
+/// Read a page's pointer-map entry.
+pub fn read_entry(db: &mut Database, page_num: u32) -> DbResult<PtrmapEntry> {
+    let (map, offset) = entry_location(db, page_num)?;
+    let data = db.read_page(map)?;
+    let kind = PtrmapType::from_byte(data[offset]).ok_or_else(|| DbError::CorruptPage {
+        page: map,
+        reason: format!(
+            "invalid pointer-map entry type {} for page {}",
+            data[offset], page_num
+        ),
+    })?;
+    Ok(PtrmapEntry::new(kind, read_u32(&data, offset + 1)?))
+}
+
+/// Set the pointer-map entries of several pages, writing each pointer-map
+/// page once, and only if an entry changes.
+pub fn write_entries(db: &mut Database, entries: &[(u32, PtrmapEntry)]) -> DbResult<()> {
+    let mut maps: BTreeMap<u32, (Vec<u8>, bool)> = BTreeMap::new();
+    for &(page_num, entry) in entries {
+        let (map, offset) = entry_location(db, page_num)?;
+        let (data, changed) = match maps.entry(map) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert((db.read_page(map)?, false)),
+        };
+        if data[offset] != entry.kind as u8 || read_u32(data, offset + 1)? != entry.parent {
+            data[offset] = entry.kind as u8;
+            write_u32(data, offset + 1, entry.parent)?;
+            *changed = true;
+        }
+    }
+    for (map, (data, changed)) in maps {
+        if changed {
+            db.write_page(map, data)?;
+        }
+    }
+    Ok(())
+}
+
+/// Point the entries of a B-tree page's children and of its cells' first
+/// overflow pages at the page.
+pub fn set_child_entries(db: &mut Database, page: &Page) -> DbResult<()> {
+    let usable_size = db.usable_size();
+    let parent = page.page_num();
+    let mut entries = Vec::new();
+    for offset in page.cell_offsets()? {
+        if page.is_interior() {
+            let child = page.left_child(offset)?;
+            entries.push((child, PtrmapEntry::new(PtrmapType::Btree, parent)));
+        }
+        // Interior table cells hold only a rowid
+        if page.page_type() == PageType::InteriorTable {
+            continue;
+        }
+        if let Some(overflow) = page.cell_payload(offset, usable_size)?.overflow_page {
+            entries.push((overflow, PtrmapEntry::new(PtrmapType::Overflow1, parent)));
+        }
+    }
+    if let Some(rightmost) = page.rightmost_pointer() {
+        entries.push((rightmost, PtrmapEntry::new(PtrmapType::Btree, parent)));
+    }
+    write_entries(db, &entries)
+}
+
+/// Move the contents of page `from` to page `to`, which is no longer
+/// used, and point whatever pointed at it, and the pages it points at, at
+/// its new place. Nothing points at a root page: sqlite_schema must be
+/// changed to match.
+pub fn relocate_page(db: &mut Database, from: u32, to: u32) -> DbResult<()> {
+    let entry = read_entry(db, from)?;
+    let data = db.read_page(from)?;
+    db.write_page(to, data.clone())?;
+    match entry.kind {
+        PtrmapType::RootPage | PtrmapType::Btree => set_child_entries(db, &Page::new(data, to)?)?,
+        PtrmapType::Overflow1 | PtrmapType::Overflow2 => {
+            let next = read_u32(&data, 0)?;
+            if next != 0 {
+                write_entries(db, &[(next, PtrmapEntry::new(PtrmapType::Overflow2, to))])?;
+            }
+        }
+        PtrmapType::FreePage => {
+            return Err(DbError::CorruptPage {
+                page: from,
+                reason: "cannot move a free page".to_string(),
+            });
+        }
+    }
+    if entry.kind != PtrmapType::RootPage {
+        repoint_parent(db, entry, from, to)?;
+    }
+    write_entries(db, &[(to, entry)])
+}
+
+/// Change the pointer to page `from` in the page its entry names to `to`.
+fn repoint_parent(db: &mut Database, entry: PtrmapEntry, from: u32, to: u32) -> DbResult<()> {
+    let mut data = db.read_page(entry.parent)?;
+    // An overflow page starts with the number of the next one
+    if entry.kind == PtrmapType::Overflow2 {
+        write_u32(&mut data, 0, to)?;
+        return db.write_page(entry.parent, data);
+    }
+
+    let usable_size = db.usable_size();
+    let page = Page::new(data.clone(), entry.parent)?;
+    let mut position = None;
+    for offset in page.cell_offsets()? {
+        if entry.kind == PtrmapType::Btree {
+            if page.left_child(offset)? == from {
+                position = Some(offset);
+                break;
+            }
+            continue;
+        }
+        // A cell's overflow page number comes last
+        let cell = page.cell_payload(offset, usable_size)?;
+        if cell.overflow_page == Some(from) {
+            position = Some(offset + cell.cell_size - 4);
+            break;
+        }
+    }
+    if position.is_none()
+        && entry.kind == PtrmapType::Btree
+        && page.rightmost_pointer() == Some(from)
+    {
+        position = Some(Database::header_offset(entry.parent) + RIGHTMOST_POINTER_OFFSET);
+    }
+    let Some(position) = position else {
+        return Err(DbError::CorruptPage {
+            page: entry.parent,
+            reason: format!("page has no pointer to page {}", from),
+        });
+    };
+    write_u32(&mut data, position, to)?;
+    db.write_page(entry.parent, data)
+}
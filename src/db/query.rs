@@ -0,0 +1,639 @@
+//! Query execution.
+
+use crate::sql::ast::{BinaryOp, ConflictAction, Expr, ResultColumn, Select, Statement};
+
+use super::affinity::Affinity;
+use super::alter::execute_alter_table;
+use super::btree::{
+    find_record_by_rowid, search_index_btree, search_without_rowid_table, traverse_btree_index,
+    traverse_btree_table,
+};
+use super::collation::Collation;
+use super::create::{execute_create_index, execute_create_table};
+use super::database::Database;
+use super::delete::execute_delete;
+use super::drop::{execute_drop_index, execute_drop_table};
+use super::encoding::TextEncoding;
+use super::error::{DbError, DbResult};
+use super::eval::{EmptyScope, RowScope, eval, eval_condition, expr_collation};
+use super::insert::execute_insert;
+use super::page::Record;
+use super::pager::Pager;
+use super::pragma::execute_pragma;
+use super::schema::{ColumnDef, SchemaEntry, find_index_for_column, find_table, parse_columns};
+use super::table_write::default_value;
+use super::update::execute_update;
+use super::vacuum::execute_vacuum;
+use super::value::Value;
+
+/// Names that refer to the rowid when no column is called that.
+const ROWID_NAMES: &[&str] = &["rowid", "oid", "_rowid_"];
+
+/// The column names and rows produced by a query.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// Execute a parsed statement, as part of the pager's open transaction if it has one.
+pub fn execute(pager: &mut Pager, statement: &Statement) -> DbResult<QueryResult> {
+    let result = match statement {
+        Statement::Select(select) => execute_select(pager.read()?, select),
+        Statement::Insert(insert) => pager.write(insert.conflict, |db| execute_insert(db, insert)),
+        Statement::Update(update) => pager.write(update.conflict, |db| execute_update(db, update)),
+        Statement::Delete(delete) => {
+            pager.write(ConflictAction::default(), |db| execute_delete(db, delete))
+        }
+        Statement::Begin(kind) => pager.begin(*kind).map(|_| QueryResult::default()),
+        Statement::Commit => pager.commit().map(|_| QueryResult::default()),
+        Statement::Rollback(None) => pager.rollback().map(|_| QueryResult::default()),
+        Statement::Rollback(Some(name)) => pager.rollback_to(name).map(|_| QueryResult::default()),
+        Statement::Savepoint(name) => pager.savepoint(name).map(|_| QueryResult::default()),
+        Statement::Release(name) => pager.release(name).map(|_| QueryResult::default()),
+        Statement::Pragma(pragma) => execute_pragma(pager, pragma),
+        Statement::CreateTable(create) => pager.write(ConflictAction::default(), |db| {
+            execute_create_table(db, create)
+        }),
+        Statement::CreateIndex(create) => pager.write(ConflictAction::default(), |db| {
+            execute_create_index(db, create)
+        }),
+        Statement::DropTable(drop) => {
+            pager.write(ConflictAction::default(), |db| execute_drop_table(db, drop))
+        }
+        Statement::DropIndex(drop) => {
+            pager.write(ConflictAction::default(), |db| execute_drop_index(db, drop))
+        }
+        Statement::AlterTable(alter) => pager.write(ConflictAction::default(), |db| {
+            execute_alter_table(db, alter)
+        }),
+        Statement::Vacuum(into) => execute_vacuum(pager, into.as_ref()),
+    };
+    let ended = pager.end_statement();
+    result.and_then(|result| ended.map(|_| result))
+}
+
+/// Run a SELECT and collect its rows.
+pub(super) fn execute_select(db: &mut Database, select: &Select) -> DbResult<QueryResult> {
+    let Some(table_name) = &select.from else {
+        // Without FROM the query runs once against an empty row
+        let rows = [EmptyScope(db.encoding)];
+        return project(select, &[], &rows);
+    };
+
+    let table = Table::new(find_table(db, table_name)?, db.encoding)?;
+
+    check_columns(&table, select_exprs(select))?;
+    let rows: Vec<TableRow> = filter_rows(db, &table, select.where_clause.as_ref())?
+        .into_iter()
+        .map(|record| TableRow {
+            table: &table,
+            record,
+        })
+        .collect();
+
+    let names: Vec<String> = table.columns.iter().map(|c| c.name.clone()).collect();
+    project(select, &names, &rows)
+}
+
+/// Report the first column the expressions name that the table doesn't have,
+/// so that unknown columns are caught even if no row would be evaluated.
+pub(super) fn check_columns<'a>(
+    table: &Table,
+    exprs: impl IntoIterator<Item = &'a Expr>,
+) -> DbResult<()> {
+    for expr in exprs {
+        let mut missing = None;
+        expr.walk(&mut |e| {
+            if let Expr::Column(name) = e
+                && table.resolve(name).is_none()
+            {
+                missing.get_or_insert(name.clone());
+            }
+        });
+        if let Some(column) = missing {
+            return Err(DbError::ColumnNotFound {
+                table: table.entry.tbl_name.clone(),
+                column,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Read the records of a table that satisfy a WHERE clause.
+pub(super) fn filter_rows(
+    db: &mut Database,
+    table: &Table,
+    where_clause: Option<&Expr>,
+) -> DbResult<Vec<Record>> {
+    let mut records = Vec::new();
+    for record in scan(db, table, where_clause)? {
+        let row = TableRow { table, record };
+        if let Some(condition) = where_clause
+            && !eval_condition(condition, &row)?
+        {
+            continue;
+        }
+        records.push(row.record);
+    }
+    Ok(records)
+}
+
+/// Evaluate the result columns over the rows that passed the WHERE clause.
+fn project<R: RowScope>(
+    select: &Select,
+    table_columns: &[String],
+    rows: &[R],
+) -> DbResult<QueryResult> {
+    // Expand `*` into the table's columns
+    let mut outputs = Vec::new();
+    for column in &select.columns {
+        match column {
+            ResultColumn::Star if table_columns.is_empty() => {
+                return Err(DbError::Parse {
+                    pos: 0,
+                    msg: "no tables specified".to_string(),
+                });
+            }
+            ResultColumn::Star => {
+                for name in table_columns {
+                    outputs.push((name.clone(), Expr::Column(name.clone())));
+                }
+            }
+            ResultColumn::Expr { expr, alias, text } => {
+                outputs.push((alias.clone().unwrap_or_else(|| text.clone()), expr.clone()));
+            }
+        }
+    }
+
+    let columns = outputs.iter().map(|(name, _)| name.clone()).collect();
+
+    if !outputs.iter().any(|(_, expr)| expr.is_aggregate()) {
+        let mut sorted = Vec::with_capacity(rows.len());
+        for row in rows {
+            let values = outputs
+                .iter()
+                .map(|(_, expr)| eval(expr, row))
+                .collect::<DbResult<Vec<_>>>()?;
+            let keys = select
+                .order_by
+                .iter()
+                .map(|term| match output_reference(&term.expr, &outputs) {
+                    Some(idx) => Ok(values[idx].clone()),
+                    None => eval(&term.expr, row),
+                })
+                .collect::<DbResult<Vec<_>>>()?;
+            sorted.push((keys, values));
+        }
+        if let Some(first) = rows.first()
+            && !select.order_by.is_empty()
+        {
+            sort_rows(&mut sorted, select, &outputs, first)?;
+        }
+        let rows = sorted.into_iter().map(|(_, values)| values).collect();
+        return Ok(QueryResult { columns, rows });
+    }
+
+    // An aggregate query produces a single row. Bare columns take their
+    // values from the last row, as in SQLite.
+    let mut aggregates = Vec::new();
+    for (_, expr) in &outputs {
+        let mut calls = Vec::new();
+        expr.walk(&mut |e| {
+            if e.is_aggregate_call() {
+                calls.push(e);
+            }
+        });
+        for call in calls {
+            aggregates.push((call, aggregate(call, rows)?));
+        }
+    }
+    let scope = AggregateScope {
+        row: rows.last().map(|row| row as &dyn RowScope),
+        aggregates,
+    };
+    let row = outputs
+        .iter()
+        .map(|(_, expr)| eval(expr, &scope))
+        .collect::<DbResult<Vec<_>>>()?;
+    Ok(QueryResult {
+        columns,
+        rows: vec![row],
+    })
+}
+
+/// Find the result column an ORDER BY term refers to, either by its 1-based
+/// position (`ORDER BY 2`) or by its alias.
+fn output_reference(expr: &Expr, outputs: &[(String, Expr)]) -> Option<usize> {
+    match strip_collate(expr).0 {
+        Expr::Literal(Value::Integer(n)) if *n >= 1 && (*n as usize) <= outputs.len() => {
+            Some(*n as usize - 1)
+        }
+        Expr::Column(name) => outputs.iter().position(|(alias, expr)| {
+            alias.eq_ignore_ascii_case(name) && *expr != Expr::Column(alias.clone())
+        }),
+        _ => None,
+    }
+}
+
+/// Sort rows by their ORDER BY keys. Each key is compared with the collation
+/// its term names, or that of the column it refers to.
+fn sort_rows(
+    sorted: &mut [(Vec<Value>, Vec<Value>)],
+    select: &Select,
+    outputs: &[(String, Expr)],
+    scope: &dyn RowScope,
+) -> DbResult<()> {
+    let mut collations = Vec::new();
+    for term in &select.order_by {
+        let explicit = expr_collation(&term.expr, scope).filter(|(_, explicit)| *explicit);
+        let collation = explicit.or_else(|| match output_reference(&term.expr, outputs) {
+            Some(idx) => expr_collation(&outputs[idx].1, scope),
+            None => expr_collation(&term.expr, scope),
+        });
+        collations.push(Collation::lookup_or_binary(
+            collation.as_ref().map(|(name, _)| name.as_str()),
+        )?);
+    }
+
+    let encoding = scope.encoding();
+    sorted.sort_by(|(a, _), (b, _)| {
+        for ((term, collation), (a, b)) in
+            select.order_by.iter().zip(&collations).zip(a.iter().zip(b))
+        {
+            let ordering = a.compare_collated(b, collation, encoding);
+            let ordering = if term.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            if ordering.is_ne() {
+                return ordering;
+            }
+        }
+        std::cmp::Ordering::Equal
+    });
+    Ok(())
+}
+
+/// Compute an aggregate function call over all rows.
+fn aggregate<R: RowScope>(call: &Expr, rows: &[R]) -> DbResult<Value> {
+    let Expr::Function { name, args, star } = call else {
+        unreachable!("not an aggregate call");
+    };
+    match (args.as_slice(), *star) {
+        ([], true) => Ok(Value::Integer(rows.len() as i64)),
+        ([arg], false) => {
+            let mut count = 0;
+            for row in rows {
+                if eval(arg, row)? != Value::Null {
+                    count += 1;
+                }
+            }
+            Ok(Value::Integer(count))
+        }
+        _ => Err(DbError::UnsupportedFeature(format!(
+            "wrong number of arguments to function {}()",
+            name
+        ))),
+    }
+}
+
+/// Every expression in the result columns, WHERE and ORDER BY clauses.
+/// ORDER BY terms that name a result column alias are left out.
+fn select_exprs(select: &Select) -> impl Iterator<Item = &Expr> {
+    let aliases: Vec<&str> = select
+        .columns
+        .iter()
+        .filter_map(|column| match column {
+            ResultColumn::Expr { alias, .. } => alias.as_deref(),
+            ResultColumn::Star => None,
+        })
+        .collect();
+    select
+        .columns
+        .iter()
+        .filter_map(|column| match column {
+            ResultColumn::Expr { expr, .. } => Some(expr),
+            ResultColumn::Star => None,
+        })
+        .chain(select.where_clause.as_ref())
+        .chain(
+            select
+                .order_by
+                .iter()
+                .map(|term| &term.expr)
+                .filter(move |expr| match strip_collate(expr).0 {
+                    Expr::Column(name) => !aliases.iter().any(|a| a.eq_ignore_ascii_case(name)),
+                    _ => true,
+                }),
+        )
+}
+
+/// Read the records that may match the WHERE clause, using an index or a
+/// rowid lookup when the clause pins a column to a literal.
+fn scan(db: &mut Database, table: &Table, where_clause: Option<&Expr>) -> DbResult<Vec<Record>> {
+    let mut terms = Vec::new();
+    if let Some(condition) = where_clause {
+        equality_terms(condition, &mut terms);
+    }
+
+    if table.without_rowid {
+        return scan_without_rowid(db, table, &terms);
+    }
+
+    for term in terms {
+        let Some(column) = table.resolve(term.column) else {
+            continue;
+        };
+
+        // Rowid lookup
+        if column == ColumnRef::Rowid {
+            return match Affinity::Integer.apply(term.value.clone()) {
+                Value::Integer(rowid) => Ok(find_record_by_rowid(db, table.entry.rootpage, rowid)?
+                    .into_iter()
+                    .collect()),
+                _ => Ok(Vec::new()),
+            };
+        }
+
+        // Index seek
+        let ColumnRef::Column(idx) = column else {
+            continue;
+        };
+        let def = &table.columns[idx];
+        // The index must order keys the same way the comparison does
+        let collation = Collation::lookup_or_binary(term.collation.or(def.collation.as_deref()))?;
+        if let Some((index, key)) =
+            find_index_for_column(db, &table.entry.tbl_name, def, &collation)?
+        {
+            // The index holds values converted by the column's affinity
+            let search_value = def.affinity().apply(term.value.clone());
+            let rowids = search_index_btree(
+                db,
+                index.rootpage,
+                &search_value,
+                &collation,
+                key.descending,
+            )?;
+            let mut records = Vec::new();
+            for rowid in rowids {
+                if let Some(record) = find_record_by_rowid(db, table.entry.rootpage, rowid)? {
+                    records.push(record);
+                }
+            }
+            return Ok(records);
+        }
+    }
+
+    // Full table scan
+    let mut records = Vec::new();
+    traverse_btree_table(db, table.entry.rootpage, &mut records)?;
+    Ok(records)
+}
+
+/// Read the rows of a WITHOUT ROWID table that may match the WHERE terms,
+/// seeking on the PRIMARY KEY when a term pins its first column.
+fn scan_without_rowid(
+    db: &mut Database,
+    table: &Table,
+    terms: &[EqualityTerm],
+) -> DbResult<Vec<Record>> {
+    for term in terms {
+        let Some(ColumnRef::Column(idx)) = table.resolve(term.column) else {
+            continue;
+        };
+        let def = &table.columns[idx];
+        if def.primary_key != Some(0) {
+            continue;
+        }
+        // Rows are ordered by the key column's own collation
+        let key_collation = Collation::lookup_or_binary(def.collation.as_deref())?;
+        let collation = Collation::lookup_or_binary(term.collation.or(def.collation.as_deref()))?;
+        if collation == key_collation {
+            let key = def.affinity().apply(term.value.clone());
+            return search_without_rowid_table(
+                db,
+                table.entry.rootpage,
+                &key,
+                &collation,
+                def.primary_key_descending,
+            );
+        }
+    }
+
+    // Full scan of the table's index B-tree
+    let mut records = Vec::new();
+    traverse_btree_index(db, table.entry.rootpage, &mut records)?;
+    Ok(records)
+}
+
+/// A `column = literal` term of a WHERE clause.
+struct EqualityTerm<'a> {
+    column: &'a str,
+    value: &'a Value,
+    /// Collation named by a COLLATE on either side of the comparison.
+    collation: Option<&'a str>,
+}
+
+/// Collect the `column = literal` terms that must all hold for the condition to be true.
+fn equality_terms<'a>(expr: &'a Expr, terms: &mut Vec<EqualityTerm<'a>>) {
+    let Expr::Binary { op, left, right } = expr else {
+        return;
+    };
+    if *op == BinaryOp::And {
+        equality_terms(left, terms);
+        equality_terms(right, terms);
+        return;
+    }
+    if *op != BinaryOp::Eq {
+        return;
+    }
+    let ((left, left_collation), (right, right_collation)) =
+        (strip_collate(left), strip_collate(right));
+    let collation = left_collation.or(right_collation);
+    match (left, right) {
+        (Expr::Column(column), Expr::Literal(value))
+        | (Expr::Literal(value), Expr::Column(column))
+            if *value != Value::Null =>
+        {
+            terms.push(EqualityTerm {
+                column,
+                value,
+                collation,
+            });
+        }
+        _ => {}
+    }
+}
+
+/// Remove COLLATE clauses from an expression, returning the outermost collation name.
+fn strip_collate(mut expr: &Expr) -> (&Expr, Option<&str>) {
+    let mut outer = None;
+    while let Expr::Collate {
+        expr: inner,
+        collation,
+    } = expr
+    {
+        outer = outer.or(Some(collation.as_str()));
+        expr = inner;
+    }
+    (expr, outer)
+}
+
+/// A table being queried and its parsed column definitions.
+pub(super) struct Table {
+    pub(super) entry: SchemaEntry,
+    pub(super) columns: Vec<ColumnDef>,
+    /// Position of each column's value in the table's records.
+    pub(super) record_index: Vec<usize>,
+    pub(super) without_rowid: bool,
+    pub(super) encoding: TextEncoding,
+}
+
+/// Where a column name's value comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ColumnRef {
+    Rowid,
+    Column(usize),
+}
+
+impl Table {
+    pub(super) fn new(entry: SchemaEntry, encoding: TextEncoding) -> DbResult<Self> {
+        let columns = parse_columns(&entry.sql)?;
+        let without_rowid = entry.is_without_rowid();
+
+        // WITHOUT ROWID records hold the PRIMARY KEY columns first, then the rest in order
+        let mut order: Vec<usize> = (0..columns.len()).collect();
+        if without_rowid {
+            order.sort_by_key(|&idx| columns[idx].primary_key.unwrap_or(usize::MAX));
+        }
+        let mut record_index = vec![0; columns.len()];
+        for (position, &idx) in order.iter().enumerate() {
+            record_index[idx] = position;
+        }
+
+        Ok(Self {
+            entry,
+            columns,
+            record_index,
+            without_rowid,
+            encoding,
+        })
+    }
+
+    /// Read a column's value from one of the table's records. Records
+    /// written before ALTER TABLE added the column lack it, and read as its
+    /// DEFAULT.
+    pub(super) fn read_column(&self, record: &Record, idx: usize) -> Value {
+        let position = self.record_index[idx];
+        if position < record.column_count() {
+            return record.read_value(position);
+        }
+        // Only constant defaults can be added to a table with rows, and those always evaluate
+        let def = &self.columns[idx];
+        default_value(def, self.encoding).map_or(Value::Null, |value| def.affinity().apply(value))
+    }
+
+    /// Resolve a column name, falling back to the rowid's built-in names.
+    pub(super) fn resolve(&self, name: &str) -> Option<ColumnRef> {
+        match self
+            .columns
+            .iter()
+            .position(|c| c.name.eq_ignore_ascii_case(name))
+        {
+            Some(idx) if self.columns[idx].is_rowid_alias => Some(ColumnRef::Rowid),
+            Some(idx) => Some(ColumnRef::Column(idx)),
+            None if !self.without_rowid
+                && ROWID_NAMES.iter().any(|n| n.eq_ignore_ascii_case(name)) =>
+            {
+                Some(ColumnRef::Rowid)
+            }
+            None => None,
+        }
+    }
+}
+
+/// A row of a table, as seen by expressions.
+pub(super) struct TableRow<'a> {
+    pub(super) table: &'a Table,
+    pub(super) record: Record,
+}
+
+impl RowScope for TableRow<'_> {
+    fn column(&self, name: &str) -> DbResult<Value> {
+        match self.table.resolve(name) {
+            Some(ColumnRef::Rowid) => Ok(Value::Integer(self.record.rowid)),
+            Some(ColumnRef::Column(idx)) => {
+                // Integral values in REAL columns are stored as integers
+                match self.table.read_column(&self.record, idx) {
+                    Value::Integer(i) if self.table.columns[idx].affinity() == Affinity::Real => {
+                        Ok(Value::Real(i as f64))
+                    }
+                    value => Ok(value),
+                }
+            }
+            None => Err(DbError::ColumnNotFound {
+                table: self.table.entry.tbl_name.clone(),
+                column: name.to_string(),
+            }),
+        }
+    }
+
+    fn column_affinity(&self, name: &str) -> Affinity {
+        match self.table.resolve(name) {
+            Some(ColumnRef::Rowid) => Affinity::Integer,
+            Some(ColumnRef::Column(idx)) => self.table.columns[idx].affinity(),
+            None => Affinity::None,
+        }
+    }
+
+    fn column_collation(&self, name: &str) -> Option<String> {
+        let declared = match self.table.resolve(name)? {
+            ColumnRef::Column(idx) => self.table.columns[idx].collation.clone(),
+            ColumnRef::Rowid => None,
+        };
+        Some(declared.unwrap_or_else(|| Collation::Binary.name().to_string()))
+    }
+
+    fn encoding(&self) -> TextEncoding {
+        self.table.encoding
+    }
+}
+
+/// The scope an aggregate query's result columns are evaluated in.
+struct AggregateScope<'a> {
+    row: Option<&'a dyn RowScope>,
+    aggregates: Vec<(&'a Expr, Value)>,
+}
+
+impl RowScope for AggregateScope<'_> {
+    fn column(&self, name: &str) -> DbResult<Value> {
+        match self.row {
+            Some(row) => row.column(name),
+            None => Ok(Value::Null),
+        }
+    }
+
+    fn column_affinity(&self, name: &str) -> Affinity {
+        self.row
+            .map(|row| row.column_affinity(name))
+            .unwrap_or_default()
+    }
+
+    fn column_collation(&self, name: &str) -> Option<String> {
+        self.row.and_then(|row| row.column_collation(name))
+    }
+
+    fn encoding(&self) -> TextEncoding {
+        self.row.map(|row| row.encoding()).unwrap_or_default()
+    }
+
+    fn aggregate(&self, expr: &Expr) -> Option<Value> {
+        self.aggregates
+            .iter()
+            .find(|(call, _)| std::ptr::eq(*call, expr))
+            .map(|(_, value)| value.clone())
+    }
+}
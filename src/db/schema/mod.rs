@@ -1,5 +1,11 @@
 //! Schema parsing for SQLite databases.
 
+#[allow(clippy::module_inception)]
 mod schema;
 
-pub use schema::{count_table_rows, read_table_names, select_columns, select_columns_with_filter};
+pub use schema::{
+    ColumnDef, SchemaEntry, add_column_definition, check_schema_sql, drop_column_definition,
+    find_index_for_column, find_table, find_table_indexes, is_table_constraint_word, parse_columns,
+    parse_table_sql, read_schema, read_schema_entries, read_schema_rows, table_columns,
+    unique_constraints,
+};
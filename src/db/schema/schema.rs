@@ -1,12 +1,22 @@
 //! SQLite schema table parsing.
 
-use anyhow::Result;
+use std::ops::Range;
 
+use crate::db::affinity::Affinity;
+use crate::db::btree::traverse_btree_table;
+use crate::db::collation::Collation;
 use crate::db::database::Database;
-use crate::db::page::{Page, Record, parse_index_cell};
+use crate::db::error::{DbError, DbResult};
+use crate::db::page::Record;
+use crate::db::pager::Pager;
+use crate::sql::ast::{
+    ColumnConstraint, CreateIndex, CreateTable, Expr, IndexedColumn, Statement, TableConstraint,
+};
+use crate::sql::{Token, parse, tokenize};
 
 /// Column indices in the sqlite_schema table.
 const SCHEMA_TYPE_COLUMN: usize = 0;
+const SCHEMA_NAME_COLUMN: usize = 1;
 const SCHEMA_TBL_NAME_COLUMN: usize = 2;
 const SCHEMA_ROOTPAGE_COLUMN: usize = 3;
 const SCHEMA_SQL_COLUMN: usize = 4;
@@ -15,6 +25,7 @@ const SCHEMA_SQL_COLUMN: usize = 4;
 #[derive(Debug, Clone)]
 pub struct SchemaEntry {
     pub entry_type: String,
+    pub name: String,
     pub tbl_name: String,
     pub rootpage: u32,
     pub sql: String,
@@ -28,463 +39,529 @@ impl SchemaEntry {
 
         Some(Self {
             entry_type,
+            name: record.read_string(SCHEMA_NAME_COLUMN).unwrap_or_default(),
             tbl_name,
             rootpage: record.read_int(SCHEMA_ROOTPAGE_COLUMN).unwrap_or(0) as u32,
             sql: record.read_string(SCHEMA_SQL_COLUMN).unwrap_or_default(),
         })
     }
 
-    /// Check if this is a user table (not an internal sqlite_ table).
-    pub fn is_user_table(&self) -> bool {
-        self.entry_type == "table" && !self.tbl_name.starts_with("sqlite_")
-    }
-
     /// Check if this is an index.
     pub fn is_index(&self) -> bool {
         self.entry_type == "index"
     }
-}
 
-/// Read all schema entries from the database.
-pub fn read_schema(db: &mut Database) -> Result<Vec<SchemaEntry>> {
-    let page_data = db.read_page(1)?;
-    let page = Page::new(page_data, 1);
-
-    let mut entries = Vec::new();
-    for cell_offset in page.cell_offsets() {
-        let (record, _) = Record::parse(page.data(), cell_offset);
-        if let Some(entry) = SchemaEntry::from_record(&record) {
-            entries.push(entry);
-        }
+    /// Check if this is a WITHOUT ROWID table, stored as an index B-tree keyed
+    /// by its PRIMARY KEY instead of a table B-tree keyed by rowid.
+    pub fn is_without_rowid(&self) -> bool {
+        self.entry_type == "table" && is_without_rowid(&self.sql)
     }
+}
 
-    Ok(entries)
+/// Read all schema entries from the database.
+pub fn read_schema(db: &mut Database) -> DbResult<Vec<SchemaEntry>> {
+    Ok(read_schema_rows(db)?
+        .into_iter()
+        .map(|(_, entry)| entry)
+        .collect())
 }
 
-/// Read user table names from the database.
-pub fn read_table_names(path: &str) -> Result<Vec<String>> {
-    let mut db = Database::open(path)?;
-    let entries = read_schema(&mut db)?;
+/// Read all schema entries from the database, along with their rowids.
+pub fn read_schema_rows(db: &mut Database) -> DbResult<Vec<(i64, SchemaEntry)>> {
+    // The schema table is itself a table B-tree rooted at page 1
+    let mut records = Vec::new();
+    traverse_btree_table(db, 1, &mut records)?;
 
-    Ok(entries
-        .into_iter()
-        .filter(|e| e.is_user_table())
-        .map(|e| e.tbl_name)
+    Ok(records
+        .iter()
+        .filter_map(|record| Some((record.rowid, SchemaEntry::from_record(record)?)))
         .collect())
 }
 
+/// Read every schema entry, in the order sqlite_schema stores them.
+pub fn read_schema_entries(pager: &mut Pager) -> DbResult<Vec<SchemaEntry>> {
+    let entries = read_schema(pager.read()?);
+    pager.end_statement()?;
+    entries
+}
+
 /// Find a table's schema entry by name.
-pub fn find_table(db: &mut Database, table_name: &str) -> Result<SchemaEntry> {
+pub fn find_table(db: &mut Database, table_name: &str) -> DbResult<SchemaEntry> {
     let entries = read_schema(db)?;
 
     entries
         .into_iter()
-        .find(|e| e.entry_type == "table" && e.tbl_name == table_name)
-        .ok_or_else(|| anyhow::anyhow!("Table '{}' not found", table_name))
+        .find(|e| e.entry_type == "table" && e.tbl_name.eq_ignore_ascii_case(table_name))
+        .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))
 }
 
-/// Find an index that can be used for a column on a table.
+/// A column of an index, parsed from its CREATE INDEX statement.
+#[derive(Debug, Clone)]
+pub struct IndexColumn {
+    pub name: String,
+    /// Collation given in the index definition, overriding the column's own.
+    pub collation: Option<String>,
+    pub descending: bool,
+}
+
+impl From<IndexedColumn> for IndexColumn {
+    fn from(column: IndexedColumn) -> Self {
+        Self {
+            name: column.name,
+            collation: column.collation,
+            descending: column.descending,
+        }
+    }
+}
+
+/// Find an index whose first column is `column` and whose keys are ordered by
+/// `collation`, so that it can answer `column = value` under that collation.
+/// Returns the index's schema entry and its first column.
 pub fn find_index_for_column(
     db: &mut Database,
     table_name: &str,
-    column_name: &str,
-) -> Result<Option<SchemaEntry>> {
+    column: &ColumnDef,
+    collation: &Collation,
+) -> DbResult<Option<(SchemaEntry, IndexColumn)>> {
     let entries = read_schema(db)?;
 
-    // Look for an index on this table and column
-    // Index SQL looks like: CREATE INDEX idx_name on table_name (column_name)
     for entry in entries {
-        if entry.is_index() {
-            let sql_lower = entry.sql.to_lowercase();
-            let expected_pattern = format!("on {} ({})", table_name, column_name).to_lowercase();
-            if sql_lower.contains(&expected_pattern) {
-                return Ok(Some(entry));
-            }
+        // Automatic indexes have no SQL to say how they are ordered, and an
+        // index this can't parse is simply not used: the table can be scanned
+        if !entry.is_index()
+            || !entry.tbl_name.eq_ignore_ascii_case(table_name)
+            || entry.sql.is_empty()
+        {
+            continue;
+        }
+        let Ok(index) = parse_index_sql(&entry) else {
+            continue;
+        };
+        // Partial indexes only hold some rows, so they can't be searched blindly
+        if index.where_clause.is_some() {
+            continue;
+        }
+        let Some(first) = index.columns.into_iter().next().map(IndexColumn::from) else {
+            continue;
+        };
+        if !first.name.eq_ignore_ascii_case(&column.name) {
+            continue;
+        }
+        let index_collation = first.collation.as_deref().or(column.collation.as_deref());
+        if Collation::lookup_or_binary(index_collation)? == *collation {
+            return Ok(Some((entry, first)));
         }
     }
 
     Ok(None)
 }
 
-/// Parse column names from a CREATE TABLE statement.
-pub fn parse_column_names(create_sql: &str) -> Vec<String> {
-    let start = match create_sql.find('(') {
-        Some(idx) => idx + 1,
-        None => return Vec::new(),
-    };
-    let end = match create_sql.rfind(')') {
-        Some(idx) => idx,
-        None => return Vec::new(),
-    };
-
-    create_sql[start..end]
-        .split(',')
-        .filter_map(|col_def| col_def.trim().split_whitespace().next())
-        .map(String::from)
-        .collect()
+/// Parse the CREATE INDEX statement sqlite_schema stores for an index,
+/// which automatic indexes don't have. SQL that doesn't parse is an error,
+/// as the index can't be kept up to date without knowing its columns.
+pub fn parse_index_sql(entry: &SchemaEntry) -> DbResult<CreateIndex> {
+    let malformed =
+        || DbError::InvalidStatement(format!("malformed database schema ({})", entry.name));
+    let statements = parse(&entry.sql).map_err(|err| match err {
+        DbError::Parse { .. } => malformed(),
+        err => err,
+    })?;
+    match statements.into_iter().next() {
+        Some(Statement::CreateIndex(index)) => Ok(index),
+        _ => Err(malformed()),
+    }
 }
 
-/// Count the number of rows in a table.
-pub fn count_table_rows(path: &str, table_name: &str) -> Result<usize> {
-    let mut db = Database::open(path)?;
-    let table = find_table(&mut db, table_name)?;
-
-    // Collect all records from the B-tree
-    let mut records = Vec::new();
-    traverse_btree_table(&mut db, table.rootpage, &mut records)?;
-
-    Ok(records.len())
+/// Check that the stored SQL of every table and index parses, as SQLite
+/// does before running anything against the schema. Indexes using features
+/// this can't read, such as expressions, are not malformed, and virtual
+/// tables, which have no root page, aren't parsed.
+pub fn check_schema_sql(entries: &[SchemaEntry]) -> DbResult<()> {
+    for entry in entries.iter().filter(|e| !e.sql.is_empty()) {
+        let parsed = match entry.entry_type.as_str() {
+            "table" if entry.rootpage == 0 => true,
+            "table" => matches!(
+                parse(&entry.sql).map(|s| s.into_iter().next()),
+                Ok(Some(Statement::CreateTable(_)))
+            ),
+            "index" => !matches!(parse_index_sql(entry), Err(DbError::InvalidStatement(_))),
+            _ => true,
+        };
+        if !parsed {
+            return Err(DbError::InvalidStatement(format!(
+                "malformed database schema ({})",
+                entry.name
+            )));
+        }
+    }
+    Ok(())
 }
 
-/// Select multiple columns from a table and return all rows.
-pub fn select_columns(
-    path: &str,
-    table_name: &str,
-    column_names: &[&str],
-) -> Result<Vec<Vec<String>>> {
-    let mut db = Database::open(path)?;
-    let table = find_table(&mut db, table_name)?;
-
-    // Parse column names from CREATE TABLE
-    let columns = parse_column_names(&table.sql);
+/// An index on a table, parsed from its definition.
+#[derive(Debug, Clone)]
+pub struct IndexDef {
+    pub entry: SchemaEntry,
+    pub columns: Vec<IndexColumn>,
+    /// No two rows may have equal, non-NULL values in the indexed columns.
+    pub unique: bool,
+    /// Condition of a partial index: only rows satisfying it are indexed.
+    pub where_clause: Option<Expr>,
+}
 
-    // Find column indices
-    let column_indices: Vec<usize> = column_names
-        .iter()
-        .map(|col_name| {
-            columns
-                .iter()
-                .position(|c| c.eq_ignore_ascii_case(col_name))
-                .ok_or_else(|| {
-                    anyhow::anyhow!("Column '{}' not found in table '{}'", col_name, table_name)
-                })
-        })
-        .collect::<Result<Vec<_>>>()?;
+/// Read every index on a table, including the automatic indexes SQLite
+/// creates for UNIQUE and PRIMARY KEY constraints.
+pub fn find_table_indexes(db: &mut Database, table: &SchemaEntry) -> DbResult<Vec<IndexDef>> {
+    let constraints = unique_constraints(&table.sql)?;
+    let autoindex_prefix = format!("sqlite_autoindex_{}_", table.tbl_name);
 
-    // Collect all records from the B-tree
-    let mut record_data = Vec::new();
-    traverse_btree_table(&mut db, table.rootpage, &mut record_data)?;
+    let mut indexes = Vec::new();
+    for entry in read_schema(db)? {
+        if !entry.is_index() || !entry.tbl_name.eq_ignore_ascii_case(&table.tbl_name) {
+            continue;
+        }
 
-    // Parse all records and extract requested columns
-    let rows: Vec<Vec<String>> = record_data
-        .iter()
-        .map(|(page_data, offset)| {
-            let (record, _) = Record::parse(page_data, *offset);
-            record.read_strings(&column_indices)
-        })
-        .collect();
+        // Automatic indexes have no SQL; their number says which constraint they enforce
+        if let Some(number) = entry.name.strip_prefix(&autoindex_prefix) {
+            let columns = number
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| constraints.get(n.checked_sub(1)?))
+                .filter(|constraint| !constraint.keys_table)
+                .map(|constraint| constraint.columns.clone())
+                .ok_or_else(|| DbError::CorruptPage {
+                    page: entry.rootpage,
+                    reason: format!("no constraint matches automatic index {}", entry.name),
+                })?;
+            indexes.push(IndexDef {
+                entry,
+                columns,
+                unique: true,
+                where_clause: None,
+            });
+            continue;
+        }
 
-    Ok(rows)
+        let index = parse_index_sql(&entry)?;
+        indexes.push(IndexDef {
+            columns: index.columns.into_iter().map(IndexColumn::from).collect(),
+            unique: index.unique,
+            where_clause: index.where_clause,
+            entry,
+        });
+    }
+    Ok(indexes)
 }
 
-/// Select multiple columns from a table with a WHERE filter and return matching rows.
-pub fn select_columns_with_filter(
-    path: &str,
-    table_name: &str,
-    column_names: &[&str],
-    where_clause: &str,
-) -> Result<Vec<Vec<String>>> {
-    let mut db = Database::open(path)?;
-    let table = find_table(&mut db, table_name)?;
-
-    // Parse WHERE clause to get column and value
-    let (filter_column, filter_value) = parse_where_clause(where_clause)?;
-
-    // Check if there's an index on the filter column
-    let index_opt = find_index_for_column(&mut db, table_name, &filter_column)?;
-
-    // Parse column names from CREATE TABLE
-    let columns = parse_column_names(&table.sql);
+/// A UNIQUE or PRIMARY KEY constraint, which SQLite enforces with an automatic index.
+#[derive(Debug, Clone)]
+pub struct UniqueConstraint {
+    pub columns: Vec<IndexColumn>,
+    /// The PRIMARY KEY of a WITHOUT ROWID table, which keys the table itself
+    /// rather than an index of its own, though it still takes a number.
+    pub keys_table: bool,
+}
 
-    // Find column indices for SELECT columns
-    // Special handling for "id" column which might be the rowid
-    let column_indices: Vec<usize> = column_names
-        .iter()
-        .map(|col_name| {
-            if col_name.eq_ignore_ascii_case("id") {
-                // Check if this is an INTEGER PRIMARY KEY column (which aliases rowid)
-                if table.sql.to_lowercase().contains("id integer primary key") {
-                    return Ok(usize::MAX); // Special marker for rowid
-                }
-            }
-            columns
+/// A table's UNIQUE and PRIMARY KEY constraints, in the order SQLite
+/// numbers their automatic indexes.
+///
+/// A PRIMARY KEY that aliases the rowid needs no index and is left out, as
+/// are repeats of earlier column lists.
+pub fn unique_constraints(create_sql: &str) -> DbResult<Vec<UniqueConstraint>> {
+    let table = parse_table_sql(create_sql)?;
+    let columns = table_columns(&table);
+    let is_rowid_key = |keys: &[IndexColumn]| {
+        keys.len() == 1
+            && columns
                 .iter()
-                .position(|c| c.eq_ignore_ascii_case(col_name))
-                .ok_or_else(|| {
-                    anyhow::anyhow!("Column '{}' not found in table '{}'", col_name, table_name)
-                })
-        })
-        .collect::<Result<Vec<_>>>()?;
-
-    let mut rows = Vec::new();
+                .any(|c| c.is_rowid_alias && c.name.eq_ignore_ascii_case(&keys[0].name))
+    };
 
-    if let Some(index) = index_opt {
-        // Use index to find matching rowids
-        eprintln!(
-            "Using index {} for column {}",
-            index.tbl_name, filter_column
-        );
-        let matching_rowids = search_index_btree(&mut db, index.rootpage, &filter_value)?;
+    let mut constraints: Vec<UniqueConstraint> = Vec::new();
+    let mut add = |keys: Vec<IndexColumn>, primary: bool| {
+        let repeat = constraints.iter().any(|existing| {
+            existing.columns.len() == keys.len()
+                && existing
+                    .columns
+                    .iter()
+                    .zip(&keys)
+                    .all(|(a, b)| a.name.eq_ignore_ascii_case(&b.name))
+        });
+        if !repeat {
+            constraints.push(UniqueConstraint {
+                columns: keys,
+                keys_table: primary && table.without_rowid,
+            });
+        }
+    };
 
-        // Fetch each record by rowid
-        for rowid in matching_rowids {
-            if let Some((page_data, offset)) = find_record_by_rowid(&mut db, table.rootpage, rowid)?
-            {
-                let (record, _) = Record::parse(&page_data, offset);
-                rows.push(record.read_strings(&column_indices));
+    // Column constraints number first, as they come first in the statement
+    for column in &table.columns {
+        for constraint in &column.constraints {
+            let key = |descending| IndexColumn {
+                name: column.name.clone(),
+                collation: None,
+                descending,
+            };
+            match constraint {
+                ColumnConstraint::PrimaryKey { descending, .. } => {
+                    let key = key(*descending);
+                    if !is_rowid_key(std::slice::from_ref(&key)) {
+                        add(vec![key], true);
+                    }
+                }
+                ColumnConstraint::Unique => add(vec![key(false)], false),
+                _ => {}
             }
         }
-    } else {
-        // No index available, do full table scan
-        eprintln!(
-            "No index found for column {}, doing full table scan",
-            filter_column
-        );
-
-        // Find the index of the filter column
-        let filter_column_index = columns
-            .iter()
-            .position(|c| c.eq_ignore_ascii_case(&filter_column))
-            .ok_or_else(|| {
-                anyhow::anyhow!(
-                    "Column '{}' not found in table '{}'",
-                    filter_column,
-                    table_name
-                )
-            })?;
-
-        // Collect all records from the B-tree
-        let mut record_data = Vec::new();
-        traverse_btree_table(&mut db, table.rootpage, &mut record_data)?;
-
-        // Filter and extract matching records
-        for (page_data, offset) in record_data {
-            let (record, _) = Record::parse(&page_data, offset);
-
-            // Check if this row matches the filter
-            if let Some(value) = record.read_string(filter_column_index) {
-                if value == filter_value {
-                    // This row matches - extract the requested columns
-                    rows.push(record.read_strings(&column_indices));
+    }
+    for constraint in &table.constraints {
+        match constraint {
+            TableConstraint::PrimaryKey(keys) => {
+                let keys: Vec<IndexColumn> = keys.iter().cloned().map(IndexColumn::from).collect();
+                if !is_rowid_key(&keys) {
+                    add(keys, true);
                 }
             }
+            TableConstraint::Unique(keys) => {
+                add(keys.iter().cloned().map(IndexColumn::from).collect(), false)
+            }
+            _ => {}
         }
     }
-
-    Ok(rows)
+    Ok(constraints)
 }
 
-/// Parse a simple WHERE clause of the form "column = 'value'"
-fn parse_where_clause(where_clause: &str) -> Result<(String, String)> {
-    // Simple parsing for "column = 'value'" pattern
-    let parts: Vec<&str> = where_clause.split('=').collect();
-    if parts.len() != 2 {
-        anyhow::bail!("Invalid WHERE clause format. Expected: column = 'value'");
-    }
+/// Words that start a table constraint rather than a column definition.
+const TABLE_CONSTRAINT_WORDS: &[&str] = &["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"];
 
-    let column = parts[0].trim();
-    let value_part = parts[1].trim();
+/// Check if a word starts a table constraint rather than a column definition.
+pub fn is_table_constraint_word(word: &str) -> bool {
+    is_one_of(word, TABLE_CONSTRAINT_WORDS)
+}
 
-    // Remove quotes from value if present
-    let value = if value_part.starts_with('\'') && value_part.ends_with('\'') {
-        &value_part[1..value_part.len() - 1]
-    } else {
-        value_part
-    };
+/// A column definition parsed from a CREATE TABLE statement.
+#[derive(Debug, Clone)]
+pub struct ColumnDef {
+    pub name: String,
+    pub declared_type: String,
+    /// INTEGER PRIMARY KEY columns are stored as the rowid, not in the record.
+    pub is_rowid_alias: bool,
+    /// Collating sequence from a `COLLATE name` constraint.
+    pub collation: Option<String>,
+    /// Position of the column within the PRIMARY KEY, if it is part of it.
+    pub primary_key: Option<usize>,
+    /// Whether the PRIMARY KEY orders this column in descending order.
+    pub primary_key_descending: bool,
+    pub not_null: bool,
+    /// Expression of a `DEFAULT` constraint.
+    pub default: Option<Expr>,
+    /// Whether this is an `INTEGER PRIMARY KEY AUTOINCREMENT` column, whose
+    /// rowids are never used again.
+    pub autoincrement: bool,
+}
 
-    Ok((column.to_string(), value.to_string()))
+impl ColumnDef {
+    /// The column's type affinity, from its declared type.
+    pub fn affinity(&self) -> Affinity {
+        Affinity::from_declared_type(&self.declared_type)
+    }
 }
 
-/// Traverse a B-tree starting from the given page and collect all leaf records.
-/// Search an index B-tree for matching values and return rowids.
-fn search_index_btree(db: &mut Database, page_num: u32, search_value: &str) -> Result<Vec<i64>> {
-    let page_data = db.read_page(page_num)?;
-    let page = Page::new(page_data, page_num);
-
-    let mut rowids = Vec::new();
-
-    if page.is_leaf() {
-        // This is a leaf page, check each cell
-        let cells = page.cell_offsets();
-        if !cells.is_empty() {
-            // Show first few values for debugging
-            let first_cell = parse_index_cell(page.data(), cells[0]);
-            let last_cell = parse_index_cell(page.data(), cells[cells.len() - 1]);
-            eprintln!(
-                "Page {} range: '{}' to '{}'",
-                page_num,
-                first_cell.values.get(0).unwrap_or(&String::new()),
-                last_cell.values.get(0).unwrap_or(&String::new())
-            );
-        }
+/// Parse the CREATE TABLE statement sqlite_schema stores for a table.
+pub fn parse_table_sql(create_sql: &str) -> DbResult<CreateTable> {
+    let malformed = || DbError::InvalidStatement(format!("malformed schema SQL: {}", create_sql));
+    let statements = parse(create_sql).map_err(|err| match err {
+        DbError::Parse { .. } => malformed(),
+        err => err,
+    })?;
+    match statements.into_iter().next() {
+        Some(Statement::CreateTable(table)) => Ok(table),
+        _ => Err(malformed()),
+    }
+}
 
-        for offset in cells {
-            let cell = parse_index_cell(page.data(), offset);
-            // For now, assume single-column index
-            if !cell.values.is_empty() && cell.values[0] == search_value {
-                eprintln!(
-                    "Found match: rowid={}, value='{}'",
-                    cell.rowid, cell.values[0]
-                );
-                rowids.push(cell.rowid);
-            }
-        }
-        eprintln!(
-            "Searched leaf page {}, found {} matches",
-            page_num,
-            rowids.len()
-        );
-    } else {
-        // This is an interior page, we need to find all matching pages
-        // Since the same value can appear in multiple leaf pages, we need to check
-        // multiple children
-        eprintln!("Searching interior page {}", page_num);
-        let cells = page.cell_offsets();
-        let mut children_to_search = Vec::new();
-
-        // Find all children that might contain our search value
-        for (i, offset) in cells.iter().enumerate() {
-            match page.parse_interior_index_cell(*offset) {
-                Ok((left_child, key)) => {
-                    // The left child contains all values < key
-                    // So if our search_value <= key, we need to search left child
-                    if search_value <= &key {
-                        children_to_search.push(left_child);
-                        // If search_value == key, we also need to search the next child
-                        // because the value might span pages
-                        if search_value == &key && i + 1 < cells.len() {
-                            // The next cell's left child or rightmost if last cell
-                            continue; // Will be handled by next iteration
-                        }
-                    }
-                    // If this is the last cell and search_value >= key,
-                    // we need to search rightmost
-                    if i == cells.len() - 1 && search_value >= &key {
-                        if let Some(rightmost) = page.rightmost_pointer() {
-                            children_to_search.push(rightmost);
-                        }
-                    }
-                }
-                Err(_) => {
-                    // If parsing fails, search this child to be safe
-                    let (left_child, _) = page.parse_interior_cell(*offset);
-                    if left_child != 0 {
-                        children_to_search.push(left_child);
+/// Parse column definitions from a CREATE TABLE statement.
+pub fn parse_columns(create_sql: &str) -> DbResult<Vec<ColumnDef>> {
+    Ok(table_columns(&parse_table_sql(create_sql)?))
+}
+
+/// The columns of a parsed CREATE TABLE statement, with what its
+/// constraints say about each.
+pub fn table_columns(table: &CreateTable) -> Vec<ColumnDef> {
+    let mut columns: Vec<ColumnDef> = table
+        .columns
+        .iter()
+        .map(|column| {
+            let mut def = ColumnDef {
+                name: column.name.clone(),
+                declared_type: column.type_name.clone(),
+                is_rowid_alias: false,
+                collation: None,
+                primary_key: None,
+                primary_key_descending: false,
+                not_null: false,
+                default: None,
+                autoincrement: false,
+            };
+            for constraint in &column.constraints {
+                match constraint {
+                    ColumnConstraint::PrimaryKey {
+                        descending,
+                        autoincrement,
+                    } => {
+                        def.primary_key = Some(0);
+                        def.primary_key_descending = *descending;
+                        def.autoincrement = *autoincrement;
                     }
+                    ColumnConstraint::NotNull => def.not_null = true,
+                    ColumnConstraint::Default(expr) => def.default = Some(expr.clone()),
+                    ColumnConstraint::Collate(name) => def.collation = Some(name.clone()),
+                    _ => {}
                 }
             }
-        }
+            // INTEGER PRIMARY KEY DESC is famously not a rowid alias, and WITHOUT ROWID
+            // tables have no rowid to alias
+            def.is_rowid_alias = def.declared_type.eq_ignore_ascii_case("INTEGER")
+                && def.primary_key.is_some()
+                && !def.primary_key_descending
+                && !table.without_rowid;
+            def
+        })
+        .collect();
 
-        // If no specific children selected, check rightmost
-        if children_to_search.is_empty() {
-            if let Some(rightmost) = page.rightmost_pointer() {
-                children_to_search.push(rightmost);
+    // A table-level PRIMARY KEY (col) makes an INTEGER column the rowid
+    for constraint in &table.constraints {
+        let TableConstraint::PrimaryKey(keys) = constraint else {
+            continue;
+        };
+        for (position, key) in keys.iter().enumerate() {
+            if let Some(column) = columns
+                .iter_mut()
+                .find(|c| c.name.eq_ignore_ascii_case(&key.name))
+            {
+                column.primary_key = Some(position);
+                column.primary_key_descending = key.descending;
+                column.is_rowid_alias = keys.len() == 1
+                    && column.declared_type.eq_ignore_ascii_case("INTEGER")
+                    && !table.without_rowid;
             }
         }
-
-        // Search all selected children
-        for child_page in children_to_search {
-            let mut child_rowids = search_index_btree(db, child_page, search_value)?;
-            rowids.append(&mut child_rowids);
-        }
     }
 
-    Ok(rowids)
+    columns
 }
 
-/// Find a record in a table B-tree by rowid.
-fn find_record_by_rowid(
-    db: &mut Database,
-    page_num: u32,
-    target_rowid: i64,
-) -> Result<Option<(Vec<u8>, usize)>> {
-    let page_data = db.read_page(page_num)?;
-    let page = Page::new(page_data, page_num);
-
-    if page.is_leaf() {
-        // Search this leaf page for the rowid
-        for offset in page.cell_offsets() {
-            let (record, _) = Record::parse(page.data(), offset);
-            if record.rowid == target_rowid {
-                return Ok(Some((page.data().to_vec(), offset)));
-            }
-        }
-    } else {
-        // This is an interior page, determine which child to search
-        let mut child_to_search = None;
-
-        // Check each interior cell
-        for offset in page.cell_offsets() {
-            let (left_child, key) = page.parse_interior_cell(offset);
-            if target_rowid <= key {
-                child_to_search = Some(left_child);
-                break;
-            }
-        }
-
-        // If not found in any cell, search the rightmost child
-        if child_to_search.is_none() {
-            if let Some(rightmost) = page.rightmost_pointer() {
-                child_to_search = Some(rightmost);
-            }
-        }
+/// Add a column definition to a CREATE TABLE statement, after the last
+/// column and before any table constraints.
+pub fn add_column_definition(create_sql: &str, definition: &str) -> String {
+    let end = table_definitions(create_sql)
+        .into_iter()
+        .rfind(|(_, is_constraint)| !is_constraint)
+        .map_or(create_sql.len(), |(range, _)| range.end);
+    format!(
+        "{}, {}{}",
+        &create_sql[..end],
+        definition,
+        &create_sql[end..]
+    )
+}
 
-        // Search the appropriate child
-        if let Some(child_page) = child_to_search {
-            return find_record_by_rowid(db, child_page, target_rowid);
-        }
-    }
+/// Remove a column's definition from a CREATE TABLE statement, along with
+/// the comma separating it from its neighbour. Returns `None` if the table
+/// has no such column, or no other definitions.
+pub fn drop_column_definition(create_sql: &str, column: &str) -> Option<String> {
+    let definitions = table_definitions(create_sql);
+    let position = definitions.iter().position(|(range, is_constraint)| {
+        !is_constraint
+            && split_identifier(&create_sql[range.clone()])
+                .is_some_and(|(name, _)| name.eq_ignore_ascii_case(column))
+    })?;
+    let removed = match position {
+        0 => definitions[0].0.start..definitions.get(1)?.0.start,
+        _ => definitions[position - 1].0.end..definitions[position].0.end,
+    };
+    Some(format!(
+        "{}{}",
+        &create_sql[..removed.start],
+        &create_sql[removed.end..]
+    ))
+}
 
-    Ok(None)
+/// The byte ranges of the column definitions and table constraints of a
+/// CREATE TABLE statement, without surrounding whitespace, and whether each
+/// is a table constraint.
+fn table_definitions(create_sql: &str) -> Vec<(Range<usize>, bool)> {
+    let (Some(start), Some(end)) = (create_sql.find('('), create_sql.rfind(')')) else {
+        return Vec::new();
+    };
+    split_ranges(&create_sql[start + 1..end])
+        .into_iter()
+        .map(|range| {
+            let part = &create_sql[start + 1 + range.start..start + 1 + range.end];
+            let trimmed_start = start + 1 + range.start + (part.len() - part.trim_start().len());
+            let trimmed = trimmed_start..trimmed_start + part.trim().len();
+            let first_word = create_sql[trimmed.clone()]
+                .split(|c: char| c.is_whitespace() || c == '(')
+                .next()
+                .unwrap_or_default();
+            (trimmed, is_one_of(first_word, TABLE_CONSTRAINT_WORDS))
+        })
+        .collect()
 }
 
-fn traverse_btree_table(
-    db: &mut Database,
-    page_num: u32,
-    records: &mut Vec<(Vec<u8>, usize)>,
-) -> Result<()> {
-    let page_data = db.read_page(page_num)?;
-    let page = Page::new(page_data, page_num);
-
-    if page.is_leaf() {
-        // This is a leaf page, collect all records
-        for offset in page.cell_offsets() {
-            records.push((page.data().to_vec(), offset));
-        }
-    } else {
-        // This is an interior page, traverse child pages
-        let mut child_pages = Vec::new();
-
-        // Process each interior cell to get left child pointers
-        for offset in page.cell_offsets() {
-            let (left_child, _key) = page.parse_interior_cell(offset);
-            if left_child == 0 {
-                eprintln!(
-                    "Warning: found zero page number in interior cell at page {}",
-                    page_num
-                );
-                continue;
-            }
-            child_pages.push(left_child);
-        }
+/// Check if a CREATE TABLE statement ends with the WITHOUT ROWID option.
+fn is_without_rowid(create_sql: &str) -> bool {
+    let Some(end) = create_sql.rfind(')') else {
+        return false;
+    };
+    let options: Vec<String> = create_sql[end + 1..]
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|word| !word.is_empty())
+        .map(str::to_uppercase)
+        .collect();
+    options.windows(2).any(|pair| pair == ["WITHOUT", "ROWID"])
+}
 
-        // Add the rightmost child
-        if let Some(rightmost) = page.rightmost_pointer() {
-            if rightmost == 0 {
-                eprintln!(
-                    "Warning: found zero page number in rightmost pointer at page {}",
-                    page_num
-                );
-            } else {
-                child_pages.push(rightmost);
+/// The byte ranges of the parts of text between commas that aren't inside
+/// parentheses or quotes.
+fn split_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '[') => quote = Some(']'),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                parts.push(start..i);
+                start = i + 1;
             }
+            _ => {}
         }
+    }
+    parts.push(start..text.len());
+    parts
+}
 
-        // Recursively traverse all child pages
-        for child_page in child_pages {
-            traverse_btree_table(db, child_page, records)?;
+/// Split a leading, possibly quoted, identifier from the rest of a
+/// definition, reading it as the lexer does so that doubled quotes inside
+/// stand for one.
+fn split_identifier(text: &str) -> Option<(String, &str)> {
+    let first = tokenize(text).ok()?.into_iter().next()?;
+    match first.token {
+        Token::Word(name) | Token::QuotedIdentifier(name) | Token::String(name) => {
+            Some((name, &text[first.end..]))
         }
+        _ => None,
     }
+}
 
-    Ok(())
+/// Check if a word is one of the given keywords, ignoring case.
+fn is_one_of(word: &str, keywords: &[&str]) -> bool {
+    keywords.iter().any(|k| k.eq_ignore_ascii_case(word))
 }
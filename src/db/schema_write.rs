@@ -0,0 +1,73 @@
+//! Changing sqlite_schema: adding, replacing and removing its rows, and
+//! bumping the schema cookie so that other connections notice.
+
+use super::btree_write::{delete_table_row, insert_table_row, max_rowid};
+use super::constants::SCHEMA_COOKIE_OFFSET;
+use super::database::Database;
+use super::encoding::TextEncoding;
+use super::error::{DbResult, read_u32, write_u32};
+use super::page::encode_record;
+use super::schema::{SchemaEntry, read_schema_rows};
+use super::value::Value;
+
+/// Add a row to sqlite_schema, after every existing row.
+pub fn insert_schema_entry(db: &mut Database, entry: &SchemaEntry) -> DbResult<()> {
+    let rowid = max_rowid(db, 1)? + 1;
+    write_schema_entry(db, rowid, entry)
+}
+
+/// Replace the sqlite_schema row with the given rowid.
+pub fn replace_schema_entry(db: &mut Database, rowid: i64, entry: &SchemaEntry) -> DbResult<()> {
+    delete_table_row(db, 1, rowid)?;
+    write_schema_entry(db, rowid, entry)
+}
+
+/// Remove the sqlite_schema row with the given rowid.
+pub fn delete_schema_entry(db: &mut Database, rowid: i64) -> DbResult<()> {
+    delete_table_row(db, 1, rowid)?;
+    Ok(())
+}
+
+/// Point the sqlite_schema row of the B-tree rooted at `from` at its new root.
+pub fn move_schema_root(db: &mut Database, from: u32, to: u32) -> DbResult<()> {
+    for (rowid, entry) in read_schema_rows(db)? {
+        if entry.rootpage == from {
+            let entry = SchemaEntry {
+                rootpage: to,
+                ..entry
+            };
+            return replace_schema_entry(db, rowid, &entry);
+        }
+    }
+    Ok(())
+}
+
+fn write_schema_entry(db: &mut Database, rowid: i64, entry: &SchemaEntry) -> DbResult<()> {
+    insert_table_row(db, 1, rowid, &encode_schema_entry(entry, db.encoding))?;
+    Ok(())
+}
+
+/// The record a sqlite_schema row holds for an entry.
+pub fn encode_schema_entry(entry: &SchemaEntry, encoding: TextEncoding) -> Vec<u8> {
+    // Automatic indexes have NULL for their SQL
+    let sql = match entry.sql.as_str() {
+        "" => Value::Null,
+        sql => Value::Text(sql.to_string()),
+    };
+    let record = [
+        Value::Text(entry.entry_type.clone()),
+        Value::Text(entry.name.clone()),
+        Value::Text(entry.tbl_name.clone()),
+        Value::Integer(entry.rootpage as i64),
+        sql,
+    ];
+    encode_record(&record, encoding)
+}
+
+/// Change the schema cookie, telling other connections to reread the schema.
+pub fn bump_schema_cookie(db: &mut Database) -> DbResult<()> {
+    let mut header = db.read_page(1)?;
+    let cookie = read_u32(&header, SCHEMA_COOKIE_OFFSET)?.wrapping_add(1);
+    write_u32(&mut header, SCHEMA_COOKIE_OFFSET, cookie)?;
+    db.write_page(1, header)
+}
@@ -0,0 +1,574 @@
+//! Writing rows to a table, keeping its indexes in step and enforcing its constraints.
+
+use crate::sql::ast::{ConflictAction, Expr};
+
+use super::affinity::Affinity;
+use super::btree::{find_record_by_rowid, traverse_btree_table};
+use super::btree_write::{
+    KeyColumn, compare_keys, delete_index_entry, delete_table_row, find_index_entry,
+    insert_index_entry, insert_table_row, load_index, max_rowid,
+};
+use super::collation::Collation;
+use super::database::Database;
+use super::encoding::TextEncoding;
+use super::error::{DbError, DbResult};
+use super::eval::{EmptyScope, RowScope, eval, eval_condition};
+use super::page::{Record, encode_record, index_cell_rowid};
+use super::query::{ColumnRef, Table, filter_rows};
+use super::schema::{ColumnDef, SchemaEntry, find_table, find_table_indexes};
+use super::value::Value;
+
+/// The table AUTOINCREMENT tables record their largest rowid in.
+pub const SEQUENCE_TABLE: &str = "sqlite_sequence";
+
+/// A table row, with a value for every column in table order.
+/// The rowid alias column, if any, holds the rowid.
+pub struct Row {
+    pub rowid: i64,
+    pub values: Vec<Value>,
+}
+
+/// An index on the table being written.
+struct Index {
+    entry: SchemaEntry,
+    /// Table column positions of the indexed columns.
+    columns: Vec<usize>,
+    /// Ordering of every column of an entry, including the trailing row key.
+    key: Vec<KeyColumn>,
+    unique: bool,
+    where_clause: Option<Expr>,
+}
+
+/// A table opened for writing, along with its indexes.
+pub struct TableWriter {
+    pub table: Table,
+    indexes: Vec<Index>,
+    /// Table column positions of the PRIMARY KEY of a WITHOUT ROWID table, in key order.
+    primary_key: Vec<usize>,
+    primary_key_order: Vec<KeyColumn>,
+    autoincrement: bool,
+}
+
+impl TableWriter {
+    /// Open a table and its indexes for writing.
+    pub fn open(db: &mut Database, table_name: &str) -> DbResult<Self> {
+        let entry = find_table(db, table_name)?;
+        let index_defs = find_table_indexes(db, &entry)?;
+        let table = Table::new(entry, db.encoding)?;
+        let autoincrement = table.columns.iter().any(|c| c.autoincrement);
+
+        let mut primary_key: Vec<usize> = (0..table.columns.len())
+            .filter(|&idx| table.without_rowid && table.columns[idx].primary_key.is_some())
+            .collect();
+        primary_key.sort_by_key(|&idx| table.columns[idx].primary_key);
+        let primary_key_order = primary_key
+            .iter()
+            .map(|&idx| {
+                let def = &table.columns[idx];
+                column_key(def, None, def.primary_key_descending)
+            })
+            .collect::<DbResult<Vec<_>>>()?;
+
+        let mut indexes = Vec::new();
+        for def in index_defs {
+            let mut columns = Vec::new();
+            let mut key = Vec::new();
+            for column in &def.columns {
+                let Some(idx) = table
+                    .columns
+                    .iter()
+                    .position(|c| c.name.eq_ignore_ascii_case(&column.name))
+                else {
+                    return Err(DbError::UnsupportedFeature(format!(
+                        "index {} on an expression",
+                        def.entry.name
+                    )));
+                };
+                columns.push(idx);
+                key.push(column_key(
+                    &table.columns[idx],
+                    column.collation.as_deref(),
+                    column.descending,
+                )?);
+            }
+            // Entries end with the rowid, or the PRIMARY KEY columns not already indexed
+            if table.without_rowid {
+                for (&idx, order) in primary_key.iter().zip(&primary_key_order) {
+                    if !columns.contains(&idx) {
+                        key.push(order.clone());
+                    }
+                }
+            } else {
+                key.push(KeyColumn::default());
+            }
+            indexes.push(Index {
+                columns,
+                key,
+                unique: def.unique,
+                where_clause: def.where_clause,
+                entry: def.entry,
+            });
+        }
+
+        Ok(Self {
+            table,
+            indexes,
+            primary_key,
+            primary_key_order,
+            autoincrement,
+        })
+    }
+
+    /// Insert a row, given its values in table order and, for rowid tables,
+    /// the rowid to use (NULL picks the next one). Values are converted by
+    /// their column's affinity first.
+    ///
+    /// A row that violates a constraint is handled as `conflict` says.
+    /// Returns `false` if the row was skipped by `OR IGNORE`.
+    pub fn insert(
+        &self,
+        db: &mut Database,
+        rowid: Value,
+        mut values: Vec<Value>,
+        conflict: ConflictAction,
+    ) -> DbResult<bool> {
+        let table = &self.table;
+        for (idx, def) in table.columns.iter().enumerate() {
+            if def.is_rowid_alias {
+                continue;
+            }
+            values[idx] = def
+                .affinity()
+                .apply(std::mem::replace(&mut values[idx], Value::Null));
+
+            // WITHOUT ROWID tables can't hold NULL in their PRIMARY KEY
+            let not_null = def.not_null || (table.without_rowid && def.primary_key.is_some());
+            if !not_null || values[idx] != Value::Null {
+                continue;
+            }
+            match conflict {
+                ConflictAction::Ignore => return Ok(false),
+                ConflictAction::Replace => {
+                    values[idx] = def.affinity().apply(default_value(def, table.encoding)?)
+                }
+                _ => {}
+            }
+            if values[idx] == Value::Null {
+                return Err(DbError::Constraint("NOT NULL", self.column_names(&[idx])));
+            }
+        }
+
+        let root = table.entry.rootpage;
+        let row = if table.without_rowid {
+            Row { rowid: 0, values }
+        } else {
+            let rowid = match Affinity::Integer.apply(rowid) {
+                Value::Null => self.next_rowid(db)?,
+                Value::Integer(rowid) => rowid,
+                _ => return Err(DbError::InvalidStatement("datatype mismatch".to_string())),
+            };
+            let alias = table.columns.iter().position(|c| c.is_rowid_alias);
+            if let Some(record) = find_record_by_rowid(db, root, rowid)? {
+                let existing = self.row_from_record(&record);
+                let columns: Vec<usize> = alias.into_iter().collect();
+                if !self.resolve_conflict(db, conflict, &existing, &columns)? {
+                    return Ok(false);
+                }
+            }
+            if let Some(alias) = alias {
+                values[alias] = Value::Integer(rowid);
+            }
+            Row { rowid, values }
+        };
+
+        // Like SQLite, check the most recently created index first. NULLs are
+        // distinct from each other, so they never violate a UNIQUE index.
+        for index in self.indexes.iter().rev().filter(|index| index.unique) {
+            if !self.is_indexed(index, &row)? {
+                continue;
+            }
+            let entry = self.index_entry(index, &row);
+            let key = &entry[..index.columns.len()];
+            if key.contains(&Value::Null) {
+                continue;
+            }
+            let order = &index.key[..index.columns.len()];
+            if let Some(payload) = find_index_entry(db, index.entry.rootpage, key, order)? {
+                let existing = self.row_for_index_entry(db, index, &payload)?;
+                if !self.resolve_conflict(db, conflict, &existing, &index.columns)? {
+                    return Ok(false);
+                }
+            }
+        }
+
+        // The PRIMARY KEY of a WITHOUT ROWID table is checked after its other indexes
+        if table.without_rowid
+            && let Some(record) = self.find_record(db, &row)?
+        {
+            let existing = self.row_from_record(&record);
+            if !self.resolve_conflict(db, conflict, &existing, &self.primary_key)? {
+                return Ok(false);
+            }
+        }
+
+        if table.without_rowid {
+            let payload = encode_record(&self.table_record(&row), db.encoding);
+            insert_index_entry(db, root, &payload, &self.primary_key_order)?;
+        } else {
+            let payload = encode_record(&self.table_record(&row), db.encoding);
+            insert_table_row(db, root, row.rowid, &payload)?;
+        }
+        for index in &self.indexes {
+            if self.is_indexed(index, &row)? {
+                let payload = encode_record(&self.index_entry(index, &row), db.encoding);
+                insert_index_entry(db, index.entry.rootpage, &payload, &index.key)?;
+            }
+        }
+        if self.autoincrement {
+            self.update_sequence(db, row.rowid)?;
+        }
+        Ok(true)
+    }
+
+    /// Delete a row and its index entries.
+    pub fn delete(&self, db: &mut Database, row: &Row) -> DbResult<()> {
+        for index in &self.indexes {
+            if self.is_indexed(index, row)? {
+                let entry = self.index_entry(index, row);
+                delete_index_entry(db, index.entry.rootpage, &entry, &index.key)?;
+            }
+        }
+        let root = self.table.entry.rootpage;
+        if self.table.without_rowid {
+            let record = self.table_record(row);
+            delete_index_entry(db, root, &record, &self.primary_key_order)?;
+        } else {
+            delete_table_row(db, root, row.rowid)?;
+        }
+        Ok(())
+    }
+
+    /// Fill a newly created, empty index with an entry for every row. The
+    /// entries are sorted first, so that the B-tree is built bottom-up.
+    pub fn build_index(&self, db: &mut Database, name: &str) -> DbResult<()> {
+        let index = self
+            .indexes
+            .iter()
+            .find(|index| index.entry.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| DbError::InvalidStatement(format!("no such index: {}", name)))?;
+
+        let mut entries = Vec::new();
+        for record in filter_rows(db, &self.table, None)? {
+            let row = self.row_from_record(&record);
+            if self.is_indexed(index, &row)? {
+                entries.push(self.index_entry(index, &row));
+            }
+        }
+        let encoding = db.encoding;
+        entries.sort_by(|a, b| compare_keys(a, b, &index.key, encoding));
+
+        // Sorting brings equal keys together. NULLs are distinct from each other.
+        if index.unique {
+            let columns = index.columns.len();
+            let order = &index.key[..columns];
+            let duplicate = entries.windows(2).any(|pair| {
+                !pair[0][..columns].contains(&Value::Null)
+                    && compare_keys(&pair[0], &pair[1], order, encoding).is_eq()
+            });
+            if duplicate {
+                return Err(DbError::Constraint(
+                    "UNIQUE",
+                    self.column_names(&index.columns),
+                ));
+            }
+        }
+
+        let payloads: Vec<Vec<u8>> = entries
+            .iter()
+            .map(|entry| encode_record(entry, encoding))
+            .collect();
+        load_index(db, index.entry.rootpage, &payloads)
+    }
+
+    /// The entry each index should hold for a row, for the indexes that index it.
+    pub fn index_entries(&self, row: &Row) -> DbResult<Vec<(&SchemaEntry, Vec<Value>)>> {
+        let mut entries = Vec::new();
+        for index in &self.indexes {
+            if self.is_indexed(index, row)? {
+                entries.push((&index.entry, self.index_entry(index, row)));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// How the entries of the table's index B-trees are ordered, keyed by
+    /// root page. A WITHOUT ROWID table's own B-tree is included.
+    pub fn key_orders(&self) -> Vec<(u32, Vec<KeyColumn>)> {
+        let mut orders: Vec<(u32, Vec<KeyColumn>)> = self
+            .indexes
+            .iter()
+            .map(|index| (index.entry.rootpage, index.key.clone()))
+            .collect();
+        if self.table.without_rowid {
+            orders.push((self.table.entry.rootpage, self.primary_key_order.clone()));
+        }
+        orders
+    }
+
+    /// Read the current record of a row, found by its rowid or PRIMARY KEY.
+    pub fn find_record(&self, db: &mut Database, row: &Row) -> DbResult<Option<Record>> {
+        let root = self.table.entry.rootpage;
+        if !self.table.without_rowid {
+            return find_record_by_rowid(db, root, row.rowid);
+        }
+        let key: Vec<Value> = self
+            .primary_key
+            .iter()
+            .map(|&idx| row.values[idx].clone())
+            .collect();
+        find_index_entry(db, root, &key, &self.primary_key_order)?
+            .map(|payload| Record::parse(&payload, 0, db.encoding))
+            .transpose()
+    }
+
+    /// Read a row from one of the table's records.
+    pub fn row_from_record(&self, record: &Record) -> Row {
+        let values = self
+            .table
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(idx, def)| match def.is_rowid_alias {
+                true => Value::Integer(record.rowid),
+                false => self.table.read_column(record, idx),
+            })
+            .collect();
+        Row {
+            rowid: record.rowid,
+            values,
+        }
+    }
+
+    /// Handle an existing row that conflicts with a new one on `columns`:
+    /// REPLACE deletes it and IGNORE skips the new row, while anything else
+    /// is an error. Returns whether to go on with the insert.
+    fn resolve_conflict(
+        &self,
+        db: &mut Database,
+        conflict: ConflictAction,
+        existing: &Row,
+        columns: &[usize],
+    ) -> DbResult<bool> {
+        match conflict {
+            ConflictAction::Replace => {
+                self.delete(db, existing)?;
+                Ok(true)
+            }
+            ConflictAction::Ignore => Ok(false),
+            _ => Err(DbError::Constraint("UNIQUE", self.column_names(columns))),
+        }
+    }
+
+    /// Name columns the way constraint errors do: `t.a, t.b`, or `t.rowid`
+    /// for an empty list.
+    fn column_names(&self, columns: &[usize]) -> String {
+        let table = &self.table.entry.tbl_name;
+        if columns.is_empty() {
+            return format!("{}.rowid", table);
+        }
+        columns
+            .iter()
+            .map(|&idx| format!("{}.{}", table, self.table.columns[idx].name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// The values stored in the table's record for a row.
+    fn table_record(&self, row: &Row) -> Vec<Value> {
+        let mut record = vec![Value::Null; row.values.len()];
+        for (idx, def) in self.table.columns.iter().enumerate() {
+            // The rowid alias is stored as the rowid, with NULL in the record
+            if !def.is_rowid_alias {
+                record[self.table.record_index[idx]] = row.values[idx].clone();
+            }
+        }
+        record
+    }
+
+    /// The values stored in an index entry for a row: the indexed columns,
+    /// then the rowid or the rest of the PRIMARY KEY.
+    fn index_entry(&self, index: &Index, row: &Row) -> Vec<Value> {
+        let mut entry: Vec<Value> = index
+            .columns
+            .iter()
+            .map(|&idx| row.values[idx].clone())
+            .collect();
+        if self.table.without_rowid {
+            for &idx in &self.primary_key {
+                if !index.columns.contains(&idx) {
+                    entry.push(row.values[idx].clone());
+                }
+            }
+        } else {
+            entry.push(Value::Integer(row.rowid));
+        }
+        entry
+    }
+
+    /// Look up the row an index entry points to.
+    fn row_for_index_entry(
+        &self,
+        db: &mut Database,
+        index: &Index,
+        payload: &[u8],
+    ) -> DbResult<Row> {
+        let root = self.table.entry.rootpage;
+        let missing = || DbError::CorruptPage {
+            page: index.entry.rootpage,
+            reason: format!("index {} refers to a missing row", index.entry.name),
+        };
+        if !self.table.without_rowid {
+            let rowid = index_cell_rowid(payload)?;
+            let record = find_record_by_rowid(db, root, rowid)?.ok_or_else(missing)?;
+            return Ok(self.row_from_record(&record));
+        }
+
+        // Collect the PRIMARY KEY from the indexed columns and the entry's tail
+        let entry = Record::parse(payload, 0, db.encoding)?;
+        let mut tail = index.columns.len();
+        let mut key = Vec::with_capacity(self.primary_key.len());
+        for &idx in &self.primary_key {
+            match index.columns.iter().position(|&c| c == idx) {
+                Some(position) => key.push(entry.read_value(position)),
+                None => {
+                    key.push(entry.read_value(tail));
+                    tail += 1;
+                }
+            }
+        }
+        let payload =
+            find_index_entry(db, root, &key, &self.primary_key_order)?.ok_or_else(missing)?;
+        Ok(self.row_from_record(&Record::parse(&payload, 0, db.encoding)?))
+    }
+
+    /// Check if a row belongs in an index: partial indexes only hold rows
+    /// satisfying their WHERE clause.
+    fn is_indexed(&self, index: &Index, row: &Row) -> DbResult<bool> {
+        match &index.where_clause {
+            Some(condition) => eval_condition(
+                condition,
+                &RowValues {
+                    table: &self.table,
+                    row,
+                },
+            ),
+            None => Ok(true),
+        }
+    }
+
+    /// The rowid after the largest in use. AUTOINCREMENT tables also skip
+    /// every rowid ever used, as recorded in sqlite_sequence.
+    fn next_rowid(&self, db: &mut Database) -> DbResult<i64> {
+        let mut largest = max_rowid(db, self.table.entry.rootpage)?;
+        if self.autoincrement {
+            largest = largest.max(self.sequence(db)?.map_or(0, |(_, seq)| seq));
+        }
+        largest
+            .checked_add(1)
+            .ok_or_else(|| DbError::InvalidStatement("database or disk is full".to_string()))
+    }
+
+    /// Find this table's sqlite_sequence row: its rowid and the largest rowid recorded.
+    fn sequence(&self, db: &mut Database) -> DbResult<Option<(i64, i64)>> {
+        let sequence = find_table(db, SEQUENCE_TABLE)?;
+        let mut records = Vec::new();
+        traverse_btree_table(db, sequence.rootpage, &mut records)?;
+        Ok(records
+            .iter()
+            .find(|r| {
+                r.read_string(0)
+                    .is_some_and(|name| name.eq_ignore_ascii_case(&self.table.entry.tbl_name))
+            })
+            .map(|r| (r.rowid, r.read_int(1).unwrap_or(0))))
+    }
+
+    /// Record a newly used rowid in sqlite_sequence if it is the largest yet.
+    fn update_sequence(&self, db: &mut Database, rowid: i64) -> DbResult<()> {
+        let root = find_table(db, SEQUENCE_TABLE)?.rootpage;
+        let existing = self.sequence(db)?;
+        let sequence_rowid = match existing {
+            Some((_, seq)) if seq >= rowid => return Ok(()),
+            Some((sequence_rowid, _)) => {
+                delete_table_row(db, root, sequence_rowid)?;
+                sequence_rowid
+            }
+            None => max_rowid(db, root)? + 1,
+        };
+        let record = [
+            Value::Text(self.table.entry.tbl_name.clone()),
+            Value::Integer(rowid),
+        ];
+        insert_table_row(
+            db,
+            root,
+            sequence_rowid,
+            &encode_record(&record, db.encoding),
+        )?;
+        Ok(())
+    }
+}
+
+/// Evaluate a column's DEFAULT expression, or NULL if it has none.
+pub fn default_value(def: &ColumnDef, encoding: TextEncoding) -> DbResult<Value> {
+    match &def.default {
+        Some(expr) => eval(expr, &EmptyScope(encoding)),
+        None => Ok(Value::Null),
+    }
+}
+
+/// How a column orders in an index, with the index's own collation if it names one.
+fn column_key(def: &ColumnDef, collation: Option<&str>, descending: bool) -> DbResult<KeyColumn> {
+    Ok(KeyColumn {
+        collation: Collation::lookup_or_binary(collation.or(def.collation.as_deref()))?,
+        descending,
+    })
+}
+
+/// A row being written, as seen by a partial index's WHERE clause.
+struct RowValues<'a> {
+    table: &'a Table,
+    row: &'a Row,
+}
+
+impl RowScope for RowValues<'_> {
+    fn column(&self, name: &str) -> DbResult<Value> {
+        match self.table.resolve(name) {
+            Some(ColumnRef::Rowid) => Ok(Value::Integer(self.row.rowid)),
+            Some(ColumnRef::Column(idx)) => Ok(self.row.values[idx].clone()),
+            None => Err(DbError::ColumnNotFound {
+                table: self.table.entry.tbl_name.clone(),
+                column: name.to_string(),
+            }),
+        }
+    }
+
+    fn column_affinity(&self, name: &str) -> Affinity {
+        match self.table.resolve(name) {
+            Some(ColumnRef::Rowid) => Affinity::Integer,
+            Some(ColumnRef::Column(idx)) => self.table.columns[idx].affinity(),
+            None => Affinity::None,
+        }
+    }
+
+    fn column_collation(&self, name: &str) -> Option<String> {
+        let declared = match self.table.resolve(name)? {
+            ColumnRef::Column(idx) => self.table.columns[idx].collation.clone(),
+            ColumnRef::Rowid => None,
+        };
+        Some(declared.unwrap_or_else(|| Collation::Binary.name().to_string()))
+    }
+
+    fn encoding(&self) -> TextEncoding {
+        self.table.encoding
+    }
+}
@@ -0,0 +1,100 @@
+//! UPDATE execution.
+
+use crate::sql::ast::{ConflictAction, Update};
+
+use super::database::Database;
+use super::error::{DbError, DbResult};
+use super::eval::eval;
+use super::query::{ColumnRef, QueryResult, TableRow, check_columns, filter_rows};
+use super::table_write::{Row, TableWriter};
+use super::value::Value;
+
+/// Apply an UPDATE statement.
+///
+/// Every matching row is found before any changes, using the same index and
+/// rowid seeks as SELECT.
+pub fn execute_update(db: &mut Database, update: &Update) -> DbResult<QueryResult> {
+    let writer = TableWriter::open(db, &update.table)?;
+    let table = &writer.table;
+
+    // The column each assignment sets
+    let targets = update
+        .assignments
+        .iter()
+        .map(|(name, _)| {
+            table.resolve(name).ok_or_else(|| DbError::ColumnNotFound {
+                table: table.entry.tbl_name.clone(),
+                column: name.clone(),
+            })
+        })
+        .collect::<DbResult<Vec<_>>>()?;
+    check_columns(
+        table,
+        update
+            .assignments
+            .iter()
+            .map(|(_, expr)| expr)
+            .chain(update.where_clause.as_ref()),
+    )?;
+
+    let rows: Vec<Row> = filter_rows(db, table, update.where_clause.as_ref())?
+        .iter()
+        .map(|record| writer.row_from_record(record))
+        .collect();
+    for row in rows {
+        update_row(db, &writer, &targets, update, row)?;
+    }
+    Ok(QueryResult::default())
+}
+
+/// Update one row by replacing it with a row holding the new values.
+fn update_row(
+    db: &mut Database,
+    writer: &TableWriter,
+    targets: &[ColumnRef],
+    update: &Update,
+    row: Row,
+) -> DbResult<()> {
+    // A REPLACE while updating an earlier row may have deleted this one
+    let Some(record) = writer.find_record(db, &row)? else {
+        return Ok(());
+    };
+    let old = writer.row_from_record(&record);
+    let scope = TableRow {
+        table: &writer.table,
+        record,
+    };
+
+    // Every assignment sees the row's values from before the update
+    let mut values = old.values.clone();
+    let mut rowid = Value::Integer(old.rowid);
+    for (target, (_, expr)) in targets.iter().zip(&update.assignments) {
+        let value = eval(expr, &scope)?;
+        match *target {
+            ColumnRef::Rowid if value == Value::Null => {
+                return Err(DbError::InvalidStatement("datatype mismatch".to_string()));
+            }
+            ColumnRef::Rowid => rowid = value,
+            ColumnRef::Column(idx) => values[idx] = value,
+        }
+    }
+
+    writer.delete(db, &old)?;
+    // A row that is skipped or fails leaves the table as it was, so put the old one back
+    let restore = |db: &mut Database, old: Row| {
+        writer.insert(
+            db,
+            Value::Integer(old.rowid),
+            old.values,
+            ConflictAction::Abort,
+        )
+    };
+    match writer.insert(db, rowid, values, update.conflict) {
+        Ok(true) => Ok(()),
+        Ok(false) => restore(db, old).map(drop),
+        Err(err) => {
+            restore(db, old)?;
+            Err(err)
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! VACUUM and VACUUM INTO: rebuilding a database without its free pages.
+//!
+//! Every B-tree is copied into a new database in key order, packing its
+//! pages full. `VACUUM INTO` keeps that copy; `VACUUM` writes it back over
+//! the original, which shrinks to fit.
+
+use std::fs;
+
+use crate::sql::ast::{ConflictAction, Expr};
+
+use super::autovacuum::AutoVacuum;
+use super::btree::btree_payloads;
+use super::btree_write::{create_btree, load_index, load_table};
+use super::constants::{
+    CHANGE_COUNTER_OFFSET, FREELIST_COUNT_OFFSET, FREELIST_TRUNK_OFFSET, INCREMENTAL_VACUUM_OFFSET,
+    LARGEST_ROOT_PAGE_OFFSET, PAGE1_HEADER_OFFSET, READ_VERSION_OFFSET, SCHEMA_COOKIE_OFFSET,
+    WRITE_VERSION_OFFSET,
+};
+use super::database::Database;
+use super::error::{DbError, DbResult, read_u32, write_u32};
+use super::eval::{EmptyScope, eval};
+use super::page::{Page, PageType};
+use super::pager::Pager;
+use super::query::QueryResult;
+use super::schema::{SchemaEntry, read_schema_rows};
+use super::schema_write::encode_schema_entry;
+use super::value::Value;
+
+/// Handle `VACUUM` and `VACUUM INTO file`.
+pub fn execute_vacuum(pager: &mut Pager, into: Option<&Expr>) -> DbResult<QueryResult> {
+    if pager.in_transaction() {
+        return Err(DbError::InvalidStatement(
+            "cannot VACUUM from within a transaction".to_string(),
+        ));
+    }
+    match into {
+        Some(expr) => {
+            let path = match eval(expr, &EmptyScope::default())? {
+                Value::Text(path) => path,
+                _ => {
+                    return Err(DbError::InvalidStatement("non-text filename".to_string()));
+                }
+            };
+            vacuum_into(pager, &path)?;
+        }
+        None => {
+            let mode = match pager.next_auto_vacuum() {
+                Some(mode) => mode,
+                None => pager.read()?.auto_vacuum()?,
+            };
+            pager.write(ConflictAction::default(), |db| vacuum(db, mode))?;
+        }
+    }
+    Ok(QueryResult::default())
+}
+
+/// Write a compacted copy of the database to a new file, leaving the
+/// original alone. The copy uses a rollback journal.
+fn vacuum_into(pager: &mut Pager, path: &str) -> DbResult<()> {
+    if fs::metadata(path).is_ok_and(|metadata| metadata.len() > 0) {
+        return Err(DbError::InvalidStatement(
+            "output file already exists".to_string(),
+        ));
+    }
+    let mut target = Database::open(path, pager.busy_timeout())?;
+    let source = pager.read()?;
+    let mode = source.auto_vacuum()?;
+    let result = rebuild(source, &mut target, mode).and_then(|_| {
+        let mut page1 = target.read_page(1)?;
+        let versions = [page1[WRITE_VERSION_OFFSET], page1[READ_VERSION_OFFSET]];
+        copy_header(&source.read_page(1)?, &mut page1)?;
+        // The new file starts its own count of changes
+        page1[WRITE_VERSION_OFFSET] = versions[0];
+        page1[READ_VERSION_OFFSET] = versions[1];
+        write_u32(&mut page1, CHANGE_COUNTER_OFFSET, 0)?;
+        target.write_page(1, page1)?;
+        target.lock_exclusive()?;
+        target.commit()
+    });
+    target.rollback();
+    target.end_read()?;
+    result
+}
+
+/// Rebuild the database in place, with the given auto-vacuum mode: compact
+/// it in a scratch file next to it, then replace every page with the
+/// scratch file's and drop the rest.
+fn vacuum(db: &mut Database, mode: AutoVacuum) -> DbResult<()> {
+    if db.committed_page_count() == 0 {
+        return Ok(());
+    }
+    let scratch = scratch_path(db.path());
+    if fs::metadata(&scratch).is_ok() {
+        fs::remove_file(&scratch)?;
+    }
+    let mut target = Database::open(&scratch, db.busy_timeout())?;
+    let result = rebuild(db, &mut target, mode).and_then(|_| {
+        let page_count = target.page_count();
+        let pending_page = db.pending_page();
+        for page_num in (1..=page_count).filter(|&page_num| page_num != pending_page) {
+            let mut data = target.read_page(page_num)?;
+            if page_num == 1 {
+                copy_header(&db.read_page(1)?, &mut data)?;
+            }
+            db.write_page(page_num, data)?;
+        }
+        db.truncate(page_count);
+        Ok(())
+    });
+    // The scratch file is never committed, so it stays empty
+    target.rollback();
+    target.end_read()?;
+    drop(target);
+    fs::remove_file(&scratch)?;
+    result
+}
+
+/// Where `VACUUM` builds the compacted database before copying it back.
+fn scratch_path(path: &str) -> String {
+    format!("{}-vacuum", path)
+}
+
+/// Copy every B-tree of `source` into the new, empty database `target`,
+/// which gets the given auto-vacuum mode, recording them in its
+/// sqlite_schema with the same rowids.
+fn rebuild(source: &mut Database, target: &mut Database, mode: AutoVacuum) -> DbResult<()> {
+    target.page_size = source.page_size;
+    target.reserved_bytes = source.reserved_bytes;
+    target.encoding = source.encoding;
+    target.set_new_auto_vacuum(mode);
+    target.begin_write()?;
+
+    // Like SQLite, every root comes before the contents of any B-tree,
+    // which auto-vacuum needs anyway
+    let rows = read_schema_rows(source)?;
+    let mut roots = Vec::with_capacity(rows.len());
+    for (_, entry) in &rows {
+        // Views and triggers have no B-tree
+        let root = match entry.rootpage {
+            0 => None,
+            root => {
+                let page_type = match Page::new(source.read_page(root)?, root)?.page_type() {
+                    PageType::LeafTable | PageType::InteriorTable => PageType::LeafTable,
+                    PageType::LeafIndex | PageType::InteriorIndex => PageType::LeafIndex,
+                };
+                Some((create_btree(target, page_type)?, page_type))
+            }
+        };
+        roots.push(root);
+    }
+
+    let mut schema = Vec::with_capacity(rows.len());
+    for ((rowid, entry), root) in rows.into_iter().zip(roots) {
+        let rootpage = match root {
+            Some((rootpage, page_type)) => {
+                copy_btree(source, entry.rootpage, target, rootpage, page_type)?;
+                rootpage
+            }
+            None => 0,
+        };
+        let entry = SchemaEntry { rootpage, ..entry };
+        schema.push((rowid, encode_schema_entry(&entry, target.encoding)));
+    }
+    load_table(target, 1, &schema)
+}
+
+/// Copy the entries of a B-tree into an empty one in another database,
+/// whose root page has the given type.
+fn copy_btree(
+    source: &mut Database,
+    root: u32,
+    target: &mut Database,
+    target_root: u32,
+    page_type: PageType,
+) -> DbResult<()> {
+    let mut payloads = Vec::new();
+    btree_payloads(source, root, &mut payloads)?;
+    if page_type == PageType::LeafIndex {
+        let entries: Vec<Vec<u8>> = payloads.into_iter().map(|(_, payload)| payload).collect();
+        return load_index(target, target_root, &entries);
+    }
+    let rows: Vec<(i64, Vec<u8>)> = payloads
+        .into_iter()
+        .map(|(rowid, payload)| (rowid.unwrap_or_default(), payload))
+        .collect();
+    load_table(target, target_root, &rows)
+}
+
+/// Give the rebuilt page 1 the original database header, which keeps its
+/// settings, without the freelist it no longer has. The rebuild's own
+/// auto-vacuum fields stay. The schema cookie changes, since every root
+/// page may have moved.
+fn copy_header(original: &[u8], page1: &mut [u8]) -> DbResult<()> {
+    let largest_root = read_u32(page1, LARGEST_ROOT_PAGE_OFFSET)?;
+    let incremental = read_u32(page1, INCREMENTAL_VACUUM_OFFSET)?;
+    page1[..PAGE1_HEADER_OFFSET].copy_from_slice(&original[..PAGE1_HEADER_OFFSET]);
+    write_u32(page1, LARGEST_ROOT_PAGE_OFFSET, largest_root)?;
+    write_u32(page1, INCREMENTAL_VACUUM_OFFSET, incremental)?;
+    write_u32(page1, FREELIST_TRUNK_OFFSET, 0)?;
+    write_u32(page1, FREELIST_COUNT_OFFSET, 0)?;
+    let cookie = read_u32(page1, SCHEMA_COOKIE_OFFSET)?.wrapping_add(1);
+    write_u32(page1, SCHEMA_COOKIE_OFFSET, cookie)
+}
@@ -0,0 +1,465 @@
+//! Typed column values and their SQLite formatting and ordering rules.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use super::collation::Collation;
+use super::encoding::TextEncoding;
+
+/// Significant digits SQLite uses when converting a REAL to text.
+const REAL_TEXT_PRECISION: usize = 15;
+
+/// A single value read from a record or written in a query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl Value {
+    /// Get the value as a number, if it is one or is text that looks like one.
+    pub fn as_number(&self) -> Option<Value> {
+        match self {
+            Value::Integer(_) | Value::Real(_) => Some(self.clone()),
+            Value::Text(text) => parse_number(text.trim()),
+            Value::Null | Value::Blob(_) => None,
+        }
+    }
+
+    /// Convert the value to a number the way SQL arithmetic does.
+    ///
+    /// Text and blobs use their longest numeric prefix, or 0 if there is none.
+    /// NULL stays NULL.
+    pub fn to_numeric(&self) -> Value {
+        match self {
+            Value::Null | Value::Integer(_) | Value::Real(_) => self.clone(),
+            Value::Text(text) => numeric_prefix(text),
+            Value::Blob(bytes) => numeric_prefix(&String::from_utf8_lossy(bytes)),
+        }
+    }
+
+    /// Get a numeric value as a float; anything else counts as 0.
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            Value::Integer(i) => *i as f64,
+            Value::Real(r) => *r,
+            _ => 0.0,
+        }
+    }
+
+    /// Interpret the value as a boolean, or None for NULL.
+    pub fn is_truthy(&self) -> Option<bool> {
+        match self.to_numeric() {
+            Value::Null => None,
+            Value::Integer(i) => Some(i != 0),
+            value => Some(value.as_f64() != 0.0),
+        }
+    }
+
+    /// Compare two values using SQLite's sort order.
+    ///
+    /// NULLs sort first, then numbers (compared numerically regardless of
+    /// storage class), then text (compared by its bytes in the database
+    /// encoding), then blobs (compared byte by byte).
+    pub fn compare(&self, other: &Value, encoding: TextEncoding) -> Ordering {
+        self.compare_collated(other, &Collation::Binary, encoding)
+    }
+
+    /// Compare two values using SQLite's sort order, ordering text with the
+    /// given collating sequence.
+    pub fn compare_collated(
+        &self,
+        other: &Value,
+        collation: &Collation,
+        encoding: TextEncoding,
+    ) -> Ordering {
+        match (self, other) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Real(a), Value::Real(b)) => a.total_cmp(b),
+            (Value::Integer(a), Value::Real(b)) => compare_int_real(*a, *b),
+            (Value::Real(a), Value::Integer(b)) => compare_int_real(*b, *a).reverse(),
+            (Value::Text(a), Value::Text(b)) => collation.compare(a, b, encoding),
+            (Value::Blob(a), Value::Blob(b)) => a.cmp(b),
+            _ => self.type_rank().cmp(&other.type_rank()),
+        }
+    }
+
+    /// Position of the value's storage class in SQLite's sort order.
+    fn type_rank(&self) -> u8 {
+        match self {
+            Value::Null => 0,
+            Value::Integer(_) | Value::Real(_) => 1,
+            Value::Text(_) => 2,
+            Value::Blob(_) => 3,
+        }
+    }
+
+    /// Get the raw bytes of the value as the list output mode prints them.
+    /// Blobs are written as-is; everything else as its text form.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Value::Blob(bytes) => bytes.clone(),
+            value => value.to_string().into_bytes(),
+        }
+    }
+
+    /// Render the value as an SQL literal, as the quote output mode does.
+    pub fn to_sql_literal(&self) -> String {
+        match self {
+            Value::Null => "NULL".to_string(),
+            Value::Integer(i) => i.to_string(),
+            Value::Real(r) => format_real_exact(*r),
+            Value::Text(s) => format!("'{}'", s.replace('\'', "''")),
+            Value::Blob(bytes) => format!("X'{}'", to_hex(bytes).to_lowercase()),
+        }
+    }
+
+    /// Render the value as a JSON value, with blobs as base64 strings.
+    pub fn to_json(&self) -> String {
+        match self {
+            Value::Null => "null".to_string(),
+            Value::Integer(i) => i.to_string(),
+            Value::Real(r) if r.is_finite() => format_real_exact(*r),
+            // JSON has no infinities; SQLite writes them as out-of-range numbers
+            Value::Real(r) => if *r > 0.0 { "9.0e+999" } else { "-9.0e+999" }.to_string(),
+            Value::Text(s) => json_string(s),
+            Value::Blob(bytes) => json_string(&to_base64(bytes)),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Real(r) => write!(f, "{}", format_real(*r)),
+            Value::Text(s) => write!(f, "{}", s),
+            Value::Blob(bytes) => write!(f, "{}", String::from_utf8_lossy(bytes)),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Integer(b as i64)
+    }
+}
+
+/// Parse the longest prefix of `text` that forms a number, as SQLite does
+/// when text is used in arithmetic. Returns 0 if there is no such prefix.
+fn numeric_prefix(text: &str) -> Value {
+    let text = text.trim_start();
+    let bytes = text.as_bytes();
+    let mut end = 0;
+    if matches!(bytes.first(), Some(b'+' | b'-')) {
+        end += 1;
+    }
+    let digits_start = end;
+    while end < bytes.len() && bytes[end].is_ascii_digit() {
+        end += 1;
+    }
+    let int_end = end;
+    if end < bytes.len() && bytes[end] == b'.' {
+        end += 1;
+        while end < bytes.len() && bytes[end].is_ascii_digit() {
+            end += 1;
+        }
+    }
+    if end == digits_start || (end == digits_start + 1 && bytes[digits_start] == b'.') {
+        return Value::Integer(0);
+    }
+    if end < bytes.len() && matches!(bytes[end], b'e' | b'E') {
+        let mut exp_end = end + 1;
+        if matches!(bytes.get(exp_end), Some(b'+' | b'-')) {
+            exp_end += 1;
+        }
+        if bytes.get(exp_end).is_some_and(u8::is_ascii_digit) {
+            while exp_end < bytes.len() && bytes[exp_end].is_ascii_digit() {
+                exp_end += 1;
+            }
+            end = exp_end;
+        }
+    }
+    // Integers too large for 64 bits fall through to REAL
+    if end == int_end
+        && let Ok(i) = text[..end].parse::<i64>()
+    {
+        return Value::Integer(i);
+    }
+    text[..end]
+        .parse::<f64>()
+        .map_or(Value::Integer(0), Value::Real)
+}
+
+/// Parse text as an INTEGER or REAL value.
+fn parse_number(text: &str) -> Option<Value> {
+    if let Ok(i) = text.parse::<i64>() {
+        return Some(Value::Integer(i));
+    }
+    // Rust accepts "inf" and "nan", which SQL number literals can't spell
+    let looks_numeric = text
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'));
+    if looks_numeric && text.chars().any(|c| c.is_ascii_digit()) {
+        text.parse::<f64>().ok().map(Value::Real)
+    } else {
+        None
+    }
+}
+
+/// Compare an integer with a float exactly, without rounding the integer.
+fn compare_int_real(i: i64, r: f64) -> Ordering {
+    if r.is_nan() {
+        return Ordering::Greater;
+    }
+    // Every i64 lies in [-2^63, 2^63)
+    if r < -9_223_372_036_854_775_808.0 {
+        return Ordering::Greater;
+    }
+    if r >= 9_223_372_036_854_775_808.0 {
+        return Ordering::Less;
+    }
+    let whole = r.floor();
+    match i.cmp(&(whole as i64)) {
+        Ordering::Equal if r > whole => Ordering::Less,
+        ordering => ordering,
+    }
+}
+
+/// Format a REAL the way sqlite3 prints it.
+///
+/// sqlite3 scales the value into a 64-bit integer of about 19 significant
+/// digits with double-double arithmetic, then rounds that half-up to 15,
+/// dropping trailing zeros. The scaling isn't exact, so rounding the exact
+/// binary value instead gives a different last digit for some values, such
+/// as 2.48754560329901e+283. Always shows a decimal point (`1.0`,
+/// `1.0e+20`) and spells infinities `Inf`/`-Inf`.
+pub fn format_real(r: f64) -> String {
+    if r.is_nan() {
+        return "NaN".to_string();
+    }
+    if r.is_infinite() {
+        return if r > 0.0 { "Inf" } else { "-Inf" }.to_string();
+    }
+    if r == 0.0 {
+        return "0.0".to_string();
+    }
+
+    let (significand, mut exponent) = decimal_significand(r.abs());
+    let mut digits: Vec<u8> = significand.to_string().into_bytes();
+    exponent += digits.len() as i32 - 1;
+
+    // Round half-up to the significant digits SQLite keeps
+    if digits.len() > REAL_TEXT_PRECISION {
+        let round_up = digits[REAL_TEXT_PRECISION] >= b'5';
+        digits.truncate(REAL_TEXT_PRECISION);
+        if round_up {
+            let mut i = digits.len();
+            loop {
+                if i == 0 {
+                    // Carried out of the leading digit: 9.99.. became 10.0..
+                    digits.insert(0, b'1');
+                    digits.pop();
+                    exponent += 1;
+                    break;
+                }
+                i -= 1;
+                if digits[i] == b'9' {
+                    digits[i] = b'0';
+                } else {
+                    digits[i] += 1;
+                    break;
+                }
+            }
+        }
+    }
+    while digits.len() > 1 && digits.last() == Some(&b'0') {
+        digits.pop();
+    }
+    let digits = String::from_utf8(digits).unwrap_or_default();
+    let sign = if r < 0.0 { "-" } else { "" };
+
+    if exponent < -4 || exponent >= REAL_TEXT_PRECISION as i32 {
+        let fraction = if digits.len() > 1 { &digits[1..] } else { "0" };
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        format!(
+            "{}{}.{}e{}{:02}",
+            sign,
+            &digits[..1],
+            fraction,
+            exponent_sign,
+            exponent.abs()
+        )
+    } else if exponent < 0 {
+        let zeros = "0".repeat((-exponent - 1) as usize);
+        format!("{}0.{}{}", sign, zeros, digits)
+    } else {
+        let point = exponent as usize + 1;
+        if digits.len() > point {
+            format!("{}{}.{}", sign, &digits[..point], &digits[point..])
+        } else {
+            let zeros = "0".repeat(point - digits.len());
+            format!("{}{}{}.0", sign, digits, zeros)
+        }
+    }
+}
+
+/// Scale a positive finite value to an integer of 18 to 20 digits and the
+/// power of ten it was scaled by, as `sqlite3FpDecode` does: multiplying by
+/// powers of ten in double-double arithmetic, whose constants carry their
+/// rounding errors in a second term.
+// The constants are written as in SQLite's source
+#[allow(clippy::excessive_precision)]
+fn decimal_significand(r: f64) -> (u64, i32) {
+    let mut x = (r, 0.0);
+    let mut exponent = 0;
+    if x.0 > 9.223372036854774784e18 {
+        while x.0 > 9.223372036854774784e118 {
+            exponent += 100;
+            x = dekker_multiply(x, 1.0e-100, -1.99918998026028836196e-117);
+        }
+        while x.0 > 9.223372036854774784e28 {
+            exponent += 10;
+            x = dekker_multiply(x, 1.0e-10, -3.6432197315497741579e-27);
+        }
+        while x.0 > 9.223372036854774784e18 {
+            exponent += 1;
+            x = dekker_multiply(x, 1.0e-01, -5.5511151231257827021e-18);
+        }
+    } else {
+        while x.0 < 9.223372036854774784e-83 {
+            exponent -= 100;
+            x = dekker_multiply(x, 1.0e+100, -1.5902891109759918046e+83);
+        }
+        while x.0 < 9.223372036854774784e+07 {
+            exponent -= 10;
+            x = dekker_multiply(x, 1.0e+10, 0.0);
+        }
+        while x.0 < 9.22337203685477478e+17 {
+            exponent -= 1;
+            x = dekker_multiply(x, 1.0e+01, 0.0);
+        }
+    }
+    let significand = if x.1 < 0.0 {
+        (x.0 as u64).wrapping_sub((-x.1) as u64)
+    } else {
+        (x.0 as u64).wrapping_add(x.1 as u64)
+    };
+    (significand, exponent)
+}
+
+/// Multiply the double-double `x` by `y + yy`, splitting the factors in
+/// halves so that the products are exact (Dekker's algorithm).
+fn dekker_multiply(x: (f64, f64), y: f64, yy: f64) -> (f64, f64) {
+    let split = |v: f64| f64::from_bits(v.to_bits() & 0xffff_ffff_fc00_0000);
+    let hx = split(x.0);
+    let tx = x.0 - hx;
+    let hy = split(y);
+    let ty = y - hy;
+    let p = hx * hy;
+    let q = hx * ty + tx * hy;
+    let c = p + q;
+    let cc = p - c + q + tx * ty;
+    let cc = x.0 * yy + x.1 * y + cc;
+    let high = c + cc;
+    (high, c - high + cc)
+}
+
+/// Format a REAL so that parsing the text gives back exactly the same value.
+fn format_real_exact(r: f64) -> String {
+    let text = format_real(r);
+    if text.parse::<f64>().is_ok_and(|parsed| parsed == r) {
+        text
+    } else {
+        format!("{:?}", r)
+    }
+}
+
+/// Encode bytes as uppercase hexadecimal.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+/// Encode bytes as standard padded base64.
+fn to_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Quote and escape a string for JSON output.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_real;
+
+    #[test]
+    fn formats_reals_like_sqlite3() {
+        let cases = [
+            (0.1 + 0.2, "0.3"),
+            (100.0, "100.0"),
+            (1.0 / 3.0, "0.333333333333333"),
+            (1.5e-7, "1.5e-07"),
+            (1e15, "1.0e+15"),
+            (123456789012345678.0, "1.23456789012346e+17"),
+            (f64::INFINITY, "Inf"),
+            (f64::NEG_INFINITY, "-Inf"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(format_real(value), expected, "formatting {:?}", value);
+        }
+    }
+
+    #[test]
+    fn formats_large_exponents_like_sqlite3() {
+        // sqlite3's inexact scaling rounds these differently from their
+        // exact decimal expansions
+        let cases = [
+            (4.750750204750985e+182, "4.75075020475099e+182"),
+            (7.930940904982885e+182, "7.93094090498288e+182"),
+            (9.400438323489415e+165, "9.40043832348941e+165"),
+            (2.5169978242547252e-197, "2.51699782425472e-197"),
+            (-7.317495976062606e-221, "-7.3174959760626e-221"),
+            (1e100, "1.0e+100"),
+            (f64::MAX, "1.79769313486232e+308"),
+            (5e-324, "4.94065645841247e-324"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(format_real(value), expected, "formatting {:?}", value);
+        }
+    }
+}
@@ -1,5 +1,7 @@
 //! Variable-length integer (varint) operations for SQLite format.
 
+use super::error::{DbError, DbResult};
+
 const VARINT_MAX_BYTES: usize = 9;
 const VARINT_CONTINUATION_BIT: u8 = 0x80;
 const VARINT_DATA_MASK: u8 = 0x7F;
@@ -16,15 +18,20 @@ const VARINT_DATA_MASK: u8 = 0x7F;
 ///
 /// # Returns
 ///
-/// Returns a tuple of (value, number of bytes read).
-pub fn read_varint(data: &[u8], pos: usize) -> (u64, usize) {
+/// Returns a tuple of (value, number of bytes read), or an error if the
+/// slice ends before the varint does.
+pub fn read_varint(data: &[u8], pos: usize) -> DbResult<(u64, usize)> {
     let mut value: u64 = 0;
     let mut bytes_read = 0;
 
     for i in 0..VARINT_MAX_BYTES {
         // Check bounds
         if pos + i >= data.len() {
-            break;
+            return Err(DbError::OutOfBounds {
+                offset: pos + i,
+                len: 1,
+                size: data.len(),
+            });
         }
 
         let byte = data[pos + i];
@@ -45,5 +52,34 @@ pub fn read_varint(data: &[u8], pos: usize) -> (u64, usize) {
         }
     }
 
-    (value, bytes_read)
+    Ok((value, bytes_read))
+}
+
+/// Append the varint encoding of `value` to `out`.
+///
+/// Values that need more than 56 bits use all 8 bits of the ninth byte.
+pub fn write_varint(out: &mut Vec<u8>, value: u64) {
+    if value >> 56 != 0 {
+        // Eight 7-bit groups followed by a full final byte
+        for i in (0..VARINT_MAX_BYTES - 1).rev() {
+            out.push(((value >> (8 + i * 7)) as u8 & VARINT_DATA_MASK) | VARINT_CONTINUATION_BIT);
+        }
+        out.push(value as u8);
+        return;
+    }
+
+    let mut groups = vec![(value & VARINT_DATA_MASK as u64) as u8];
+    let mut rest = value >> 7;
+    while rest != 0 {
+        groups.push((rest & VARINT_DATA_MASK as u64) as u8 | VARINT_CONTINUATION_BIT);
+        rest >>= 7;
+    }
+    out.extend(groups.iter().rev());
+}
+
+/// Number of bytes the varint encoding of `value` takes.
+pub fn varint_len(value: u64) -> usize {
+    let mut out = Vec::with_capacity(VARINT_MAX_BYTES);
+    write_varint(&mut out, value);
+    out.len()
 }
@@ -0,0 +1,876 @@
+//! A database's write-ahead log.
+//!
+//! In WAL mode, commits append new versions of the pages they change to a
+//! `-wal` file next to the database instead of overwriting the pages in
+//! place. The newest committed version of a page is the last copy of it in
+//! the log; pages not in the log are read from the database file as usual.
+//! A checkpoint copies the logged pages back into the database file, after
+//! which the log can start over from the beginning.
+//!
+//! Connections share the wal-index, a `-shm` file that maps pages to the
+//! frames holding them, and lock bytes in it to coordinate:
+//!
+//! - A reader holds a read lock on one of five read marks, each recording
+//!   the last frame of a snapshot. Checkpoints copy no frame past a mark in
+//!   use, and the log only starts over once no reader is using it.
+//! - A writer holds the write lock, and a checkpoint the checkpoint lock.
+//! - Every connection holds a read lock on the DMS byte, which tells a new
+//!   connection whether the index is in use or must be rebuilt.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::time::Duration;
+
+use super::error::{DbError, DbResult, read_u32};
+use super::lock::{RangeLock, is_range_locked, lock_range, wait_for_lock};
+
+/// Magic number of a log whose checksums use little-endian words.
+const WAL_MAGIC_LE: u32 = 0x377f_0682;
+
+/// Magic number of a log whose checksums use big-endian words.
+const WAL_MAGIC_BE: u32 = 0x377f_0683;
+
+/// The only log format version there is.
+const WAL_VERSION: u32 = 3_007_000;
+
+/// Size of the log header.
+const WAL_HEADER_SIZE: usize = 32;
+
+/// Size of the header in front of each page in the log.
+const FRAME_HEADER_SIZE: usize = 24;
+
+/// Size of each of the two copies of the wal-index header.
+const INDEX_HEADER_SIZE: usize = 48;
+
+/// Offset of the checkpoint information in the wal-index, after both header copies.
+const CHECKPOINT_INFO_OFFSET: usize = 2 * INDEX_HEADER_SIZE;
+
+/// Offset of the read marks, after the number of frames checkpointed.
+const READ_MARKS_OFFSET: usize = CHECKPOINT_INFO_OFFSET + 4;
+
+/// Number of read marks, each recording a frame a reader's snapshot ends at.
+const READ_MARKS: usize = 5;
+
+/// A read mark no reader is using.
+const READ_MARK_UNUSED: u32 = u32::MAX;
+
+/// Offset of the bytes connections lock, which hold no data.
+const LOCKS_OFFSET: u64 = READ_MARKS_OFFSET as u64 + 4 * READ_MARKS as u64;
+
+/// Lock held by the connection writing to the log.
+const WRITE_LOCK: u64 = LOCKS_OFFSET;
+
+/// Lock held by the connection running a checkpoint.
+const CHECKPOINT_LOCK: u64 = LOCKS_OFFSET + 1;
+
+/// Lock on the first read mark; the others follow it.
+const READ_LOCK: u64 = LOCKS_OFFSET + 3;
+
+/// Lock every connection using the wal-index holds a read lock on.
+const DMS_LOCK: u64 = LOCKS_OFFSET + 8;
+
+/// Size of the wal-index header area: both header copies and the checkpoint information.
+const INDEX_HEADER_AREA: usize = CHECKPOINT_INFO_OFFSET + 40;
+
+/// Frames whose page numbers fit in each 32KB block of the wal-index.
+const INDEX_BLOCK_FRAMES: usize = 4096;
+
+/// Frames in the first block, which also holds the header area.
+const INDEX_FIRST_BLOCK_FRAMES: usize = INDEX_BLOCK_FRAMES - INDEX_HEADER_AREA / 4;
+
+/// Slots in the hash table of each wal-index block.
+const INDEX_HASH_SLOTS: usize = 2 * INDEX_BLOCK_FRAMES;
+
+/// Size of a wal-index block: page numbers followed by a hash table of u16 slots.
+const INDEX_BLOCK_SIZE: usize = 4 * INDEX_BLOCK_FRAMES + 2 * INDEX_HASH_SLOTS;
+
+/// How many times to retry starting a read when other connections keep
+/// changing the log, before reporting the database as busy.
+const READ_RETRIES: usize = 100;
+
+/// How a checkpoint treats the log once every frame is in the database file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CheckpointMode {
+    /// Copy what readers allow without waiting; the next commit starts the log over.
+    Passive,
+    /// Wait for readers so that every frame can be copied.
+    Full,
+    /// Also start the log over right away.
+    Restart,
+    /// Also truncate the log file to nothing.
+    Truncate,
+}
+
+/// Which parts of the wal-index to rewrite. Each connection only writes the
+/// parts the lock it holds protects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IndexUpdate {
+    /// The header and frame tables, which only the writer changes.
+    Frames,
+    /// The number of frames checkpointed, which only a checkpoint changes.
+    Backfill,
+    /// Everything, including the read marks.
+    All,
+}
+
+/// The committed contents of a write-ahead log.
+pub struct Wal {
+    /// Path of the database the log belongs to.
+    path: String,
+    file: File,
+    /// The wal-index, unless it couldn't be opened.
+    index: Option<File>,
+    page_size: usize,
+    /// Whether checksums read words as big-endian.
+    big_endian: bool,
+    /// Whether the file starts with a valid header for the current frames.
+    has_header: bool,
+    /// Number of times the log has been started over.
+    checkpoint_seq: u32,
+    /// Random bytes every frame of the current log repeats.
+    salt: [u8; 8],
+    /// Checksum of the last committed frame, which the next frame's continues.
+    checksum: (u32, u32),
+    /// The page held by each committed frame, in log order.
+    frame_pages: Vec<u32>,
+    /// The last committed frame holding each page, keyed by page number.
+    /// Frames are numbered from 1.
+    frames: HashMap<u32, u32>,
+    /// Size of the database in pages as of the last commit in the log.
+    page_count: Option<u32>,
+    /// Number of frames already copied into the database file.
+    backfilled: u32,
+    /// Counts changes to the log, so that other connections notice them.
+    change: u32,
+    read_marks: [u32; READ_MARKS],
+    /// The read mark this connection's snapshot is locked with, while reading.
+    read_slot: Option<usize>,
+    /// Whether this connection holds the write lock.
+    writing: bool,
+}
+
+impl Wal {
+    /// Open the log of the database at `path`, if it has one. When `create`
+    /// is set, a missing log is created. The log isn't read until
+    /// [`Wal::begin_read`].
+    pub fn open(path: &str, page_size: usize, create: bool) -> DbResult<Option<Self>> {
+        let wal_path = wal_path(path);
+        let file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(create)
+            .truncate(false)
+            .open(&wal_path)
+        {
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => File::open(&wal_path),
+            file => file,
+        };
+        let file = match file {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        // Without a wal-index this connection can still read, but can't
+        // coordinate with others
+        let index = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(index_path(path))
+            .or_else(|_| File::open(index_path(path)))
+            .ok();
+        if let Some(index) = &index {
+            lock_range(index, RangeLock::Read, DMS_LOCK, 1)?;
+        }
+
+        Ok(Some(Self {
+            path: path.to_string(),
+            file,
+            index,
+            page_size,
+            big_endian: cfg!(target_endian = "big"),
+            has_header: false,
+            checkpoint_seq: 0,
+            salt: [0; 8],
+            checksum: (0, 0),
+            frame_pages: Vec::new(),
+            frames: HashMap::new(),
+            page_count: None,
+            backfilled: 0,
+            change: 0,
+            read_marks: [READ_MARK_UNUSED; READ_MARKS],
+            read_slot: None,
+            writing: false,
+        }))
+    }
+
+    /// Size of the database in pages as of the last commit in the log, or
+    /// `None` if the log holds no commits.
+    pub fn page_count(&self) -> Option<u32> {
+        self.page_count
+    }
+
+    /// Number of committed frames in the log.
+    pub fn frame_count(&self) -> u32 {
+        self.frame_pages.len() as u32
+    }
+
+    /// The salt and number of committed frames, which together change with
+    /// every commit to the log.
+    pub fn version(&self) -> (u64, u32) {
+        (u64::from_be_bytes(self.salt), self.frame_count())
+    }
+
+    /// Read the log and lock its latest snapshot. Returns false if other
+    /// connections kept the snapshot from being locked.
+    pub fn begin_read(&mut self) -> DbResult<bool> {
+        if self.read_slot.is_some() {
+            return Ok(true);
+        }
+        for _ in 0..READ_RETRIES {
+            let index_valid = self.scan()?;
+            if self.index.is_none() {
+                return Ok(true);
+            }
+            // Rebuild a wal-index no connection has written yet, unless
+            // another connection is busy writing one
+            if !index_valid && self.lock_index(RangeLock::Write, WRITE_LOCK)? {
+                let rebuilt = self.write_index(IndexUpdate::All);
+                self.lock_index(RangeLock::Unlock, WRITE_LOCK)?;
+                rebuilt?;
+            }
+            if let Some(slot) = self.lock_read_mark()? {
+                self.read_slot = Some(slot);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Let go of the snapshot, and the write lock if held.
+    pub fn end_read(&mut self) -> DbResult<()> {
+        if self.writing {
+            self.lock_index(RangeLock::Unlock, WRITE_LOCK)?;
+            self.writing = false;
+        }
+        if let Some(slot) = self.read_slot.take() {
+            self.lock_index(RangeLock::Unlock, READ_LOCK + slot as u64)?;
+        }
+        Ok(())
+    }
+
+    /// Take the write lock. Returns false if another connection holds it,
+    /// and fails with [`DbError::Busy`] if another connection committed
+    /// since this one's snapshot was taken, as writing on top of an old
+    /// snapshot would lose that commit.
+    pub fn begin_write(&mut self) -> DbResult<bool> {
+        if self.writing {
+            return Ok(true);
+        }
+        if !self.lock_index(RangeLock::Write, WRITE_LOCK)? {
+            return Ok(false);
+        }
+        if !self.is_latest()? {
+            self.lock_index(RangeLock::Unlock, WRITE_LOCK)?;
+            return Err(DbError::Busy);
+        }
+        self.writing = true;
+        Ok(true)
+    }
+
+    /// Check if the wal-index still describes the log as this connection
+    /// last read it, so that no other connection has committed since.
+    fn is_latest(&self) -> DbResult<bool> {
+        let Some(file) = &self.index else {
+            return Ok(true);
+        };
+        let mut headers = [0u8; 2 * INDEX_HEADER_SIZE];
+        if file.read_exact_at(&mut headers, 0).is_err() {
+            return Ok(false);
+        }
+        let (header, copy) = headers.split_at(INDEX_HEADER_SIZE);
+        Ok(header == copy
+            && read_native(header, 16) == self.frame_count()
+            && header[32..40] == self.salt)
+    }
+
+    /// Read the newest committed version of a page, or `None` if the log
+    /// doesn't hold it.
+    pub fn read_page(&mut self, page_num: u32) -> DbResult<Option<Vec<u8>>> {
+        let Some(&frame) = self.frames.get(&page_num) else {
+            return Ok(None);
+        };
+        self.read_frame(frame).map(Some)
+    }
+
+    /// Append a commit to the log: a frame for each changed page, the last
+    /// of which records the size of the database after the commit. The
+    /// write lock must be held.
+    pub fn append(&mut self, pages: &BTreeMap<u32, Vec<u8>>, page_count: u32) -> DbResult<()> {
+        // Once every frame is in the database file and no other reader
+        // needs them, the log can start over
+        if !self.frame_pages.is_empty()
+            && self.backfilled == self.frame_count()
+            && !self.readers_elsewhere(1..READ_MARKS)?
+        {
+            self.restart()?;
+        }
+        if !self.has_header {
+            if self.checkpoint_seq == 0 {
+                self.salt = random_u64().to_be_bytes();
+            }
+            self.write_header()?;
+        }
+
+        let mut data = Vec::with_capacity(pages.len() * (FRAME_HEADER_SIZE + self.page_size));
+        let mut checksum = self.checksum;
+        for (i, (&page_num, page)) in pages.iter().enumerate() {
+            let commit_size = if i == pages.len() - 1 { page_count } else { 0 };
+            let mut header = [0u8; FRAME_HEADER_SIZE];
+            header[..4].copy_from_slice(&page_num.to_be_bytes());
+            header[4..8].copy_from_slice(&commit_size.to_be_bytes());
+            header[8..16].copy_from_slice(&self.salt);
+            checksum = wal_checksum(self.big_endian, &header[..8], checksum);
+            checksum = wal_checksum(self.big_endian, page, checksum);
+            header[16..20].copy_from_slice(&checksum.0.to_be_bytes());
+            header[20..24].copy_from_slice(&checksum.1.to_be_bytes());
+            data.extend_from_slice(&header);
+            data.extend_from_slice(page);
+        }
+        self.file
+            .seek(SeekFrom::Start(self.frame_offset(self.frame_count() + 1)))?;
+        self.file.write_all(&data)?;
+        self.file.sync_all()?;
+
+        for &page_num in pages.keys() {
+            self.frame_pages.push(page_num);
+            self.frames.insert(page_num, self.frame_count());
+        }
+        self.checksum = checksum;
+        self.page_count = Some(page_count);
+        self.change = self.change.wrapping_add(1);
+        self.write_index(IndexUpdate::Frames)
+    }
+
+    /// Copy the pages in the log that aren't yet in the database file into
+    /// it, stopping at the oldest snapshot another reader is using. Once
+    /// every frame is copied the file is truncated to the size of the
+    /// database. Modes other than PASSIVE wait up to `busy_timeout` for
+    /// readers to finish.
+    ///
+    /// Returns whether another connection kept the checkpoint from running,
+    /// or from finishing unless it is PASSIVE, the number of frames in the
+    /// log and the number copied into the database. Both
+    /// counts are zero once a truncating checkpoint empties the log.
+    pub fn checkpoint(
+        &mut self,
+        db: &mut File,
+        mode: CheckpointMode,
+        busy_timeout: Duration,
+    ) -> DbResult<(bool, u32, u32)> {
+        let timeout = if mode == CheckpointMode::Passive {
+            Duration::ZERO
+        } else {
+            busy_timeout
+        };
+        match wait_for_lock(timeout, || {
+            self.lock_index(RangeLock::Write, CHECKPOINT_LOCK)
+        }) {
+            Err(DbError::Busy) => return Ok((true, self.frame_count(), self.backfilled)),
+            result => result?,
+        }
+        let result = self.checkpoint_locked(db, mode, timeout);
+        self.lock_index(RangeLock::Unlock, CHECKPOINT_LOCK)?;
+        result
+    }
+
+    fn checkpoint_locked(
+        &mut self,
+        db: &mut File,
+        mode: CheckpointMode,
+        timeout: Duration,
+    ) -> DbResult<(bool, u32, u32)> {
+        // Checkpoint the latest commit, not just this connection's snapshot
+        self.scan()?;
+        let frame_count = self.frame_count();
+
+        // Frames past a read mark in use are newer than that reader's snapshot
+        let mut safe = frame_count;
+        let waited = wait_for_lock(timeout, || {
+            safe = frame_count;
+            for slot in 1..READ_MARKS {
+                let mark = self.read_marks[slot];
+                if mark < safe && self.readers_elsewhere(slot..slot + 1)? {
+                    safe = mark;
+                }
+            }
+            Ok(safe == frame_count)
+        });
+        match waited {
+            Err(DbError::Busy) => {}
+            result => result?,
+        }
+
+        // Readers of the database file alone would see pages change under them
+        if safe > self.backfilled
+            && !matches!(
+                wait_for_lock(timeout, || Ok(!self.readers_elsewhere(0..1)?)),
+                Err(DbError::Busy)
+            )
+        {
+            let mut pages = BTreeMap::new();
+            for frame in self.backfilled + 1..=safe {
+                pages.insert(self.frame_pages[frame as usize - 1], frame);
+            }
+            let page_count = self.page_count.unwrap_or_default();
+            for (page_num, frame) in pages {
+                if page_num > page_count {
+                    continue;
+                }
+                let data = self.read_frame(frame)?;
+                db.seek(SeekFrom::Start(
+                    (page_num as u64 - 1) * self.page_size as u64,
+                ))?;
+                db.write_all(&data)?;
+            }
+            if safe == frame_count && self.page_count.is_some() {
+                db.set_len(page_count as u64 * self.page_size as u64)?;
+            }
+            db.sync_all()?;
+            self.backfilled = safe;
+            self.write_index(IndexUpdate::Backfill)?;
+        }
+        // Readers only keep a PASSIVE checkpoint from finishing, not from succeeding
+        let busy = mode != CheckpointMode::Passive && self.backfilled < frame_count;
+        let result = (busy, frame_count, self.backfilled);
+        if mode < CheckpointMode::Restart || busy {
+            return Ok(result);
+        }
+
+        // Starting the log over waits for every reader of it to finish, and
+        // for the writer, who may have committed meanwhile
+        let writing = self.writing;
+        if !writing
+            && matches!(
+                wait_for_lock(timeout, || self.lock_index(RangeLock::Write, WRITE_LOCK)),
+                Err(DbError::Busy)
+            )
+        {
+            return Ok((true, frame_count, self.backfilled));
+        }
+        let restarted = self.restart_when_idle(mode, timeout);
+        if !writing {
+            self.lock_index(RangeLock::Unlock, WRITE_LOCK)?;
+        }
+        match restarted? {
+            false => Ok((true, frame_count, self.backfilled)),
+            true if mode == CheckpointMode::Truncate => Ok((false, 0, 0)),
+            true => Ok(result),
+        }
+    }
+
+    /// Start the log over once no other connection is reading it, truncating
+    /// it for a TRUNCATE checkpoint. The write lock must be held. Returns
+    /// false if the log changed or readers kept it from starting over.
+    fn restart_when_idle(&mut self, mode: CheckpointMode, timeout: Duration) -> DbResult<bool> {
+        if !self.is_latest()?
+            || matches!(
+                wait_for_lock(timeout, || Ok(!self.readers_elsewhere(1..READ_MARKS)?)),
+                Err(DbError::Busy)
+            )
+        {
+            return Ok(false);
+        }
+        self.restart()?;
+        if mode == CheckpointMode::Truncate {
+            self.file.set_len(0)?;
+            self.file.sync_all()?;
+            self.has_header = false;
+        }
+        Ok(true)
+    }
+
+    /// Checkpoint the whole log and delete it along with its wal-index. No
+    /// other connection may be using the database.
+    pub fn close(mut self, db: &mut File) -> DbResult<()> {
+        let (busy, _, _) = self.checkpoint(db, CheckpointMode::Truncate, Duration::ZERO)?;
+        if busy {
+            return Err(DbError::Busy);
+        }
+        drop(self.file);
+        fs::remove_file(wal_path(&self.path))?;
+        drop(self.index);
+        match fs::remove_file(index_path(&self.path)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Start the log over with a new salt, so that the frames already in the
+    /// file no longer count. Every frame must be in the database file.
+    fn restart(&mut self) -> DbResult<()> {
+        self.checkpoint_seq = self.checkpoint_seq.wrapping_add(1);
+        let salt1 = u32::from_be_bytes([self.salt[0], self.salt[1], self.salt[2], self.salt[3]]);
+        self.salt[..4].copy_from_slice(&salt1.wrapping_add(1).to_be_bytes());
+        self.salt[4..].copy_from_slice(&(random_u64() as u32).to_be_bytes());
+        self.frame_pages.clear();
+        self.frames.clear();
+        self.backfilled = 0;
+        self.read_marks = [0, 0, READ_MARK_UNUSED, READ_MARK_UNUSED, READ_MARK_UNUSED];
+        self.write_header()?;
+        self.write_index(IndexUpdate::All)
+    }
+
+    /// Write the log header for the current salt, starting a log with no frames.
+    fn write_header(&mut self) -> DbResult<()> {
+        self.big_endian = cfg!(target_endian = "big");
+        let magic = if self.big_endian {
+            WAL_MAGIC_BE
+        } else {
+            WAL_MAGIC_LE
+        };
+        let mut header = [0u8; WAL_HEADER_SIZE];
+        header[..4].copy_from_slice(&magic.to_be_bytes());
+        header[4..8].copy_from_slice(&WAL_VERSION.to_be_bytes());
+        header[8..12].copy_from_slice(&(self.page_size as u32).to_be_bytes());
+        header[12..16].copy_from_slice(&self.checkpoint_seq.to_be_bytes());
+        header[16..24].copy_from_slice(&self.salt);
+        self.checksum = wal_checksum(self.big_endian, &header[..24], (0, 0));
+        header[24..28].copy_from_slice(&self.checksum.0.to_be_bytes());
+        header[28..32].copy_from_slice(&self.checksum.1.to_be_bytes());
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        self.has_header = true;
+        Ok(())
+    }
+
+    /// Read the page a frame holds.
+    fn read_frame(&mut self, frame: u32) -> DbResult<Vec<u8>> {
+        let mut page = vec![0u8; self.page_size];
+        self.file.seek(SeekFrom::Start(
+            self.frame_offset(frame) + FRAME_HEADER_SIZE as u64,
+        ))?;
+        self.file
+            .read_exact(&mut page)
+            .map_err(|_| DbError::CorruptPage {
+                page: self.frame_pages[frame as usize - 1],
+                reason: format!("frame {} is past the end of the write-ahead log", frame),
+            })?;
+        Ok(page)
+    }
+
+    /// Offset of a frame in the log file.
+    fn frame_offset(&self, frame: u32) -> u64 {
+        WAL_HEADER_SIZE as u64 + (frame as u64 - 1) * (FRAME_HEADER_SIZE + self.page_size) as u64
+    }
+
+    /// Lock a read mark recording the end of the snapshot just scanned,
+    /// claiming an unused one if no mark matches. Returns the mark's slot, or
+    /// `None` if the log changed meanwhile or no mark could be claimed.
+    fn lock_read_mark(&mut self) -> DbResult<Option<usize>> {
+        let Some(index) = &self.index else {
+            return Ok(Some(0));
+        };
+        let frame_count = self.frame_count();
+        // An empty log is read through the first mark, which checkpoints respect
+        let slot = if frame_count == 0 {
+            Some(0)
+        } else if let Some(slot) = (1..READ_MARKS).find(|&i| self.read_marks[i] == frame_count) {
+            Some(slot)
+        } else {
+            let mut claimed = None;
+            for slot in 1..READ_MARKS {
+                let lock = READ_LOCK + slot as u64;
+                if lock_range(index, RangeLock::Write, lock, 1)? {
+                    let offset = (READ_MARKS_OFFSET + 4 * slot) as u64;
+                    let written = index.write_all_at(&frame_count.to_ne_bytes(), offset);
+                    lock_range(index, RangeLock::Unlock, lock, 1)?;
+                    written?;
+                    claimed = Some(slot);
+                    break;
+                }
+            }
+            claimed
+        };
+        let Some(slot) = slot else {
+            return Ok(None);
+        };
+        let lock = READ_LOCK + slot as u64;
+        if !lock_range(index, RangeLock::Read, lock, 1)? {
+            return Ok(None);
+        }
+
+        // The log may have changed between scanning it and taking the lock
+        let version = self.version();
+        self.scan()?;
+        if self.version() != version || (slot > 0 && self.read_marks[slot] != frame_count) {
+            let index = self.index.as_ref().expect("index");
+            lock_range(index, RangeLock::Unlock, lock, 1)?;
+            return Ok(None);
+        }
+        Ok(Some(slot))
+    }
+
+    /// Lock or unlock a byte of the wal-index without waiting, as
+    /// [`lock_range`] does. Without a wal-index there is nothing to lock.
+    fn lock_index(&self, kind: RangeLock, offset: u64) -> DbResult<bool> {
+        match &self.index {
+            Some(index) => Ok(lock_range(index, kind, offset, 1)?),
+            None => Ok(true),
+        }
+    }
+
+    /// Check if another connection is reading with any of the given read marks.
+    fn readers_elsewhere(&self, slots: std::ops::Range<usize>) -> DbResult<bool> {
+        let Some(index) = &self.index else {
+            return Ok(false);
+        };
+        let len = slots.len() as u64;
+        Ok(is_range_locked(
+            index,
+            RangeLock::Write,
+            READ_LOCK + slots.start as u64,
+            len,
+        )?)
+    }
+
+    /// Read the log and what the wal-index says about it. Returns whether
+    /// the wal-index describes the log.
+    fn scan(&mut self) -> DbResult<bool> {
+        self.has_header = false;
+        self.checkpoint_seq = 0;
+        self.checksum = (0, 0);
+        self.frame_pages.clear();
+        self.frames.clear();
+        self.page_count = None;
+        self.read_frames()?;
+        self.read_index()
+    }
+
+    /// Scan the log, indexing the frames of every complete commit.
+    fn read_frames(&mut self) -> DbResult<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        let mut reader = BufReader::new(&self.file);
+
+        let mut header = [0u8; WAL_HEADER_SIZE];
+        if reader.read_exact(&mut header).is_err() {
+            return Ok(());
+        }
+        let big_endian = match read_u32(&header, 0)? {
+            WAL_MAGIC_LE => false,
+            WAL_MAGIC_BE => true,
+            _ => return Ok(()),
+        };
+        if read_u32(&header, 4)? != WAL_VERSION || read_u32(&header, 8)? as usize != self.page_size
+        {
+            return Ok(());
+        }
+        let header_checksum = wal_checksum(big_endian, &header[..24], (0, 0));
+        if header_checksum != (read_u32(&header, 24)?, read_u32(&header, 28)?) {
+            return Ok(());
+        }
+        self.big_endian = big_endian;
+        self.has_header = true;
+        self.checkpoint_seq = read_u32(&header, 12)?;
+        self.salt.copy_from_slice(&header[16..24]);
+        self.checksum = header_checksum;
+
+        // Frames written since the last commit frame, not yet visible
+        let mut pending = Vec::new();
+        let mut checksum = header_checksum;
+        let mut frame = vec![0u8; FRAME_HEADER_SIZE + self.page_size];
+        while reader.read_exact(&mut frame).is_ok() {
+            let page_num = read_u32(&frame, 0)?;
+            // Frames left over from before the log was last restarted have the old salt
+            if page_num == 0 || frame[8..16] != self.salt {
+                break;
+            }
+            checksum = wal_checksum(big_endian, &frame[..8], checksum);
+            checksum = wal_checksum(big_endian, &frame[FRAME_HEADER_SIZE..], checksum);
+            if checksum != (read_u32(&frame, 16)?, read_u32(&frame, 20)?) {
+                break;
+            }
+
+            pending.push(page_num);
+            // A commit frame records the size of the database after the commit
+            let commit_size = read_u32(&frame, 4)?;
+            if commit_size != 0 {
+                for page_num in pending.drain(..) {
+                    self.frame_pages.push(page_num);
+                    self.frames.insert(page_num, self.frame_count());
+                }
+                self.checksum = checksum;
+                self.page_count = Some(commit_size);
+            }
+        }
+        Ok(())
+    }
+
+    /// Pick up how far the log has been checkpointed, and the read marks,
+    /// from the wal-index if it describes the same frames the log holds.
+    /// Returns whether it does.
+    fn read_index(&mut self) -> DbResult<bool> {
+        self.backfilled = 0;
+        self.read_marks = [
+            0,
+            READ_MARK_UNUSED,
+            READ_MARK_UNUSED,
+            READ_MARK_UNUSED,
+            READ_MARK_UNUSED,
+        ];
+        if !self.frame_pages.is_empty() {
+            self.read_marks[1] = self.frame_count();
+        }
+
+        let mut index = [0u8; INDEX_HEADER_AREA];
+        let Some(file) = &self.index else {
+            return Ok(false);
+        };
+        if file.read_exact_at(&mut index, 0).is_err() {
+            return Ok(false);
+        }
+        let header = &index[..INDEX_HEADER_SIZE];
+        let native = |offset: usize| read_native(&index, offset);
+        let valid = header == &index[INDEX_HEADER_SIZE..CHECKPOINT_INFO_OFFSET]
+            && header[12] == 1
+            && wal_checksum(cfg!(target_endian = "big"), &header[..40], (0, 0))
+                == (native(40), native(44))
+            && native(16) == self.frame_count()
+            && header[32..40] == self.salt;
+        if valid {
+            self.change = native(8);
+            self.backfilled = native(CHECKPOINT_INFO_OFFSET).min(self.frame_count());
+            for (i, mark) in self.read_marks.iter_mut().enumerate() {
+                *mark = native(READ_MARKS_OFFSET + 4 * i);
+            }
+        }
+        Ok(valid)
+    }
+
+    /// Rewrite the wal-index to describe the log as it is now.
+    fn write_index(&mut self, update: IndexUpdate) -> DbResult<()> {
+        let Some(file) = &self.index else {
+            return Ok(());
+        };
+        let info = CHECKPOINT_INFO_OFFSET as u64;
+        if update != IndexUpdate::Frames {
+            file.write_all_at(&self.backfilled.to_ne_bytes(), info)?;
+            // The number of frames a checkpoint last tried to copy
+            file.write_all_at(&self.backfilled.to_ne_bytes(), info + 32)?;
+        }
+        if update == IndexUpdate::Backfill {
+            return Ok(());
+        }
+        if update == IndexUpdate::All {
+            let marks: Vec<u8> = self
+                .read_marks
+                .iter()
+                .flat_map(|m| m.to_ne_bytes())
+                .collect();
+            file.write_all_at(&marks, READ_MARKS_OFFSET as u64)?;
+        }
+
+        let frames = self.frame_pages.len();
+        let blocks = if frames <= INDEX_FIRST_BLOCK_FRAMES {
+            1
+        } else {
+            2 + (frames - INDEX_FIRST_BLOCK_FRAMES - 1) / INDEX_BLOCK_FRAMES
+        };
+        let mut index = vec![0u8; blocks * INDEX_BLOCK_SIZE];
+
+        // Each block lists the page of each of its frames, and hashes page
+        // numbers to the frames' positions in the block
+        for (i, &page_num) in self.frame_pages.iter().enumerate() {
+            let (block, slot, pages_offset) = if i < INDEX_FIRST_BLOCK_FRAMES {
+                (0, i, INDEX_HEADER_AREA)
+            } else {
+                let i = i - INDEX_FIRST_BLOCK_FRAMES;
+                (1 + i / INDEX_BLOCK_FRAMES, i % INDEX_BLOCK_FRAMES, 0)
+            };
+            let start = block * INDEX_BLOCK_SIZE;
+            let entry = start + pages_offset + 4 * slot;
+            index[entry..entry + 4].copy_from_slice(&page_num.to_ne_bytes());
+
+            let hash_table = start + 4 * INDEX_BLOCK_FRAMES;
+            let mut key = (page_num as usize * 383) % INDEX_HASH_SLOTS;
+            while index[hash_table + 2 * key..hash_table + 2 * key + 2] != [0, 0] {
+                key = (key + 1) % INDEX_HASH_SLOTS;
+            }
+            let position = (slot + 1) as u16;
+            index[hash_table + 2 * key..hash_table + 2 * key + 2]
+                .copy_from_slice(&position.to_ne_bytes());
+        }
+        file.write_all_at(&index[INDEX_HEADER_AREA..], INDEX_HEADER_AREA as u64)?;
+
+        // The header, in the machine's byte order like the rest of the index.
+        // Readers check that both copies match, so the second is written first.
+        let mut header = [0u8; INDEX_HEADER_SIZE];
+        header[..4].copy_from_slice(&WAL_VERSION.to_ne_bytes());
+        header[8..12].copy_from_slice(&self.change.to_ne_bytes());
+        header[12] = 1;
+        header[13] = self.big_endian as u8;
+        // A 65536-byte page size is stored as 1
+        let page_size = ((self.page_size & 0xff00) | (self.page_size >> 16)) as u16;
+        header[14..16].copy_from_slice(&page_size.to_ne_bytes());
+        header[16..20].copy_from_slice(&(frames as u32).to_ne_bytes());
+        header[20..24].copy_from_slice(&self.page_count.unwrap_or_default().to_ne_bytes());
+        header[24..28].copy_from_slice(&self.checksum.0.to_ne_bytes());
+        header[28..32].copy_from_slice(&self.checksum.1.to_ne_bytes());
+        header[32..40].copy_from_slice(&self.salt);
+        let checksum = wal_checksum(cfg!(target_endian = "big"), &header[..40], (0, 0));
+        header[40..44].copy_from_slice(&checksum.0.to_ne_bytes());
+        header[44..48].copy_from_slice(&checksum.1.to_ne_bytes());
+        file.write_all_at(&header, INDEX_HEADER_SIZE as u64)?;
+        file.write_all_at(&header, 0)?;
+        Ok(())
+    }
+}
+
+/// The write-ahead log of the database at `path`.
+pub fn wal_path(path: &str) -> String {
+    format!("{}-wal", path)
+}
+
+/// The wal-index of the database at `path`.
+fn index_path(path: &str) -> String {
+    format!("{}-shm", path)
+}
+
+/// Read a u32 in the machine's byte order, as the wal-index stores them.
+fn read_native(data: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// Random bits for a log's salt.
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Continue a log checksum over `data`, which is read as pairs of 32-bit
+/// words in the byte order the log's magic number selects.
+fn wal_checksum(big_endian: bool, data: &[u8], (mut s0, mut s1): (u32, u32)) -> (u32, u32) {
+    let word = |bytes: &[u8]| {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+    for pair in data.chunks_exact(8) {
+        s0 = s0.wrapping_add(word(&pair[..4])).wrapping_add(s1);
+        s1 = s1.wrapping_add(word(&pair[4..])).wrapping_add(s0);
+    }
+    (s0, s1)
+}
@@ -0,0 +1,8 @@
+//! Reading and writing SQLite database files, and the SQL to query them.
+//!
+//! The `codecrafters-sqlite` binary is a sqlite3-like shell built on this
+//! library. Programs can also use it directly, for instance to register
+//! their own collating sequences with [`db::register_collation`].
+
+pub mod db;
+pub mod sql;
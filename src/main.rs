@@ -1,25 +1,82 @@
-use anyhow::{Result, bail};
+use std::process::ExitCode;
+
+use anyhow::{Context, Result, bail};
+
+use codecrafters_sqlite::{db, sql};
+use output::{OutputMode, OutputOptions};
 
 mod commands;
-mod db;
+mod output;
+mod repl;
+
+fn main() -> Result<ExitCode> {
+    // Parse arguments, allowing sqlite3-style options before the database path
+    let mut args = std::env::args().skip(1).peekable();
+    let mut options = OutputOptions::default();
+    while let Some(flag) = args.next_if(|arg| arg.starts_with('-')) {
+        match flag.trim_start_matches('-') {
+            "nullvalue" => {
+                options.null_value = args.next().context("Missing value for -nullvalue")?;
+            }
+            "separator" => {
+                options.separator = args.next().context("Missing value for -separator")?;
+            }
+            "newline" => {
+                options.row_separator = args.next().context("Missing value for -newline")?;
+            }
+            "header" | "headers" | "noheader" | "noheaders" => {
+                options.headers = !flag.contains("no");
+                options.headers_set = true;
+            }
+            _ => match OutputMode::from_flag(&flag) {
+                Some(mode) => {
+                    // Unlike `.mode`, sqlite3's flags leave headers and row endings alone
+                    let headers = options.headers;
+                    let row_separator = options.row_separator.clone();
+                    options.set_mode(mode);
+                    options.headers = headers;
+                    options.row_separator = row_separator;
+                }
+                None => bail!("Unknown option: {}", flag),
+            },
+        }
+    }
+    let args: Vec<String> = args.collect();
+    let Some(path) = args.first() else {
+        bail!("Missing <database path>");
+    };
 
-fn main() -> Result<()> {
-    // Parse arguments
-    let args: Vec<String> = std::env::args().collect();
-    match args.len() {
-        0 | 1 => bail!("Missing <database path> and <command>"),
-        2 => bail!("Missing <command>"),
-        _ => {}
+    // With no commands, read them interactively
+    let mut pager = db::Pager::open(path);
+    if args.len() == 1 {
+        return repl::run(&mut pager, &mut options);
+    }
+    // Run each command in turn, like sqlite3 does with several arguments
+    for command in &args[1..] {
+        run_command(&mut pager, &mut options, command)?;
     }
-    // Parse command and act accordingly
-    let command = &args[2];
-    match command.as_str() {
-        ".dbinfo" => commands::dbinfo(&args[1])?,
-        ".tables" => commands::table(&args[1])?,
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Run a dot-command, or else SQL.
+fn run_command(pager: &mut db::Pager, options: &mut OutputOptions, command: &str) -> Result<()> {
+    let (name, rest) = command
+        .trim()
+        .split_once(char::is_whitespace)
+        .unwrap_or((command.trim(), ""));
+    match name {
+        ".dbinfo" => commands::dbinfo(pager),
+        ".tables" => commands::table(pager, rest),
+        ".indexes" | ".indices" => commands::indexes(pager, rest),
+        ".schema" => commands::schema(pager, rest),
+        ".fullschema" => commands::fullschema(pager),
+        ".nullvalue" => commands::nullvalue(options, rest),
+        ".mode" => commands::mode(options, rest),
+        ".headers" | ".header" => commands::headers(options, rest),
+        ".separator" => commands::separator(options, rest),
         _ => {
             // Treat as SQL query
-            commands::sql(&args[1], command)?
+            commands::sql(pager, command, options)
         }
     }
-    Ok(())
 }
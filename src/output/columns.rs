@@ -0,0 +1,198 @@
+//! Modes that line values up in columns as wide as their widest value.
+//!
+//! A value with newlines takes a line for each. Tabs are expanded and other
+//! control characters shown as `^X`, so that they can't upset the columns.
+//! Widths are those text takes on a terminal, where East Asian wide
+//! characters take two columns.
+
+use std::io::{self, Write};
+
+use unicode_width::UnicodeWidthStr;
+
+use crate::db::{QueryResult, Value};
+
+use super::{OutputOptions, Renderer, display_bytes};
+
+/// How the columns are drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnStyle {
+    /// Columns two spaces apart, with names underlined if headers are on.
+    Column,
+    /// An ASCII-art table.
+    Table,
+    /// A table of box-drawing characters.
+    Box,
+    /// A Markdown table.
+    Markdown,
+}
+
+/// The `column`, `table`, `box` and `markdown` modes.
+pub struct Columns(pub ColumnStyle);
+
+/// Which horizontal rule of a table to draw.
+#[derive(Clone, Copy)]
+enum Rule {
+    Top,
+    Middle,
+    Bottom,
+}
+
+impl Renderer for Columns {
+    fn render(
+        &self,
+        out: &mut dyn Write,
+        result: &QueryResult,
+        options: &OutputOptions,
+    ) -> io::Result<()> {
+        let style = self.0;
+        let names: Vec<String> = result.columns.iter().map(|name| translate(name)).collect();
+        let rows: Vec<Vec<Vec<String>>> = result
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|value| display_lines(value, options))
+                    .collect()
+            })
+            .collect();
+
+        // Names count towards the widths even when they aren't shown
+        let mut widths: Vec<usize> = names.iter().map(|name| name.width()).collect();
+        for row in &rows {
+            for (width, lines) in widths.iter_mut().zip(row) {
+                for line in lines {
+                    *width = (*width).max(line.width());
+                }
+            }
+        }
+
+        if style != ColumnStyle::Column {
+            write_rule(out, style, &widths, Rule::Top)?;
+        }
+        if style != ColumnStyle::Column || options.headers {
+            let cells: Vec<String> = names
+                .iter()
+                .zip(&widths)
+                .map(|(name, &width)| {
+                    // Only column mode leaves names on the left
+                    if style == ColumnStyle::Column {
+                        pad(name, width)
+                    } else {
+                        center(name, width)
+                    }
+                })
+                .collect();
+            write_cells(out, style, &cells)?;
+            write_rule(out, style, &widths, Rule::Middle)?;
+        }
+
+        // Once any row takes several lines, every row is set apart
+        let multi_line = rows.iter().flatten().any(|lines| lines.len() > 1);
+        for (i, row) in rows.iter().enumerate() {
+            let height = row.iter().map(Vec::len).max().unwrap_or(1);
+            for line in 0..height {
+                let cells: Vec<String> = row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(lines, &width)| pad(lines.get(line).map_or("", String::as_str), width))
+                    .collect();
+                write_cells(out, style, &cells)?;
+            }
+            if multi_line && i + 1 < rows.len() {
+                match style {
+                    ColumnStyle::Column => writeln!(out)?,
+                    ColumnStyle::Table | ColumnStyle::Box => {
+                        write_rule(out, style, &widths, Rule::Middle)?
+                    }
+                    ColumnStyle::Markdown => {}
+                }
+            }
+        }
+        if matches!(style, ColumnStyle::Table | ColumnStyle::Box) {
+            write_rule(out, style, &widths, Rule::Bottom)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write a line of padded cells between the style's borders.
+fn write_cells(out: &mut dyn Write, style: ColumnStyle, cells: &[String]) -> io::Result<()> {
+    match style {
+        ColumnStyle::Column => writeln!(out, "{}", cells.join("  ")),
+        ColumnStyle::Table | ColumnStyle::Markdown => writeln!(out, "| {} |", cells.join(" | ")),
+        ColumnStyle::Box => writeln!(out, "│ {} │", cells.join(" │ ")),
+    }
+}
+
+/// Write a horizontal rule across the columns: the underlining of the
+/// names in column mode, and a border of a table in the others. Markdown
+/// only has the rule under the names.
+fn write_rule(
+    out: &mut dyn Write,
+    style: ColumnStyle,
+    widths: &[usize],
+    rule: Rule,
+) -> io::Result<()> {
+    let (left, line, cross, right) = match (style, rule) {
+        (ColumnStyle::Column, _) => {
+            let dashes: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+            return writeln!(out, "{}", dashes.join("  "));
+        }
+        (ColumnStyle::Markdown, Rule::Middle) => ("|", "-", "|", "|"),
+        (ColumnStyle::Markdown, _) => return Ok(()),
+        (ColumnStyle::Table, _) => ("+", "-", "+", "+"),
+        (ColumnStyle::Box, Rule::Top) => ("┌", "─", "┬", "┐"),
+        (ColumnStyle::Box, Rule::Middle) => ("├", "─", "┼", "┤"),
+        (ColumnStyle::Box, Rule::Bottom) => ("└", "─", "┴", "┘"),
+    };
+    let segments: Vec<String> = widths.iter().map(|&width| line.repeat(width + 2)).collect();
+    writeln!(out, "{}{}{}", left, segments.join(cross), right)
+}
+
+/// Split a value into the lines it is shown as.
+fn display_lines(value: &Value, options: &OutputOptions) -> Vec<String> {
+    let text = match value {
+        Value::Null => options.null_value.clone(),
+        value => String::from_utf8_lossy(&display_bytes(value)).into_owned(),
+    };
+    text.split('\n').map(translate).collect()
+}
+
+/// Make a line of text safe to show in a column: tabs become spaces up to
+/// the next multiple of eight, and other control characters `^X`.
+fn translate(line: &str) -> String {
+    let mut shown = String::new();
+    let mut column = 0;
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = 8 - column % 8;
+            shown.push_str(&" ".repeat(spaces));
+            column += spaces;
+        } else if c < ' ' {
+            shown.push('^');
+            shown.push((c as u8 + b'@') as char);
+            column += 2;
+        } else {
+            shown.push(c);
+            column += 1;
+        }
+    }
+    shown
+}
+
+/// Pad text with spaces on the right to a width.
+fn pad(text: &str, width: usize) -> String {
+    let space = width.saturating_sub(text.width());
+    format!("{}{}", text, " ".repeat(space))
+}
+
+/// Pad text with spaces on both sides to a width, leaning left.
+fn center(text: &str, width: usize) -> String {
+    let space = width.saturating_sub(text.width());
+    format!(
+        "{}{}{}",
+        " ".repeat(space / 2),
+        text,
+        " ".repeat(space - space / 2)
+    )
+}
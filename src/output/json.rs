@@ -0,0 +1,58 @@
+//! JSON output: an array of objects, or an object per line.
+
+use std::io::{self, Write};
+
+use crate::db::{QueryResult, Value};
+
+use super::{OutputOptions, Renderer};
+
+/// The `json` mode: an array of objects keyed by column name.
+pub struct Json;
+
+/// The `jsonl` mode: an object keyed by column name on each line.
+pub struct JsonLines;
+
+impl Renderer for Json {
+    fn render(
+        &self,
+        out: &mut dyn Write,
+        result: &QueryResult,
+        _options: &OutputOptions,
+    ) -> io::Result<()> {
+        for (i, row) in result.rows.iter().enumerate() {
+            let open = if i == 0 { "[" } else { ",\n" };
+            write!(out, "{}{}", open, json_object(&result.columns, row))?;
+        }
+        writeln!(out, "]")
+    }
+}
+
+impl Renderer for JsonLines {
+    fn render(
+        &self,
+        out: &mut dyn Write,
+        result: &QueryResult,
+        _options: &OutputOptions,
+    ) -> io::Result<()> {
+        for row in &result.rows {
+            writeln!(out, "{}", json_object(&result.columns, row))?;
+        }
+        Ok(())
+    }
+}
+
+/// A row as a JSON object with a member for each column.
+fn json_object(columns: &[String], row: &[Value]) -> String {
+    let fields: Vec<String> = columns
+        .iter()
+        .zip(row)
+        .map(|(name, value)| {
+            format!(
+                "{}:{}",
+                Value::Text(name.clone()).to_json(),
+                value.to_json()
+            )
+        })
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
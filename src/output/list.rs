@@ -0,0 +1,219 @@
+//! Modes that print each row, or each value, on a line of its own.
+
+use std::io::{self, Write};
+
+use crate::db::{QueryResult, Value, identifier_sql};
+
+use super::{OutputOptions, Renderer, display_bytes, value_bytes};
+
+/// The `list` and `tabs` modes: values between separators.
+pub struct List;
+
+/// The `csv` mode: values between separators, quoted where needed.
+pub struct Csv;
+
+/// The `quote` mode: SQL literals between separators.
+pub struct Quote;
+
+/// The `insert` mode: an INSERT statement per row.
+pub struct Insert;
+
+/// The `line` mode: a `name = value` line per value, with a blank line
+/// between rows.
+pub struct Line;
+
+impl Renderer for List {
+    fn render(
+        &self,
+        out: &mut dyn Write,
+        result: &QueryResult,
+        options: &OutputOptions,
+    ) -> io::Result<()> {
+        if options.headers {
+            let names: Vec<Vec<u8>> = result
+                .columns
+                .iter()
+                .map(|name| name.clone().into_bytes())
+                .collect();
+            write_row(out, &names, options)?;
+        }
+        for row in &result.rows {
+            let values: Vec<Vec<u8>> = row
+                .iter()
+                .map(|value| value_bytes(value, options))
+                .collect();
+            write_row(out, &values, options)?;
+        }
+        Ok(())
+    }
+}
+
+impl Renderer for Csv {
+    fn render(
+        &self,
+        out: &mut dyn Write,
+        result: &QueryResult,
+        options: &OutputOptions,
+    ) -> io::Result<()> {
+        if options.headers {
+            let names: Vec<Vec<u8>> = result
+                .columns
+                .iter()
+                .map(|name| csv_field(name.as_bytes(), options))
+                .collect();
+            write_row(out, &names, options)?;
+        }
+        for row in &result.rows {
+            let values: Vec<Vec<u8>> = row
+                .iter()
+                .map(|value| match value {
+                    // NULL is the one value never quoted, telling it apart from ''
+                    Value::Null => options.null_value.clone().into_bytes(),
+                    value => csv_field(&display_bytes(value), options),
+                })
+                .collect();
+            write_row(out, &values, options)?;
+        }
+        Ok(())
+    }
+}
+
+impl Renderer for Quote {
+    fn render(
+        &self,
+        out: &mut dyn Write,
+        result: &QueryResult,
+        options: &OutputOptions,
+    ) -> io::Result<()> {
+        if options.headers {
+            let names: Vec<Vec<u8>> = result
+                .columns
+                .iter()
+                .map(|name| Value::Text(name.clone()).to_sql_literal().into_bytes())
+                .collect();
+            write_row(out, &names, options)?;
+        }
+        for row in &result.rows {
+            let values: Vec<Vec<u8>> = row
+                .iter()
+                .map(|value| value.to_sql_literal().into_bytes())
+                .collect();
+            write_row(out, &values, options)?;
+        }
+        Ok(())
+    }
+}
+
+impl Renderer for Insert {
+    fn render(
+        &self,
+        out: &mut dyn Write,
+        result: &QueryResult,
+        options: &OutputOptions,
+    ) -> io::Result<()> {
+        let table = identifier_sql(&options.insert_table);
+        let columns = if options.headers {
+            let names: Vec<String> = result
+                .columns
+                .iter()
+                .map(|name| identifier_sql(name))
+                .collect();
+            format!("({})", names.join(","))
+        } else {
+            String::new()
+        };
+        for row in &result.rows {
+            let values: Vec<String> = row.iter().map(insert_literal).collect();
+            writeln!(
+                out,
+                "INSERT INTO {}{} VALUES({});",
+                table,
+                columns,
+                values.join(",")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Renderer for Line {
+    fn render(
+        &self,
+        out: &mut dyn Write,
+        result: &QueryResult,
+        options: &OutputOptions,
+    ) -> io::Result<()> {
+        // Names are right-aligned, in at least five characters
+        let width = result
+            .columns
+            .iter()
+            .map(|name| name.chars().count())
+            .fold(5, usize::max);
+        for (i, row) in result.rows.iter().enumerate() {
+            if i > 0 {
+                out.write_all(options.row_separator.as_bytes())?;
+            }
+            for (name, value) in result.columns.iter().zip(row) {
+                write!(out, "{:>width$} = ", name, width = width)?;
+                out.write_all(&value_bytes(value, options))?;
+                out.write_all(options.row_separator.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write values between separators, ending the row.
+fn write_row(out: &mut dyn Write, values: &[Vec<u8>], options: &OutputOptions) -> io::Result<()> {
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            out.write_all(options.separator.as_bytes())?;
+        }
+        out.write_all(value)?;
+    }
+    out.write_all(options.row_separator.as_bytes())
+}
+
+/// Quote a CSV value in double quotes if it is empty or holds the
+/// separator, a quote, whitespace, a control character or any non-ASCII
+/// byte, as sqlite3 does.
+fn csv_field(bytes: &[u8], options: &OutputOptions) -> Vec<u8> {
+    let separator = options.separator.as_bytes();
+    let needs_quotes = bytes.is_empty()
+        || bytes
+            .iter()
+            .any(|&b| b <= b' ' || b == b'"' || b == b'\'' || b >= 0x7f)
+        || (!separator.is_empty() && bytes.windows(separator.len()).any(|w| w == separator));
+    if !needs_quotes {
+        return bytes.to_vec();
+    }
+    let mut field = vec![b'"'];
+    for &b in bytes {
+        if b == b'"' {
+            field.push(b'"');
+        }
+        field.push(b);
+    }
+    field.push(b'"');
+    field
+}
+
+/// Write a value as an SQL literal for an INSERT statement. Text holding
+/// control characters, which a literal can't show, uses `unistr()` escapes.
+fn insert_literal(value: &Value) -> String {
+    match value {
+        Value::Text(text) if text.chars().any(|c| c < ' ') => {
+            let mut escaped = String::new();
+            for c in text.chars() {
+                match c {
+                    '\\' => escaped.push_str("\\\\"),
+                    '\'' => escaped.push_str("''"),
+                    c if c < ' ' => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+                    c => escaped.push(c),
+                }
+            }
+            format!("unistr('{}')", escaped)
+        }
+        value => value.to_sql_literal(),
+    }
+}
@@ -0,0 +1,238 @@
+//! Rendering query results in sqlite3's output modes.
+
+mod columns;
+mod json;
+mod list;
+
+use std::io::{self, Write};
+
+use crate::db::{QueryResult, Value};
+
+use columns::{ColumnStyle, Columns};
+use json::{Json, JsonLines};
+use list::{Csv, Insert, Line, List, Quote};
+
+/// How query results are printed, as chosen with `.mode` or a command line
+/// flag such as `-json`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    /// Values separated by `|`, with blobs written as raw bytes up to a NUL.
+    #[default]
+    List,
+    /// Comma-separated values, quoted where needed as RFC 4180 describes.
+    Csv,
+    /// Values separated by tabs, otherwise like list mode.
+    Tabs,
+    /// A JSON array of objects, with blobs as base64 strings.
+    Json,
+    /// A JSON object per line.
+    JsonLines,
+    /// Values lined up in columns under underlined names.
+    Column,
+    /// Columns drawn in an ASCII-art table.
+    Table,
+    /// Columns drawn in a table of box-drawing characters.
+    Box,
+    /// Columns as a Markdown table.
+    Markdown,
+    /// Each value on its own line, after its column's name.
+    Line,
+    /// Values as SQL literals separated by commas, with blobs as `X'..'`.
+    Quote,
+    /// An INSERT statement per row.
+    Insert,
+}
+
+impl OutputMode {
+    /// Every mode, by the name `.mode` takes.
+    const NAMES: &[(&str, OutputMode)] = &[
+        ("box", OutputMode::Box),
+        ("column", OutputMode::Column),
+        ("csv", OutputMode::Csv),
+        ("insert", OutputMode::Insert),
+        ("json", OutputMode::Json),
+        ("jsonl", OutputMode::JsonLines),
+        ("line", OutputMode::Line),
+        ("list", OutputMode::List),
+        ("markdown", OutputMode::Markdown),
+        ("quote", OutputMode::Quote),
+        ("table", OutputMode::Table),
+        ("tabs", OutputMode::Tabs),
+    ];
+
+    /// Parse a mode name, as `.mode` takes it.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(mode_name, _)| mode_name.eq_ignore_ascii_case(name))
+            .map(|&(_, mode)| mode)
+    }
+
+    /// Parse a command line flag such as `-json`.
+    pub fn from_flag(flag: &str) -> Option<Self> {
+        Self::from_name(flag.trim_start_matches('-'))
+    }
+
+    /// The name `.mode` takes for the mode.
+    pub fn name(self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|&&(_, mode)| mode == self)
+            .map_or("list", |&(name, _)| name)
+    }
+
+    /// The names of all modes, for error messages.
+    pub fn names() -> Vec<&'static str> {
+        Self::NAMES.iter().map(|&(name, _)| name).collect()
+    }
+
+    /// The renderer that prints results in the mode.
+    fn renderer(self) -> &'static dyn Renderer {
+        match self {
+            OutputMode::List | OutputMode::Tabs => &List,
+            OutputMode::Csv => &Csv,
+            OutputMode::Json => &Json,
+            OutputMode::JsonLines => &JsonLines,
+            OutputMode::Column => &Columns(ColumnStyle::Column),
+            OutputMode::Table => &Columns(ColumnStyle::Table),
+            OutputMode::Box => &Columns(ColumnStyle::Box),
+            OutputMode::Markdown => &Columns(ColumnStyle::Markdown),
+            OutputMode::Line => &Line,
+            OutputMode::Quote => &Quote,
+            OutputMode::Insert => &Insert,
+        }
+    }
+}
+
+/// Settings that control how query results are printed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputOptions {
+    pub mode: OutputMode,
+    /// Text printed for NULL, set with `.nullvalue`. Empty by default.
+    pub null_value: String,
+    /// Whether modes that can start with a row of column names do, set
+    /// with `.headers`. Table, box and markdown modes always do.
+    pub headers: bool,
+    /// Whether `.headers` has been used, after which column mode no longer
+    /// turns headers on.
+    pub headers_set: bool,
+    /// Printed between values in list, csv and quote modes.
+    pub separator: String,
+    /// Printed after each row in list, csv and quote modes, and after each
+    /// value in line mode.
+    pub row_separator: String,
+    /// The table insert mode's statements insert into.
+    pub insert_table: String,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            mode: OutputMode::default(),
+            null_value: String::new(),
+            headers: false,
+            headers_set: false,
+            separator: "|".to_string(),
+            row_separator: "\n".to_string(),
+            insert_table: "table".to_string(),
+        }
+    }
+}
+
+impl OutputOptions {
+    /// Switch to an output mode. Like sqlite3, modes with separators reset
+    /// them to their own, and column mode turns headers on unless they have
+    /// been set.
+    pub fn set_mode(&mut self, mode: OutputMode) {
+        self.mode = mode;
+        let separators = match mode {
+            OutputMode::List => Some(("|", "\n")),
+            OutputMode::Tabs => Some(("\t", "\n")),
+            OutputMode::Csv => Some((",", "\r\n")),
+            OutputMode::Quote => Some((",", "\n")),
+            _ => None,
+        };
+        if let Some((separator, row_separator)) = separators {
+            self.separator = separator.to_string();
+            self.row_separator = row_separator.to_string();
+        }
+        if mode == OutputMode::Column && !self.headers_set {
+            self.headers = true;
+        }
+    }
+}
+
+/// Prints query results in one output mode.
+trait Renderer {
+    /// Write the rows of a result, and a header naming its columns if the
+    /// mode has one. Nothing is written for a result without rows.
+    fn render(
+        &self,
+        out: &mut dyn Write,
+        result: &QueryResult,
+        options: &OutputOptions,
+    ) -> io::Result<()>;
+}
+
+/// Write a query result to `out` using the given options.
+pub fn write_result(
+    out: &mut impl Write,
+    result: &QueryResult,
+    options: &OutputOptions,
+) -> io::Result<()> {
+    if result.rows.is_empty() {
+        return Ok(());
+    }
+    options.mode.renderer().render(out, result, options)
+}
+
+/// The bytes list-like modes print for a value: NULL as the null value,
+/// and everything else as its displayed bytes.
+fn value_bytes(value: &Value, options: &OutputOptions) -> Vec<u8> {
+    match value {
+        Value::Null => options.null_value.as_bytes().to_vec(),
+        value => display_bytes(value),
+    }
+}
+
+/// The bytes every text mode shows for a value: a blob's own bytes or the
+/// value as text, ending at the first NUL as sqlite3's do.
+fn display_bytes(value: &Value) -> Vec<u8> {
+    let mut bytes = value.to_bytes();
+    if let Some(end) = bytes.iter().position(|&b| b == 0) {
+        bytes.truncate(end);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OutputMode, OutputOptions, write_result};
+    use crate::db::{QueryResult, Value};
+
+    #[test]
+    fn every_text_mode_ends_values_at_a_nul() {
+        let result = QueryResult {
+            columns: vec!["z".to_string(), "t".to_string(), "a".to_string()],
+            rows: vec![vec![
+                Value::Blob(vec![0; 3]),
+                Value::Text("a\0b".to_string()),
+                Value::Text("a".to_string()),
+            ]],
+        };
+        let cases = [
+            (OutputMode::List, "|a|a\n"),
+            (OutputMode::Tabs, "\ta\ta\n"),
+            (OutputMode::Csv, "\"\",a,a\r\n"),
+            (OutputMode::Line, "    z = \n    t = a\n    a = a\n"),
+            (OutputMode::Column, "z  t  a\n-  -  -\n   a  a\n"),
+        ];
+        for (mode, expected) in cases {
+            let mut options = OutputOptions::default();
+            options.set_mode(mode);
+            let mut out = Vec::new();
+            write_result(&mut out, &result, &options).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), expected, "{:?}", mode);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! The interactive shell, run when no command is given: like sqlite3, it
+//! reads SQL statements, which may span lines up to a `;`, and
+//! dot-commands, which take a line each.
+
+use std::env;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use anyhow::Result;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+use crate::db;
+use crate::output::OutputOptions;
+use crate::sql;
+
+/// The prompt for the start of a statement or dot-command.
+const PROMPT: &str = "sqlite> ";
+
+/// The prompt for the later lines of a statement.
+const CONTINUATION_PROMPT: &str = "   ...> ";
+
+/// Read and run commands until end of input or `.quit`. Line editing,
+/// history and prompts are only for a terminal; input from a file or pipe
+/// just runs. A failing command is reported, and the commands after it
+/// still run, but like sqlite3 the exit status for such input is then a
+/// failure.
+pub fn run(pager: &mut db::Pager, options: &mut OutputOptions) -> Result<ExitCode> {
+    let interactive = io::stdin().is_terminal();
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path().filter(|_| interactive);
+    if let Some(path) = &history {
+        // There is no history file until the first session ends
+        let _ = editor.load_history(path);
+    }
+    if interactive {
+        catch_interrupts();
+    }
+
+    let mut failed = false;
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl-C while typing discards the statement so far
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+
+        // Dot-commands are recognized only at the start of a statement
+        if input.is_empty() && line.trim_start().starts_with('.') {
+            let command = line.trim();
+            editor.add_history_entry(command)?;
+            if matches!(command, ".quit" | ".exit") {
+                break;
+            }
+            failed |= !run_input(pager, options, command);
+            continue;
+        }
+        if input.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+        input.push_str(&line);
+        input.push('\n');
+        if sql::is_complete(&input) {
+            editor.add_history_entry(input.trim())?;
+            failed |= !run_input(pager, options, &input);
+            input.clear();
+        }
+    }
+
+    // Like sqlite3, run a last statement left without its semicolon
+    if !input.trim().is_empty() {
+        failed |= !run_input(pager, options, &input);
+    }
+    if let Some(path) = &history {
+        editor.save_history(path)?;
+    }
+    match failed && !interactive {
+        true => Ok(ExitCode::FAILURE),
+        false => Ok(ExitCode::SUCCESS),
+    }
+}
+
+/// Run a dot-command or SQL, reporting rather than returning any error.
+/// Returns whether it succeeded.
+fn run_input(pager: &mut db::Pager, options: &mut OutputOptions, input: &str) -> bool {
+    // A Ctrl-C only cancels what was running when it was pressed
+    db::clear_interrupt();
+    match crate::run_command(pager, options, input) {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("Error: {:#}", err);
+            false
+        }
+    }
+}
+
+/// Where history is kept between sessions: `$SQLITE_HISTORY`, or
+/// `~/.sqlite_history` as for sqlite3.
+fn history_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("SQLITE_HISTORY") {
+        return Some(PathBuf::from(path));
+    }
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".sqlite_history"))
+}
+
+/// Make Ctrl-C interrupt the running statement instead of ending the
+/// process. While a line is being edited, the terminal hands Ctrl-C to the
+/// editor as a key instead.
+fn catch_interrupts() {
+    extern "C" fn on_interrupt(_signal: libc::c_int) {
+        db::interrupt();
+    }
+    let handler: extern "C" fn(libc::c_int) = on_interrupt;
+    // SAFETY: the handler only sets an atomic flag, which is async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
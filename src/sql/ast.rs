@@ -0,0 +1,354 @@
+//! Syntax tree for parsed SQL statements.
+
+use crate::db::Value;
+
+/// A parsed SQL statement.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Select(Select),
+    Insert(Insert),
+    Update(Update),
+    Delete(Delete),
+    Begin(TransactionKind),
+    Commit,
+    /// `ROLLBACK`, or `ROLLBACK TO name` to undo the changes since a savepoint.
+    Rollback(Option<String>),
+    Savepoint(String),
+    Release(String),
+    Pragma(Pragma),
+    CreateTable(CreateTable),
+    CreateIndex(CreateIndex),
+    DropTable(DropObject),
+    DropIndex(DropObject),
+    AlterTable(AlterTable),
+    /// `VACUUM`, or `VACUUM INTO file` to write a compacted copy to the named file.
+    Vacuum(Option<Expr>),
+}
+
+/// A CREATE TABLE statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateTable {
+    pub name: String,
+    pub if_not_exists: bool,
+    pub columns: Vec<ColumnDefinition>,
+    pub constraints: Vec<TableConstraint>,
+    pub without_rowid: bool,
+    /// The statement as sqlite_schema stores it: `CREATE TABLE`, then the
+    /// text from the table name on, as written.
+    pub sql: String,
+}
+
+/// A column definition of a CREATE TABLE or ALTER TABLE ADD COLUMN statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDefinition {
+    pub name: String,
+    /// The declared type as written, such as `VARCHAR(10)`, or empty if none.
+    pub type_name: String,
+    pub constraints: Vec<ColumnConstraint>,
+}
+
+/// A constraint in a column definition. Conflict clauses are accepted but
+/// not kept: the statement's own conflict action decides.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnConstraint {
+    /// `PRIMARY KEY [ASC | DESC] [AUTOINCREMENT]`.
+    PrimaryKey {
+        descending: bool,
+        autoincrement: bool,
+    },
+    NotNull,
+    Unique,
+    /// `CHECK (expr)`, with the expression as written.
+    Check(String),
+    /// `DEFAULT value`: a literal, a signed number or a parenthesized expression.
+    Default(Expr),
+    Collate(String),
+    References(ForeignKeyClause),
+    /// `[GENERATED ALWAYS] AS (expr) [STORED | VIRTUAL]`, with the expression as written.
+    Generated(String),
+}
+
+/// A constraint of a CREATE TABLE statement listed after its columns.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableConstraint {
+    PrimaryKey(Vec<IndexedColumn>),
+    Unique(Vec<IndexedColumn>),
+    /// `CHECK (expr)`, with the expression as written.
+    Check(String),
+    ForeignKey {
+        columns: Vec<String>,
+        clause: ForeignKeyClause,
+    },
+}
+
+/// `REFERENCES table [(column, ...)]`, the parent a foreign key refers to.
+/// Actions and deferral are accepted but not kept, as foreign keys aren't enforced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKeyClause {
+    pub table: String,
+    pub columns: Vec<String>,
+}
+
+/// A CREATE INDEX statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateIndex {
+    pub name: String,
+    pub table: String,
+    pub unique: bool,
+    pub if_not_exists: bool,
+    pub columns: Vec<IndexedColumn>,
+    /// Condition of a partial index: only rows satisfying it are indexed.
+    pub where_clause: Option<Expr>,
+    /// The statement as sqlite_schema stores it, like [`CreateTable::sql`].
+    pub sql: String,
+}
+
+/// A DROP TABLE or DROP INDEX statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DropObject {
+    pub name: String,
+    pub if_exists: bool,
+}
+
+/// An ALTER TABLE statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlterTable {
+    pub table: String,
+    pub action: AlterAction,
+}
+
+/// What an ALTER TABLE statement changes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlterAction {
+    /// `RENAME TO name`.
+    RenameTable(String),
+    /// `RENAME [COLUMN] old TO new`.
+    RenameColumn { old: String, new: String },
+    /// `ADD [COLUMN] definition`; `text` is the column definition as written.
+    AddColumn {
+        column: ColumnDefinition,
+        text: String,
+    },
+    /// `DROP [COLUMN] name`.
+    DropColumn(String),
+}
+
+/// A column of an index being created, as in `name COLLATE NOCASE DESC`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedColumn {
+    pub name: String,
+    pub collation: Option<String>,
+    pub descending: bool,
+}
+
+/// A PRAGMA statement, which reads or changes a setting of the database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pragma {
+    pub name: String,
+    /// The argument given after `=` or in parentheses, if any.
+    pub value: Option<String>,
+}
+
+/// When a transaction started with `BEGIN` takes its locks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransactionKind {
+    /// Only once it first reads or writes.
+    #[default]
+    Deferred,
+    /// Right away, so that it can write.
+    Immediate,
+    /// Right away, keeping other connections from reading as well.
+    Exclusive,
+}
+
+/// An INSERT statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Insert {
+    pub conflict: ConflictAction,
+    pub table: String,
+    /// The columns named after the table, or `None` to fill every column in order.
+    pub columns: Option<Vec<String>>,
+    pub source: InsertSource,
+}
+
+/// How a statement resolves a constraint violation, as in `INSERT OR IGNORE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictAction {
+    Rollback,
+    /// Undo the statement's changes and fail.
+    #[default]
+    Abort,
+    /// Fail, keeping the changes the statement made before the violation.
+    Fail,
+    /// Skip the row that would violate the constraint.
+    Ignore,
+    /// Delete the rows that conflict with the new one.
+    Replace,
+}
+
+/// Where the rows of an INSERT come from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InsertSource {
+    /// `VALUES (...), (...)`: one list of expressions per row.
+    Values(Vec<Vec<Expr>>),
+    /// `INSERT ... SELECT`: the rows of a query.
+    Select(Box<Select>),
+    /// `DEFAULT VALUES`: a single row of column defaults.
+    DefaultValues,
+}
+
+/// An UPDATE statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    pub conflict: ConflictAction,
+    pub table: String,
+    /// The `column = expr` assignments of the SET clause, in order.
+    pub assignments: Vec<(String, Expr)>,
+    pub where_clause: Option<Expr>,
+}
+
+/// A DELETE statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delete {
+    pub table: String,
+    pub where_clause: Option<Expr>,
+}
+
+/// A SELECT statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+    pub columns: Vec<ResultColumn>,
+    pub from: Option<String>,
+    pub where_clause: Option<Expr>,
+    pub order_by: Vec<OrderingTerm>,
+}
+
+/// One term of an ORDER BY clause.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub descending: bool,
+}
+
+/// One entry in the result column list of a SELECT.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResultColumn {
+    /// `*`: every column of the table.
+    Star,
+    /// An expression, optionally named with `AS alias`.
+    /// `text` is the expression as written, used as the column name otherwise.
+    Expr {
+        expr: Expr,
+        alias: Option<String>,
+        text: String,
+    },
+}
+
+/// Binary operators, from loosest to tightest binding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    /// `IS`: like `=`, but NULL equals NULL and the result is never NULL.
+    Is,
+    IsNot,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Concat,
+}
+
+impl BinaryOp {
+    /// Check if the operator compares its operands, so affinity applies to them.
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Eq
+                | BinaryOp::NotEq
+                | BinaryOp::Is
+                | BinaryOp::IsNot
+                | BinaryOp::Lt
+                | BinaryOp::LtEq
+                | BinaryOp::Gt
+                | BinaryOp::GtEq
+        )
+    }
+}
+
+/// Unary prefix operators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Negate,
+    Plus,
+}
+
+/// An expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    Column(String),
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    /// `CAST(expr AS type_name)`.
+    Cast {
+        expr: Box<Expr>,
+        type_name: String,
+    },
+    /// `expr COLLATE collation`: compare the value with a named collating sequence.
+    Collate {
+        expr: Box<Expr>,
+        collation: String,
+    },
+    /// A function call; `star` is set for `count(*)`.
+    Function {
+        name: String,
+        args: Vec<Expr>,
+        star: bool,
+    },
+}
+
+impl Expr {
+    /// Call `f` on this expression and every expression nested in it.
+    pub fn walk<'a>(&'a self, f: &mut impl FnMut(&'a Expr)) {
+        f(self);
+        match self {
+            Expr::Unary { expr, .. } | Expr::Cast { expr, .. } | Expr::Collate { expr, .. } => {
+                expr.walk(f)
+            }
+            Expr::Binary { left, right, .. } => {
+                left.walk(f);
+                right.walk(f);
+            }
+            Expr::Function { args, .. } => args.iter().for_each(|arg| arg.walk(f)),
+            Expr::Literal(_) | Expr::Column(_) => {}
+        }
+    }
+
+    /// Check if this is a call to an aggregate function.
+    pub fn is_aggregate_call(&self) -> bool {
+        matches!(self, Expr::Function { name, .. } if name.eq_ignore_ascii_case("count"))
+    }
+
+    /// Check if the expression contains an aggregate function call.
+    pub fn is_aggregate(&self) -> bool {
+        let mut found = false;
+        self.walk(&mut |expr| found |= expr.is_aggregate_call());
+        found
+    }
+}
@@ -0,0 +1,323 @@
+//! SQL tokenizer.
+
+use crate::db::{DbError, DbResult};
+
+/// Punctuation and operators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symbol {
+    LeftParen,
+    RightParen,
+    Comma,
+    Semicolon,
+    Dot,
+    Star,
+    Plus,
+    Minus,
+    Slash,
+    Percent,
+    Concat,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+/// A lexical token.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// An unquoted word: a keyword or a bare identifier.
+    Word(String),
+    /// A "quoted", [bracketed] or `backticked` identifier.
+    QuotedIdentifier(String),
+    /// A 'single-quoted' string literal.
+    String(String),
+    /// An X'hex' blob literal.
+    Blob(Vec<u8>),
+    Integer(i64),
+    Float(f64),
+    Symbol(Symbol),
+    Eof,
+}
+
+/// A token and the byte offsets where it starts and ends in the SQL text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned {
+    pub token: Token,
+    pub pos: usize,
+    pub end: usize,
+}
+
+/// Split SQL text into tokens, ending with `Token::Eof`.
+pub fn tokenize(sql: &str) -> DbResult<Vec<Spanned>> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        let start = pos;
+
+        // Whitespace and comments
+        if c.is_ascii_whitespace() {
+            pos += 1;
+            continue;
+        }
+        if sql[pos..].starts_with("--") {
+            pos = sql[pos..].find('\n').map_or(bytes.len(), |n| pos + n + 1);
+            continue;
+        }
+        if sql[pos..].starts_with("/*") {
+            pos = sql[pos + 2..]
+                .find("*/")
+                .map_or(bytes.len(), |n| pos + 2 + n + 2);
+            continue;
+        }
+
+        let token = match c {
+            b'\'' => {
+                let (text, end) = read_quoted(sql, pos, '\'')?;
+                pos = end;
+                Token::String(text)
+            }
+            b'"' | b'`' => {
+                let (text, end) = read_quoted(sql, pos, c as char)?;
+                pos = end;
+                Token::QuotedIdentifier(text)
+            }
+            b'[' => {
+                let end = sql[pos..].find(']').ok_or_else(|| DbError::Parse {
+                    pos,
+                    msg: "unterminated [identifier]".to_string(),
+                })?;
+                let text = sql[pos + 1..pos + end].to_string();
+                pos += end + 1;
+                Token::QuotedIdentifier(text)
+            }
+            b'x' | b'X' if bytes.get(pos + 1) == Some(&b'\'') => {
+                let (hex, end) = read_quoted(sql, pos + 1, '\'')?;
+                pos = end;
+                Token::Blob(decode_hex_literal(&hex).ok_or_else(|| DbError::Parse {
+                    pos: start,
+                    msg: format!("malformed blob literal X'{}'", hex),
+                })?)
+            }
+            b'0'..=b'9' => {
+                let (token, end) = read_number(sql, pos)?;
+                pos = end;
+                token
+            }
+            b'.' if bytes.get(pos + 1).is_some_and(u8::is_ascii_digit) => {
+                let (token, end) = read_number(sql, pos)?;
+                pos = end;
+                token
+            }
+            c if c.is_ascii_alphabetic() || c == b'_' || c >= 0x80 => {
+                let end = sql[pos..]
+                    .find(|ch: char| !is_identifier_char(ch))
+                    .map_or(bytes.len(), |n| pos + n);
+                let word = sql[pos..end].to_string();
+                pos = end;
+                Token::Word(word)
+            }
+            _ => {
+                let (symbol, len) = read_symbol(&sql[pos..]).ok_or_else(|| DbError::Parse {
+                    pos,
+                    msg: format!("unrecognized token: \"{}\"", &sql[pos..pos + 1]),
+                })?;
+                pos += len;
+                Token::Symbol(symbol)
+            }
+        };
+
+        if pos == start {
+            return Err(DbError::Parse {
+                pos,
+                msg: "empty token".to_string(),
+            });
+        }
+        tokens.push(Spanned {
+            token,
+            pos: start,
+            end: pos,
+        });
+    }
+
+    tokens.push(Spanned {
+        token: Token::Eof,
+        pos: sql.len(),
+        end: sql.len(),
+    });
+    Ok(tokens)
+}
+
+/// Check if SQL text ends with a complete statement: a semicolon that isn't
+/// in a string, quoted identifier or comment, followed by nothing but
+/// whitespace and comments. Like `sqlite3_complete`, nothing else about the
+/// SQL is checked.
+pub fn is_complete(sql: &str) -> bool {
+    let mut complete = false;
+    let mut pos = 0;
+    while pos < sql.len() {
+        let rest = &sql[pos..];
+        let skip = if rest.starts_with("--") {
+            rest.find('\n').map_or(rest.len(), |n| n + 1)
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            match comment.find("*/") {
+                Some(n) => n + 4,
+                None => return false,
+            }
+        } else {
+            let c = rest.as_bytes()[0];
+            let close = match c {
+                b'\'' | b'"' | b'`' => Some(c as char),
+                b'[' => Some(']'),
+                _ => None,
+            };
+            match close {
+                // A doubled quote inside reads as two quoted strings, which
+                // makes no difference here
+                Some(close) => match rest[1..].find(close) {
+                    Some(n) => {
+                        complete = false;
+                        n + 2
+                    }
+                    None => return false,
+                },
+                None => {
+                    if !c.is_ascii_whitespace() {
+                        complete = c == b';';
+                    }
+                    rest.chars().next().map_or(1, char::len_utf8)
+                }
+            }
+        };
+        pos += skip;
+    }
+    complete
+}
+
+/// Whether a character can continue a bare identifier. As in SQLite, every
+/// non-ASCII character can, whatever its Unicode class.
+fn is_identifier_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_' || ch == '$' || !ch.is_ascii()
+}
+
+/// Read text between `quote` characters starting at `pos`, where a doubled
+/// quote stands for a literal one. Returns the text and the offset after it.
+fn read_quoted(sql: &str, pos: usize, quote: char) -> DbResult<(String, usize)> {
+    let mut text = String::new();
+    let mut chars = sql[pos + 1..].char_indices().peekable();
+    while let Some((i, ch)) = chars.next() {
+        if ch == quote {
+            if chars.peek().is_some_and(|&(_, next)| next == quote) {
+                chars.next();
+                text.push(quote);
+            } else {
+                return Ok((text, pos + 1 + i + 1));
+            }
+        } else {
+            text.push(ch);
+        }
+    }
+    Err(DbError::Parse {
+        pos,
+        msg: "unterminated quoted string".to_string(),
+    })
+}
+
+/// Read an integer or floating point literal starting at `pos`.
+fn read_number(sql: &str, pos: usize) -> DbResult<(Token, usize)> {
+    let bytes = sql.as_bytes();
+
+    // Hexadecimal integers
+    if sql[pos..].starts_with("0x") || sql[pos..].starts_with("0X") {
+        let end = sql[pos + 2..]
+            .find(|ch: char| !ch.is_ascii_hexdigit())
+            .map_or(bytes.len(), |n| pos + 2 + n);
+        let value = u64::from_str_radix(&sql[pos + 2..end], 16).map_err(|_| DbError::Parse {
+            pos,
+            msg: format!("hex literal too big: {}", &sql[pos..end]),
+        })?;
+        return Ok((Token::Integer(value as i64), end));
+    }
+
+    let mut end = pos;
+    let mut is_float = false;
+    while end < bytes.len() && bytes[end].is_ascii_digit() {
+        end += 1;
+    }
+    if end < bytes.len() && bytes[end] == b'.' {
+        is_float = true;
+        end += 1;
+        while end < bytes.len() && bytes[end].is_ascii_digit() {
+            end += 1;
+        }
+    }
+    if end < bytes.len() && (bytes[end] == b'e' || bytes[end] == b'E') {
+        let mut exp_end = end + 1;
+        if exp_end < bytes.len() && (bytes[exp_end] == b'+' || bytes[exp_end] == b'-') {
+            exp_end += 1;
+        }
+        if exp_end < bytes.len() && bytes[exp_end].is_ascii_digit() {
+            is_float = true;
+            end = exp_end;
+            while end < bytes.len() && bytes[end].is_ascii_digit() {
+                end += 1;
+            }
+        }
+    }
+
+    let text = &sql[pos..end];
+    let malformed = || DbError::Parse {
+        pos,
+        msg: format!("malformed number: {}", text),
+    };
+    if !is_float && let Ok(i) = text.parse::<i64>() {
+        return Ok((Token::Integer(i), end));
+    }
+    // Integers too large for 64 bits become floats, as in SQLite
+    let f = text.parse::<f64>().map_err(|_| malformed())?;
+    Ok((Token::Float(f), end))
+}
+
+/// Match the longest operator at the start of `text`.
+fn read_symbol(text: &str) -> Option<(Symbol, usize)> {
+    const SYMBOLS: &[(&str, Symbol)] = &[
+        ("||", Symbol::Concat),
+        ("==", Symbol::Eq),
+        ("!=", Symbol::NotEq),
+        ("<>", Symbol::NotEq),
+        ("<=", Symbol::LtEq),
+        (">=", Symbol::GtEq),
+        ("(", Symbol::LeftParen),
+        (")", Symbol::RightParen),
+        (",", Symbol::Comma),
+        (";", Symbol::Semicolon),
+        (".", Symbol::Dot),
+        ("*", Symbol::Star),
+        ("+", Symbol::Plus),
+        ("-", Symbol::Minus),
+        ("/", Symbol::Slash),
+        ("%", Symbol::Percent),
+        ("=", Symbol::Eq),
+        ("<", Symbol::Lt),
+        (">", Symbol::Gt),
+    ];
+    SYMBOLS
+        .iter()
+        .find(|(s, _)| text.starts_with(s))
+        .map(|&(s, symbol)| (symbol, s.len()))
+}
+
+/// Decode the hex digits of an X'..' literal, which must come in pairs.
+fn decode_hex_literal(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
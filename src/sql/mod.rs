@@ -0,0 +1,8 @@
+//! SQL tokenizing and parsing.
+
+pub mod ast;
+mod lexer;
+mod parser;
+
+pub use lexer::{Spanned, Symbol, Token, is_complete, tokenize};
+pub use parser::{parse, parse_expr};
@@ -0,0 +1,1206 @@
+//! Recursive-descent SQL parser.
+
+use crate::db::{DbError, DbResult, Value};
+
+use super::ast::{
+    AlterAction, AlterTable, BinaryOp, ColumnConstraint, ColumnDefinition, ConflictAction,
+    CreateIndex, CreateTable, Delete, DropObject, Expr, ForeignKeyClause, IndexedColumn, Insert,
+    InsertSource, OrderingTerm, Pragma, ResultColumn, Select, Statement, TableConstraint,
+    TransactionKind, UnaryOp, Update,
+};
+use super::lexer::{Spanned, Symbol, Token, tokenize};
+
+/// Words that end an expression or clause and so can't be used as bare aliases.
+const RESERVED: &[&str] = &[
+    "SELECT", "FROM", "WHERE", "AND", "OR", "NOT", "AS", "ORDER", "GROUP", "LIMIT", "HAVING", "IS",
+    "ISNULL", "NOTNULL", "COLLATE",
+];
+
+/// Words that start a column constraint, ending the column's declared type.
+const COLUMN_CONSTRAINT_WORDS: &[&str] = &[
+    "CONSTRAINT",
+    "PRIMARY",
+    "NOT",
+    "NULL",
+    "UNIQUE",
+    "CHECK",
+    "DEFAULT",
+    "COLLATE",
+    "REFERENCES",
+    "GENERATED",
+    "AS",
+];
+
+/// Parse SQL statements separated by semicolons, allowing a trailing one.
+pub fn parse(sql: &str) -> DbResult<Vec<Statement>> {
+    let mut parser = Parser {
+        sql,
+        tokens: tokenize(sql)?,
+        pos: 0,
+    };
+    let mut statements = Vec::new();
+    loop {
+        while parser.eat_symbol(Symbol::Semicolon) {}
+        if parser.peek() == &Token::Eof {
+            break;
+        }
+        statements.push(parser.parse_statement()?);
+        if !parser.eat_symbol(Symbol::Semicolon) && parser.peek() != &Token::Eof {
+            return Err(parser.error("unexpected text after end of statement"));
+        }
+    }
+    if statements.is_empty() {
+        return Err(parser.error("expected a statement"));
+    }
+    Ok(statements)
+}
+
+/// Parse a standalone expression, such as a column's DEFAULT value.
+pub fn parse_expr(sql: &str) -> DbResult<Expr> {
+    let mut parser = Parser {
+        sql,
+        tokens: tokenize(sql)?,
+        pos: 0,
+    };
+    let expr = parser.parse_expr()?;
+    if parser.peek() != &Token::Eof {
+        return Err(parser.error("unexpected text after end of expression"));
+    }
+    Ok(expr)
+}
+
+struct Parser<'a> {
+    sql: &'a str,
+    tokens: Vec<Spanned>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn parse_statement(&mut self) -> DbResult<Statement> {
+        if self.is_keyword("SELECT") {
+            return Ok(Statement::Select(self.parse_select()?));
+        }
+        if self.is_keyword("INSERT") || self.is_keyword("REPLACE") {
+            return Ok(Statement::Insert(self.parse_insert()?));
+        }
+        if self.is_keyword("UPDATE") {
+            return Ok(Statement::Update(self.parse_update()?));
+        }
+        if self.is_keyword("DELETE") {
+            return Ok(Statement::Delete(self.parse_delete()?));
+        }
+        if self.eat_keyword("BEGIN") {
+            let kind = if self.eat_keyword("IMMEDIATE") {
+                TransactionKind::Immediate
+            } else if self.eat_keyword("EXCLUSIVE") {
+                TransactionKind::Exclusive
+            } else {
+                self.eat_keyword("DEFERRED");
+                TransactionKind::Deferred
+            };
+            self.eat_keyword("TRANSACTION");
+            return Ok(Statement::Begin(kind));
+        }
+        if self.eat_keyword("COMMIT") || self.eat_keyword("END") {
+            self.eat_keyword("TRANSACTION");
+            return Ok(Statement::Commit);
+        }
+        if self.eat_keyword("ROLLBACK") {
+            self.eat_keyword("TRANSACTION");
+            if !self.eat_keyword("TO") {
+                return Ok(Statement::Rollback(None));
+            }
+            self.eat_keyword("SAVEPOINT");
+            return Ok(Statement::Rollback(Some(self.parse_identifier()?)));
+        }
+        if self.eat_keyword("SAVEPOINT") {
+            return Ok(Statement::Savepoint(self.parse_identifier()?));
+        }
+        if self.eat_keyword("RELEASE") {
+            self.eat_keyword("SAVEPOINT");
+            return Ok(Statement::Release(self.parse_identifier()?));
+        }
+        if self.eat_keyword("PRAGMA") {
+            return Ok(Statement::Pragma(self.parse_pragma()?));
+        }
+        if self.eat_keyword("CREATE") {
+            let unique = self.eat_keyword("UNIQUE");
+            if !unique && self.eat_keyword("TABLE") {
+                return Ok(Statement::CreateTable(self.parse_create_table()?));
+            }
+            self.expect_keyword("INDEX")?;
+            return Ok(Statement::CreateIndex(self.parse_create_index(unique)?));
+        }
+        if self.eat_keyword("DROP") {
+            if self.eat_keyword("TABLE") {
+                return Ok(Statement::DropTable(self.parse_drop()?));
+            }
+            self.expect_keyword("INDEX")?;
+            return Ok(Statement::DropIndex(self.parse_drop()?));
+        }
+        if self.eat_keyword("ALTER") {
+            self.expect_keyword("TABLE")?;
+            return Ok(Statement::AlterTable(self.parse_alter_table()?));
+        }
+        if self.eat_keyword("VACUUM") {
+            // Only the main database exists, so a schema name changes nothing
+            if matches!(self.peek(), Token::Word(_) | Token::QuotedIdentifier(_))
+                && !self.is_keyword("INTO")
+            {
+                self.parse_identifier()?;
+            }
+            let into = if self.eat_keyword("INTO") {
+                Some(self.parse_expr()?)
+            } else {
+                None
+            };
+            return Ok(Statement::Vacuum(into));
+        }
+        Err(self.error("expected a statement"))
+    }
+
+    /// Parse the rest of `DROP TABLE` or `DROP INDEX`: `[IF EXISTS] [schema.]name`.
+    fn parse_drop(&mut self) -> DbResult<DropObject> {
+        let if_exists = if self.eat_keyword("IF") {
+            self.expect_keyword("EXISTS")?;
+            true
+        } else {
+            false
+        };
+        let (name, _) = self.parse_schema_object_name()?;
+        Ok(DropObject { name, if_exists })
+    }
+
+    /// Parse the rest of `ALTER TABLE [schema.]name`, followed by `RENAME TO`,
+    /// `RENAME [COLUMN]`, `ADD [COLUMN]` or `DROP [COLUMN]`.
+    fn parse_alter_table(&mut self) -> DbResult<AlterTable> {
+        let (table, _) = self.parse_schema_object_name()?;
+        let action = if self.eat_keyword("RENAME") {
+            if self.eat_keyword("TO") {
+                AlterAction::RenameTable(self.parse_identifier()?)
+            } else {
+                self.eat_keyword("COLUMN");
+                let old = self.parse_identifier()?;
+                self.expect_keyword("TO")?;
+                let new = self.parse_identifier()?;
+                AlterAction::RenameColumn { old, new }
+            }
+        } else if self.eat_keyword("ADD") {
+            self.eat_keyword("COLUMN");
+            let start = self.tokens[self.pos].pos;
+            let column = self.parse_column_definition()?;
+            AlterAction::AddColumn {
+                column,
+                text: self.text_since(start).to_string(),
+            }
+        } else if self.eat_keyword("DROP") {
+            self.eat_keyword("COLUMN");
+            AlterAction::DropColumn(self.parse_identifier()?)
+        } else {
+            return Err(self.error("expected RENAME, ADD or DROP"));
+        };
+        Ok(AlterTable { table, action })
+    }
+
+    /// Parse the rest of `CREATE TABLE [IF NOT EXISTS] [schema.]name (...) [WITHOUT ROWID]`.
+    fn parse_create_table(&mut self) -> DbResult<CreateTable> {
+        let if_not_exists = self.parse_if_not_exists()?;
+        let (name, start) = self.parse_schema_object_name()?;
+        if self.is_keyword("AS") {
+            return Err(DbError::UnsupportedFeature(
+                "CREATE TABLE ... AS SELECT".to_string(),
+            ));
+        }
+        self.expect_symbol(Symbol::LeftParen)?;
+        if self.peek() == &Token::Symbol(Symbol::RightParen) {
+            return Err(self.error("expected a column definition"));
+        }
+        // Columns come first, then table constraints
+        let mut columns = Vec::new();
+        let mut constraints = Vec::new();
+        loop {
+            if !constraints.is_empty() || self.is_table_constraint() {
+                constraints.push(self.parse_table_constraint()?);
+            } else {
+                columns.push(self.parse_column_definition()?);
+            }
+            if !self.eat_symbol(Symbol::Comma) {
+                break;
+            }
+        }
+        self.expect_symbol(Symbol::RightParen)?;
+        let without_rowid = self.eat_keyword("WITHOUT");
+        if without_rowid {
+            self.expect_keyword("ROWID")?;
+        }
+        Ok(CreateTable {
+            name,
+            if_not_exists,
+            columns,
+            constraints,
+            without_rowid,
+            sql: format!("CREATE TABLE {}", self.text_since(start)),
+        })
+    }
+
+    /// Check whether a table constraint, rather than a column, comes next.
+    fn is_table_constraint(&self) -> bool {
+        ["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"]
+            .iter()
+            .any(|keyword| self.is_keyword(keyword))
+    }
+
+    /// Parse `name [type] [constraint ...]`.
+    fn parse_column_definition(&mut self) -> DbResult<ColumnDefinition> {
+        let name = self.parse_identifier()?;
+        let type_name = self.parse_column_type()?;
+        let mut constraints = Vec::new();
+        while !matches!(
+            self.peek(),
+            Token::Symbol(Symbol::Comma | Symbol::RightParen | Symbol::Semicolon) | Token::Eof
+        ) {
+            if let Some(constraint) = self.parse_column_constraint()? {
+                constraints.push(constraint);
+            }
+        }
+        Ok(ColumnDefinition {
+            name,
+            type_name,
+            constraints,
+        })
+    }
+
+    /// Parse a column's declared type, such as `UNSIGNED BIG INT` or
+    /// `VARCHAR(10)`, returning it as written, or empty if there is none.
+    fn parse_column_type(&mut self) -> DbResult<String> {
+        let start = self.tokens[self.pos].pos;
+        let mut words = 0;
+        while let Token::Word(word) | Token::QuotedIdentifier(word) = self.peek() {
+            if COLUMN_CONSTRAINT_WORDS
+                .iter()
+                .any(|keyword| word.eq_ignore_ascii_case(keyword))
+            {
+                break;
+            }
+            self.advance();
+            words += 1;
+        }
+        if words == 0 {
+            return Ok(String::new());
+        }
+        if self.eat_symbol(Symbol::LeftParen) {
+            loop {
+                if !self.eat_symbol(Symbol::Plus) {
+                    self.eat_symbol(Symbol::Minus);
+                }
+                match self.peek() {
+                    Token::Integer(_) | Token::Float(_) => self.advance(),
+                    _ => return Err(self.error("expected a type size")),
+                }
+                if !self.eat_symbol(Symbol::Comma) {
+                    break;
+                }
+            }
+            self.expect_symbol(Symbol::RightParen)?;
+        }
+        Ok(self.text_since(start).to_string())
+    }
+
+    /// Parse one column constraint, or `None` for a bare `NULL`, which
+    /// allows what is allowed anyway.
+    fn parse_column_constraint(&mut self) -> DbResult<Option<ColumnConstraint>> {
+        if self.eat_keyword("CONSTRAINT") {
+            self.parse_identifier()?;
+        }
+        let constraint = if self.eat_keyword("PRIMARY") {
+            self.expect_keyword("KEY")?;
+            let descending = self.parse_sort_order();
+            self.parse_conflict_clause()?;
+            let autoincrement = self.eat_keyword("AUTOINCREMENT");
+            ColumnConstraint::PrimaryKey {
+                descending,
+                autoincrement,
+            }
+        } else if self.eat_keyword("NOT") {
+            self.expect_keyword("NULL")?;
+            self.parse_conflict_clause()?;
+            ColumnConstraint::NotNull
+        } else if self.eat_keyword("NULL") {
+            self.parse_conflict_clause()?;
+            return Ok(None);
+        } else if self.eat_keyword("UNIQUE") {
+            self.parse_conflict_clause()?;
+            ColumnConstraint::Unique
+        } else if self.eat_keyword("CHECK") {
+            ColumnConstraint::Check(self.parse_parenthesized_text()?)
+        } else if self.eat_keyword("DEFAULT") {
+            ColumnConstraint::Default(self.parse_default()?)
+        } else if self.eat_keyword("COLLATE") {
+            ColumnConstraint::Collate(self.parse_identifier()?)
+        } else if self.eat_keyword("REFERENCES") {
+            ColumnConstraint::References(self.parse_foreign_key_clause()?)
+        } else if self.is_keyword("GENERATED") || self.is_keyword("AS") {
+            if self.eat_keyword("GENERATED") {
+                self.expect_keyword("ALWAYS")?;
+            }
+            self.expect_keyword("AS")?;
+            let expr = self.parse_parenthesized_text()?;
+            if !self.eat_keyword("STORED") {
+                self.eat_keyword("VIRTUAL");
+            }
+            ColumnConstraint::Generated(expr)
+        } else {
+            return Err(self.error("expected a column constraint"));
+        };
+        Ok(Some(constraint))
+    }
+
+    /// Parse a table constraint: `[CONSTRAINT name]` followed by `PRIMARY
+    /// KEY (...)`, `UNIQUE (...)`, `CHECK (expr)` or `FOREIGN KEY (...) REFERENCES ...`.
+    fn parse_table_constraint(&mut self) -> DbResult<TableConstraint> {
+        if self.eat_keyword("CONSTRAINT") {
+            self.parse_identifier()?;
+        }
+        if self.eat_keyword("PRIMARY") {
+            self.expect_keyword("KEY")?;
+            let columns = self.parse_indexed_columns()?;
+            self.parse_conflict_clause()?;
+            Ok(TableConstraint::PrimaryKey(columns))
+        } else if self.eat_keyword("UNIQUE") {
+            let columns = self.parse_indexed_columns()?;
+            self.parse_conflict_clause()?;
+            Ok(TableConstraint::Unique(columns))
+        } else if self.eat_keyword("CHECK") {
+            Ok(TableConstraint::Check(self.parse_parenthesized_text()?))
+        } else if self.eat_keyword("FOREIGN") {
+            self.expect_keyword("KEY")?;
+            let columns = self.parse_name_list()?;
+            self.expect_keyword("REFERENCES")?;
+            Ok(TableConstraint::ForeignKey {
+                columns,
+                clause: self.parse_foreign_key_clause()?,
+            })
+        } else {
+            Err(self.error("expected a table constraint"))
+        }
+    }
+
+    /// Parse the rest of `REFERENCES table [(column, ...)]`, skipping the
+    /// actions and deferral that may follow.
+    fn parse_foreign_key_clause(&mut self) -> DbResult<ForeignKeyClause> {
+        let table = self.parse_identifier()?;
+        let columns = if self.peek() == &Token::Symbol(Symbol::LeftParen) {
+            self.parse_name_list()?
+        } else {
+            Vec::new()
+        };
+        loop {
+            if self.eat_keyword("ON") {
+                if !self.eat_keyword("DELETE") {
+                    self.expect_keyword("UPDATE")?;
+                }
+                if self.eat_keyword("SET") {
+                    if !self.eat_keyword("NULL") {
+                        self.expect_keyword("DEFAULT")?;
+                    }
+                } else if self.eat_keyword("NO") {
+                    self.expect_keyword("ACTION")?;
+                } else if !self.eat_keyword("CASCADE") {
+                    self.expect_keyword("RESTRICT")?;
+                }
+            } else if self.eat_keyword("MATCH") {
+                self.parse_identifier()?;
+            } else if self.is_keyword("DEFERRABLE")
+                || (self.is_keyword("NOT") && self.next_is_keyword("DEFERRABLE"))
+            {
+                self.eat_keyword("NOT");
+                self.advance();
+                if self.eat_keyword("INITIALLY") && !self.eat_keyword("DEFERRED") {
+                    self.expect_keyword("IMMEDIATE")?;
+                }
+            } else {
+                break;
+            }
+        }
+        Ok(ForeignKeyClause { table, columns })
+    }
+
+    /// Parse an optional `ON CONFLICT action` of a constraint.
+    fn parse_conflict_clause(&mut self) -> DbResult<()> {
+        if self.is_keyword("ON") && self.next_is_keyword("CONFLICT") {
+            self.advance();
+            self.advance();
+            self.parse_conflict_action()?;
+        }
+        Ok(())
+    }
+
+    /// Parse a column's DEFAULT value. A bare name is taken as a string, as
+    /// SQLite does.
+    fn parse_default(&mut self) -> DbResult<Expr> {
+        if self.eat_symbol(Symbol::LeftParen) {
+            let expr = self.parse_expr()?;
+            self.expect_symbol(Symbol::RightParen)?;
+            return Ok(expr);
+        }
+        let op = if self.eat_symbol(Symbol::Minus) {
+            Some(UnaryOp::Negate)
+        } else if self.eat_symbol(Symbol::Plus) {
+            Some(UnaryOp::Plus)
+        } else {
+            None
+        };
+        let expr = match self.parse_primary()? {
+            Expr::Column(name) => Expr::Literal(Value::Text(name)),
+            expr => expr,
+        };
+        Ok(match op {
+            Some(op) => Expr::Unary {
+                op,
+                expr: Box::new(expr),
+            },
+            None => expr,
+        })
+    }
+
+    /// Parse `(...)`, returning the text inside the parentheses as written.
+    fn parse_parenthesized_text(&mut self) -> DbResult<String> {
+        self.expect_symbol(Symbol::LeftParen)?;
+        let start = self.tokens[self.pos].pos;
+        let mut depth = 0;
+        loop {
+            match self.peek() {
+                Token::Symbol(Symbol::LeftParen) => depth += 1,
+                Token::Symbol(Symbol::RightParen) if depth == 0 => break,
+                Token::Symbol(Symbol::RightParen) => depth -= 1,
+                Token::Eof => return Err(self.error("expected RightParen")),
+                _ => {}
+            }
+            self.advance();
+        }
+        let text = self.text_since(start).to_string();
+        self.advance();
+        Ok(text)
+    }
+
+    /// Parse a parenthesized list of names, as in a foreign key.
+    fn parse_name_list(&mut self) -> DbResult<Vec<String>> {
+        self.expect_symbol(Symbol::LeftParen)?;
+        let mut names = vec![self.parse_identifier()?];
+        while self.eat_symbol(Symbol::Comma) {
+            names.push(self.parse_identifier()?);
+        }
+        self.expect_symbol(Symbol::RightParen)?;
+        Ok(names)
+    }
+
+    /// Parse a parenthesized list of `column [COLLATE name] [ASC | DESC]`,
+    /// as in an index or a PRIMARY KEY or UNIQUE table constraint.
+    fn parse_indexed_columns(&mut self) -> DbResult<Vec<IndexedColumn>> {
+        self.expect_symbol(Symbol::LeftParen)?;
+        let mut columns = Vec::new();
+        loop {
+            let name = self.parse_identifier()?;
+            if self.peek() == &Token::Symbol(Symbol::LeftParen) {
+                return Err(DbError::UnsupportedFeature(
+                    "indexes on expressions".to_string(),
+                ));
+            }
+            let collation = if self.eat_keyword("COLLATE") {
+                Some(self.parse_identifier()?)
+            } else {
+                None
+            };
+            let descending = self.parse_sort_order();
+            columns.push(IndexedColumn {
+                name,
+                collation,
+                descending,
+            });
+            if !self.eat_symbol(Symbol::Comma) {
+                break;
+            }
+        }
+        self.expect_symbol(Symbol::RightParen)?;
+        Ok(columns)
+    }
+
+    /// Parse an optional `ASC` or `DESC`, returning whether it is descending.
+    fn parse_sort_order(&mut self) -> bool {
+        if self.eat_keyword("DESC") {
+            true
+        } else {
+            self.eat_keyword("ASC");
+            false
+        }
+    }
+
+    /// Parse the rest of `CREATE [UNIQUE] INDEX [IF NOT EXISTS] [schema.]name
+    /// ON table (column [COLLATE name] [ASC | DESC], ...) [WHERE expr]`.
+    fn parse_create_index(&mut self, unique: bool) -> DbResult<CreateIndex> {
+        let if_not_exists = self.parse_if_not_exists()?;
+        let (name, start) = self.parse_schema_object_name()?;
+        self.expect_keyword("ON")?;
+        let table = self.parse_identifier()?;
+        let columns = self.parse_indexed_columns()?;
+        let where_clause = if self.eat_keyword("WHERE") {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+        let kind = if unique { "UNIQUE INDEX" } else { "INDEX" };
+        Ok(CreateIndex {
+            name,
+            table,
+            unique,
+            if_not_exists,
+            columns,
+            where_clause,
+            sql: format!("CREATE {} {}", kind, self.text_since(start)),
+        })
+    }
+
+    /// Parse an optional `IF NOT EXISTS`.
+    fn parse_if_not_exists(&mut self) -> DbResult<bool> {
+        if !self.eat_keyword("IF") {
+            return Ok(false);
+        }
+        self.expect_keyword("NOT")?;
+        self.expect_keyword("EXISTS")?;
+        Ok(true)
+    }
+
+    /// Parse the `[schema.]name` of a table or index being created. Returns
+    /// the name and where it starts in the SQL text.
+    fn parse_schema_object_name(&mut self) -> DbResult<(String, usize)> {
+        let mut start = self.tokens[self.pos].pos;
+        let mut name = self.parse_identifier()?;
+        // Only the main database exists, so a schema name changes nothing
+        if self.eat_symbol(Symbol::Dot) {
+            start = self.tokens[self.pos].pos;
+            name = self.parse_identifier()?;
+        }
+        Ok((name, start))
+    }
+
+    /// Parse the rest of `PRAGMA [schema.]name [= value | (value)]`.
+    fn parse_pragma(&mut self) -> DbResult<Pragma> {
+        let mut name = self.parse_identifier()?;
+        // Only the main database exists, so a schema name changes nothing
+        if self.eat_symbol(Symbol::Dot) {
+            name = self.parse_identifier()?;
+        }
+        let value = if self.eat_symbol(Symbol::Eq) {
+            Some(self.parse_pragma_value()?)
+        } else if self.eat_symbol(Symbol::LeftParen) {
+            let value = self.parse_pragma_value()?;
+            self.expect_symbol(Symbol::RightParen)?;
+            Some(value)
+        } else {
+            None
+        };
+        Ok(Pragma { name, value })
+    }
+
+    /// Parse a pragma argument: a name, string or number.
+    fn parse_pragma_value(&mut self) -> DbResult<String> {
+        let sign = if self.eat_symbol(Symbol::Minus) {
+            "-"
+        } else {
+            self.eat_symbol(Symbol::Plus);
+            ""
+        };
+        let value = match self.peek().clone() {
+            Token::Integer(n) => format!("{}{}", sign, n),
+            Token::Float(f) => format!("{}{}", sign, f),
+            _ if sign.is_empty() => return self.parse_identifier(),
+            _ => return Err(self.error("expected a number")),
+        };
+        self.advance();
+        Ok(value)
+    }
+
+    fn parse_insert(&mut self) -> DbResult<Insert> {
+        // REPLACE is short for INSERT OR REPLACE
+        let conflict = if self.eat_keyword("REPLACE") {
+            ConflictAction::Replace
+        } else {
+            self.expect_keyword("INSERT")?;
+            if self.eat_keyword("OR") {
+                self.parse_conflict_action()?
+            } else {
+                ConflictAction::default()
+            }
+        };
+        self.expect_keyword("INTO")?;
+        let table = self.parse_identifier()?;
+
+        let columns = if self.eat_symbol(Symbol::LeftParen) {
+            let mut columns = Vec::new();
+            loop {
+                columns.push(self.parse_identifier()?);
+                if !self.eat_symbol(Symbol::Comma) {
+                    break;
+                }
+            }
+            self.expect_symbol(Symbol::RightParen)?;
+            Some(columns)
+        } else {
+            None
+        };
+
+        let source = if self.eat_keyword("VALUES") {
+            let mut rows = Vec::new();
+            loop {
+                self.expect_symbol(Symbol::LeftParen)?;
+                let mut row = Vec::new();
+                loop {
+                    row.push(self.parse_expr()?);
+                    if !self.eat_symbol(Symbol::Comma) {
+                        break;
+                    }
+                }
+                self.expect_symbol(Symbol::RightParen)?;
+                rows.push(row);
+                if !self.eat_symbol(Symbol::Comma) {
+                    break;
+                }
+            }
+            InsertSource::Values(rows)
+        } else if self.is_keyword("SELECT") {
+            InsertSource::Select(Box::new(self.parse_select()?))
+        } else if self.eat_keyword("DEFAULT") {
+            self.expect_keyword("VALUES")?;
+            InsertSource::DefaultValues
+        } else {
+            return Err(self.error("expected VALUES, SELECT or DEFAULT VALUES"));
+        };
+
+        Ok(Insert {
+            conflict,
+            table,
+            columns,
+            source,
+        })
+    }
+
+    fn parse_update(&mut self) -> DbResult<Update> {
+        self.expect_keyword("UPDATE")?;
+        let conflict = if self.eat_keyword("OR") {
+            self.parse_conflict_action()?
+        } else {
+            ConflictAction::default()
+        };
+        let table = self.parse_identifier()?;
+        self.expect_keyword("SET")?;
+
+        let mut assignments = Vec::new();
+        loop {
+            let column = self.parse_identifier()?;
+            self.expect_symbol(Symbol::Eq)?;
+            assignments.push((column, self.parse_expr()?));
+            if !self.eat_symbol(Symbol::Comma) {
+                break;
+            }
+        }
+
+        let where_clause = if self.eat_keyword("WHERE") {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+        Ok(Update {
+            conflict,
+            table,
+            assignments,
+            where_clause,
+        })
+    }
+
+    fn parse_delete(&mut self) -> DbResult<Delete> {
+        self.expect_keyword("DELETE")?;
+        self.expect_keyword("FROM")?;
+        let table = self.parse_identifier()?;
+        let where_clause = if self.eat_keyword("WHERE") {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+        Ok(Delete {
+            table,
+            where_clause,
+        })
+    }
+
+    fn parse_conflict_action(&mut self) -> DbResult<ConflictAction> {
+        let action = match self.peek() {
+            Token::Word(word) => match word.to_uppercase().as_str() {
+                "ROLLBACK" => ConflictAction::Rollback,
+                "ABORT" => ConflictAction::Abort,
+                "FAIL" => ConflictAction::Fail,
+                "IGNORE" => ConflictAction::Ignore,
+                "REPLACE" => ConflictAction::Replace,
+                _ => return Err(self.error("expected a conflict resolution")),
+            },
+            _ => return Err(self.error("expected a conflict resolution")),
+        };
+        self.advance();
+        Ok(action)
+    }
+
+    fn parse_select(&mut self) -> DbResult<Select> {
+        self.expect_keyword("SELECT")?;
+
+        let mut columns = Vec::new();
+        loop {
+            columns.push(self.parse_result_column()?);
+            if !self.eat_symbol(Symbol::Comma) {
+                break;
+            }
+        }
+
+        let from = if self.eat_keyword("FROM") {
+            Some(self.parse_identifier()?)
+        } else {
+            None
+        };
+
+        let where_clause = if self.eat_keyword("WHERE") {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+
+        let mut order_by = Vec::new();
+        if self.eat_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            loop {
+                let expr = self.parse_expr()?;
+                let descending = if self.eat_keyword("DESC") {
+                    true
+                } else {
+                    self.eat_keyword("ASC");
+                    false
+                };
+                order_by.push(OrderingTerm { expr, descending });
+                if !self.eat_symbol(Symbol::Comma) {
+                    break;
+                }
+            }
+        }
+
+        Ok(Select {
+            columns,
+            from,
+            where_clause,
+            order_by,
+        })
+    }
+
+    fn parse_result_column(&mut self) -> DbResult<ResultColumn> {
+        if self.eat_symbol(Symbol::Star) {
+            return Ok(ResultColumn::Star);
+        }
+
+        let start = self.tokens[self.pos].pos;
+        let expr = self.parse_expr()?;
+        let text = self.sql[start..self.tokens[self.pos].pos]
+            .trim_end()
+            .to_string();
+        let alias = if self.eat_keyword("AS") {
+            Some(self.parse_identifier()?)
+        } else {
+            match self.peek() {
+                Token::Word(word) if !is_reserved(word) => Some(self.parse_identifier()?),
+                Token::QuotedIdentifier(_) | Token::String(_) => Some(self.parse_identifier()?),
+                _ => None,
+            }
+        };
+        Ok(ResultColumn::Expr { expr, alias, text })
+    }
+
+    /// Parse an expression, starting at the loosest-binding operator.
+    fn parse_expr(&mut self) -> DbResult<Expr> {
+        self.parse_or()
+    }
+
+    fn parse_or(&mut self) -> DbResult<Expr> {
+        let mut left = self.parse_and()?;
+        while self.eat_keyword("OR") {
+            let right = self.parse_and()?;
+            left = binary(BinaryOp::Or, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> DbResult<Expr> {
+        let mut left = self.parse_not()?;
+        while self.eat_keyword("AND") {
+            let right = self.parse_not()?;
+            left = binary(BinaryOp::And, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> DbResult<Expr> {
+        if self.eat_keyword("NOT") {
+            let expr = self.parse_not()?;
+            return Ok(Expr::Unary {
+                op: UnaryOp::Not,
+                expr: Box::new(expr),
+            });
+        }
+        self.parse_equality()
+    }
+
+    fn parse_equality(&mut self) -> DbResult<Expr> {
+        let mut left = self.parse_comparison()?;
+        loop {
+            let op = match self.peek() {
+                Token::Symbol(Symbol::Eq) => BinaryOp::Eq,
+                Token::Symbol(Symbol::NotEq) => BinaryOp::NotEq,
+                Token::Word(word) if word.eq_ignore_ascii_case("IS") => {
+                    self.advance();
+                    let op = if self.eat_keyword("NOT") {
+                        BinaryOp::IsNot
+                    } else {
+                        BinaryOp::Is
+                    };
+                    let right = self.parse_comparison()?;
+                    left = binary(op, left, right);
+                    continue;
+                }
+                // Postfix NULL tests: `x ISNULL`, `x NOTNULL`, `x NOT NULL`
+                Token::Word(word) if word.eq_ignore_ascii_case("ISNULL") => {
+                    self.advance();
+                    left = binary(BinaryOp::Is, left, Expr::Literal(Value::Null));
+                    continue;
+                }
+                Token::Word(word) if word.eq_ignore_ascii_case("NOTNULL") => {
+                    self.advance();
+                    left = binary(BinaryOp::IsNot, left, Expr::Literal(Value::Null));
+                    continue;
+                }
+                Token::Word(word)
+                    if word.eq_ignore_ascii_case("NOT") && self.next_is_keyword("NULL") =>
+                {
+                    self.advance();
+                    self.advance();
+                    left = binary(BinaryOp::IsNot, left, Expr::Literal(Value::Null));
+                    continue;
+                }
+                _ => break,
+            };
+            self.advance();
+            let right = self.parse_comparison()?;
+            left = binary(op, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_comparison(&mut self) -> DbResult<Expr> {
+        let mut left = self.parse_additive()?;
+        loop {
+            let op = match self.peek() {
+                Token::Symbol(Symbol::Lt) => BinaryOp::Lt,
+                Token::Symbol(Symbol::LtEq) => BinaryOp::LtEq,
+                Token::Symbol(Symbol::Gt) => BinaryOp::Gt,
+                Token::Symbol(Symbol::GtEq) => BinaryOp::GtEq,
+                _ => break,
+            };
+            self.advance();
+            let right = self.parse_additive()?;
+            left = binary(op, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_additive(&mut self) -> DbResult<Expr> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Token::Symbol(Symbol::Plus) => BinaryOp::Add,
+                Token::Symbol(Symbol::Minus) => BinaryOp::Subtract,
+                _ => break,
+            };
+            self.advance();
+            let right = self.parse_multiplicative()?;
+            left = binary(op, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_multiplicative(&mut self) -> DbResult<Expr> {
+        let mut left = self.parse_concat()?;
+        loop {
+            let op = match self.peek() {
+                Token::Symbol(Symbol::Star) => BinaryOp::Multiply,
+                Token::Symbol(Symbol::Slash) => BinaryOp::Divide,
+                Token::Symbol(Symbol::Percent) => BinaryOp::Remainder,
+                _ => break,
+            };
+            self.advance();
+            let right = self.parse_concat()?;
+            left = binary(op, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_concat(&mut self) -> DbResult<Expr> {
+        let mut left = self.parse_unary()?;
+        while self.eat_symbol(Symbol::Concat) {
+            let right = self.parse_unary()?;
+            left = binary(BinaryOp::Concat, left, right);
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> DbResult<Expr> {
+        let op = match self.peek() {
+            Token::Symbol(Symbol::Minus) => UnaryOp::Negate,
+            Token::Symbol(Symbol::Plus) => UnaryOp::Plus,
+            _ => return self.parse_collate(),
+        };
+        self.advance();
+        let expr = self.parse_unary()?;
+        Ok(Expr::Unary {
+            op,
+            expr: Box::new(expr),
+        })
+    }
+
+    /// Parse a primary expression with any number of postfix `COLLATE name` clauses.
+    fn parse_collate(&mut self) -> DbResult<Expr> {
+        let mut expr = self.parse_primary()?;
+        while self.eat_keyword("COLLATE") {
+            let collation = self.parse_identifier()?;
+            expr = Expr::Collate {
+                expr: Box::new(expr),
+                collation,
+            };
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> DbResult<Expr> {
+        let token = self.peek().clone();
+        match token {
+            Token::Integer(i) => {
+                self.advance();
+                Ok(Expr::Literal(Value::Integer(i)))
+            }
+            Token::Float(f) => {
+                self.advance();
+                Ok(Expr::Literal(Value::Real(f)))
+            }
+            Token::String(s) => {
+                self.advance();
+                Ok(Expr::Literal(Value::Text(s)))
+            }
+            Token::Blob(b) => {
+                self.advance();
+                Ok(Expr::Literal(Value::Blob(b)))
+            }
+            Token::Symbol(Symbol::LeftParen) => {
+                self.advance();
+                let expr = self.parse_expr()?;
+                self.expect_symbol(Symbol::RightParen)?;
+                Ok(expr)
+            }
+            Token::Word(word) if word.eq_ignore_ascii_case("NULL") => {
+                self.advance();
+                Ok(Expr::Literal(Value::Null))
+            }
+            // Keywords for the time the statement runs, evaluated like the
+            // functions of the same name, as SQLite does
+            Token::Word(word)
+                if ["CURRENT_TIMESTAMP", "CURRENT_DATE", "CURRENT_TIME"]
+                    .iter()
+                    .any(|keyword| word.eq_ignore_ascii_case(keyword)) =>
+            {
+                let name = word.to_lowercase();
+                self.advance();
+                Ok(Expr::Function {
+                    name,
+                    args: Vec::new(),
+                    star: false,
+                })
+            }
+            Token::Word(word)
+                if word.eq_ignore_ascii_case("CAST")
+                    && self.tokens.get(self.pos + 1).map(|t| &t.token)
+                        == Some(&Token::Symbol(Symbol::LeftParen)) =>
+            {
+                self.advance();
+                self.advance();
+                let expr = self.parse_expr()?;
+                self.expect_keyword("AS")?;
+                let type_name = self.parse_type_name()?;
+                self.expect_symbol(Symbol::RightParen)?;
+                Ok(Expr::Cast {
+                    expr: Box::new(expr),
+                    type_name,
+                })
+            }
+            Token::Word(_) | Token::QuotedIdentifier(_) => {
+                let name = self.parse_identifier()?;
+                if self.eat_symbol(Symbol::LeftParen) {
+                    return self.parse_function_call(name);
+                }
+                Ok(Expr::Column(name))
+            }
+            _ => Err(self.error("expected an expression")),
+        }
+    }
+
+    /// Parse the arguments of a function call after its opening parenthesis.
+    fn parse_function_call(&mut self, name: String) -> DbResult<Expr> {
+        let mut args = Vec::new();
+        let mut star = false;
+        if self.eat_symbol(Symbol::Star) {
+            star = true;
+        } else if !matches!(self.peek(), Token::Symbol(Symbol::RightParen)) {
+            loop {
+                args.push(self.parse_expr()?);
+                if !self.eat_symbol(Symbol::Comma) {
+                    break;
+                }
+            }
+        }
+        self.expect_symbol(Symbol::RightParen)?;
+        Ok(Expr::Function { name, args, star })
+    }
+
+    /// Parse a type name such as `INTEGER`, `UNSIGNED BIG INT` or `DECIMAL(10, 2)`.
+    fn parse_type_name(&mut self) -> DbResult<String> {
+        let start = self.tokens[self.pos].pos;
+        let mut words = 0;
+        while let Token::Word(_) | Token::QuotedIdentifier(_) = self.peek() {
+            self.advance();
+            words += 1;
+        }
+        if words == 0 {
+            return Err(self.error("expected a type name"));
+        }
+        // Size arguments like (10, 2) don't affect the type
+        let end = self.tokens[self.pos].pos;
+        if self.eat_symbol(Symbol::LeftParen) {
+            loop {
+                self.eat_symbol(Symbol::Plus);
+                self.eat_symbol(Symbol::Minus);
+                match self.peek() {
+                    Token::Integer(_) | Token::Float(_) => self.advance(),
+                    _ => return Err(self.error("expected a type size")),
+                }
+                if !self.eat_symbol(Symbol::Comma) {
+                    break;
+                }
+            }
+            self.expect_symbol(Symbol::RightParen)?;
+        }
+        Ok(self.sql[start..end].trim_end().to_string())
+    }
+
+    /// Parse a table, column or alias name.
+    fn parse_identifier(&mut self) -> DbResult<String> {
+        match self.peek().clone() {
+            Token::Word(word) => {
+                self.advance();
+                Ok(word)
+            }
+            Token::QuotedIdentifier(name) | Token::String(name) => {
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.error("expected an identifier")),
+        }
+    }
+
+    /// The SQL text from `start` to the end of the last token parsed.
+    fn text_since(&self, start: usize) -> &str {
+        let end = self.tokens[..self.pos].last().map_or(start, |t| t.end);
+        &self.sql[start..end.max(start)]
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].token
+    }
+
+    fn advance(&mut self) {
+        if self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Token::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    /// Check whether the token after the current one is the given keyword.
+    fn next_is_keyword(&self, keyword: &str) -> bool {
+        matches!(
+            self.tokens.get(self.pos + 1).map(|t| &t.token),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword)
+        )
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.is_keyword(keyword) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> DbResult<()> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected {}", keyword)))
+        }
+    }
+
+    fn eat_symbol(&mut self, symbol: Symbol) -> bool {
+        if self.peek() == &Token::Symbol(symbol) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: Symbol) -> DbResult<()> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected {:?}", symbol)))
+        }
+    }
+
+    /// Build a parse error at the current token, quoting its text as it
+    /// appears in the SQL, as sqlite3 does.
+    fn error(&self, msg: &str) -> DbError {
+        let spanned = &self.tokens[self.pos];
+        let near = match spanned.token {
+            Token::Eof => "end of input".to_string(),
+            _ => format!("\"{}\"", &self.sql[spanned.pos..spanned.end]),
+        };
+        DbError::Parse {
+            pos: spanned.pos,
+            msg: format!("{} near {}", msg, near),
+        }
+    }
+}
+
+fn binary(op: BinaryOp, left: Expr, right: Expr) -> Expr {
+    Expr::Binary {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+fn is_reserved(word: &str) -> bool {
+    RESERVED.iter().any(|r| r.eq_ignore_ascii_case(word))
+}
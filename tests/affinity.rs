@@ -0,0 +1,32 @@
+//! Comparison affinity: which side of a comparison is converted, as sqlite3
+//! decides it.
+
+mod common;
+
+use common::{TempDb, query, texts};
+
+#[test]
+fn blob_columns_are_not_converted_like_expressions() {
+    let file = TempDb::new("affinity-blob-columns");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE t(x BLOB, t TEXT, b BLOB, n INTEGER);
+         INSERT INTO t VALUES ('1', '1', '1', 1), (2, '2', 2, 2), (X'33', '3', X'33', 3)",
+    );
+    let rowids = |pager: &mut _, condition: &str| {
+        texts(&query(
+            pager,
+            &format!("SELECT rowid FROM t WHERE {}", condition),
+        ))
+    };
+
+    // A TEXT column converts an operand with no affinity, but not a BLOB column
+    assert_eq!(rowids(&mut pager, "x = t"), ["1"]);
+    assert_eq!(rowids(&mut pager, "t = b"), ["1"]);
+    assert_eq!(rowids(&mut pager, "t = 2"), ["2"]);
+    assert_eq!(rowids(&mut pager, "CAST(x AS TEXT) = 2"), ["2"]);
+    assert!(rowids(&mut pager, "t = CAST(2 AS BLOB)").is_empty());
+    // A numeric column still converts a BLOB column
+    assert_eq!(rowids(&mut pager, "n = b"), ["1", "2"]);
+}
@@ -0,0 +1,131 @@
+//! ALTER TABLE renaming and dropping columns and tables, checked against
+//! the SQL sqlite3 leaves in sqlite_schema.
+
+mod common;
+
+use codecrafters_sqlite::db;
+use common::{TempDb, make_view_or_trigger, query, query_error, schema_sql, texts};
+
+#[test]
+fn quoted_names_with_doubled_quotes_are_renamed_and_dropped() {
+    let file = TempDb::new("alter-quoted-names");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE t(\"we\"\"ird\" TEXT, `b``q` INT, c);
+         INSERT INTO t VALUES (1, 2, 3)",
+    );
+    let rows = query(&mut pager, "SELECT \"we\"\"ird\", `b``q` FROM t");
+    assert_eq!(rows[0].len(), 2);
+    query(
+        &mut pager,
+        "ALTER TABLE t RENAME COLUMN \"we\"\"ird\" TO \"o\"\"k\";
+         ALTER TABLE t DROP COLUMN `b``q`",
+    );
+    assert_eq!(
+        schema_sql(&mut pager, "t"),
+        "CREATE TABLE t(\"o\"\"k\" TEXT, c)"
+    );
+    assert_eq!(texts(&query(&mut pager, "SELECT \"o\"\"k\" FROM t")), ["1"]);
+}
+
+/// Views and triggers as sqlite3 would have created them over tables
+/// t(a, b) and s(d, c): their type, name, table and SQL.
+const DEPENDENTS: &[(&str, &str, &str, &str)] = &[
+    (
+        "view",
+        "v",
+        "v",
+        "CREATE VIEW v AS SELECT a, t.b, x.d AS z FROM t JOIN s AS x ON t.a = x.d WHERE b > 1",
+    ),
+    (
+        "view",
+        "q",
+        "q",
+        "CREATE VIEW q AS SELECT count(*) AS a FROM t, s AS t2 WHERE t.a = t2.c",
+    ),
+    (
+        "trigger",
+        "tr",
+        "t",
+        "CREATE TRIGGER tr AFTER UPDATE OF a ON t WHEN new.a > 0 BEGIN \
+         INSERT INTO s(d, c) VALUES (new.a, old.b); UPDATE t SET b = a WHERE a = new.a; END",
+    ),
+    (
+        "trigger",
+        "tr2",
+        "s",
+        "CREATE TRIGGER tr2 AFTER INSERT ON s BEGIN INSERT INTO t(a) VALUES (new.d); END",
+    ),
+];
+
+/// Add a view or trigger to a database.
+fn add_dependent(file: &TempDb, entry_type: &str, name: &str, tbl_name: &str, sql: &str) {
+    // A table whose schema row is long enough to be turned into it
+    let mut pager = file.open();
+    let padding = "-".repeat(sql.len());
+    query(
+        &mut pager,
+        &format!("CREATE TABLE {}(x /* {} */)", name, padding),
+    );
+    drop(pager);
+    make_view_or_trigger(file, name, entry_type, tbl_name, sql);
+}
+
+/// A database with tables t(a, b) and s(d, c) and the views and triggers
+/// above.
+fn with_dependents(name: &str) -> TempDb {
+    let file = TempDb::new(name);
+    let mut pager = file.open();
+    query(&mut pager, "CREATE TABLE t(a, b); CREATE TABLE s(d, c)");
+    drop(pager);
+    for (entry_type, name, tbl_name, sql) in DEPENDENTS {
+        add_dependent(&file, entry_type, name, tbl_name, sql);
+    }
+    file
+}
+
+#[test]
+fn renames_are_carried_into_views_and_triggers() {
+    let file = with_dependents("alter-dependents");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "ALTER TABLE t RENAME COLUMN a TO aa; ALTER TABLE t RENAME TO u",
+    );
+    let expected = [
+        "CREATE VIEW v AS SELECT aa, \"u\".b, x.d AS z FROM \"u\" JOIN s AS x ON \"u\".aa = x.d WHERE b > 1",
+        "CREATE VIEW q AS SELECT count(*) AS a FROM \"u\", s AS t2 WHERE \"u\".aa = t2.c",
+        "CREATE TRIGGER tr AFTER UPDATE OF aa ON \"u\" WHEN new.aa > 0 BEGIN \
+         INSERT INTO s(d, c) VALUES (new.aa, old.b); UPDATE \"u\" SET b = aa WHERE aa = new.aa; END",
+        "CREATE TRIGGER tr2 AFTER INSERT ON s BEGIN INSERT INTO \"u\"(aa) VALUES (new.d); END",
+    ];
+    for ((_, name, _, _), sql) in DEPENDENTS.iter().zip(expected) {
+        assert_eq!(schema_sql(&mut pager, name).trim_end(), sql);
+    }
+    let entries = db::read_schema_entries(&mut pager).unwrap();
+    let trigger = entries.iter().find(|entry| entry.name == "tr").unwrap();
+    assert_eq!(trigger.tbl_name, "u");
+}
+
+#[test]
+fn column_rename_is_refused_when_a_view_may_mean_another_table() {
+    let file = with_dependents("alter-ambiguous-view");
+    let mut pager = file.open();
+    query(&mut pager, "CREATE TABLE r(a)");
+    drop(pager);
+    add_dependent(
+        &file,
+        "view",
+        "w",
+        "w",
+        "CREATE VIEW w AS SELECT a FROM r WHERE a IN (SELECT a FROM t)",
+    );
+    let mut pager = file.open();
+    assert_eq!(
+        query_error(&mut pager, "ALTER TABLE t RENAME COLUMN a TO aa"),
+        "cannot rename column a: view w may mean the column of that name in table r"
+    );
+    assert_eq!(schema_sql(&mut pager, "t"), "CREATE TABLE t(a, b)");
+    assert_eq!(schema_sql(&mut pager, "v").trim_end(), DEPENDENTS[0].3);
+}
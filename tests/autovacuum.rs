@@ -0,0 +1,73 @@
+//! Incremental auto-vacuum: pointer maps kept up to date as pages move,
+//! and `PRAGMA incremental_vacuum(N)` giving free pages back.
+
+mod common;
+
+use common::{TempDb, header_u32, query, texts};
+
+/// Offsets in the header of the page count, the number of free pages, the
+/// largest root page and the incremental-vacuum flag.
+const PAGE_COUNT_OFFSET: usize = 28;
+const FREELIST_COUNT_OFFSET: usize = 36;
+const LARGEST_ROOT_PAGE_OFFSET: usize = 52;
+const INCREMENTAL_VACUUM_OFFSET: usize = 64;
+
+#[test]
+fn incremental_vacuum_gives_back_free_pages() {
+    let file = TempDb::new("autovacuum-incremental");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "PRAGMA auto_vacuum = INCREMENTAL;
+         CREATE TABLE t(k INTEGER PRIMARY KEY, v TEXT, b BLOB);
+         CREATE INDEX t_v ON t(v);
+         CREATE TABLE u(x)",
+    );
+    let rows: Vec<String> = (1..=300)
+        .map(|k| format!("({}, 'row{:03}', zeroblob({}))", k, k, k % 4 * 2000))
+        .collect();
+    query(
+        &mut pager,
+        &format!(
+            "INSERT INTO t VALUES {}; INSERT INTO u VALUES ('after t')",
+            rows.join(", ")
+        ),
+    );
+    assert_eq!(texts(&query(&mut pager, "PRAGMA auto_vacuum")), ["2"]);
+    drop(pager);
+    let pages = header_u32(&file, PAGE_COUNT_OFFSET);
+    assert_eq!(header_u32(&file, FREELIST_COUNT_OFFSET), 0);
+    assert_eq!(header_u32(&file, INCREMENTAL_VACUUM_OFFSET), 1);
+    // Page 2 is the first pointer map, so the roots come after it
+    assert_eq!(header_u32(&file, LARGEST_ROOT_PAGE_OFFSET), 5);
+
+    // Deleting frees pages without shrinking the file
+    let mut pager = file.open();
+    query(&mut pager, "DELETE FROM t WHERE k % 2 = 1 OR k > 200");
+    drop(pager);
+    let free = header_u32(&file, FREELIST_COUNT_OFFSET);
+    assert!(free > 100, "{} free pages", free);
+    assert_eq!(header_u32(&file, PAGE_COUNT_OFFSET), pages);
+
+    // Each vacuum moves pages from the end into free ones, leaving the
+    // pointer maps and the rows intact
+    let mut pager = file.open();
+    query(&mut pager, "PRAGMA incremental_vacuum(10)");
+    assert_eq!(texts(&query(&mut pager, "PRAGMA integrity_check")), ["ok"]);
+    drop(pager);
+    assert_eq!(header_u32(&file, PAGE_COUNT_OFFSET), pages - 10);
+    assert_eq!(header_u32(&file, FREELIST_COUNT_OFFSET), free - 10);
+
+    let mut pager = file.open();
+    query(&mut pager, "PRAGMA incremental_vacuum");
+    assert_eq!(texts(&query(&mut pager, "PRAGMA integrity_check")), ["ok"]);
+    let rows = query(&mut pager, "SELECT count(*) FROM t");
+    assert_eq!(texts(&rows), ["100"]);
+    let rows = query(&mut pager, "SELECT v, length(b) FROM t WHERE k = 198");
+    assert_eq!(rows[0][0].to_string(), "row198");
+    assert_eq!(rows[0][1].to_string(), "4000");
+    assert_eq!(texts(&query(&mut pager, "SELECT x FROM u")), ["after t"]);
+    drop(pager);
+    assert_eq!(header_u32(&file, PAGE_COUNT_OFFSET), pages - free);
+    assert_eq!(header_u32(&file, FREELIST_COUNT_OFFSET), 0);
+}
@@ -0,0 +1,126 @@
+//! Writes that split B-tree pages, and deletes and updates that merge
+//! them again, leave a file SQLite's integrity check accepts.
+
+mod common;
+
+use codecrafters_sqlite::db::Pager;
+
+use common::{TempDb, header_u32, query, root_page, texts};
+
+/// Number of rows written, enough to split the roots of the table and index.
+const ROWS: i64 = 3000;
+
+/// Offsets in the header of the page count and the number of free pages.
+const PAGE_COUNT_OFFSET: usize = 28;
+const FREELIST_COUNT_OFFSET: usize = 36;
+
+/// The type byte of a page's B-tree header.
+fn page_type(file: &TempDb, page: u32) -> u8 {
+    let offset = if page == 1 { 100 } else { 0 };
+    file.bytes()[(page as usize - 1) * 4096 + offset]
+}
+
+/// A table with an index, filled in scrambled key order so that pages
+/// split in the middle as well as at the end. Every seventh row has no
+/// blob, and the rest have blobs that spill onto overflow pages.
+fn fill(pager: &mut Pager) {
+    query(
+        pager,
+        "CREATE TABLE t(k INTEGER PRIMARY KEY, v TEXT, b BLOB);
+         CREATE INDEX t_v ON t(v)",
+    );
+    let rows: Vec<String> = (0..ROWS)
+        .map(|i| {
+            // 1237 is coprime with ROWS + 1, so every key comes up once
+            let k = i * 1237 % (ROWS + 1) + 1;
+            format!(
+                "({}, 'value{:05}{}', zeroblob({}))",
+                k,
+                k,
+                "x".repeat(80),
+                k % 7 * 900
+            )
+        })
+        .collect();
+    for chunk in rows.chunks(500) {
+        query(pager, &format!("INSERT INTO t VALUES {}", chunk.join(", ")));
+    }
+}
+
+fn integrity_check(pager: &mut Pager) -> Vec<String> {
+    texts(&query(pager, "PRAGMA integrity_check"))
+}
+
+#[test]
+fn inserts_split_table_and_index_pages() {
+    let file = TempDb::new("btree-split");
+    let mut pager = file.open();
+    fill(&mut pager);
+
+    assert_eq!(integrity_check(&mut pager), ["ok"]);
+    let rows = query(&mut pager, "SELECT count(*) FROM t");
+    assert_eq!(texts(&rows), [ROWS.to_string()]);
+    let rows = query(&mut pager, "SELECT v, length(b) FROM t WHERE k = 1238");
+    assert_eq!(
+        rows[0][0].to_string(),
+        format!("value01238{}", "x".repeat(80))
+    );
+    assert_eq!(rows[0][1].to_string(), "5400");
+    let rows = query(
+        &mut pager,
+        &format!("SELECT k FROM t WHERE v = 'value02999{}'", "x".repeat(80)),
+    );
+    assert_eq!(texts(&rows), ["2999"]);
+
+    // Both roots have become interior pages
+    let table_root = root_page(&mut pager, "t");
+    let index_root = root_page(&mut pager, "t_v");
+    drop(pager);
+    assert_eq!(page_type(&file, table_root), 0x05);
+    assert_eq!(page_type(&file, index_root), 0x02);
+    assert!(header_u32(&file, PAGE_COUNT_OFFSET) > 1000);
+}
+
+#[test]
+fn deletes_and_updates_merge_pages_again() {
+    let file = TempDb::new("btree-merge");
+    let mut pager = file.open();
+    fill(&mut pager);
+
+    // Thin out every page, grow and shrink some of the rows left, and
+    // check the file after each step
+    for sql in [
+        "DELETE FROM t WHERE k % 3 != 0",
+        "UPDATE t SET b = zeroblob(6000) WHERE k % 5 = 0",
+        "UPDATE t SET v = 'short' || k, b = NULL WHERE k % 2 = 0",
+    ] {
+        query(&mut pager, sql);
+        assert_eq!(integrity_check(&mut pager), ["ok"], "after {}", sql);
+    }
+    let rows = query(&mut pager, "SELECT count(*) FROM t");
+    assert_eq!(texts(&rows), [(ROWS / 3).to_string()]);
+    let rows = query(&mut pager, "SELECT v, length(b) FROM t WHERE k = 15");
+    assert_eq!(
+        rows[0][0].to_string(),
+        format!("value00015{}", "x".repeat(80))
+    );
+    assert_eq!(rows[0][1].to_string(), "6000");
+    let rows = query(&mut pager, "SELECT k FROM t WHERE v = 'short300'");
+    assert_eq!(texts(&rows), ["300"]);
+
+    // With only a few small rows left, both trees shrink back to a leaf and
+    // the pages they used are free
+    query(&mut pager, "DELETE FROM t WHERE k > 12 OR k % 2 = 1");
+    assert_eq!(integrity_check(&mut pager), ["ok"]);
+    let rows = query(&mut pager, "SELECT k FROM t");
+    assert_eq!(texts(&rows), ["6", "12"]);
+    let table_root = root_page(&mut pager, "t");
+    let index_root = root_page(&mut pager, "t_v");
+    drop(pager);
+    assert_eq!(page_type(&file, table_root), 0x0d);
+    assert_eq!(page_type(&file, index_root), 0x0a);
+    assert_eq!(
+        header_u32(&file, FREELIST_COUNT_OFFSET),
+        header_u32(&file, PAGE_COUNT_OFFSET) - 3
+    );
+}
@@ -0,0 +1,126 @@
+//! Custom collating sequences registered through the library API.
+
+mod common;
+
+use codecrafters_sqlite::db;
+use codecrafters_sqlite::sql;
+
+use common::{TempDb, query, texts};
+
+#[test]
+fn custom_collation_orders_and_compares() {
+    db::register_collation("reverse", |a, b| b.cmp(a)).unwrap();
+    db::register_collation("first_letter", |a, b| {
+        a.chars().next().cmp(&b.chars().next())
+    })
+    .unwrap();
+    let file = TempDb::new("custom-collation");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE fruit(name TEXT COLLATE reverse);
+         INSERT INTO fruit VALUES ('banana'), ('apple'), ('cherry'), ('avocado')",
+    );
+
+    // A column's collation orders it, and COLLATE overrides it
+    let rows = query(&mut pager, "SELECT name FROM fruit ORDER BY name");
+    assert_eq!(texts(&rows), ["cherry", "banana", "avocado", "apple"]);
+    let rows = query(
+        &mut pager,
+        "SELECT name FROM fruit ORDER BY name COLLATE binary",
+    );
+    assert_eq!(texts(&rows), ["apple", "avocado", "banana", "cherry"]);
+
+    // COLLATE in an expression decides equality
+    let rows = query(
+        &mut pager,
+        "SELECT name FROM fruit WHERE name = 'almond' COLLATE first_letter",
+    );
+    assert_eq!(texts(&rows), ["apple", "avocado"]);
+}
+
+#[test]
+fn index_ordered_by_custom_collation_is_searched_with_it() {
+    // Descending and ignoring case, so that neither the order nor the
+    // equality of the index keys is BINARY's
+    db::register_collation("backwards_nocase", |a, b| {
+        b.to_lowercase().cmp(&a.to_lowercase())
+    })
+    .unwrap();
+    let file = TempDb::new("custom-collation-index");
+    let mut pager = file.open();
+    let values: Vec<String> = (0..2000).map(|i| format!("('word{:04}')", i)).collect();
+    query(
+        &mut pager,
+        &format!(
+            "CREATE TABLE word(w TEXT);
+             INSERT INTO word VALUES {};
+             CREATE INDEX word_backwards ON word(w COLLATE backwards_nocase);
+             INSERT INTO word VALUES ('EXTRA')",
+            values.join(", ")
+        ),
+    );
+
+    // The keys span several pages, so only a seek comparing them with the
+    // collation finds these
+    for (key, expected) in [
+        ("WORD0007", "word0007"),
+        ("Word1500", "word1500"),
+        ("extra", "EXTRA"),
+    ] {
+        let rows = query(
+            &mut pager,
+            &format!(
+                "SELECT w FROM word WHERE w = '{}' COLLATE backwards_nocase",
+                key
+            ),
+        );
+        assert_eq!(texts(&rows), [expected]);
+    }
+    let rows = query(&mut pager, "PRAGMA integrity_check");
+    assert_eq!(texts(&rows), ["ok"]);
+}
+
+#[test]
+fn unknown_and_built_in_collations() {
+    let file = TempDb::new("unknown-collation");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE t(a TEXT); INSERT INTO t VALUES ('x'), ('y')",
+    );
+    let statement = sql::parse("SELECT a FROM t ORDER BY a COLLATE nowhere").unwrap();
+    let err = db::execute(&mut pager, &statement[0]).unwrap_err();
+    assert_eq!(err.to_string(), "no such collation sequence: nowhere");
+
+    assert!(db::register_collation("NOCASE", |a, b| a.cmp(b)).is_err());
+}
+
+#[test]
+fn left_column_collation_wins_even_when_binary() {
+    let file = TempDb::new("collation-left-column");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE t(p, q TEXT COLLATE NOCASE, s TEXT COLLATE RTRIM);
+         INSERT INTO t VALUES ('a', 'A', 'a'), ('A', 'a', 'A '), ('a ', 'a ', 'a'),
+                              ('b', 'B', 'b  ')",
+    );
+
+    // p has no COLLATE clause, so it compares with BINARY from the left,
+    // and only from the right does the other column's collation apply
+    for (condition, expected) in [
+        ("p = q", vec!["3"]),
+        ("p < q", vec!["2"]),
+        ("p > q", vec!["1", "4"]),
+        ("p = s", vec!["1"]),
+        ("q = p", vec!["1", "2", "3", "4"]),
+        ("s = p", vec!["1", "2", "3", "4"]),
+    ] {
+        let rows = query(
+            &mut pager,
+            &format!("SELECT rowid FROM t WHERE {}", condition),
+        );
+        assert_eq!(texts(&rows), expected, "{}", condition);
+    }
+}
@@ -0,0 +1,218 @@
+//! Helpers shared by the integration tests: scratch database files, running
+//! SQL against them and patching their bytes to build corrupt fixtures.
+
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+use codecrafters_sqlite::db::{self, DbResult, Pager, Value};
+use codecrafters_sqlite::sql;
+
+/// A new, empty database file that is removed, along with its journal and
+/// WAL files, when dropped.
+pub struct TempDb(PathBuf);
+
+impl TempDb {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("{}-{}.db", name, std::process::id()));
+        let file = TempDb(path);
+        file.remove();
+        file
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// A file next to the database, such as `-journal` or `-wal`.
+    pub fn sibling(&self, suffix: &str) -> PathBuf {
+        PathBuf::from(format!("{}{}", self.0.display(), suffix))
+    }
+
+    pub fn open(&self) -> Pager {
+        Pager::open(self.0.to_str().unwrap())
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        std::fs::read(&self.0).unwrap()
+    }
+
+    /// Overwrite bytes of the file, starting at `offset`.
+    pub fn patch(&self, offset: usize, bytes: &[u8]) {
+        let mut data = self.bytes();
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        std::fs::write(&self.0, data).unwrap();
+    }
+
+    fn remove(&self) {
+        for suffix in ["", "-journal", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(self.sibling(suffix));
+        }
+    }
+}
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// Run SQL and return the rows of its last statement, or the first error.
+pub fn try_query(pager: &mut Pager, sql: &str) -> DbResult<Vec<Vec<Value>>> {
+    let mut rows = Vec::new();
+    for statement in sql::parse(sql)? {
+        rows = db::execute(pager, &statement)?.rows;
+    }
+    Ok(rows)
+}
+
+/// Run SQL and return the rows of its last statement.
+pub fn query(pager: &mut Pager, sql: &str) -> Vec<Vec<Value>> {
+    try_query(pager, sql).unwrap()
+}
+
+/// The message of the error SQL fails with.
+pub fn query_error(pager: &mut Pager, sql: &str) -> String {
+    match try_query(pager, sql) {
+        Ok(rows) => panic!("{:?} succeeded with {:?}", sql, rows),
+        Err(err) => err.to_string(),
+    }
+}
+
+/// The first column of each row, as text.
+pub fn texts(rows: &[Vec<Value>]) -> Vec<String> {
+    rows.iter().map(|row| row[0].to_string()).collect()
+}
+
+/// The root page of a table or index, from the schema.
+pub fn root_page(pager: &mut Pager, name: &str) -> u32 {
+    db::read_schema_entries(pager)
+        .unwrap()
+        .into_iter()
+        .find(|entry| entry.name == name)
+        .unwrap()
+        .rootpage
+}
+
+/// The SQL sqlite_schema stores for a table, index, view or trigger.
+pub fn schema_sql(pager: &mut Pager, name: &str) -> String {
+    db::read_schema_entries(pager)
+        .unwrap()
+        .into_iter()
+        .find(|entry| entry.name == name)
+        .unwrap()
+        .sql
+}
+
+/// A UTF-8 record of text and small integer values, laid out as SQLite
+/// writes one.
+fn record(values: &[Value]) -> Vec<u8> {
+    let mut header = Vec::new();
+    let mut body = Vec::new();
+    for value in values {
+        match value {
+            Value::Integer(0) => header.push(8),
+            Value::Integer(n @ 1..=127) => {
+                header.push(1);
+                body.push(*n as u8);
+            }
+            Value::Text(text) => {
+                let serial_type = text.len() * 2 + 13;
+                if serial_type >= 0x80 {
+                    header.push(0x80 | (serial_type >> 7) as u8);
+                }
+                header.push((serial_type & 0x7f) as u8);
+                body.extend_from_slice(text.as_bytes());
+            }
+            value => panic!("can't lay out {:?}", value),
+        }
+    }
+    let mut data = vec![header.len() as u8 + 1];
+    data.extend(header);
+    data.extend(body);
+    data
+}
+
+/// Turn the sqlite_schema row of a table into that of a view or trigger,
+/// which the library can't create. The table must have been created with
+/// SQL long enough that `sql`, padded with spaces, fits in its place.
+pub fn make_view_or_trigger(
+    file: &TempDb,
+    table: &str,
+    entry_type: &str,
+    tbl_name: &str,
+    sql: &str,
+) {
+    let mut pager = file.open();
+    let entry = db::read_schema_entries(&mut pager)
+        .unwrap()
+        .into_iter()
+        .find(|entry| entry.name == table)
+        .unwrap();
+    drop(pager);
+    let old = record(&[
+        Value::Text("table".to_string()),
+        Value::Text(table.to_string()),
+        Value::Text(table.to_string()),
+        Value::Integer(entry.rootpage as i64),
+        Value::Text(entry.sql),
+    ]);
+    let offset = file
+        .bytes()
+        .windows(old.len())
+        .position(|w| w == old)
+        .unwrap();
+    let mut padded = sql.to_string();
+    loop {
+        let new = record(&[
+            Value::Text(entry_type.to_string()),
+            Value::Text(table.to_string()),
+            Value::Text(tbl_name.to_string()),
+            Value::Integer(0),
+            Value::Text(padded.clone()),
+        ]);
+        assert!(new.len() <= old.len(), "{:?} doesn't fit", sql);
+        if new.len() == old.len() {
+            file.patch(offset, &new);
+            return;
+        }
+        padded.push(' ');
+    }
+}
+
+/// A big-endian 4-byte field of the database header.
+pub fn header_u32(file: &TempDb, offset: usize) -> u32 {
+    let data = file.bytes();
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Write a database with no tables, laid out as sqlite3 lays one out, with
+/// the given page size, bytes reserved at the end of each page and text
+/// encoding (1 for UTF-8, 2 for UTF-16le, 3 for UTF-16be).
+pub fn write_empty_database(file: &TempDb, page_size: usize, reserved: u8, encoding: u32) {
+    let mut data = vec![0; page_size];
+    data[..16].copy_from_slice(b"SQLite format 3\0");
+    // A page size of 65536 is stored as 1
+    let stored_size = if page_size == 65536 {
+        1
+    } else {
+        page_size as u16
+    };
+    data[16..18].copy_from_slice(&stored_size.to_be_bytes());
+    // File format versions, reserved bytes and payload fractions
+    data[18..24].copy_from_slice(&[1, 1, reserved, 64, 32, 32]);
+    // Change counter, page count, schema format and encoding
+    data[24..28].copy_from_slice(&1u32.to_be_bytes());
+    data[28..32].copy_from_slice(&1u32.to_be_bytes());
+    data[44..48].copy_from_slice(&4u32.to_be_bytes());
+    data[56..60].copy_from_slice(&encoding.to_be_bytes());
+    // The version the change counter is valid for, and the SQLite version
+    data[92..96].copy_from_slice(&1u32.to_be_bytes());
+    data[96..100].copy_from_slice(&3045000u32.to_be_bytes());
+    // An empty leaf table page for sqlite_schema, whose cell content area
+    // starts at the end of the usable space (0 standing for 65536)
+    data[100] = 0x0d;
+    let usable = (page_size - reserved as usize) as u32;
+    data[105..107].copy_from_slice(&(usable as u16).to_be_bytes());
+    std::fs::write(file.path(), data).unwrap();
+}
@@ -0,0 +1,144 @@
+//! Corrupt database files fail with errors instead of crashing.
+
+mod common;
+
+use common::{TempDb, query, query_error, root_page};
+
+const PAGE_SIZE: usize = 4096;
+
+/// Offset in the file of a page's B-tree header.
+fn page_offset(page: u32) -> usize {
+    (page as usize - 1) * PAGE_SIZE
+}
+
+/// Offset in the file of the first cell on a leaf page.
+fn first_cell(file: &TempDb, page: u32) -> usize {
+    let header = page_offset(page);
+    let data = file.bytes();
+    let pointer = u16::from_be_bytes([data[header + 8], data[header + 9]]);
+    header + pointer as usize
+}
+
+#[test]
+fn interior_page_pointing_at_itself() {
+    let file = TempDb::new("corrupt-cycle");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE t(x);
+         INSERT INTO t SELECT 1;",
+    );
+    for _ in 0..6 {
+        query(&mut pager, "INSERT INTO t SELECT zeroblob(200) FROM t");
+    }
+    let root = root_page(&mut pager, "t");
+    drop(pager);
+
+    // The root is an interior page; make its rightmost child the root again
+    assert_eq!(file.bytes()[page_offset(root)], 0x05);
+    file.patch(page_offset(root) + 8, &root.to_be_bytes());
+
+    let mut pager = file.open();
+    let message = format!("corrupt page {}: page is referenced more than once", root);
+    for sql in [
+        "SELECT count(*) FROM t",
+        "SELECT x FROM t WHERE rowid = 1000000",
+        "DELETE FROM t",
+        "VACUUM",
+    ] {
+        let err = query_error(&mut pager, sql);
+        assert!(err.starts_with(&message), "{}: {}", sql, err);
+    }
+}
+
+#[test]
+fn serial_types_larger_than_the_record() {
+    let file = TempDb::new("corrupt-serial-type");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE t(a, b); INSERT INTO t VALUES ('aaaaaaaaaa', 'bbbbbbbbbb')",
+    );
+    let root = root_page(&mut pager, "t");
+    drop(pager);
+
+    // Payload size, rowid, then a 22-byte record: give it two columns whose
+    // serial types claim 2^63 bytes each
+    let cell = first_cell(&file, root);
+    assert_eq!(file.bytes()[cell..cell + 2], [23, 1]);
+    let mut record = vec![19];
+    record.extend_from_slice(&[0xff; 18]);
+    file.patch(cell + 2, &record);
+
+    let mut pager = file.open();
+    assert_eq!(
+        query_error(&mut pager, "SELECT * FROM t"),
+        "corrupt page 0: record columns overrun its payload of 23 bytes"
+    );
+}
+
+#[test]
+fn payload_size_larger_than_the_file() {
+    let file = TempDb::new("corrupt-payload-size");
+    let mut pager = file.open();
+    query(&mut pager, "CREATE TABLE t(a); INSERT INTO t VALUES (1)");
+    let root = root_page(&mut pager, "t");
+    drop(pager);
+
+    // Point the only cell at free space holding a payload size of 2^64 - 1
+    let cell = 1000;
+    let mut bytes = vec![0xff; 9];
+    bytes.push(1);
+    file.patch(page_offset(root) + cell, &bytes);
+    file.patch(page_offset(root) + 8, &(cell as u16).to_be_bytes());
+
+    let mut pager = file.open();
+    let err = query_error(&mut pager, "SELECT * FROM t");
+    assert!(
+        err.starts_with(&format!("corrupt page {}: cell payload of", root)),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn byte_flipped_inside_schema_sql() {
+    for (sql, statements) in [
+        (
+            "CREATE TABLE t(a)",
+            ["PRAGMA integrity_check", "SELECT * FROM t"],
+        ),
+        (
+            "CREATE INDEX i ON t(a)",
+            ["PRAGMA integrity_check", "INSERT INTO t VALUES (2)"],
+        ),
+    ] {
+        let file = TempDb::new("corrupt-schema-sql");
+        let mut pager = file.open();
+        query(
+            &mut pager,
+            "CREATE TABLE t(a); CREATE INDEX i ON t(a); INSERT INTO t VALUES (1)",
+        );
+        drop(pager);
+
+        // A byte that isn't valid UTF-8, read as U+FFFD, such as CREATE INDE\xf2
+        let offset = file
+            .bytes()
+            .windows(sql.len())
+            .position(|w| w == sql.as_bytes())
+            .unwrap();
+        file.patch(offset + 11, &[0xf2]);
+
+        let mut pager = file.open();
+        for statement in statements {
+            let err = query_error(&mut pager, statement);
+            assert!(
+                err.starts_with("malformed database schema")
+                    || err.starts_with("malformed schema SQL"),
+                "{}: {}",
+                statement,
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Databases storing text as UTF-16le or UTF-16be.
+
+mod common;
+
+use common::{TempDb, query, texts, write_empty_database};
+
+const UTF16LE: u32 = 2;
+const UTF16BE: u32 = 3;
+
+/// Text outside ASCII, from two and three byte UTF-8 up to a surrogate pair.
+const VALUES: &str = "('héllo'), ('Ā'), ('ÿ'), ('日本'), ('z'), ('😀'), ('ｚ')";
+
+#[test]
+fn text_is_stored_and_ordered_in_utf16() {
+    for (encoding, order) in [
+        // BINARY compares the stored bytes, so the byte order decides
+        (UTF16LE, ["Ā", "😀", "ｚ", "héllo", "z", "日本", "ÿ"]),
+        (UTF16BE, ["héllo", "z", "ÿ", "Ā", "日本", "😀", "ｚ"]),
+    ] {
+        let file = TempDb::new(&format!("encoding-utf16-{}", encoding));
+        write_empty_database(&file, 4096, 0, encoding);
+        let mut pager = file.open();
+        query(
+            &mut pager,
+            &format!(
+                "CREATE TABLE t(v TEXT); CREATE INDEX t_v ON t(v); INSERT INTO t VALUES {}",
+                VALUES
+            ),
+        );
+
+        let rows = query(&mut pager, "SELECT v FROM t ORDER BY v");
+        assert_eq!(texts(&rows), order, "encoding {}", encoding);
+        let rows = query(&mut pager, "SELECT length(v) FROM t WHERE v = '日本'");
+        assert_eq!(texts(&rows), ["2"]);
+        let rows = query(&mut pager, "SELECT length(v) FROM t WHERE v = '😀'");
+        assert_eq!(texts(&rows), ["1"]);
+        assert_eq!(texts(&query(&mut pager, "PRAGMA integrity_check")), ["ok"]);
+        drop(pager);
+
+        // Both the rows and the schema are stored in the database's encoding
+        let data = file.bytes();
+        for text in ["héllo", "CREATE TABLE t(v TEXT)"] {
+            let stored: Vec<u8> = text
+                .encode_utf16()
+                .flat_map(|unit| match encoding {
+                    UTF16LE => unit.to_le_bytes(),
+                    _ => unit.to_be_bytes(),
+                })
+                .collect();
+            assert!(
+                data.windows(stored.len()).any(|w| w == stored),
+                "{:?} in encoding {}",
+                text,
+                encoding
+            );
+            assert!(!data.windows(text.len()).any(|w| w == text.as_bytes()));
+        }
+    }
+}
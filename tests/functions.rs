@@ -0,0 +1,49 @@
+//! Built-in functions and operators whose results sqlite3 pins down exactly.
+
+mod common;
+
+use common::{TempDb, query};
+
+/// Offset of the text encoding in the database header.
+const ENCODING_OFFSET: usize = 56;
+
+/// The columns of the single row a query returns, as text.
+fn row(file: &TempDb, sql: &str) -> Vec<String> {
+    let mut pager = file.open();
+    query(&mut pager, sql)[0]
+        .iter()
+        .map(|value| value.to_string())
+        .collect()
+}
+
+#[test]
+fn remainder_of_reals_is_that_of_their_integer_parts() {
+    let file = TempDb::new("functions-remainder");
+    assert_eq!(
+        row(&file, "SELECT 5.5 % 2, -7.9 % 2, 7 % 2.5, 1 % 0.5, 7 % 3"),
+        ["1.0", "-1.0", "1.0", "", "1"]
+    );
+}
+
+#[test]
+fn hex_encodes_text_in_the_database_encoding() {
+    let file = TempDb::new("functions-hex-utf16");
+    // An empty database can still change its encoding
+    let mut pager = file.open();
+    query(&mut pager, "CREATE TABLE t(a); DROP TABLE t");
+    drop(pager);
+    file.patch(ENCODING_OFFSET, &2u32.to_be_bytes());
+
+    assert_eq!(
+        row(&file, "SELECT hex('a'), hex(1.5), hex(X'00FF')"),
+        ["6100", "312E35", "00FF"]
+    );
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE t(a, b DEFAULT (hex('é')));
+         INSERT INTO t(a) VALUES ('x')",
+    );
+    drop(pager);
+    assert_eq!(row(&file, "SELECT hex(a), b FROM t"), ["7800", "E900"]);
+}
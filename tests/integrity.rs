@@ -0,0 +1,128 @@
+//! PRAGMA integrity_check and quick_check on databases damaged on purpose.
+
+mod common;
+
+use common::{TempDb, query, root_page, texts};
+
+const PAGE_SIZE: usize = 4096;
+
+/// Offset in the file of a page's B-tree header.
+fn page_offset(page: u32) -> usize {
+    (page as usize - 1) * PAGE_SIZE
+}
+
+#[test]
+fn intact_database_is_ok() {
+    let file = TempDb::new("integrity-ok");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE t(x NOT NULL, y);
+         CREATE INDEX ty ON t(y);
+         INSERT INTO t VALUES (1, 'a'), (2, 'b');
+         DELETE FROM t WHERE x = 1",
+    );
+    assert_eq!(texts(&query(&mut pager, "PRAGMA integrity_check")), ["ok"]);
+    assert_eq!(texts(&query(&mut pager, "PRAGMA quick_check")), ["ok"]);
+}
+
+#[test]
+fn null_in_not_null_column_and_missing_index_entry() {
+    let file = TempDb::new("integrity-rows");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE t(x NOT NULL, y);
+         CREATE INDEX ty ON t(y);
+         INSERT INTO t VALUES (0, 'a'), (5, 'b'), (6, 'c')",
+    );
+    let table = root_page(&mut pager, "t");
+    let index = root_page(&mut pager, "ty");
+    drop(pager);
+
+    // Make row 1's x, stored as the constant 0, a NULL instead
+    let header = page_offset(table);
+    let data = file.bytes();
+    let cell = header + u16::from_be_bytes([data[header + 8], data[header + 9]]) as usize;
+    assert_eq!(data[cell..cell + 4], [4, 1, 3, 8]);
+    file.patch(cell + 3, &[0]);
+    // Leave the index's last entry, for row 3, out of its cell count
+    file.patch(page_offset(index) + 3, &[0, 2]);
+
+    // Dropping the entry also leaves its bytes unaccounted for
+    let fragmented = format!(
+        "Tree {0} page {0}: fragmentation of 6 bytes reported as 0",
+        index
+    );
+    let mut pager = file.open();
+    assert_eq!(
+        texts(&query(&mut pager, "PRAGMA integrity_check")),
+        [
+            fragmented.clone(),
+            "NULL value in t.x".to_string(),
+            "row 3 missing from index ty".to_string(),
+            "wrong # of entries in index ty".to_string(),
+        ]
+    );
+    // A quick check doesn't compare the index with the table
+    assert_eq!(
+        texts(&query(&mut pager, "PRAGMA quick_check")),
+        [fragmented.clone(), "NULL value in t.x".to_string()]
+    );
+    assert_eq!(
+        texts(&query(&mut pager, "PRAGMA integrity_check(1)")),
+        [fragmented]
+    );
+}
+
+#[test]
+fn interior_page_pointing_at_itself() {
+    let file = TempDb::new("integrity-cycle");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE t(x); CREATE INDEX tx ON t(x); INSERT INTO t SELECT 1",
+    );
+    for _ in 0..6 {
+        query(&mut pager, "INSERT INTO t SELECT zeroblob(200) FROM t");
+    }
+    let root = root_page(&mut pager, "t");
+    drop(pager);
+
+    // Make the root's rightmost child the root again, which leaves the
+    // page it pointed at unused
+    let header = page_offset(root);
+    let data = file.bytes();
+    assert_eq!(data[header], 0x05);
+    let orphan = u32::from_be_bytes(data[header + 8..header + 12].try_into().unwrap());
+    file.patch(header + 8, &root.to_be_bytes());
+
+    // The index isn't compared with a table that can't be read
+    let mut pager = file.open();
+    assert_eq!(
+        texts(&query(&mut pager, "PRAGMA integrity_check")),
+        [
+            format!(
+                "Tree {0} page {0} right child: 2nd reference to page {0}",
+                root
+            ),
+            format!("Page {}: never used", orphan),
+        ]
+    );
+}
+
+#[test]
+fn header_page_count_beyond_the_file() {
+    let file = TempDb::new("integrity-page-count");
+    let mut pager = file.open();
+    query(&mut pager, "CREATE TABLE t(x); INSERT INTO t VALUES (1)");
+    drop(pager);
+    assert_eq!(file.bytes().len(), 2 * PAGE_SIZE);
+    file.patch(28, &10u32.to_be_bytes());
+
+    let mut pager = file.open();
+    assert_eq!(
+        texts(&query(&mut pager, "PRAGMA integrity_check")),
+        ["Page count in header (10) exceeds the 2 pages in the file"]
+    );
+}
@@ -0,0 +1,127 @@
+//! Rolling back commits a crash interrupted, from the hot journal they left.
+
+mod common;
+
+use common::{TempDb, query, texts};
+
+const PAGE_SIZE: usize = 4096;
+const SECTOR_SIZE: usize = 512;
+const JOURNAL_MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+const NONCE: u32 = 0x1234_5678;
+
+/// Checksum of a journaled page: every 200th byte, counting back from the
+/// end, added to the nonce.
+fn checksum(data: &[u8]) -> u32 {
+    let mut sum = NONCE;
+    let mut i = data.len() as isize - 200;
+    while i > 0 {
+        sum = sum.wrapping_add(data[i as usize] as u32);
+        i -= 200;
+    }
+    sum
+}
+
+/// A journal in SQLite's format, of a database that had `page_count` pages,
+/// saving the given pages. Records marked torn get a checksum that doesn't
+/// match, as if the crash came while writing them.
+fn journal(page_count: u32, records: &[(u32, &[u8], bool)]) -> Vec<u8> {
+    let mut journal = JOURNAL_MAGIC.to_vec();
+    for field in [
+        records.len() as u32,
+        NONCE,
+        page_count,
+        SECTOR_SIZE as u32,
+        PAGE_SIZE as u32,
+    ] {
+        journal.extend_from_slice(&field.to_be_bytes());
+    }
+    journal.resize(SECTOR_SIZE, 0);
+    for &(page, data, torn) in records {
+        journal.extend_from_slice(&page.to_be_bytes());
+        journal.extend_from_slice(data);
+        let sum = checksum(data).wrapping_add(torn as u32);
+        journal.extend_from_slice(&sum.to_be_bytes());
+    }
+    journal
+}
+
+fn page(data: &[u8], page: u32) -> &[u8] {
+    &data[(page as usize - 1) * PAGE_SIZE..page as usize * PAGE_SIZE]
+}
+
+#[test]
+fn hot_journal_rolls_back_an_interrupted_commit() {
+    let file = TempDb::new("journal-hot");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE t(v); INSERT INTO t VALUES ('before')",
+    );
+    let original = file.bytes();
+
+    // The commit got as far as the database file: it grew and changed
+    query(
+        &mut pager,
+        "UPDATE t SET v = 'after'; INSERT INTO t VALUES (zeroblob(10000))",
+    );
+    drop(pager);
+    assert!(file.bytes().len() > original.len());
+    let saved = [1, 2].map(|n| (n, page(&original, n), false));
+    let page_count = (original.len() / PAGE_SIZE) as u32;
+    std::fs::write(file.sibling("-journal"), journal(page_count, &saved)).unwrap();
+
+    let mut pager = file.open();
+    assert_eq!(texts(&query(&mut pager, "SELECT v FROM t")), ["before"]);
+    assert_eq!(file.bytes(), original);
+    assert!(!file.sibling("-journal").exists());
+    assert_eq!(texts(&query(&mut pager, "PRAGMA integrity_check")), ["ok"]);
+}
+
+#[test]
+fn replay_stops_at_a_torn_record() {
+    let file = TempDb::new("journal-torn");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE a(v); CREATE TABLE b(v);
+         INSERT INTO a VALUES ('a before'); INSERT INTO b VALUES ('b before')",
+    );
+    let original = file.bytes();
+    query(
+        &mut pager,
+        "UPDATE a SET v = 'a after'; UPDATE b SET v = 'b after'",
+    );
+    drop(pager);
+
+    // Tables a and b are on pages 2 and 3; the record of page 3 is torn
+    let saved = [
+        (2, page(&original, 2), false),
+        (3, page(&original, 3), true),
+    ];
+    std::fs::write(file.sibling("-journal"), journal(3, &saved)).unwrap();
+
+    let mut pager = file.open();
+    assert_eq!(texts(&query(&mut pager, "SELECT v FROM a")), ["a before"]);
+    assert_eq!(texts(&query(&mut pager, "SELECT v FROM b")), ["b after"]);
+}
+
+#[test]
+fn journal_without_a_header_is_not_replayed() {
+    let file = TempDb::new("journal-finished");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE t(v); INSERT INTO t VALUES ('before')",
+    );
+    let original = file.bytes();
+    query(&mut pager, "UPDATE t SET v = 'after'");
+    drop(pager);
+
+    // A committed transaction zeroes the journal header in some journal modes
+    let mut finished = journal(2, &[(2, page(&original, 2), false)]);
+    finished[..SECTOR_SIZE].fill(0);
+    std::fs::write(file.sibling("-journal"), finished).unwrap();
+
+    let mut pager = file.open();
+    assert_eq!(texts(&query(&mut pager, "SELECT v FROM t")), ["after"]);
+}
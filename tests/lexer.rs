@@ -0,0 +1,34 @@
+//! Tokenizing SQL text.
+
+use codecrafters_sqlite::sql::{Symbol, Token, tokenize};
+
+/// The tokens of SQL text, without the final `Token::Eof`.
+fn tokens(sql: &str) -> Vec<Token> {
+    let mut tokens: Vec<Token> = tokenize(sql)
+        .unwrap()
+        .into_iter()
+        .map(|spanned| spanned.token)
+        .collect();
+    assert_eq!(tokens.pop(), Some(Token::Eof));
+    tokens
+}
+
+fn word(text: &str) -> Token {
+    Token::Word(text.to_string())
+}
+
+#[test]
+fn non_ascii_characters_are_identifier_characters() {
+    // Symbols, typographic quotes and the replacement character for a
+    // corrupt byte are all part of words, whatever their Unicode class
+    assert_eq!(tokens("¤"), [word("¤")]);
+    assert_eq!(
+        tokens("a¤b = ‘bob’"),
+        [word("a¤b"), Token::Symbol(Symbol::Eq), word("‘bob’")]
+    );
+    assert_eq!(
+        tokens("CREATE INDE\u{fffd} i"),
+        [word("CREATE"), word("INDE\u{fffd}"), word("i")]
+    );
+    assert_eq!(tokens("\u{fffd}$1"), [word("\u{fffd}$1")]);
+}
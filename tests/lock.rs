@@ -0,0 +1,108 @@
+//! File locks shared with other processes. Locks belong to a process, so
+//! the other connection is the shell binary, fed statements on its stdin.
+
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{TempDb, query, query_error, texts};
+
+/// A shell running in another process against the same database.
+struct OtherProcess {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl OtherProcess {
+    fn open(file: &TempDb) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_codecrafters-sqlite"))
+            .arg(file.path())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        OtherProcess {
+            child,
+            stdin,
+            stdout,
+        }
+    }
+
+    /// Run statements, waiting until they have finished.
+    fn run(&mut self, sql: &str) {
+        writeln!(self.stdin, "{}\nSELECT 'done';", sql).unwrap();
+        let mut line = String::new();
+        while line.trim_end() != "done" {
+            line.clear();
+            assert!(
+                self.stdout.read_line(&mut line).unwrap() > 0,
+                "shell exited"
+            );
+        }
+    }
+}
+
+impl Drop for OtherProcess {
+    fn drop(&mut self) {
+        let _ = writeln!(self.stdin, ".quit");
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn reserved_lock_lets_readers_in_but_not_writers() {
+    let file = TempDb::new("lock-reserved");
+    let mut pager = file.open();
+    query(&mut pager, "CREATE TABLE t(a); INSERT INTO t VALUES (1)");
+
+    let mut other = OtherProcess::open(&file);
+    other.run("BEGIN IMMEDIATE; INSERT INTO t VALUES (2);");
+
+    // The other process's change isn't committed, and it holds RESERVED
+    assert_eq!(texts(&query(&mut pager, "SELECT a FROM t")), ["1"]);
+    assert_eq!(
+        query_error(&mut pager, "INSERT INTO t VALUES (3)"),
+        "database is locked"
+    );
+
+    // Once it commits, its change is seen and writing is possible again
+    other.run("COMMIT;");
+    assert_eq!(texts(&query(&mut pager, "SELECT a FROM t")), ["1", "2"]);
+    query(&mut pager, "INSERT INTO t VALUES (3)");
+    assert_eq!(
+        texts(&query(&mut pager, "SELECT a FROM t")),
+        ["1", "2", "3"]
+    );
+}
+
+#[test]
+fn exclusive_lock_keeps_readers_out_until_busy_timeout_waits_for_it() {
+    let file = TempDb::new("lock-exclusive");
+    let mut pager = file.open();
+    query(&mut pager, "CREATE TABLE t(a); INSERT INTO t VALUES (1)");
+
+    let mut other = OtherProcess::open(&file);
+    other.run("BEGIN EXCLUSIVE;");
+    assert_eq!(
+        query_error(&mut pager, "SELECT a FROM t"),
+        "database is locked"
+    );
+
+    // With a busy timeout, the read retries until the other process commits
+    query(&mut pager, "PRAGMA busy_timeout = 5000");
+    let committer = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        other.run("INSERT INTO t VALUES (2); COMMIT;");
+        other
+    });
+    let start = Instant::now();
+    assert_eq!(texts(&query(&mut pager, "SELECT a FROM t")), ["1", "2"]);
+    assert!(start.elapsed() >= Duration::from_millis(250));
+    drop(committer.join().unwrap());
+}
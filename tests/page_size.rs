@@ -0,0 +1,65 @@
+//! Databases with 64 KiB pages and bytes reserved at the end of each page.
+
+mod common;
+
+use common::{TempDb, header_u32, query, root_page, texts, write_empty_database};
+
+const PAGE_SIZE: usize = 65536;
+const RESERVED: usize = 32;
+
+/// Number of small rows, enough to split a 64 KiB table page.
+const ROWS: i64 = 400;
+
+/// Offset in the header of the page count.
+const PAGE_COUNT_OFFSET: usize = 28;
+
+#[test]
+fn largest_pages_with_reserved_bytes() {
+    let file = TempDb::new("page-size-64k");
+    write_empty_database(&file, PAGE_SIZE, RESERVED as u8, 1);
+    // Whatever an extension keeps in the reserved bytes is left alone
+    file.patch(PAGE_SIZE - RESERVED, &[0xaa; RESERVED]);
+
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE t(k INTEGER PRIMARY KEY, v TEXT, b BLOB);
+         CREATE INDEX t_v ON t(v)",
+    );
+    // Small rows that split the table's root, and blobs larger than a page,
+    // which overflow onto a chain of pages
+    let rows: Vec<String> = (1..=ROWS)
+        .map(|k| format!("({}, 'value{:04}', zeroblob(400))", k, k))
+        .collect();
+    query(
+        &mut pager,
+        &format!("INSERT INTO t VALUES {}", rows.join(", ")),
+    );
+    query(
+        &mut pager,
+        "INSERT INTO t VALUES (5000, 'big', zeroblob(200000)), (5001, 'bigger', zeroblob(300000))",
+    );
+
+    assert_eq!(texts(&query(&mut pager, "PRAGMA integrity_check")), ["ok"]);
+    let rows = query(&mut pager, "SELECT count(*) FROM t");
+    assert_eq!(texts(&rows), [(ROWS + 2).to_string()]);
+    let rows = query(&mut pager, "SELECT k FROM t WHERE v = 'value0234'");
+    assert_eq!(texts(&rows), ["234"]);
+    let rows = query(&mut pager, "SELECT length(b) FROM t WHERE v = 'bigger'");
+    assert_eq!(texts(&rows), ["300000"]);
+    let root = root_page(&mut pager, "t") as usize;
+    drop(pager);
+
+    let data = std::fs::read(file.path()).unwrap();
+    let pages = header_u32(&file, PAGE_COUNT_OFFSET) as usize;
+    assert!(pages > 8, "{} pages", pages);
+    assert_eq!(data.len(), pages * PAGE_SIZE);
+    assert_eq!(data[16..18], [0, 1]);
+    assert_eq!(data[20] as usize, RESERVED);
+    assert_eq!(data[PAGE_SIZE - RESERVED..PAGE_SIZE], [0xaa; RESERVED]);
+    assert_eq!(data[(root - 1) * PAGE_SIZE], 0x05, "interior table root");
+    // No page's reserved bytes hold anything written since
+    for page in data.chunks(PAGE_SIZE).skip(1) {
+        assert_eq!(page[PAGE_SIZE - RESERVED..], [0; RESERVED]);
+    }
+}
@@ -0,0 +1,95 @@
+//! Reading table and index definitions back from the SQL sqlite_schema
+//! stores, however it was laid out when written.
+
+mod common;
+
+use common::{TempDb, query, query_error, root_page, texts};
+
+const PAGE_SIZE: usize = 4096;
+
+/// The number of cells on a page, from its B-tree header.
+fn cell_count(file: &TempDb, page: u32) -> u16 {
+    let header = (page as usize - 1) * PAGE_SIZE;
+    let data = file.bytes();
+    u16::from_be_bytes([data[header + 3], data[header + 4]])
+}
+
+#[test]
+fn index_with_line_breaks_is_kept_up_to_date() {
+    let file = TempDb::new("schema-index-layout");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE a(v);
+         CREATE INDEX ia\nON a(v);
+         CREATE INDEX ib\tON\ta\t(\tv\tDESC\t);
+         INSERT INTO a VALUES (1), (2), (3);
+         DELETE FROM a WHERE v = 2",
+    );
+    assert_eq!(cell_count(&file, root_page(&mut pager, "ia")), 2);
+    assert_eq!(cell_count(&file, root_page(&mut pager, "ib")), 2);
+    assert_eq!(texts(&query(&mut pager, "PRAGMA integrity_check")), ["ok"]);
+    let rows = query(&mut pager, "SELECT v FROM a WHERE v = 3");
+    assert_eq!(texts(&rows), ["3"]);
+}
+
+#[test]
+fn writes_fail_when_an_index_definition_does_not_parse() {
+    let file = TempDb::new("schema-index-malformed");
+    let mut pager = file.open();
+    query(&mut pager, "CREATE TABLE a(v); CREATE INDEX ia ON a(v)");
+    drop(pager);
+
+    // Garble the stored CREATE INDEX, keeping its length
+    let data = file.bytes();
+    let sql = b"CREATE INDEX ia ON a(v)";
+    let offset = data.windows(sql.len()).position(|w| w == sql).unwrap();
+    file.patch(offset, b"CREATE INDEX ia OF a(v)");
+
+    let mut pager = file.open();
+    assert_eq!(
+        query_error(&mut pager, "INSERT INTO a VALUES (1)"),
+        "malformed database schema (ia)"
+    );
+}
+
+#[test]
+fn partial_index_with_line_breaks_is_not_searched() {
+    let file = TempDb::new("schema-partial-index");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE a(v);
+         CREATE INDEX ia ON a(v)\nWHERE v > 5;
+         INSERT INTO a VALUES (1), (7)",
+    );
+    // The index only holds 7, so only a scan finds 1
+    assert_eq!(
+        texts(&query(&mut pager, "SELECT v FROM a WHERE v = 1")),
+        ["1"]
+    );
+    assert_eq!(
+        texts(&query(&mut pager, "SELECT v FROM a WHERE v = 7")),
+        ["7"]
+    );
+}
+
+#[test]
+fn constraint_words_inside_literals_are_not_constraints() {
+    let file = TempDb::new("schema-column-constraints");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE t(
+             x TEXT DEFAULT 'not null',
+             y TEXT CHECK (y <> 'primary key'),
+             z DEFAULT 'it''s, unique'
+         );
+         INSERT INTO t DEFAULT VALUES;
+         INSERT INTO t(x) VALUES (NULL);
+         ALTER TABLE t DROP COLUMN y",
+    );
+    let rows = query(&mut pager, "SELECT x, z FROM t");
+    assert_eq!(texts(&rows), ["not null", ""]);
+    assert_eq!(rows[0][1].to_string(), "it's, unique");
+}
@@ -0,0 +1,86 @@
+//! The shell's dot-commands that list the schema, run as the binary.
+
+mod common;
+
+use std::process::Command;
+
+use common::{TempDb, make_view_or_trigger, query};
+
+/// Run a dot-command against the database and return what it printed.
+fn shell(file: &TempDb, command: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_codecrafters-sqlite"))
+        .arg(file.path())
+        .arg(command)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}: {:?}", command, output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Tables with an index, an automatic index and a view. The view replaces
+/// a table whose SQL is just short enough for the view's record to fit
+/// without padding.
+fn fruit_database() -> TempDb {
+    let file = TempDb::new("shell-schema");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE apples(id INTEGER PRIMARY KEY, name TEXT UNIQUE, color TEXT);
+         CREATE INDEX idx_apples_color ON apples(color);
+         CREATE TABLE oranges(id INTEGER PRIMARY KEY, name TEXT);
+         CREATE TABLE red_apples(placeholder_for_view_sql_x)",
+    );
+    drop(pager);
+    make_view_or_trigger(
+        &file,
+        "red_apples",
+        "view",
+        "red_apples",
+        "CREATE VIEW red_apples AS SELECT id, name FROM apples",
+    );
+    file
+}
+
+#[test]
+fn tables_and_indexes_match_like_patterns() {
+    let file = fruit_database();
+    assert_eq!(
+        shell(&file, ".tables"),
+        "apples      oranges     red_apples\n"
+    );
+    assert_eq!(
+        shell(&file, ".tables %s"),
+        "apples      oranges     red_apples\n"
+    );
+    assert_eq!(shell(&file, ".tables o%"), "oranges\n");
+    assert_eq!(
+        shell(&file, ".indexes"),
+        "idx_apples_color           sqlite_autoindex_apples_1\n"
+    );
+    assert_eq!(shell(&file, ".indexes oranges"), "");
+}
+
+#[test]
+fn schema_and_fullschema_print_the_stored_sql() {
+    let file = fruit_database();
+    assert_eq!(
+        shell(&file, ".schema apples"),
+        "CREATE TABLE apples(id INTEGER PRIMARY KEY, name TEXT UNIQUE, color TEXT);\n\
+         CREATE INDEX idx_apples_color ON apples(color);\n"
+    );
+    // A GLOB pattern, and a view with a comment naming its columns
+    assert_eq!(
+        shell(&file, ".schema --nosys r*"),
+        "CREATE VIEW red_apples AS SELECT id, name FROM apples\n\
+         /* red_apples(id,name) */;\n"
+    );
+    // .fullschema leaves out the comments, as sqlite3's does
+    assert_eq!(
+        shell(&file, ".fullschema"),
+        "CREATE TABLE apples(id INTEGER PRIMARY KEY, name TEXT UNIQUE, color TEXT);\n\
+         CREATE INDEX idx_apples_color ON apples(color);\n\
+         CREATE TABLE oranges(id INTEGER PRIMARY KEY, name TEXT);\n\
+         CREATE VIEW red_apples AS SELECT id, name FROM apples;\n\
+         /* No STAT tables available */\n"
+    );
+}
@@ -0,0 +1,73 @@
+//! VACUUM and VACUUM INTO: compacting a database without its free pages.
+
+mod common;
+
+use common::{TempDb, header_u32, query, query_error, texts};
+
+/// Offsets in the header of fields VACUUM sets or keeps.
+const PAGE_COUNT_OFFSET: usize = 28;
+const FREELIST_COUNT_OFFSET: usize = 36;
+const SCHEMA_COOKIE_OFFSET: usize = 40;
+const USER_VERSION_OFFSET: usize = 60;
+const APPLICATION_ID_OFFSET: usize = 68;
+
+#[test]
+fn vacuum_compacts_and_keeps_rows_and_header_settings() {
+    let file = TempDb::new("vacuum-source");
+    let copy = TempDb::new("vacuum-into");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE t(k INTEGER PRIMARY KEY, v TEXT, b BLOB);
+         CREATE INDEX t_v ON t(v)",
+    );
+    let rows: Vec<String> = (1..=400)
+        .map(|k| format!("({}, 'row{:03}', zeroblob({}))", k, 401 - k, k % 3 * 1500))
+        .collect();
+    query(
+        &mut pager,
+        &format!("INSERT INTO t VALUES {}", rows.join(", ")),
+    );
+    query(&mut pager, "DELETE FROM t WHERE k % 4 != 0");
+    drop(pager);
+    file.patch(USER_VERSION_OFFSET, &7u32.to_be_bytes());
+    file.patch(APPLICATION_ID_OFFSET, &0x1234u32.to_be_bytes());
+    let pages = header_u32(&file, PAGE_COUNT_OFFSET);
+    let cookie = header_u32(&file, SCHEMA_COOKIE_OFFSET);
+    assert!(header_u32(&file, FREELIST_COUNT_OFFSET) > 0);
+
+    // The copy has no free pages, and the original is left alone
+    let mut pager = file.open();
+    let into = format!("VACUUM INTO '{}'", copy.path().display());
+    query(&mut pager, &into);
+    assert_eq!(header_u32(&file, PAGE_COUNT_OFFSET), pages);
+    let compacted = header_u32(&copy, PAGE_COUNT_OFFSET);
+    assert!(compacted < pages / 2, "{} of {} pages", compacted, pages);
+    assert_eq!(header_u32(&copy, FREELIST_COUNT_OFFSET), 0);
+    assert_eq!(header_u32(&copy, USER_VERSION_OFFSET), 7);
+    assert_eq!(header_u32(&copy, APPLICATION_ID_OFFSET), 0x1234);
+    assert_eq!(header_u32(&copy, SCHEMA_COOKIE_OFFSET), cookie + 1);
+    assert_eq!(copy.bytes().len(), compacted as usize * 4096, "file length");
+
+    // Only an empty file can be vacuumed into
+    assert_eq!(query_error(&mut pager, &into), "output file already exists");
+
+    // VACUUM rebuilds the original the same way
+    query(&mut pager, "VACUUM");
+    assert_eq!(texts(&query(&mut pager, "PRAGMA integrity_check")), ["ok"]);
+    drop(pager);
+    assert_eq!(header_u32(&file, PAGE_COUNT_OFFSET), compacted);
+    assert_eq!(header_u32(&file, FREELIST_COUNT_OFFSET), 0);
+    assert_eq!(header_u32(&file, USER_VERSION_OFFSET), 7);
+
+    // Both keep the rows in rowid order, and the index still finds them
+    let expected: Vec<String> = (1..=100).map(|k| (k * 4).to_string()).collect();
+    for db in [&file, &copy] {
+        let mut pager = db.open();
+        assert_eq!(texts(&query(&mut pager, "PRAGMA integrity_check")), ["ok"]);
+        assert_eq!(texts(&query(&mut pager, "SELECT k FROM t")), expected);
+        let rows = query(&mut pager, "SELECT k, length(b) FROM t WHERE v = 'row201'");
+        assert_eq!(rows[0][0].to_string(), "200");
+        assert_eq!(rows[0][1].to_string(), "3000");
+    }
+}
@@ -0,0 +1,89 @@
+//! Reading and writing a database in WAL mode, where commits go to the
+//! `-wal` file until a checkpoint copies them into the database.
+
+mod common;
+
+use common::{TempDb, query, texts};
+
+const PAGE_SIZE: usize = 4096;
+/// Sizes of the log's header and of the header of each frame in it.
+const WAL_HEADER_SIZE: usize = 32;
+const FRAME_HEADER_SIZE: usize = 24;
+/// Offsets of the file format versions in the database header.
+const WRITE_VERSION_OFFSET: usize = 18;
+const READ_VERSION_OFFSET: usize = 19;
+
+fn wal_len(file: &TempDb) -> usize {
+    std::fs::metadata(file.sibling("-wal")).map_or(0, |m| m.len() as usize)
+}
+
+#[test]
+fn commits_go_to_the_log_until_a_checkpoint() {
+    let file = TempDb::new("wal-checkpoint");
+    let mut pager = file.open();
+    assert_eq!(
+        texts(&query(&mut pager, "PRAGMA journal_mode = wal")),
+        ["wal"]
+    );
+    let data = file.bytes();
+    assert_eq!(data[WRITE_VERSION_OFFSET], 2);
+    assert_eq!(data[READ_VERSION_OFFSET], 2);
+
+    query(
+        &mut pager,
+        "CREATE TABLE t(v); INSERT INTO t VALUES ('a'), ('b')",
+    );
+    // The database file is untouched; the log holds whole frames
+    assert_eq!(file.bytes(), data);
+    let frames = (wal_len(&file) - WAL_HEADER_SIZE) / (FRAME_HEADER_SIZE + PAGE_SIZE);
+    assert!(frames > 0);
+    assert_eq!(
+        (wal_len(&file) - WAL_HEADER_SIZE) % (FRAME_HEADER_SIZE + PAGE_SIZE),
+        0
+    );
+
+    // Another connection reads the commits from the log
+    let mut other = file.open();
+    assert_eq!(texts(&query(&mut other, "SELECT v FROM t")), ["a", "b"]);
+    drop(other);
+
+    let rows = query(&mut pager, "PRAGMA wal_checkpoint(truncate)");
+    let counts: Vec<String> = rows[0].iter().map(|v| v.to_string()).collect();
+    assert_eq!(counts, ["0", "0", "0"]);
+    assert_eq!(wal_len(&file), 0);
+    drop(pager);
+
+    // The database file alone now holds the rows
+    let copy = TempDb::new("wal-checkpoint-copy");
+    std::fs::write(copy.path(), file.bytes()).unwrap();
+    let mut pager = copy.open();
+    assert_eq!(texts(&query(&mut pager, "SELECT v FROM t")), ["a", "b"]);
+}
+
+#[test]
+fn torn_commit_at_the_end_of_the_log_is_ignored() {
+    let file = TempDb::new("wal-torn");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "PRAGMA journal_mode = wal;
+         CREATE TABLE t(v); INSERT INTO t VALUES ('kept')",
+    );
+    let committed = wal_len(&file);
+    query(&mut pager, "INSERT INTO t VALUES ('torn')");
+    drop(pager);
+
+    // The crash came halfway through writing the last frame
+    let wal = std::fs::read(file.sibling("-wal")).unwrap();
+    assert!(wal.len() > committed);
+    std::fs::write(file.sibling("-wal"), &wal[..wal.len() - PAGE_SIZE / 2]).unwrap();
+
+    let mut pager = file.open();
+    assert_eq!(texts(&query(&mut pager, "SELECT v FROM t")), ["kept"]);
+    query(&mut pager, "INSERT INTO t VALUES ('again')");
+    assert_eq!(
+        texts(&query(&mut pager, "SELECT v FROM t")),
+        ["kept", "again"]
+    );
+    assert_eq!(texts(&query(&mut pager, "PRAGMA integrity_check")), ["ok"]);
+}
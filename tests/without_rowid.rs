@@ -0,0 +1,64 @@
+//! WITHOUT ROWID tables, stored in an index B-tree keyed by their PRIMARY KEY.
+
+mod common;
+
+use common::{TempDb, query, query_error, root_page, texts};
+
+#[test]
+fn rows_are_read_in_key_order_and_found_by_primary_key() {
+    let file = TempDb::new("without-rowid");
+    let mut pager = file.open();
+    query(
+        &mut pager,
+        "CREATE TABLE kv(k TEXT, n INTEGER, v, PRIMARY KEY(k, n DESC)) WITHOUT ROWID",
+    );
+    // Keys in scrambled order, enough of them to split the tree
+    let rows: Vec<String> = (0..2000)
+        .map(|i| {
+            let key = i * 7919 % 2000;
+            format!(
+                "('key{:04}', {}, 'value{:04}-{}')",
+                key / 2,
+                key % 2,
+                key / 2,
+                key % 2
+            )
+        })
+        .collect();
+    query(
+        &mut pager,
+        &format!("INSERT INTO kv VALUES {}", rows.join(", ")),
+    );
+    assert_eq!(texts(&query(&mut pager, "PRAGMA integrity_check")), ["ok"]);
+
+    // The whole key orders the rows, n descending within each k
+    let rows = query(&mut pager, "SELECT v FROM kv");
+    assert_eq!(rows.len(), 2000);
+    assert_eq!(
+        texts(&rows[..4]),
+        ["value0000-1", "value0000-0", "value0001-1", "value0001-0"]
+    );
+
+    // A whole key and a prefix of it are both found
+    let rows = query(&mut pager, "SELECT v FROM kv WHERE k = 'key0777' AND n = 0");
+    assert_eq!(texts(&rows), ["value0777-0"]);
+    let rows = query(&mut pager, "SELECT n FROM kv WHERE k = 'key0999'");
+    assert_eq!(texts(&rows), ["1", "0"]);
+    let rows = query(&mut pager, "SELECT v FROM kv WHERE k = 'key1000'");
+    assert!(rows.is_empty());
+
+    // There is no rowid, and the key is unique
+    assert_eq!(
+        query_error(&mut pager, "SELECT rowid FROM kv"),
+        "no such column: rowid in table kv"
+    );
+    assert_eq!(
+        query_error(&mut pager, "INSERT INTO kv VALUES ('key0001', 1, 'again')"),
+        "UNIQUE constraint failed: kv.k, kv.n"
+    );
+
+    // The table's root is an interior index page
+    let root = root_page(&mut pager, "kv") as usize;
+    drop(pager);
+    assert_eq!(file.bytes()[(root - 1) * 4096], 0x02);
+}